@@ -373,7 +373,8 @@ Example:
 
 (defmacro func?
 "
-True if the expression is a [builtin?](#root::builtin?), a [lambda?](#root::lambda?), or a [macro?](#root::macro?)
+True if the expression is a [builtin?](#root::builtin?), a [lambda?](#root::lambda?), a [macro?](#root::macro?)
+or a [record-type?](#root::record-type?) (a struct constructor)
 
 Section: type
 
@@ -384,7 +385,7 @@ Example:
 (test::assert-true (func? first))
 (test::assert-true (func? let))
 "
-    (to-test) `(or (builtin? ,to-test) (lambda? ,to-test) (macro? ,to-test)))
+    (to-test) `(or (builtin? ,to-test) (lambda? ,to-test) (macro? ,to-test) (record-type? ,to-test)))
 
 (defmacro when
 "Usage: (when provided-condition if-true)
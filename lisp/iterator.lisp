@@ -721,6 +721,8 @@ Example:
 
 (defn iter
 "Return thing as an iterator if possible (if it is an iterator just return thing).
+Lists, vectors, strings, hash maps, sets, persistent collections, files and processes
+become native iterators (see make-iter).
 
 Section: iterator
 
@@ -729,19 +731,18 @@ Example:
 (assert-true (iterator::iter? (iterator::iter '#(1 2 3))))
 (assert-true (iterator::iter? (iterator::iter \"abc\")))
 (assert-true (iterator::iter? (iterator::iter (iterator::iter '(1 2 3)))))
+(assert-true (iterator::iter? (iterator::iter (make-hash))))
+(assert-true (iterator::iter? (iterator::iter #s(1 2))))
+(assert-true (iterator::iter? (iterator::iter (persistent-vec 1 2))))
+(assert-error (iterator::iter 1))
 "
   (thing)
   (if (iter? thing)
         thing
-      (list? thing)
-        ((list-iter) :init thing)
-      (vec? thing)
-        ((vec-iter) :init thing 0)
-      (string? thing)
-        ((string-iter) :init thing)
-      (file? thing)
-        ((file-iter) :init thing)
-      (err "iter: requires a list, vector, string, file or existing iterator")))
+      (or (list? thing) (vec? thing) (string? thing) (hash? thing) (set? thing)
+          (persistent-vec? thing) (persistent-map? thing) (file? thing) (process? thing))
+        (make-iter thing)
+      (err "iter: requires a list, vector, string, hash map, set, persistent collection, file, process or existing iterator")))
 
 (defn iter-or-single
 "Return thing as an iterator if possible (if it is an iterator just return thing).
@@ -756,18 +757,14 @@ Example:
 (assert-true (iterator::iter? (iterator::iter-or-single (iterator::iter '(1 2 3)))))
 (assert-true (iterator::iter? (iterator::iter-or-single 1)))
 (assert-true (iterator::iter? (iterator::iter-or-single #\A)))
+(assert-equal 1 ((iterator::iter-or-single (make-hash '((:a . 1)))) :count))
 "
   (thing)
   (if (iter? thing)
         thing
-      (list? thing)
-        ((list-iter) :init thing)
-      (vec? thing)
-        ((vec-iter) :init thing 0)
-      (string? thing)
-        ((string-iter) :init thing)
-      (file? thing)
-        ((file-iter) :init thing)
+      ; Hash maps and processes are loose items here.
+      (or (list? thing) (vec? thing) (string? thing) (file? thing))
+        (make-iter thing)
       ((single-iter) :init thing)))
 
 (defn next!
@@ -820,13 +817,12 @@ Example:
 (assert-equal 2 (test-iter :next!))
 (assert-true (test-iter :empty?))
 "
-    (&rest i) (do
-    (var si (iter i))
+    (&rest i)
     (if (= (length i) 1)
-          ((range-iter) :count (si :next!))
+          (iter-range (vec-nth i 0))
         (= (length i) 2)
-          (do (var first (si :next!)) ((range-iter) :init first (si :next!)))
-        (err "range: requires one or two integers"))))
+          (iter-range (vec-nth i 0) (+ (vec-nth i 1) 1))
+        (err "range: requires one or two integers")))
 
 (defn collect
 "Collect all the values into a list.  This will consume the iterator and
@@ -890,7 +886,7 @@ Example:
 (assert-equal 1 (tmap :next!))
 (assert-true (tmap :empty?))
 "
-    (map-fn items) ((iter items) :map map-fn))
+    (map-fn items) (iter-map map-fn (iter items)))
 
 (defn slice
 "Provides a slice of iterator.  Will call iter on items.  Slice iter will consume
@@ -936,7 +932,7 @@ Example:
 (assert-equal 3 (test-iter :next!))
 (assert-true (test-iter :empty?))
 "
-    (predicate items) ((iter items) :filter predicate))
+    (predicate items) (iter-filter predicate (iter items)))
 
 (defn reverse
 "Produce an iterator the is the reverse of items.  Will call iter on items and 
@@ -1055,7 +1051,10 @@ Example:
 (assert-equal nil (test-iter :next!))
 (assert-true (test-iter :empty?))
 "
-    (first-iter &rest rest-iters) (apply (append-iter) :init first-iter rest-iters))
+    (first-iter &rest rest-iters) (do
+    (var iters (vec (iter-or-single first-iter)))
+    (for i in rest-iters (vec-push! iters (iter-or-single i)))
+    (apply iter-chain iters)))
 
 (defn append-to!
 "Combine the provided items after the first (first must be a vector or list)
@@ -1302,7 +1301,102 @@ Example:
 (test::assert-equal (list #\m #\m) (collect (take (repeat #\m 4) 2)))
 "
   (provided-iter n)
-  ((take-iter) :init provided-iter n))
+  (iter-take (iter provided-iter) n))
+
+(defn skip
+"Return iterator with the items of provided-iter after the first n (calls iter
+on provided-iter).
+
+Section: iterator
+
+Example:
+(test::assert-equal (list 2 3) (collect (skip '(0 1 2 3) 2)))
+"
+  (provided-iter n)
+  (iter-skip (iter provided-iter) n))
+
+(defn zip
+"Return iterator over pairs (fst-item . scnd-item) of items from fst and scnd
+(calls iter on both), it ends when either does.
+
+Section: iterator
+
+Example:
+(test::assert-equal (list (join 1 'a) (join 2 'b)) (collect (zip '(1 2 3) '(a b))))
+"
+  (fst scnd)
+  (iter-zip (iter fst) (iter scnd)))
+
+(defn enumerate
+"Return iterator over pairs (index . item) of items (calls iter on items), the
+index starts at 0.
+
+Section: iterator
+
+Example:
+(test::assert-equal (list (join 0 'a) (join 1 'b)) (collect (enumerate '(a b))))
+"
+  (items)
+  (iter-enumerate (iter items)))
+
+(defn flat-map
+"Apply map-fn to each of items (calls iter on items and the result of map-fn)
+and return an iterator over all the resulting items.
+
+Section: iterator
+
+Example:
+(test::assert-equal (list 1 1 2 2) (collect (flat-map (fn (x) (list x x)) '(1 2))))
+"
+  (map-fn items)
+  (iter-flat-map (fn (x) (iter (map-fn x))) (iter items)))
+
+(defn take-while
+"Return iterator over items (calls iter on items) until predicate is not true.
+
+Section: iterator
+
+Example:
+(test::assert-equal (list 1 2) (collect (take-while (fn (x) (< x 3)) '(1 2 3 1))))
+"
+  (predicate items)
+  (iter-take-while predicate (iter items)))
+
+(defn chunk
+"Return iterator over vectors of n items from items (calls iter on items), the
+last vector may be shorter.
+
+Section: iterator
+
+Example:
+(test::assert-equal (list '#(0 1) '#(2)) (collect (chunk '(0 1 2) 2)))
+"
+  (items n)
+  (iter-chunk (iter items) n))
+
+(defn window
+"Return iterator over vectors of each n consecutive items from items (calls
+iter on items).
+
+Section: iterator
+
+Example:
+(test::assert-equal (list '#(0 1) '#(1 2)) (collect (window '(0 1 2) 2)))
+"
+  (items n)
+  (iter-window (iter items) n))
+
+(defn dedup
+"Return iterator over items (calls iter on items) with runs of equal? items
+reduced to the first one.
+
+Section: iterator
+
+Example:
+(test::assert-equal (list 1 2 1) (collect (dedup '(1 1 2 1 1))))
+"
+  (items)
+  (iter-dedup (iter items)))
 
 (ns-export '(
     iterator
@@ -1322,6 +1416,14 @@ Example:
     repeat
     take-iter
     take
+    skip
+    zip
+    enumerate
+    flat-map
+    take-while
+    chunk
+    window
+    dedup
     map-iter
     append-iter
     slice-iter
@@ -433,7 +433,7 @@ Example:
        (err err-msg)))))
   (:fn get-log (self log-level to-log)
        (when (>= (convert-log-level log-level) log-level-int)
-           (str (epoch)
+           (str (date-format (date-now) "%Y-%m-%d %H:%M:%S.%L")
                     "  " (with-padding (str (get-pid) ) 9 " " :center)
                     " " (with-padding (str (str-upper (apply str (rest (iterator::collect (iterator::iter (str log-level)))))) ":") 7 " " :center)
                     "[" logger-name "]"
@@ -548,21 +548,26 @@ Example:
 (defn fs-notify
 "fs-notify is designed to notify the caller of changes to a file or
 directory heirarchy via a callback that accepts two arguments, the file that
-changed and the type of change: :created, :deleted, xor :modified.
-Takes a callback and file or directory to watch for changes and the number
-of milliseconds to sleep before checking for changes to the provided file/directory
-hierarchy. This function loops forever, and calls the callback whenever it
-detects a change. This function relies on the function [collate-fs-changes](#file::colate-fs-changes)
-to compute differences.
+changed and the type of change: :created, :deleted, :modified, :moved-from,
+:moved-to xor :attrib.
+Takes a callback and file or directory to watch for changes and an optional
+number of milliseconds to wait for related changes to a file so they can be
+reported once (defaults to 50ms). This function loops forever, and calls the
+callback whenever it detects a change. It is built on [fs-watch](#file::fs-watch),
+on platforms where that is not available (see fs-watch-available?) it falls back
+to polling with [collate-fs-changes](#file::collate-fs-changes) (in which case the
+milliseconds are the polling rate, 250ms by default, and only :created, :deleted
+and :modified are reported) and says so with a warning on stderr.  Errors such as
+a path that does not exist are raised in either case.
 
 The following invocation of fs-notify prints out every change event for the provided
-directory, polling every 250ms by default.
+directory.
 
 (fs-notify
 	(fn (f e) (println \"file: \" f \", event: \" e))
 	\"/dir/to/watch\")
 
-This incovation provides an optional arg to specify the polling rate in ms.
+This incovation provides an optional arg to specify the debounce time in ms.
 
 (fs-notify
 	(fn (f e) (println \"file: \" f \", event: \" e))
@@ -578,12 +583,23 @@ Example:
 	(callback to-watch &rest args)
 	(if (> (length args) 1)
 	  (err (str "fs-notify: Too many arguments, see (doc 'fs-notify) for usage."))
-	(let ((to-sleep (if (= 0 (length args)) 250 (vec-nth args 0)))
-		  (collator (collate-fs-changes to-watch)))
-		(loop () ()
-			(let ((slept (sleep to-sleep))
-			(changes (collator)))
-				(iterator::for event in (hash-keys changes)
-					(iterator::for file in (hash-get changes event)
-						(callback file event)))
-			(recur))))))
+	(let ((wait-ms (if (= 0 (length args)) 50 (vec-nth args 0))))
+		(if (fs-watch-available?)
+			(let ((watcher (fs-watch to-watch :debounce wait-ms)))
+				(loop () ()
+					(let ((event (fs-next-event watcher)))
+						(when event (callback (vec-nth event 0) (vec-nth event 1))))
+				(recur)))
+			(let ((to-sleep (if (= 0 (length args)) 250 wait-ms)))
+				(when (not (fs-exists? to-watch))
+					(err (str "fs-notify: " to-watch " does not exist")))
+				(var collator (collate-fs-changes to-watch))
+				(eprintln "WARNING: fs-notify: fs-watch unavailable, polling every "
+						  to-sleep "ms instead.")
+				(loop () ()
+					(let ((slept (sleep to-sleep))
+					(changes (collator)))
+						(iterator::for event in (hash-keys changes)
+							(iterator::for file in (hash-get changes event)
+								(callback file event)))
+					(recur))))))))
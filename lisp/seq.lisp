@@ -290,7 +290,8 @@ Example:
 (defn qsort
   "Usage: (qsort sequence comp-lambda?) -> [sorted vector]
 
-Sort a sequence using the quick sort algorithm.  Returns a vector of the sorted sequence.
+Sort a sequence with the native stable sort.  Returns a vector of the sorted
+sequence, see sort for more options.
 
 The comp-lambda argument is optional, if provided it should be a lambda or
 builtin that takes two arguments and return t or nil (it is the compare
@@ -319,43 +320,7 @@ Example:
 (test::assert-equal '#() (qsort '#()))
 "
   (lst &rest comp)
-  (let ((sorted)
-        (to-sort)
-        (comp-fn)
-        (quick-inner
-          (fn (comp-fn sorted to-sort)
-              (if (> (length to-sort) 0)
-                  (let ((lst (vec-pop! to-sort)))
-                    (if (not (seq? lst))
-                        (do
-                         (vec-push! sorted lst)
-                         (recur comp-fn sorted to-sort))
-                        (if (<= (length lst) 1)
-                            (do
-                             (if (= (length lst) 1)
-                                 (vec-push! sorted (vec-pop! lst)))
-                             (recur comp-fn sorted to-sort))
-                            (let ((pivot (first lst))
-                                  (less (vec))
-                                  (greater (vec)))
-                              ; Don't have for yet so do it the "hard" way.
-                              ((fn (lst)
-                                   (if (non-empty-seq? lst)
-                                       (let ((i (first lst)))
-                                         (if (comp-fn i pivot) (vec-push! less i) (vec-push! greater i))
-                                         (recur (rest lst)))))(rest lst))
-                              (vec-push! to-sort greater)
-                              (vec-push! to-sort pivot)
-                              (vec-push! to-sort less)
-                              (recur comp-fn sorted to-sort)))))
-
-                  sorted))))
-
+  (do
     (if (> (length comp) 1) (err "qsort takes one option compare lambda"))
-    (set! comp-fn (if (= (length comp) 1) (first comp) <))
-    (if (not (or (lambda? comp-fn)(builtin? comp-fn))) (err "compare must be a callable"))
-    (set! sorted (vec))
-    (set! to-sort (vec))
-    (vec-push! to-sort lst)
-    (quick-inner comp-fn sorted to-sort)
-    sorted))
+    (if (and comp (not (or (lambda? (first comp))(builtin? (first comp))))) (err "compare must be a callable"))
+    (apply sort (if (vec? lst) lst (apply vec lst)) comp)))
@@ -519,6 +519,8 @@
          (set! ch (str-iter-next! stream))
          (if (not (= #\) ch))
              (err (str "Unbalanced ) in '\$' shell read macro, got " ch)))
+         ; A lisp expression is not command output so do not trim it in a string.
+         (meta-add-tags result :no-trim)
          result)
         ((= ch #\()  ; $(...) read a shell dsl list and make a call.
          (set! paren-level (+ paren-level 1))
@@ -537,7 +539,12 @@
       (do
        (str-iter-next! stream)
        (shell-read::shell-read-int stream nil))
-      (list 'str (shell-read::shell-read-int stream nil))))
+      (let ((result (shell-read::shell-read-int stream nil)))
+        (if (meta-tag? result :no-trim)
+            (let ((wrapped (list 'str result)))
+              (meta-add-tags wrapped :no-trim)
+              wrapped)
+            (list 'str result)))))
 
 (def *ns-exports* nil)
 
@@ -838,16 +838,18 @@ Section: shell
               (let ((save-last-status *last-status*)
                     (line-len)
                     (line))
-                (let ((prompt-str (get-error (get-prompt))))
-                  (if (= :error (car prompt-str))
-                      (do
-                       (println "ERROR getting prompt:")
-                       (print-error prompt-str)
-                        (set! prompt-str "ERROR> "))
-                      (set! prompt-str (cdr prompt-str)))
+                ; A function so prompt can redraw it when timers fire while waiting for input.
+                (let ((prompt-fn (fn ()
+                                     (let ((prompt-str (get-error (get-prompt))))
+                                       (if (= :error (car prompt-str))
+                                           (do
+                                            (println "ERROR getting prompt:")
+                                            (print-error prompt-str)
+                                             "ERROR> ")
+                                           (cdr prompt-str))))))
                   (if (def? *repl-std-only*)
-                      (do (print prompt-str)(set! line (read-line *stdin*)))
-                      (set! line (prompt :repl prompt-str "~/.local/share/sl-sh/history"))))
+                      (do (print (prompt-fn))(set! line (read-line *stdin*)))
+                      (set! line (prompt :repl prompt-fn "~/.local/share/sl-sh/history"))))
                 (export 'LAST_STATUS save-last-status)
                 (set! *last-status* save-last-status)
                 (set! line-len (length (str-trim line)))
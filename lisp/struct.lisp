@@ -1,8 +1,7 @@
 (ns-push 'struct)
 
-(defn method (field prefix methods dispatcher tags doc doc-exp)
+(defn method (field methods tags doc doc-exp)
   (let ((tsym (sym ":" (car field)))
-        (msym (sym prefix (car field)))
         (second (cadr field))
         (m-params nil)
         (m-body nil))
@@ -17,11 +16,26 @@
          (str-push! doc "method: " tsym "\n")
          (set! m-params (cadr field))
           (set! m-body (caddr field))))
-    (vec-push! methods `(,msym (fn ,m-params ,m-body)))
-    (vec-push! dispatcher `(= msg ,tsym))
-    (vec-push! dispatcher `(apply ,msym this-fn args))
+    (vec-push! methods (list tsym m-params m-body))
     (vec-push! tags (sym ":method" tsym))))
 
+; Build the (:name . lambda) form for a method.  The lambda takes the fields
+; then gensyms for the method parameters, a let binds the real parameter names
+; around the body so parameters and vars shadow fields like any outer binding.
+(defn method-form (field-names m)
+  (let ((params (vec))
+        (bindings (vec)))
+    ((fn (p)
+         (if (not (null p))
+             (do
+              (if (= (car p) '&rest)
+                  (vec-push! params '&rest)
+                  (let ((g (gensym)))
+                    (vec-push! params g)
+                    (vec-push! bindings (list (car p) g))))
+              (recur (cdr p)))))(cadr m))
+    `(join ,(car m) (fn (,@field-names ,@params) (let (,@bindings) ,(caddr m))))))
+
 ; macro to build a "trait" that can be implemented by a struct
 (defmacro deftrait
   "Define a trait.  Traits define methods that are added to structures (and usually require one or
@@ -57,14 +71,13 @@ Example:
 (test::assert-equal \"see\" (ts :c))
 (ts :set-b \"queen\")
 (test::assert-equal \"queen\" (ts :b))
+(test::assert-true (meta-tag? ts :trait-test-trait))
 (ns-pop)
 "
   (name &rest fields)
   ((fn ()
        (let ((tags (vec))
-             (dispatch-map (make-hash))
              (methods (vec))
-             (dispatcher (vec))
              (fields-len (length fields))
              (doc (str))
              (doc-exp (str))
@@ -81,48 +94,42 @@ Example:
               (if (< idx fields-len)
                   (let ((field (vec-nth fields idx)))
                     (if (= (car field) :fn)
-                        (method (cdr field) (str name "^") methods dispatcher tags doc doc-exp)
+                        (method (cdr field) methods tags doc doc-exp)
                         (err "Traits only have :fn fields!"))
                     (recur (+ idx 1))
                     )))idx-start)
-         (let ((keys (hash-keys dispatch-map)))
-           (let ((keys-len (length keys))
-                 (tags-len (length tags))
-                 (methods-len (length methods))
-                 (dispatcher-len (length dispatcher))
-                 (doc-final (str)))
-             (if (not (str-contains "Usage:" doc)) (str-push! doc-final "Usage: (deftrait ... (:impl " name "))\n\n"))
-             (str-push! doc-final doc (if (> (length doc-exp) 0) (str "\nSection:" doc-exp)""))
-             `(def ,name ,doc-final (fn (target-dispatch-map target-methods target-dispatcher target-tags)
-                                        ((fn (idx)
-                                             (if (< idx ,tags-len)
-                                                 (do
-                                                  (vec-push! target-tags (vec-nth ',tags idx))
-                                                  (recur (+ idx 1)))))0)
-                                        ((fn (idx)
-                                             (if (< idx ,methods-len)
-                                                 (do
-                                                  (vec-push! target-methods (vec-nth ',methods idx))
-                                                  (recur (+ idx 1)))))0)
-                                        ((fn (idx)
-                                             (if (< idx ,dispatcher-len)
-                                                 (do
-                                                  (vec-push! target-dispatcher (vec-nth ',dispatcher idx))
-                                                  (recur (+ idx 1)))))0)
-                                        ((fn (idx)
-                                             (if (< idx ,keys-len)
-                                                 (let ((key (vec-nth ',keys idx)))
-                                                   (if (not (hash-haskey target-dispatch-map key)) (hash-set! target-dispatch-map key (hash-get ,dispatch-map key)))
-                                                   (recur (+ idx 1)) )))0) )) ))))))
+         (let ((tags-len (length tags))
+               (methods-len (length methods))
+               (doc-final (str)))
+           (if (not (str-contains "Usage:" doc)) (str-push! doc-final "Usage: (deftrait ... (:impl " name "))\n\n"))
+           (str-push! doc-final doc (if (> (length doc-exp) 0) (str "\nSection:" doc-exp)""))
+           `(def ,name ,doc-final (fn (target-methods target-tags)
+                                      ((fn (idx)
+                                           (if (< idx ,tags-len)
+                                               (do
+                                                (vec-push! target-tags (vec-nth ',tags idx))
+                                                (recur (+ idx 1)))))0)
+                                      ((fn (idx)
+                                           (if (< idx ,methods-len)
+                                               (do
+                                                (vec-push! target-methods (vec-nth ',methods idx))
+                                                (recur (+ idx 1)))))0) )) )))))
 
 ; macro to build a "struct"
 (defmacro defstruct
-  "Define a structure.  This produces a lambda with name that will create an instance.
+  "Define a structure.  This produces a native record type with name that will create an instance
+when called, use (record-of? val name) to test if val is an instance.
 Each 'field' will add an attribute, method or trait.
 Use (attr-name doc-str? default? [:rw | :ro | :wo]?) if a final access modifier is not provided then it is private.
   NOTE: for attributes, if the default value is a string then doc-str is not optional (but can be empty).
 Use (:fn name doc-str? body) to add a method.
 Use (:impl trait) to add a trait.
+Defaults are evaluated for each new instance, call the struct with :attr-name value pairs to override
+them.  Call an instance with :attr-name to read an attribute (:rw or :ro), :set-attr-name to set one
+(:rw or :wo) or a method name.  Methods can read and set! any attribute by name (a parameter or
+var with the same name shadows it).  Instances print their readable attributes, str-readable
+writes every attribute as #S(name :attr-name value ...) which read turns back into an instance.
+Instances are equal? when they have the same struct type and equal? attributes.
 
 Section: struct
 
@@ -135,7 +142,9 @@ Example:
   (d \"a write only attribute\" \"dee\" :wo)
   ; methods
   (:fn what-d (self) d)
-  (:fn what-a (self) a))
+  (:fn what-a (self) a)
+  (:fn set-a (self x) (set! a x))
+  (:fn shadow-b (self) (do (var b \"local\") b)))
 
 (def ts (test-struct))
 (assert-equal nil (ts :what-a))
@@ -146,13 +155,28 @@ Example:
 (assert-equal \"see\" (ts :c))
 (ts :set-b \"queen\")
 (assert-equal \"queen\" (ts :b))
+(assert-error (ts :a))
+(assert-error (ts :d))
+(assert-error (ts :set-c \"sea\"))
+(ts :set-a 1)
+(assert-equal 1 (ts :what-a))
+(assert-equal \"test-struct\" (ts :type))
+(assert-true (record-of? ts test-struct))
+(assert-false (record-of? (make-hash) test-struct))
+(assert-equal \"local\" (ts :shadow-b))
+(assert-equal \"queen\" (ts :b))
+(assert-equal \"#<test-struct :b \\\"bee\\\" :c \\\"see\\\">\" (str (test-struct)))
+(assert-true (str-starts-with \"#S(\" (str-readable ts)))
+(assert-true (equal? ts (read (str-readable ts))))
+(assert-equal 1 ((read (str-readable ts)) :what-a))
+(assert-true (equal? (test-struct :b \"x\") (test-struct :b \"x\")))
+(assert-false (equal? (test-struct :b \"x\") (test-struct)))
+(assert-equal \"x\" ((test-struct :b \"x\") :b))
 "
   (name &rest fields)
-  ((fn (params bindings)
+  ((fn (field-names access defaults)
        (let ((tags (vec))
-             (dispatch-map (make-hash))
              (methods (vec))
-             (dispatcher (vec))
              (fields-len (length fields))
              (doc (str))
              (doc-exp (str))
@@ -177,61 +201,33 @@ Example:
                            (set! fdoc (str "\n\t" (vec-nth doc-split 0)))
                            (if (= 2 (length doc-split)) (str-push! doc-exp (vec-nth doc-split 1)))
                            (xdr! field (cddr field))))
+                     (vec-push! field-names (car field))
+                     (vec-push! defaults (if (> (length field) 1) (cadr field) nil))
                      (if (= 1 (length field))
                          (do
                           (str-push! doc "attribute: " (car field) " private" fdoc "\n")
-                          (vec-push! params (car field))
-                           (vec-push! bindings nil))
+                          (vec-push! access nil))
                          (= 2 (length field))
                          (do
                           (str-push! doc "attribute: " (car field) " private" fdoc "\n")
-                          (vec-push! params (car field))
-                           (vec-push! bindings (cadr field)))
+                          (vec-push! access nil))
                          (= 3 (length field))
                          (let ((param (car field))
-                               (binding (cadr field))
-                               (perm (caddr field))
-                               (tsym nil)
-                               (msym nil))
-                           (vec-push! params param)
-                           (vec-push! bindings binding)
+                               (perm (caddr field)))
+                           (vec-push! access perm)
                            (if (= perm :rw)
                                (do
-                                (str-push! doc "attribute: " (car field) " read/write" fdoc "\n")
-                                (set! tsym (sym ":" param))
-                                 (set! msym (sym name "^acc^" param))
-                                 (hash-set! dispatch-map tsym `(fn (_) ,param))
-                                 (vec-push! methods `(,msym (fn (_) ,param)))
-                                 (vec-push! tags (sym ":accessor:" param))
-                                 (vec-push! dispatcher `(= msg ,tsym))
-                                 (vec-push! dispatcher `(apply ,msym this-fn args))
-                                 (set! tsym (sym ":set-" param))
-                                 (set! msym (sym name "^set^" param))
-                                 (hash-set! dispatch-map tsym `(fn (_ arg) (set! ,param arg)))
-                                 (vec-push! methods `(,msym (fn (_ arg) (set! ,param arg))))
-                                 (vec-push! dispatcher `(= msg ,tsym))
-                                 (vec-push! dispatcher `(apply ,msym this-fn args))
+                                (str-push! doc "attribute: " param " read/write" fdoc "\n")
+                                (vec-push! tags (sym ":accessor:" param))
                                  (vec-push! tags (sym ":setter:" param)))
                                (= perm :ro)
                                (do
-                                (str-push! doc "attribute: " (car field) " read" fdoc "\n")
-                                (set! tsym (sym ":" param))
-                                 (set! msym (sym name "^acc^" param))
-                                 (hash-set! dispatch-map tsym `(fn (_) ,param))
-                                 (vec-push! methods `(,msym (fn (_) ,param)))
-                                 (vec-push! dispatcher `(= msg ,tsym))
-                                 (vec-push! dispatcher `(apply ,msym this-fn args))
-                                 (vec-push! tags (sym ":accessor:" param)))
+                                (str-push! doc "attribute: " param " read" fdoc "\n")
+                                (vec-push! tags (sym ":accessor:" param)))
                                (= perm :wo)
                                (do
-                                (str-push! doc "attribute: " (car field) " write" fdoc "\n")
-                                (set! tsym (sym ":set-" param))
-                                 (set! msym (sym name "^set^" param))
-                                 (hash-set! dispatch-map tsym `(fn (_ arg) (set! ,param arg)))
-                                 (vec-push! methods `(,msym (fn (_ arg) (set! ,param arg))))
-                                 (vec-push! dispatcher `(= msg ,tsym))
-                                 (vec-push! dispatcher `(apply ,msym this-fn args))
-                                 (vec-push! tags (sym ":setter:" param)))
+                                (str-push! doc "attribute: " param " write" fdoc "\n")
+                                (vec-push! tags (sym ":setter:" param)))
                                (err "defstruct: invalid field access key (valid are :rw, :ro and :wo)")))
                          (err "ERROR: invalid attribute bindings on defstruct")))))
 
@@ -240,13 +236,13 @@ Example:
                    (if (not (not field))
                        (do
                         (str-push! doc "impl " (car field) "\n")
-                        (apply (car field) dispatch-map methods dispatcher tags nil)
+                        (apply (car field) methods tags nil)
                          (recur (cdr field) doc)))))
 
          ((fn (idx)
               (if (< idx fields-len)
                   (let ((field (vec-nth fields idx)))
-                    (if (= (car field) :fn) (method (cdr field) (str name "^") methods dispatcher tags doc doc-exp)
+                    (if (= (car field) :fn) (method (cdr field) methods tags doc doc-exp)
                         (= (car field) :impl) nil ; do impls last (struct methods take precident).
                         (attrib field doc doc-exp))
                     (recur (+ idx 1)))))idx-start)
@@ -257,22 +253,20 @@ Example:
                     (if (= (car field) :impl) (impl (cdr field) doc))
                     (recur (+ idx 1)))))idx-start)
 
-         (hash-set! dispatch-map :type `(fn (_) (sym->str ',name)))
-         (let ((msym (sym name "^type"))
+         (let ((method-forms (vec))
                (doc-final (str)))
-           (vec-push! methods `(,msym (fn (_) (sym->str ',name))))
-           (vec-push! dispatcher `(= msg :type))
-           (vec-push! dispatcher `(apply ,msym this-fn args))
+           ((fn (idx)
+                (if (< idx (length methods))
+                    (do
+                     (vec-push! method-forms (method-form field-names (vec-nth methods idx)))
+                     (recur (+ idx 1)))))0)
            (str-push! doc-final doc (if (> (length doc-exp) 0) (str "\nSection:" doc-exp)""))
-           `(def ,name ,doc-final (fn () ((fn (,@params)
-                                              (let (,@methods)
-                                                (let ((self
-                                                        (fn (msg &rest args)
-                                                            (if ,@dispatcher
-                                                                (err (str "Invalid message (" msg ") to struct: " ',name))))))
-                                                  (meta-add-tags self ',tags) self))) ,@bindings))))))
-   (make-vec (length fields)) ; params
-   (make-vec (length fields)))) ; bindings
+           `(def ,name ,doc-final (make-record-type ',name ',field-names ',access ',tags
+                                                    (fn () (list ,@defaults))
+                                                    (list ,@method-forms))))))
+   (vec) ; field-names
+   (vec) ; access
+   (vec))) ; defaults
 
 ; Due to the bootstrap order of std lib files can not use the ns-export macro at this point.
 (def *ns-exports* (vec 'deftrait 'defstruct))
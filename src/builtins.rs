@@ -5,6 +5,7 @@ use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::hash::BuildHasher;
 use std::io::{self, Write};
+use std::iter;
 use std::path::Path;
 use std::rc::Rc;
 use std::str::from_utf8;
@@ -12,11 +13,15 @@ use std::str::from_utf8;
 use unicode_segmentation::UnicodeSegmentation;
 
 use crate::backquote::*;
+use crate::builtins_iterator::call_iterator;
+use crate::builtins_record::{call_record, new_record};
 use crate::builtins_util::*;
 use crate::config::VERSION_STRING;
 use crate::environment::*;
 use crate::eval::*;
+use crate::format_str::format_string;
 use crate::interner::*;
+use crate::numeric::ExactNum;
 use crate::pretty_print::*;
 use crate::reader::*;
 use crate::symbols::*;
@@ -50,6 +55,28 @@ fn builtin_eval(
     Err(LispError::new("eval can only have one form"))
 }
 
+// Construct or send a message to a record (or iterator) with already evaluated args.
+fn apply_record(
+    environment: &mut Environment,
+    command: &Expression,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let old_sup = environment.supress_eval;
+    environment.supress_eval = true;
+    let rtype = match &command.get().data {
+        ExpEnum::RecordType(t) => Some(t.clone()),
+        _ => None,
+    };
+    let is_iter = matches!(&command.get().data, ExpEnum::Iterator(_));
+    let ret = match rtype {
+        Some(rtype) => new_record(environment, &rtype, args),
+        None if is_iter => call_iterator(environment, command, args),
+        None => call_record(environment, command, args),
+    };
+    environment.supress_eval = old_sup;
+    ret
+}
+
 fn apply_fn_call(
     environment: &mut Environment,
     command: Expression,
@@ -66,6 +93,9 @@ fn apply_fn_call(
                         environment.supress_eval = old_sup;
                         ret
                     }
+                    ExpEnum::RecordType(_) | ExpEnum::Record(_) | ExpEnum::Iterator(_) => {
+                        apply_record(environment, &exp, args)
+                    }
                     ExpEnum::Lambda(_) => {
                         if environment.allow_lazy_fn {
                             //                        make_lazy(environment, exp.clone(), args)
@@ -112,6 +142,9 @@ fn apply_fn_call(
             environment.supress_eval = old_sup;
             ret
         }
+        ExpEnum::RecordType(_) | ExpEnum::Record(_) | ExpEnum::Iterator(_) => {
+            apply_record(environment, &command, args)
+        }
         _ => {
             let msg = format!(
                 "Called an invalid command {}, type {}.",
@@ -343,6 +376,7 @@ fn builtin_load(
 /// (test::assert-equal 3 (length '#(1 2 3)))
 /// (test::assert-equal 3 (length (list 1 2 3)))
 /// (test::assert-equal 3 (length (vec 1 2 3)))
+/// (test::assert-equal 3 (length #u8(1 2 3)))
 /// (test::assert-error (length 100))
 /// (test::assert-error (length 100.0))
 /// (test::assert-error (length #\\x))
@@ -358,6 +392,7 @@ fn length(exp: Expression) -> LispResult<Expression> {
             Ok(Expression::alloc_data(ExpEnum::Int(i64::from(i))))
         }
         ExpEnum::Vector(list) => Ok(Expression::alloc_data(ExpEnum::Int(list.len() as i64))),
+        ExpEnum::Bytes(b) => Ok(Expression::alloc_data(ExpEnum::Int(b.len() as i64))),
         ExpEnum::Pair(_, e2) => {
             let mut len = 0;
             let mut e_next = e2.clone();
@@ -382,6 +417,9 @@ fn length(exp: Expression) -> LispResult<Expression> {
         }
         ExpEnum::Nil => Ok(Expression::alloc_data(ExpEnum::Int(0))),
         ExpEnum::HashMap(map) => Ok(Expression::alloc_data(ExpEnum::Int(map.len() as i64))),
+        ExpEnum::Set(set) => Ok(Expression::alloc_data(ExpEnum::Int(set.len() as i64))),
+        ExpEnum::PVec(v) => Ok(Expression::alloc_data(ExpEnum::Int(v.len() as i64))),
+        ExpEnum::PMap(map) => Ok(Expression::alloc_data(ExpEnum::Int(map.len() as i64))),
         _ => Err(LispError::new(format!(
             "length: expression of type {} can not be used with this function.",
             exp.display_type()
@@ -567,6 +605,32 @@ fn builtin_eprintln(
     eprint(environment, args, true)
 }
 
+// The first arg is the format string, the rest are the values for it.
+fn format_args(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<String, LispError> {
+    let mut args = make_args(environment, args)?;
+    if args.is_empty() {
+        return Err(LispError::new(format!(
+            "{}: requires a format string",
+            fn_name
+        )));
+    }
+    let fmt = args.remove(0);
+    let fmt_d = fmt.get();
+    if let ExpEnum::String(fmt, _) = &fmt_d.data {
+        format_string(environment, fmt, args, fn_name)
+    } else {
+        Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "String",
+            &fmt.display_type(),
+        )))
+    }
+}
+
 fn builtin_format(
     environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
@@ -578,6 +642,32 @@ fn builtin_format(
     Ok(Expression::alloc_data(ExpEnum::String(res.into(), None)))
 }
 
+fn builtin_fmt(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let res = format_args(environment, args, "fmt")?;
+    Ok(Expression::alloc_data(ExpEnum::String(res.into(), None)))
+}
+
+fn builtin_printf(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let res = format_args(environment, args, "printf")?;
+    let res = Expression::alloc_data(ExpEnum::String(res.into(), None));
+    print(environment, &mut iter::once(res), false)
+}
+
+fn builtin_eprintf(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let res = format_args(environment, args, "eprintf")?;
+    let res = Expression::alloc_data(ExpEnum::String(res.into(), None));
+    eprint(environment, &mut iter::once(res), false)
+}
+
 pub fn builtin_do(
     environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
@@ -1357,6 +1447,24 @@ pub fn builtin_meta_tag_set(
                             return Ok(Expression::make_true());
                         }
                     }
+                    // Records carry the tags of their type.
+                    if let ExpEnum::Record(r) = &exp_d.data {
+                        if r.record_type().has_tag(s) {
+                            return Ok(Expression::make_true());
+                        }
+                    }
+                    // Native iterators implement the iterator traits.
+                    if let ExpEnum::Iterator(state) = &exp_d.data {
+                        let double_ended = state
+                            .try_borrow()
+                            .map(|s| s.double_ended())
+                            .unwrap_or(false);
+                        if *s == ":trait-iterator"
+                            || (*s == ":trait-double-ended-iterator" && double_ended)
+                        {
+                            return Ok(Expression::make_true());
+                        }
+                    }
                 } else {
                     return Err(LispError::new(
                         "meta-tag?: Takes an expression and a tag (symbol)",
@@ -1400,6 +1508,8 @@ macro_rules! ensure_tonicity_all {
         }
         if let Ok(ints) = parse_list_of_ints($environment, &mut list) {
             ensure_tonicity!($check_fn, ints, &i64, i64)
+        } else if let Some(exacts) = parse_list_of_exact(&list) {
+            ensure_tonicity!($check_fn, exacts, &ExactNum, ExactNum)
         } else if let Ok(floats) = parse_list_of_floats($environment, &mut list) {
             ensure_tonicity!($check_fn, floats, &f64, f64)
         } else {
@@ -1419,6 +1529,8 @@ pub fn builtin_equal(
     }
     if let Ok(ints) = parse_list_of_ints(environment, &mut args) {
         ensure_tonicity!(|a, b| a == b, ints, &i64, i64)
+    } else if let Some(exacts) = parse_list_of_exact(&args) {
+        ensure_tonicity!(|a, b| a == b, exacts, &ExactNum, ExactNum)
     } else if let Ok(floats) = parse_list_of_floats(environment, &mut args) {
         ensure_tonicity!(
             |a: &f64, b: &f64| (a - b).abs() < 0.000_001,
@@ -1685,6 +1797,43 @@ Example:
 "
         ),
     );
+    data.insert(
+        interner.intern("printf"),
+        Expression::make_function(
+            builtin_printf,
+            r#"Usage: (printf format arg0 ... argN) -> nil
+
+Print a format string (see fmt) to *stdout*, no newline is added.
+
+Section: core
+
+Example:
+; Use a file for stdout for test.
+(with-temp-file (fn (tmp)
+    (dyn *stdout* (open tmp :create :truncate) (do (printf "{:>5}|{:.2}\n" "ab" 1.005) (close *stdout*)))
+    (test::assert-equal "   ab|1.00\n" (read-line (open tmp :read)))))
+(test::assert-error (printf 1))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("eprintf"),
+        Expression::make_function(
+            builtin_eprintf,
+            r#"Usage: (eprintf format arg0 ... argN) -> nil
+
+Print a format string (see fmt) to *stderr*, no newline is added.
+
+Section: core
+
+Example:
+; Use a file for stderr for test.
+(with-temp-file (fn (tmp)
+    (dyn *stderr* (open tmp :create :truncate) (do (eprintf "{:03}|{}\n" 7 'x) (close *stderr*)))
+    (test::assert-equal "007|x\n" (read-line (open tmp :read)))))
+"#,
+        ),
+    );
     data.insert(
         interner.intern("format"),
         Expression::make_function(
@@ -1693,7 +1842,7 @@ Example:
 
 Build a formatted string from arguments.
 
-Arguments will be turned into strings.
+Arguments will be turned into strings.  See fmt for format strings.
 
 Section: core
 
@@ -1702,9 +1851,59 @@ Example:
 (test::assert-equal \"string\" (format \"string\" \"\"))
 (test::assert-equal \"string 50\" (format \"string\" \" \" 50))
 (test::assert-equal \"string 50 100.5\" (format \"string\" \" \" 50 \" \" 100.5))
+(test::assert-equal \"{} 1\" (format \"{} \" 1))
 ",
         ),
     );
+    data.insert(
+        interner.intern("fmt"),
+        Expression::make_function(
+            builtin_fmt,
+            r#"Usage: (fmt format arg0 ... argN) -> string
+
+Build a string from the format string format and arguments.
+
+Fields are {arg:spec}, both parts optional.  arg is empty (the next argument),
+an index (0 is the first argument after the format string) or a name.  If the
+format string has named fields the last argument must be a hash map with a
+:name key for each name, it is not used for positional fields.  Use {{ and }}
+for literal braces.
+
+spec is [[fill]align][+][#][0][width][.precision][type]:
+    align       < left (default for strings), > right (default for numbers), ^ center
+    +           always show the sign of numbers
+    #           prefix 0x, 0o or 0b with x, X, o and b
+    0           pad numbers with zeros after the sign
+    width       minimum width in columns (wide unicode characters count as two)
+    precision   digits after the point for numbers, max characters for strings
+    type        x X o b (Int in hex, octal, binary), e E (exponent), ? (readable)
+
+Use printf or eprintf to print a format string.
+
+Section: core
+
+Example:
+(test::assert-equal "[  abc] [abc  ] [ abc ] [**abc]" (fmt "[{:>5}] [{:<5}] [{:^5}] [{:*>5}]" "abc" "abc" "abc" "abc"))
+(test::assert-equal "3.142 -0001.50 +2" (fmt "{:.3} {:08.2} {:+}" 3.14159 -1.5 2))
+(test::assert-equal "ff 0xFF 00001010 0o17 1.5e3" (fmt "{:x} {:#X} {:08b} {:#o} {:e}" 255 255 10 15 1500.0))
+(test::assert-equal "b a b" (fmt "{1} {0} {1}" "a" "b"))
+(test::assert-equal "x=42 :y" (fmt "x={x} {}" :y (make-hash '((:x . 42)))))
+(test::assert-equal "|日本  |" (fmt "|{:<6}|" "日本"))
+(test::assert-equal "\"a\" {}" (fmt "{:?} {{}}" "a"))
+(test::assert-equal "abc" (fmt "{:.3}" "abcdef"))
+(test::assert-equal "20.00 0.333 -0.50 1/3 +02.5" (fmt "{:.2} {:.3} {:.2} {} {:+05}" #d19.999 1/3 -1/2 1/3 #d2.5))
+(test::assert-equal "2.5e0" (fmt "{:e}" 5/2))
+(test::assert-equal "no fields" (fmt "no fields"))
+(test::assert-error-msg (fmt "{} {}" 1) "fmt: format string needs argument 1 but only 1 given")
+(test::assert-error-msg (fmt "{}" 1 2) "fmt: 1 argument(s) not used by the format string")
+(test::assert-error-msg (fmt "{x}" :x 1) "fmt: named fields need a hash map of values as the last argument")
+(test::assert-error-msg (fmt "{y}" (make-hash '((:x . 1)))) "fmt: {y} is not in the named arguments")
+(test::assert-error (fmt "{:x}" "a"))
+(test::assert-error (fmt "{" 1))
+(test::assert-error (fmt 1))
+"#,
+        ),
+    );
     data.insert(
         interner.intern("do"),
         Expression::make_special(
@@ -2336,7 +2535,8 @@ Example:
             builtin_equal,
             "Usage: (= val0 ... valN)
 
-Equals.  Works for int, float or string.
+Equals.  Works for int, float, rational, decimal or string (ints, rationals and
+decimals compare exactly).
 
 Section: conditional
 
@@ -2361,6 +2561,10 @@ Example:
 (test::assert-false (= \"aaa\" \"aaaa\" \"aaa\"))
 (test::assert-false (= \"ccc\" \"aab\" \"aaa\"))
 (test::assert-false (= \"aaa\" \"aab\"))
+(test::assert-true (= 1/2 #d0.5 #r2/4))
+(test::assert-true (= #d0.3 (+ #d0.1 #d0.2)))
+(test::assert-false (= 1/3 #d0.333333333333))
+(test::assert-true (= 1/2 0.5))
 ",
         ),
     );
@@ -2370,7 +2574,7 @@ Example:
             builtin_greater_than,
             "Usage: (> val0 ... valN)
 
-Greater than.  Works for int, float or string.
+Greater than.  Works for int, float, rational, decimal or string.
 
 Section: conditional
 
@@ -2401,7 +2605,7 @@ Example:
             builtin_greater_than_equal,
             "Usage: (>= val0 ... valN)
 
-Greater than or equal.  Works for int, float or string.
+Greater than or equal.  Works for int, float, rational, decimal or string.
 
 Section: conditional
 
@@ -2431,7 +2635,7 @@ Example:
             builtin_less_than,
             "Usage: (< val0 ... valN)
 
-Less than.  Works for int, float or string.
+Less than.  Works for int, float, rational, decimal or string.
 
 Section: conditional
 
@@ -2452,6 +2656,8 @@ Example:
 (test::assert-false (< \"aaa\" \"aaa\"))
 (test::assert-true (< \"aaa\" \"aab\" \"ccc\"))
 (test::assert-false (< \"baa\" \"aab\"))
+(test::assert-true (< 1/3 #d0.34 1/2 1))
+(test::assert-false (< #d0.5 1/2))
 ",
         ),
     );
@@ -2461,7 +2667,7 @@ Example:
             builtin_less_than_equal,
             "Usage: (<= val0 ... valN)
 
-Less than or equal.  Works for int, float or string.
+Less than or equal.  Works for int, float, rational, decimal or string.
 
 Section: conditional
 
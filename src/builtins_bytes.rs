@@ -0,0 +1,710 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io::{self, Read, Write};
+
+use nix::unistd;
+
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::types::*;
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn make_bytes(bytes: Vec<u8>) -> Expression {
+    Expression::alloc_data(ExpEnum::Bytes(bytes))
+}
+
+fn make_str(s: String) -> Expression {
+    Expression::alloc_data(ExpEnum::String(s.into(), None))
+}
+
+fn param_bytes(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Vec<u8>, LispError> {
+    let exp = param_eval(environment, args, fn_name)?;
+    let exp_d = exp.get();
+    if let ExpEnum::Bytes(b) = &exp_d.data {
+        Ok(b.clone())
+    } else {
+        Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Bytes",
+            &exp.display_type(),
+        )))
+    }
+}
+
+fn param_index(
+    environment: &mut Environment,
+    exp: Expression,
+    fn_name: &str,
+) -> Result<usize, LispError> {
+    let exp = crate::eval::eval(environment, exp)?;
+    let exp_d = exp.get();
+    match &exp_d.data {
+        ExpEnum::Int(i) if *i >= 0 => Ok(*i as usize),
+        ExpEnum::Int(i) => Err(LispError::new(format!(
+            "{}: index {} can not be negative",
+            fn_name, i
+        ))),
+        _ => Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Int",
+            &exp.display_type(),
+        ))),
+    }
+}
+
+fn byte_value(exp: &Expression, fn_name: &str) -> Result<u8, LispError> {
+    match &exp.get().data {
+        ExpEnum::Int(i) if (0..=255).contains(i) => Ok(*i as u8),
+        ExpEnum::Int(i) => Err(LispError::new(format!(
+            "{}: {} is not a byte (0-255)",
+            fn_name, i
+        ))),
+        _ => Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Int",
+            &exp.display_type(),
+        ))),
+    }
+}
+
+fn builtin_bytes(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let mut bytes = Vec::new();
+    for arg in make_args(environment, args)? {
+        bytes.push(byte_value(&arg, "bytes")?);
+    }
+    Ok(make_bytes(bytes))
+}
+
+fn builtin_make_bytes(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "make-bytes";
+    let len = if let Some(len) = args.next() {
+        param_index(environment, len, fn_name)?
+    } else {
+        return Err(LispError::new("make-bytes: requires a length"));
+    };
+    let fill = if let Some(fill) = param_eval_optional(environment, args)? {
+        byte_value(&fill, fn_name)?
+    } else {
+        0
+    };
+    params_done(args, fn_name)?;
+    Ok(make_bytes(vec![fill; len]))
+}
+
+fn builtin_bytes_nth(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "bytes-nth";
+    let bytes = param_bytes(environment, args, fn_name)?;
+    let idx = if let Some(idx) = args.next() {
+        param_index(environment, idx, fn_name)?
+    } else {
+        return Err(LispError::new("bytes-nth: requires bytes and an index"));
+    };
+    params_done(args, fn_name)?;
+    match bytes.get(idx) {
+        Some(b) => Ok(Expression::alloc_data(ExpEnum::Int(i64::from(*b)))),
+        None => Err(LispError::new(format!(
+            "bytes-nth: index {} out of range (length {})",
+            idx,
+            bytes.len()
+        ))),
+    }
+}
+
+fn builtin_bytes_slice(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "bytes-slice";
+    let bytes = param_bytes(environment, args, fn_name)?;
+    let start = if let Some(start) = args.next() {
+        param_index(environment, start, fn_name)?
+    } else {
+        return Err(LispError::new("bytes-slice: requires bytes and a start"));
+    };
+    let end = if let Some(end) = args.next() {
+        param_index(environment, end, fn_name)?
+    } else {
+        bytes.len()
+    };
+    params_done(args, fn_name)?;
+    if start > end || end > bytes.len() {
+        return Err(LispError::new(format!(
+            "bytes-slice: range {}..{} out of range (length {})",
+            start,
+            end,
+            bytes.len()
+        )));
+    }
+    Ok(make_bytes(bytes[start..end].to_vec()))
+}
+
+fn builtin_bytes_concat(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let mut res = Vec::new();
+    for arg in make_args(environment, args)? {
+        if let ExpEnum::Bytes(b) = &arg.get().data {
+            res.extend_from_slice(b);
+        } else {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                "bytes-concat",
+                "Bytes",
+                &arg.display_type(),
+            )));
+        }
+    }
+    Ok(make_bytes(res))
+}
+
+fn builtin_str_to_bytes(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "str->bytes";
+    let exp = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let exp_d = exp.get();
+    match &exp_d.data {
+        ExpEnum::String(s, _) => Ok(make_bytes(s.as_bytes().to_vec())),
+        ExpEnum::Char(c) => Ok(make_bytes(c.as_bytes().to_vec())),
+        ExpEnum::CodePoint(c) => Ok(make_bytes(c.to_string().into_bytes())),
+        _ => Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "String or Char",
+            &exp.display_type(),
+        ))),
+    }
+}
+
+fn builtin_bytes_to_str(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "bytes->str";
+    let bytes = param_bytes(environment, args, fn_name)?;
+    let lossy = match param_eval_optional(environment, args)? {
+        Some(opt) => match &opt.get().data {
+            ExpEnum::Symbol(":lossy", _) => true,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: invalid option {}, expected :lossy",
+                    fn_name, opt
+                )))
+            }
+        },
+        None => false,
+    };
+    params_done(args, fn_name)?;
+    if lossy {
+        Ok(make_str(String::from_utf8_lossy(&bytes).into_owned()))
+    } else {
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok(make_str(s)),
+            Err(err) => Err(LispError::new(format!(
+                "bytes->str: invalid UTF-8 at byte {}",
+                err.utf8_error().valid_up_to()
+            ))),
+        }
+    }
+}
+
+fn builtin_bytes_to_hex(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "bytes->hex";
+    let bytes = param_bytes(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let mut res = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        res.push_str(&format!("{:02x}", b));
+    }
+    Ok(make_str(res))
+}
+
+fn builtin_hex_to_bytes(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "hex->bytes";
+    let text = param_eval(environment, args, fn_name)?.as_string(environment)?;
+    params_done(args, fn_name)?;
+    let digits: Vec<char> = text.chars().collect();
+    let pairs = digits.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(LispError::new("hex->bytes: odd number of hex digits"));
+    }
+    let mut res = Vec::with_capacity(digits.len() / 2);
+    for (i, pair) in pairs.enumerate() {
+        match (pair[0].to_digit(16), pair[1].to_digit(16)) {
+            (Some(hi), Some(lo)) => res.push((hi * 16 + lo) as u8),
+            (None, _) => {
+                return Err(LispError::new(format!(
+                    "hex->bytes: invalid hex digit '{}' at {}",
+                    pair[0],
+                    i * 2
+                )))
+            }
+            (_, None) => {
+                return Err(LispError::new(format!(
+                    "hex->bytes: invalid hex digit '{}' at {}",
+                    pair[1],
+                    i * 2 + 1
+                )))
+            }
+        }
+    }
+    Ok(make_bytes(res))
+}
+
+fn builtin_bytes_to_base64(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "bytes->base64";
+    let bytes = param_bytes(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let mut res = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(BASE64_CHARS[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    Ok(make_str(res))
+}
+
+fn builtin_base64_to_bytes(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "base64->bytes";
+    let text = param_eval(environment, args, fn_name)?.as_string(environment)?;
+    params_done(args, fn_name)?;
+    let mut res = Vec::with_capacity(text.len() / 4 * 3);
+    let mut n = 0u32;
+    let mut bits = 0;
+    let mut padding = false;
+    for (i, ch) in text.chars().enumerate() {
+        let val = match ch {
+            'A'..='Z' => ch as u32 - 'A' as u32,
+            'a'..='z' => ch as u32 - 'a' as u32 + 26,
+            '0'..='9' => ch as u32 - '0' as u32 + 52,
+            // Also accept the URL safe alphabet.
+            '+' | '-' => 62,
+            '/' | '_' => 63,
+            '=' => {
+                padding = true;
+                continue;
+            }
+            // Encoded data is often wrapped into lines.
+            _ if ch.is_whitespace() => continue,
+            _ => {
+                return Err(LispError::new(format!(
+                    "base64->bytes: invalid character '{}' at {}",
+                    ch, i
+                )))
+            }
+        };
+        if padding {
+            return Err(LispError::new(format!(
+                "base64->bytes: data after padding at {}",
+                i
+            )));
+        }
+        n = (n << 6) | val;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            res.push((n >> bits) as u8);
+            n &= (1 << bits) - 1;
+        }
+    }
+    if bits >= 6 {
+        return Err(LispError::new("base64->bytes: truncated input"));
+    }
+    Ok(make_bytes(res))
+}
+
+// Read up to limit bytes (everything if None), stops early at end of input.
+fn read_limited(reader: &mut dyn Read, limit: Option<usize>) -> io::Result<Vec<u8>> {
+    let mut res = Vec::new();
+    match limit {
+        Some(limit) => {
+            reader.take(limit as u64).read_to_end(&mut res)?;
+        }
+        None => {
+            reader.read_to_end(&mut res)?;
+        }
+    }
+    Ok(res)
+}
+
+// Reads from a raw fd without taking ownership (so it is not closed).
+pub struct FdReader(pub i32);
+
+impl Read for FdReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match unistd::read(self.0, buf) {
+                Ok(n) => return Ok(n),
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(err) => return Err(io::Error::from_raw_os_error(err as i32)),
+            }
+        }
+    }
+}
+
+fn builtin_read_bytes(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "read-bytes";
+    let source = {
+        // Capture process output instead of letting it go to stdout.
+        let gpo = set_grab_proc_output(environment, true);
+        param_eval(gpo.environment, args, fn_name)?
+    };
+    let limit = if let Some(limit) = args.next() {
+        Some(param_index(environment, limit, fn_name)?)
+    } else {
+        None
+    };
+    params_done(args, fn_name)?;
+    let source_d = source.get();
+    let bytes = match &source_d.data {
+        ExpEnum::File(file) => match &mut *file.borrow_mut() {
+            FileState::ReadBinary(f) => read_limited(f, limit)?,
+            FileState::Stdin => read_limited(&mut io::stdin().lock(), limit)?,
+            FileState::Read(_, _) => {
+                return Err(LispError::new(
+                    "read-bytes: file must be opened with :binary",
+                ))
+            }
+            _ => {
+                return Err(LispError::new(
+                    "read-bytes: requires a file opened for reading",
+                ))
+            }
+        },
+        ExpEnum::Process(ProcessState::Running(pid))
+        | ExpEnum::Process(ProcessState::Over(pid, _)) => {
+            let fd = match environment.procs.borrow().get(pid) {
+                Some((_, Some(fd))) => *fd,
+                _ => return Err(LispError::new("read-bytes: process output is not captured")),
+            };
+            read_limited(&mut FdReader(fd), limit)?
+        }
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "File or Process",
+                &source.display_type(),
+            )))
+        }
+    };
+    if bytes.is_empty() && limit != Some(0) {
+        Ok(Expression::make_nil())
+    } else {
+        Ok(make_bytes(bytes))
+    }
+}
+
+fn builtin_write_bytes(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "write-bytes";
+    let file = param_eval(environment, args, fn_name)?;
+    let bytes = param_bytes(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let file_d = file.get();
+    if let ExpEnum::File(file) = &file_d.data {
+        match &mut *file.borrow_mut() {
+            FileState::Write(f) => f.write_all(&bytes)?,
+            FileState::Stdout => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                stdout.write_all(&bytes)?;
+                stdout.flush()?;
+            }
+            FileState::Stderr => io::stderr().write_all(&bytes)?,
+            _ => {
+                return Err(LispError::new(
+                    "write-bytes: requires a file opened for writing",
+                ))
+            }
+        }
+        Ok(Expression::make_nil())
+    } else {
+        Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "File",
+            &file.display_type(),
+        )))
+    }
+}
+
+pub fn add_bytes_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("bytes"),
+        Expression::make_function(
+            builtin_bytes,
+            r#"Usage: (bytes byte*) -> bytes
+
+Make a byte vector from integers (0-255).  Byte vectors can also be written as
+a literal, #u8(1 2 3).
+
+Section: bytes
+
+Example:
+(test::assert-equal #u8(1 2 255) (bytes 1 2 255))
+(test::assert-equal 0 (length (bytes)))
+(test::assert-error (bytes 256))
+(test::assert-error (bytes "a"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("make-bytes"),
+        Expression::make_function(
+            builtin_make_bytes,
+            r#"Usage: (make-bytes length fill?) -> bytes
+
+Make a byte vector of length bytes all set to fill (default 0).
+
+Section: bytes
+
+Example:
+(test::assert-equal #u8(0 0 0) (make-bytes 3))
+(test::assert-equal #u8(7 7) (make-bytes 2 7))
+(test::assert-error (make-bytes -1))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("bytes-nth"),
+        Expression::make_function(
+            builtin_bytes_nth,
+            r#"Usage: (bytes-nth bytes index) -> int
+
+Return the byte at index (zero based) as an integer.
+
+Section: bytes
+
+Example:
+(test::assert-equal 255 (bytes-nth #u8(1 2 255) 2))
+(test::assert-error-msg (bytes-nth #u8(1 2) 2) "bytes-nth: index 2 out of range (length 2)")
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("bytes-slice"),
+        Expression::make_function(
+            builtin_bytes_slice,
+            r#"Usage: (bytes-slice bytes start end?) -> bytes
+
+Return a new byte vector with the bytes from start up to (not including) end,
+end defaults to the length of bytes.
+
+Section: bytes
+
+Example:
+(test::assert-equal #u8(2 3) (bytes-slice #u8(1 2 3 4) 1 3))
+(test::assert-equal #u8(3 4) (bytes-slice #u8(1 2 3 4) 2))
+(test::assert-equal #u8() (bytes-slice #u8(1 2 3 4) 4))
+(test::assert-error (bytes-slice #u8(1 2) 1 3))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("bytes-concat"),
+        Expression::make_function(
+            builtin_bytes_concat,
+            r#"Usage: (bytes-concat bytes*) -> bytes
+
+Return a new byte vector with the contents of each argument in order.
+
+Section: bytes
+
+Example:
+(test::assert-equal #u8(1 2 3 4) (bytes-concat #u8(1) #u8(2 3) #u8() #u8(4)))
+(test::assert-equal #u8() (bytes-concat))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("str->bytes"),
+        Expression::make_function(
+            builtin_str_to_bytes,
+            r#"Usage: (str->bytes string) -> bytes
+
+Return the UTF-8 encoding of string (or char) as bytes.
+
+Section: bytes
+
+Example:
+(test::assert-equal #u8(97 98) (str->bytes "ab"))
+(test::assert-equal #u8(226 152 131) (str->bytes "☃"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("bytes->str"),
+        Expression::make_function(
+            builtin_bytes_to_str,
+            r#"Usage: (bytes->str bytes :lossy?) -> string
+
+Decode UTF-8 bytes into a string.  Invalid UTF-8 is an error (with the offset of
+the first bad byte) unless :lossy is given, then invalid sequences are replaced
+with U+FFFD (�).
+
+Section: bytes
+
+Example:
+(test::assert-equal "a☃" (bytes->str #u8(97 226 152 131)))
+(test::assert-error-msg (bytes->str #u8(97 255 98)) "bytes->str: invalid UTF-8 at byte 1")
+(test::assert-equal "a�b" (bytes->str #u8(97 255 98) :lossy))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("bytes->hex"),
+        Expression::make_function(
+            builtin_bytes_to_hex,
+            r#"Usage: (bytes->hex bytes) -> string
+
+Encode bytes as a lower case hex string (two digits per byte).
+
+Section: bytes
+
+Example:
+(test::assert-equal "00ff10" (bytes->hex #u8(0 255 16)))
+(test::assert-equal "" (bytes->hex #u8()))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("hex->bytes"),
+        Expression::make_function(
+            builtin_hex_to_bytes,
+            r#"Usage: (hex->bytes string) -> bytes
+
+Decode a hex string (upper or lower case, two digits per byte) into bytes.
+
+Section: bytes
+
+Example:
+(test::assert-equal #u8(0 255 16) (hex->bytes "00FF10"))
+(test::assert-error-msg (hex->bytes "0g") "hex->bytes: invalid hex digit 'g' at 1")
+(test::assert-error (hex->bytes "abc"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("bytes->base64"),
+        Expression::make_function(
+            builtin_bytes_to_base64,
+            r#"Usage: (bytes->base64 bytes) -> string
+
+Encode bytes as base64 (standard alphabet with padding).
+
+Section: bytes
+
+Example:
+(test::assert-equal "aGVsbG8=" (bytes->base64 (str->bytes "hello")))
+(test::assert-equal "AP8Q" (bytes->base64 #u8(0 255 16)))
+(test::assert-equal "" (bytes->base64 #u8()))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("base64->bytes"),
+        Expression::make_function(
+            builtin_base64_to_bytes,
+            r#"Usage: (base64->bytes string) -> bytes
+
+Decode base64 into bytes.  Accepts the standard and URL safe alphabets, padding
+is optional and whitespace (line breaks) is ignored.
+
+Section: bytes
+
+Example:
+(test::assert-equal "hello" (bytes->str (base64->bytes "aGVsbG8=")))
+(test::assert-equal #u8(0 255 16) (base64->bytes "AP8Q"))
+(test::assert-equal #u8(251 255) (base64->bytes "-_8"))
+(test::assert-error-msg (base64->bytes "aG!s") "base64->bytes: invalid character '!' at 2")
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("read-bytes"),
+        Expression::make_function(
+            builtin_read_bytes,
+            r#"Usage: (read-bytes source length?) -> bytes|nil
+
+Read up to length bytes (or everything) from a file opened with :binary, stdin or
+the captured output of a process.  Returns fewer bytes at the end of input and
+nil once nothing is left.
+
+Section: file
+
+Example:
+(with-temp-file (fn (tmp)
+    (let ((out (open tmp :create :truncate)))
+        (write-bytes out #u8(0 1 2 255 254))
+        (close out))
+    (let ((in (open tmp :read :binary)))
+        (test::assert-equal #u8(0 1) (read-bytes in 2))
+        (test::assert-equal #u8(2 255 254) (read-bytes in))
+        (test::assert-false (read-bytes in 1))
+        (close in))
+    (test::assert-error-msg (read-bytes (open tmp :read)) "read-bytes: file must be opened with :binary")))
+(test::assert-equal #u8(104 105 10) (read-bytes (syscall 'echo "hi")))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("write-bytes"),
+        Expression::make_function(
+            builtin_write_bytes,
+            r#"Usage: (write-bytes file bytes) -> nil
+
+Write bytes to a file opened for writing (or *stdout*/*stderr*, for instance to
+pipe binary data to a process).
+
+Section: file
+
+Example:
+(test::assert-equal "ab" (str (pipe (write-bytes *stdout* #u8(97 98)) (syscall 'cat))))
+(test::assert-error (write-bytes *stdout* "ab"))
+"#,
+        ),
+    );
+}
@@ -0,0 +1,648 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::builtins_hashmap::LispMap;
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Same,
+    Del,
+    Add,
+}
+
+// One step of an edit script, old and new are the positions in each sequence
+// (for Del/Add the position in the other sequence is where the edit happens).
+#[derive(Clone, Copy)]
+struct Edit {
+    kind: Kind,
+    old: usize,
+    new: usize,
+}
+
+// Myers O(ND) diff.  Only the diagonals that can be reached in round d are
+// kept for each round so the trace is O(D^2) not O(D*(N+M)).
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (ma, mb) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let (n, m) = (ma.len() as isize, mb.len() as isize);
+    let max = n + m;
+    let idx = |k: isize| (k + max + 1) as usize;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    'rounds: for d in 0..=max {
+        trace.push(v[idx(-d)..=idx(d)].to_vec());
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && ma[x as usize] == mb[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                break 'rounds;
+            }
+            k += 2;
+        }
+    }
+
+    let mut middle = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let (prev_x, prev_y) = if d == 0 {
+            (0, 0)
+        } else {
+            let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+                k + 1
+            } else {
+                k - 1
+            };
+            (at(prev_k), at(prev_k) - prev_k)
+        };
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            middle.push((Kind::Same, x, y));
+        }
+        if d > 0 {
+            if x == prev_x {
+                middle.push((Kind::Add, x, y - 1));
+            } else {
+                middle.push((Kind::Del, x - 1, y));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+
+    let mut edits = Vec::with_capacity(a.len() + b.len());
+    let mut push = |kind, old, new| edits.push(Edit { kind, old, new });
+    for i in 0..prefix {
+        push(Kind::Same, i, i);
+    }
+    for (kind, x, y) in middle.into_iter().rev() {
+        push(kind, x as usize + prefix, y as usize + prefix);
+    }
+    for i in 0..suffix {
+        push(Kind::Same, a.len() - suffix + i, b.len() - suffix + i);
+    }
+    edits
+}
+
+struct Hunk {
+    old_start: usize,
+    old_count: usize,
+    new_start: usize,
+    new_count: usize,
+    edits: Vec<Edit>,
+}
+
+// Group an edit script into hunks with context lines of unchanged text around
+// the changes, changes closer than 2 * context end up in the same hunk.
+fn make_hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.kind != Kind::Same)
+        .map(|(i, _)| i)
+        .collect();
+    let mut hunks = Vec::new();
+    let mut i = 0;
+    while i < changes.len() {
+        let first = changes[i];
+        let mut last = first;
+        while i + 1 < changes.len() && changes[i + 1] - last <= 2 * context + 1 {
+            i += 1;
+            last = changes[i];
+        }
+        i += 1;
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(edits.len());
+        let edits = edits[start..end].to_vec();
+        let old_count = edits.iter().filter(|e| e.kind != Kind::Add).count();
+        let new_count = edits.iter().filter(|e| e.kind != Kind::Del).count();
+        // Unified diffs number lines from 1 and give the line before an empty range.
+        let old_start = edits[0].old + if old_count > 0 { 1 } else { 0 };
+        let new_start = edits[0].new + if new_count > 0 { 1 } else { 0 };
+        hunks.push(Hunk {
+            old_start,
+            old_count,
+            new_start,
+            new_count,
+            edits,
+        });
+    }
+    hunks
+}
+
+fn make_str(s: &str) -> Expression {
+    Expression::alloc_data(ExpEnum::String(s.to_string().into(), None))
+}
+
+fn make_int(i: usize) -> Expression {
+    Expression::alloc_data(ExpEnum::Int(i as i64))
+}
+
+fn kind_symbol(kind: Kind) -> Expression {
+    let sym = match kind {
+        Kind::Same => ":same",
+        Kind::Del => ":del",
+        Kind::Add => ":add",
+    };
+    Expression::alloc_data(ExpEnum::Symbol(sym, SymLoc::None))
+}
+
+fn param_text(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<String, LispError> {
+    let exp = param_eval(environment, args, fn_name)?;
+    let is_text = matches!(&exp.get().data, ExpEnum::String(_, _) | ExpEnum::File(_));
+    if is_text {
+        exp.as_string(environment)
+    } else {
+        Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "String or File",
+            &exp.display_type(),
+        )))
+    }
+}
+
+#[derive(Default)]
+struct DiffOpts {
+    chars: bool,
+    color: bool,
+    context: Option<usize>,
+    old_name: Option<String>,
+    new_name: Option<String>,
+}
+
+fn param_opts(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+    allowed: &[&str],
+) -> Result<DiffOpts, LispError> {
+    let mut opts = DiffOpts::default();
+    while let Some(opt) = param_eval_optional(environment, args)? {
+        let name = match &opt.get().data {
+            ExpEnum::Symbol(sym, _) if allowed.contains(sym) => *sym,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: invalid option {}, expected one of {}",
+                    fn_name,
+                    opt,
+                    allowed.join(" ")
+                )))
+            }
+        };
+        match name {
+            ":chars" => opts.chars = true,
+            ":color" => opts.color = true,
+            _ => {
+                let value = param_eval(environment, args, fn_name)?;
+                let value_d = value.get();
+                match (name, &value_d.data) {
+                    (":context", ExpEnum::Int(i)) if *i >= 0 => opts.context = Some(*i as usize),
+                    (":old-name", ExpEnum::String(s, _)) => opts.old_name = Some(s.to_string()),
+                    (":new-name", ExpEnum::String(s, _)) => opts.new_name = Some(s.to_string()),
+                    _ => {
+                        return Err(LispError::new(format!(
+                            "{}: invalid value {} for {}",
+                            fn_name, value, name
+                        )))
+                    }
+                }
+            }
+        }
+    }
+    Ok(opts)
+}
+
+fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+fn builtin_diff(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "diff";
+    let old = param_text(environment, args, fn_name)?;
+    let new = param_text(environment, args, fn_name)?;
+    let opts = param_opts(environment, args, fn_name, &[":chars", ":context"])?;
+    if opts.chars {
+        let old: Vec<&str> = UnicodeSegmentation::graphemes(&old[..], true).collect();
+        let new: Vec<&str> = UnicodeSegmentation::graphemes(&new[..], true).collect();
+        let mut runs: Vec<(Kind, String)> = Vec::new();
+        for edit in myers(&old, &new) {
+            let text = if edit.kind == Kind::Add {
+                new[edit.new]
+            } else {
+                old[edit.old]
+            };
+            match runs.last_mut() {
+                Some((kind, run)) if *kind == edit.kind => run.push_str(text),
+                _ => runs.push((edit.kind, text.to_string())),
+            }
+        }
+        let runs = runs
+            .into_iter()
+            .map(|(kind, text)| Expression::with_list(vec![kind_symbol(kind), make_str(&text)]))
+            .collect();
+        return Ok(Expression::with_list(runs));
+    }
+    let (old, new) = (lines(&old), lines(&new));
+    let mut hunks = Vec::new();
+    for hunk in make_hunks(&myers(&old, &new), opts.context.unwrap_or(3)) {
+        let lines = hunk
+            .edits
+            .iter()
+            .map(|e| {
+                let line = if e.kind == Kind::Add {
+                    new[e.new]
+                } else {
+                    old[e.old]
+                };
+                let line = line.strip_suffix('\n').unwrap_or(line);
+                Expression::with_list(vec![kind_symbol(e.kind), make_str(line)])
+            })
+            .collect();
+        let mut map = LispMap::new();
+        map.insert(":old-start".into(), make_int(hunk.old_start));
+        map.insert(":old-count".into(), make_int(hunk.old_count));
+        map.insert(":new-start".into(), make_int(hunk.new_start));
+        map.insert(":new-count".into(), make_int(hunk.new_count));
+        map.insert(":lines".into(), Expression::with_list(lines));
+        hunks.push(Expression::alloc_data(ExpEnum::HashMap(map)));
+    }
+    Ok(Expression::with_list(hunks))
+}
+
+fn shell_color(environment: &Environment, name: &str, default: &str) -> String {
+    match lookup_expression(environment, &format!("shell::{}", name)) {
+        Some(exp) => match &exp.get().data {
+            ExpEnum::String(s, _) => s.to_string(),
+            _ => default.to_string(),
+        },
+        None => default.to_string(),
+    }
+}
+
+// A unified diff hunk range, the count is left out when it is 1.
+fn range(start: usize, count: usize) -> String {
+    if count == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, count)
+    }
+}
+
+fn builtin_diff_unified(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "diff-unified";
+    let old = param_text(environment, args, fn_name)?;
+    let new = param_text(environment, args, fn_name)?;
+    let opts = param_opts(
+        environment,
+        args,
+        fn_name,
+        &[":context", ":old-name", ":new-name", ":color"],
+    )?;
+    let (red, green, cyan, default) = if opts.color {
+        (
+            shell_color(environment, "*fg-red*", "\x1b[31m"),
+            shell_color(environment, "*fg-green*", "\x1b[32m"),
+            shell_color(environment, "*fg-cyan*", "\x1b[36m"),
+            shell_color(environment, "*fg-default*", "\x1b[39m"),
+        )
+    } else {
+        Default::default()
+    };
+    let (old, new) = (lines(&old), lines(&new));
+    let hunks = make_hunks(&myers(&old, &new), opts.context.unwrap_or(3));
+    let mut out = String::new();
+    if hunks.is_empty() {
+        return Ok(make_str(&out));
+    }
+    out.push_str(&format!(
+        "--- {}\n+++ {}\n",
+        opts.old_name.as_deref().unwrap_or("a"),
+        opts.new_name.as_deref().unwrap_or("b")
+    ));
+    for hunk in hunks {
+        out.push_str(&format!(
+            "{}@@ -{} +{} @@{}\n",
+            cyan,
+            range(hunk.old_start, hunk.old_count),
+            range(hunk.new_start, hunk.new_count),
+            default
+        ));
+        for e in hunk.edits {
+            let (line, prefix, color) = match e.kind {
+                Kind::Same => (old[e.old], ' ', ""),
+                Kind::Del => (old[e.old], '-', &red[..]),
+                Kind::Add => (new[e.new], '+', &green[..]),
+            };
+            let reset = if color.is_empty() { "" } else { &default[..] };
+            match line.strip_suffix('\n') {
+                Some(line) => out.push_str(&format!("{}{}{}{}\n", color, prefix, line, reset)),
+                None => out.push_str(&format!(
+                    "{}{}{}{}\n\\ No newline at end of file\n",
+                    color, prefix, line, reset
+                )),
+            }
+        }
+    }
+    Ok(make_str(&out))
+}
+
+// Parse "@@ -l[,s] +l[,s] @@" into (old start, old count, new start, new count).
+fn parse_hunk_header(line: &str) -> Option<(usize, usize, usize, usize)> {
+    let mut parts = line.strip_prefix("@@ ")?.split_whitespace();
+    let range = |part: Option<&str>, sign: char| -> Option<(usize, usize)> {
+        let part = part?.strip_prefix(sign)?;
+        match part.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((part.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(parts.next(), '-')?;
+    let (new_start, new_count) = range(parts.next(), '+')?;
+    if parts.next() != Some("@@") {
+        return None;
+    }
+    Some((old_start, old_count, new_start, new_count))
+}
+
+struct PatchHunk {
+    old_start: usize,
+    old_lines: Vec<String>,
+    new_lines: Vec<String>,
+}
+
+fn parse_patch(patch: &str, fn_name: &str) -> Result<Vec<PatchHunk>, LispError> {
+    let mut hunks = Vec::new();
+    let mut patch_lines = lines(patch).into_iter().peekable();
+    while let Some(line) = patch_lines.next() {
+        if !line.starts_with("@@") {
+            // Headers and any other text between hunks are ignored.
+            continue;
+        }
+        let header = line.trim_end();
+        let (old_start, mut old_left, _, mut new_left) =
+            parse_hunk_header(header).ok_or_else(|| {
+                LispError::new(format!("{}: invalid hunk header '{}'", fn_name, header))
+            })?;
+        // Lines are numbered from 1, only an empty old range can start at 0.
+        if old_start == 0 && old_left > 0 {
+            return Err(LispError::new(format!(
+                "{}: invalid hunk header '{}'",
+                fn_name, header
+            )));
+        }
+        let mut hunk = PatchHunk {
+            old_start,
+            old_lines: Vec::new(),
+            new_lines: Vec::new(),
+        };
+        let mut last = Kind::Same;
+        while old_left > 0
+            || new_left > 0
+            || matches!(patch_lines.peek(), Some(l) if l.starts_with('\\'))
+        {
+            let line = patch_lines.next().ok_or_else(|| {
+                LispError::new(format!("{}: patch ends inside hunk '{}'", fn_name, header))
+            })?;
+            let (prefix, text) = match line.chars().next() {
+                Some(ch) if ch == ' ' || ch == '-' || ch == '+' || ch == '\\' => (ch, &line[1..]),
+                // Some tools strip the space from empty context lines.
+                Some('\n') => (' ', line),
+                _ => {
+                    return Err(LispError::new(format!(
+                        "{}: invalid line in hunk '{}': {}",
+                        fn_name,
+                        header,
+                        line.trim_end()
+                    )))
+                }
+            };
+            match prefix {
+                ' ' if old_left > 0 && new_left > 0 => {
+                    hunk.old_lines.push(text.to_string());
+                    hunk.new_lines.push(text.to_string());
+                    old_left -= 1;
+                    new_left -= 1;
+                    last = Kind::Same;
+                }
+                '-' if old_left > 0 => {
+                    hunk.old_lines.push(text.to_string());
+                    old_left -= 1;
+                    last = Kind::Del;
+                }
+                '+' if new_left > 0 => {
+                    hunk.new_lines.push(text.to_string());
+                    new_left -= 1;
+                    last = Kind::Add;
+                }
+                '\\' => {
+                    // "\ No newline at end of file" applies to the line before it.
+                    let strip = |lines: &mut Vec<String>| {
+                        if let Some(line) = lines.last_mut() {
+                            if line.ends_with('\n') {
+                                line.pop();
+                            }
+                        }
+                    };
+                    if last != Kind::Add {
+                        strip(&mut hunk.old_lines);
+                    }
+                    if last != Kind::Del {
+                        strip(&mut hunk.new_lines);
+                    }
+                }
+                _ => {
+                    return Err(LispError::new(format!(
+                        "{}: hunk '{}' has more lines than its header says",
+                        fn_name, header
+                    )))
+                }
+            }
+        }
+        hunks.push(hunk);
+    }
+    Ok(hunks)
+}
+
+fn builtin_apply_patch(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "apply-patch";
+    let text = param_text(environment, args, fn_name)?;
+    let patch = param_text(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let text = lines(&text);
+    let mut out = String::new();
+    let mut cursor = 0;
+    // How far earlier hunks were found from where their header said.
+    let mut drift: isize = 0;
+    for (i, hunk) in parse_patch(&patch, fn_name)?.into_iter().enumerate() {
+        let len = hunk.old_lines.len();
+        let matches = |pos: usize| {
+            pos + len <= text.len()
+                && text[pos..pos + len]
+                    .iter()
+                    .zip(hunk.old_lines.iter())
+                    .all(|(a, b)| *a == b)
+        };
+        let start = if len == 0 {
+            hunk.old_start
+        } else {
+            hunk.old_start - 1
+        };
+        let expected = (start as isize + drift).max(cursor as isize) as usize;
+        // Look for the hunk's old text nearest to where it is expected.
+        let mut found = None;
+        for d in 0..=text.len() {
+            if expected + d <= text.len() && matches(expected + d) {
+                found = Some(expected + d);
+                break;
+            }
+            if d > 0 && expected >= cursor + d && matches(expected - d) {
+                found = Some(expected - d);
+                break;
+            }
+        }
+        let pos = found
+            .ok_or_else(|| LispError::new(format!("{}: hunk {} does not apply", fn_name, i + 1)))?;
+        drift = pos as isize - start as isize;
+        for line in &text[cursor..pos] {
+            out.push_str(line);
+        }
+        for line in &hunk.new_lines {
+            out.push_str(line);
+        }
+        cursor = pos + len;
+    }
+    for line in &text[cursor..] {
+        out.push_str(line);
+    }
+    Ok(make_str(&out))
+}
+
+pub fn add_diff_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("diff"),
+        Expression::make_function(
+            builtin_diff,
+            r#"Usage: (diff old new :chars? :context n?) -> Vector
+
+Compare old and new (strings or files opened for reading) and return the
+differences.  By default the texts are compared line by line and the result
+is a vector of hunks, each a hash map with :old-start, :old-count, :new-start
+and :new-count (line numbers start at 1 as in a unified diff) and :lines, a
+vector of #(:same line), #(:del line) or #(:add line) (line without its
+newline).  Hunks include :context unchanged lines (default 3) around the
+changes.  An empty vector means the texts are the same.
+
+With :chars the texts are compared character by character and the result is
+a vector of runs #(:same text), #(:del text) or #(:add text) covering both
+texts.
+
+Section: diff
+
+Example:
+(def hunks (diff "a\nb\nc\nd\ne\nf\n" "a\nB\nc\nd\ne\nF\n" :context 1))
+(test::assert-equal 2 (length hunks))
+(test::assert-equal 1 (hash-get (vec-nth hunks 0) :old-start))
+(test::assert-equal 3 (hash-get (vec-nth hunks 0) :old-count))
+(test::assert-equal '#(#(:same "a") #(:del "b") #(:add "B") #(:same "c"))
+                    (hash-get (vec-nth hunks 0) :lines))
+(test::assert-equal '#(#(:same "e") #(:del "f") #(:add "F")) (hash-get (vec-nth hunks 1) :lines))
+(test::assert-equal 5 (hash-get (vec-nth hunks 1) :new-start))
+(test::assert-equal 1 (length (diff "a\nb\nc\nd\n" "a\nB\nc\nd\ne\n" :context 1)))
+(test::assert-equal '#() (diff "same\n" "same\n"))
+(test::assert-equal '#(#(:same "k") #(:del "i") #(:add "a") #(:same "tten"))
+                    (diff "kitten" "katten" :chars))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("diff-unified"),
+        Expression::make_function(
+            builtin_diff_unified,
+            r#"Usage: (diff-unified old new :context n? :old-name name? :new-name name? :color?) -> String
+
+Compare old and new (strings or files opened for reading) line by line and
+return the differences as a unified diff, an empty string if they are the
+same.  :context is the number of unchanged lines around each change (default
+3) and :old-name/:new-name are used for the ---/+++ header lines (default a and
+b).  With :color removed lines are colored with shell::*fg-red*, added lines
+with shell::*fg-green* and hunk headers with shell::*fg-cyan*.
+
+Section: diff
+
+Example:
+(test::assert-equal "--- a\n+++ b\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+                    (diff-unified "a\nb\nc\n" "a\nB\nc\n"))
+(test::assert-equal "--- old.txt\n+++ new.txt\n@@ -1 +1 @@\n-x\n\\ No newline at end of file\n+x\n"
+                    (diff-unified "x" "x\n" :old-name "old.txt" :new-name "new.txt"))
+(test::assert-equal "" (diff-unified "a\n" "a\n"))
+(test::assert-true (str-contains (str shell::*fg-green* "+B") (diff-unified "b\n" "B\n" :color)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("apply-patch"),
+        Expression::make_function(
+            builtin_apply_patch,
+            r#"Usage: (apply-patch text patch) -> String
+
+Apply the unified diff patch to text and return the patched text, text and
+patch are strings or files opened for reading.  Header lines (---, +++ or
+anything else outside a hunk) are ignored.  A hunk whose context has moved is
+applied where its old text is found closest to the line its header names,
+raises an error if a hunk's old text can not be found.
+
+Section: diff
+
+Example:
+(def old "one\ntwo\nthree\nfour\n")
+(def new "one\n2\nthree\nfour\nfive")
+(test::assert-equal new (apply-patch old (diff-unified old new)))
+(test::assert-equal "zero\none\n2\nthree\n" (apply-patch "zero\none\ntwo\nthree\n"
+                    "@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n"))
+(test::assert-error-msg (apply-patch "a\n" "@@ -1 +1 @@\n-b\n+c\n") "apply-patch: hunk 1 does not apply")
+(test::assert-error-msg (apply-patch "a\n" "@@ -0,1 +1 @@\n-a\n+c\n") "apply-patch: invalid hunk header '@@ -0,1 +1 @@'")
+"#,
+        ),
+    );
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::builtins_util::*;
+use crate::digest::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::types::*;
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut res = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        res.push_str(&format!("{:02x}", b));
+    }
+    res
+}
+
+fn param_digest(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Box<dyn Digest>, LispError> {
+    let algo = param_eval(environment, args, fn_name)?;
+    let digest = match &algo.get().data {
+        ExpEnum::Symbol(s, _) => new_digest(s.trim_start_matches(':')),
+        ExpEnum::String(s, _) => new_digest(s),
+        _ => None,
+    };
+    digest.ok_or_else(|| {
+        LispError::new(format!(
+            "{}: unknown hash algorithm {}, expected one of :{}",
+            fn_name,
+            algo,
+            DIGEST_NAMES.join(" :")
+        ))
+    })
+}
+
+// Returns true if the optional :raw flag was given.
+fn param_raw(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<bool, LispError> {
+    let raw = match param_eval_optional(environment, args)? {
+        Some(opt) => match &opt.get().data {
+            ExpEnum::Symbol(":raw", _) => true,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: invalid option {}, expected :raw",
+                    fn_name, opt
+                )))
+            }
+        },
+        None => false,
+    };
+    params_done(args, fn_name)?;
+    Ok(raw)
+}
+
+fn digest_result(mut digest: Box<dyn Digest>, raw: bool) -> Expression {
+    let bytes = digest.finish();
+    if raw {
+        Expression::alloc_data(ExpEnum::Bytes(bytes))
+    } else {
+        Expression::alloc_data(ExpEnum::String(to_hex(&bytes).into(), None))
+    }
+}
+
+fn digest_reader(digest: &mut dyn Digest, reader: &mut dyn Read) -> io::Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => digest.update(&buf[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Hash the file at path with the named algorithm and return the hex digest,
+/// the file is read in chunks so it is never all in memory.
+pub fn hash_path(algo: &str, path: &Path) -> io::Result<Option<String>> {
+    match new_digest(algo) {
+        Some(mut digest) => {
+            digest_reader(&mut *digest, &mut File::open(path)?)?;
+            Ok(Some(to_hex(&digest.finish())))
+        }
+        None => Ok(None),
+    }
+}
+
+fn builtin_hash_bytes(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "hash-bytes";
+    let mut digest = param_digest(environment, args, fn_name)?;
+    let data = param_eval(environment, args, fn_name)?;
+    match &data.get().data {
+        ExpEnum::Bytes(b) => digest.update(b),
+        ExpEnum::String(s, _) => digest.update(s.as_bytes()),
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "Bytes or String",
+                &data.display_type(),
+            )))
+        }
+    }
+    let raw = param_raw(environment, args, fn_name)?;
+    Ok(digest_result(digest, raw))
+}
+
+fn builtin_hash_file(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "hash-file";
+    let mut digest = param_digest(environment, args, fn_name)?;
+    let source = param_eval(environment, args, fn_name)?;
+    match &source.get().data {
+        ExpEnum::String(path, _) => {
+            let mut file = File::open(&path[..]).map_err(|err| {
+                LispError::new(format!("{}: unable to open {}: {}", fn_name, path, err))
+            })?;
+            digest_reader(&mut *digest, &mut file)?;
+        }
+        ExpEnum::File(file) => match &mut *file.borrow_mut() {
+            FileState::ReadBinary(f) => digest_reader(&mut *digest, f)?,
+            FileState::Stdin => digest_reader(&mut *digest, &mut io::stdin())?,
+            FileState::Read(Some(f_iter), _) => {
+                for ch in f_iter {
+                    digest.update(ch.as_bytes());
+                }
+            }
+            FileState::Read(None, _) => {}
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: requires a file opened for reading",
+                    fn_name
+                )))
+            }
+        },
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "String (path) or File",
+                &source.display_type(),
+            )))
+        }
+    }
+    let raw = param_raw(environment, args, fn_name)?;
+    Ok(digest_result(digest, raw))
+}
+
+pub fn add_digest_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("hash-bytes"),
+        Expression::make_function(
+            builtin_hash_bytes,
+            r#"Usage: (hash-bytes algorithm data :raw?) -> String
+
+Hash data (Bytes or a String, strings are hashed as their UTF-8 bytes) and
+return the digest as a lower case hex string, or as Bytes with :raw.
+algorithm is one of :sha1, :sha256, :sha512, :blake3, :xxhash (64 bit
+xxHash, seed 0), :crc32 or :md5 (to match md5sum, it is not collision
+resistant).
+
+Section: hash
+
+Example:
+(test::assert-equal "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                    (hash-bytes :sha256 "abc"))
+(test::assert-equal "a9993e364706816aba3e25717850c26c9cd0d89d" (hash-bytes :sha1 (str->bytes "abc")))
+(test::assert-equal "900150983cd24fb0d6963f7d28e17f72" (hash-bytes :md5 "abc"))
+(test::assert-equal "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+                    (hash-bytes :sha512 "abc"))
+(test::assert-equal "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85" (hash-bytes :blake3 "abc"))
+(test::assert-equal "44bc2cf5ad770999" (hash-bytes :xxhash "abc"))
+(test::assert-equal "cbf43926" (hash-bytes :crc32 "123456789"))
+(test::assert-equal #u8(203 244 57 38) (hash-bytes :crc32 "123456789" :raw))
+(test::assert-error (hash-bytes :sha3 "abc"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("hash-file"),
+        Expression::make_function(
+            builtin_hash_file,
+            r#"Usage: (hash-file algorithm file :raw?) -> String
+
+Hash the contents of file (a path or a file opened for reading) and return the
+digest as a lower case hex string, or as Bytes with :raw.  The file is read in
+chunks so large files are never loaded at once.  algorithm is one of :sha1,
+:sha256, :sha512, :blake3, :xxhash, :crc32 or :md5, see hash-bytes.  To build a
+content hash index of a directory tree pass an algorithm to fs-crawl.
+
+Section: hash
+
+Example:
+(with-temp-file (fn (tmp)
+    (def tst-file (open tmp :create :truncate))
+    (write-string tst-file "abc")
+    (close tst-file)
+    (test::assert-equal (hash-bytes :sha256 "abc") (hash-file :sha256 tmp))
+    (test::assert-equal (hash-bytes :blake3 "abc" :raw)
+                        (hash-file :blake3 (open tmp :binary) :raw))
+    (test::assert-equal "352441c2" (hash-file :crc32 (open tmp)))
+    (test::assert-error (hash-file :sha256 (str tmp "-missing")))))
+"#,
+        ),
+    );
+}
@@ -2,15 +2,19 @@ use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::env;
 use std::hash::BuildHasher;
-use std::io::{self, ErrorKind};
+use std::io::{self, ErrorKind, Write};
+use std::iter;
 use unicode_width::UnicodeWidthStr;
 
+use nix::sys::termios;
+use nix::unistd;
 use sl_liner::vi::AlphanumericAndVariableKeywordRule;
 use sl_liner::{
     keymap, last_non_ws_char_was_not_backslash, Buffer, ColorClosure, Context, DefaultEditorRules,
     NewlineRule, Prompt, WordDivideRule,
 };
 
+use crate::builtins_event::poll_events;
 use crate::builtins_util::*;
 use crate::completions::*;
 use crate::environment::*;
@@ -615,6 +619,82 @@ pub fn read_prompt(
     result
 }
 
+// The prompt is either a string or a lambda that returns one, a lambda is
+// called again to redraw the prompt after event callbacks run.
+fn prompt_text(
+    environment: &mut Environment,
+    prompt: &Expression,
+) -> Result<Option<String>, LispError> {
+    let data = prompt.get().data.clone();
+    match data {
+        ExpEnum::String(s, _) => Ok(Some(s.to_string())),
+        ExpEnum::Lambda(_) => {
+            let text = call_lambda(environment, prompt.clone(), &mut iter::empty(), false)?;
+            Ok(Some(text.as_string(environment)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn erase_prompt(text: &str) {
+    let lines = text.matches('\n').count();
+    if lines > 0 {
+        print!("\r\x1b[{}A\x1b[J", lines);
+    } else {
+        print!("\r\x1b[J");
+    }
+    let _ = io::stdout().flush();
+}
+
+// If anything is registered with the event loop service it until the user
+// starts typing.  The prompt is cleared before callbacks run so any output they
+// produce is not mangled.
+fn wait_for_input(environment: &mut Environment, prompt: &Expression, text: String) -> String {
+    if !environment.event_loop.has_work() || !unistd::isatty(0).unwrap_or(false) {
+        return text;
+    }
+    let cooked = match termios::tcgetattr(0) {
+        Ok(cooked) => cooked,
+        Err(_) => return text,
+    };
+    // Non-canonical so the first key press wakes poll, it is left unread for liner.
+    let mut waiting = cooked.clone();
+    waiting
+        .local_flags
+        .remove(termios::LocalFlags::ICANON | termios::LocalFlags::ECHO);
+    let _ = termios::tcsetattr(0, termios::SetArg::TCSANOW, &waiting);
+    let mut text = text;
+    print!("{}", text);
+    let _ = io::stdout().flush();
+    while environment.event_loop.has_work() {
+        let ready = match poll_events(environment, None, Some(0)) {
+            Ok(Some(ready)) => ready,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("\n{}", err);
+                break;
+            }
+        };
+        if ready.is_empty() {
+            continue;
+        }
+        erase_prompt(&text);
+        if let Err(err) = ready.run(environment) {
+            eprintln!("Error in event callback: {}", err);
+        }
+        match prompt_text(environment, prompt) {
+            Ok(Some(new_text)) => text = new_text,
+            Ok(None) => {}
+            Err(err) => eprintln!("Error getting prompt: {}", err),
+        }
+        print!("{}", text);
+        let _ = io::stdout().flush();
+    }
+    let _ = termios::tcsetattr(0, termios::SetArg::TCSANOW, &cooked);
+    erase_prompt(&text);
+    text
+}
+
 fn builtin_prompt(
     environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
@@ -648,9 +728,10 @@ fn builtin_prompt(
         None
     };
     params_done(args, "prompt")?;
-    let prompt_d = prompt.get();
-    if let ExpEnum::String(s, _) = &prompt_d.data {
-        return match read_prompt(environment, s, history_file, liner_id) {
+    let prompt_str = prompt_text(environment, &prompt)?;
+    if let Some(s) = prompt_str {
+        let s = wait_for_input(environment, &prompt, s);
+        return match read_prompt(environment, &s, history_file, liner_id) {
             Ok(input) => Ok(Expression::alloc_data(ExpEnum::String(input.into(), None))),
             Err(err) => match err.kind() {
                 ErrorKind::UnexpectedEof => {
@@ -673,7 +754,7 @@ fn builtin_prompt(
         };
     }
     Err(LispError::new(
-        "prompt: requires a prompt string and option history file.",
+        "prompt: requires a prompt string (or function returning one) and option history file.",
     ))
 }
 
@@ -871,7 +952,11 @@ pub fn add_edit_builtins<S: BuildHasher>(
             "Usage: (prompt string) -> string
 
 Starts an interactive prompt (like the repl prompt) with the supplied prompt and
-returns the input string.
+returns the input string.  The prompt can also be a lambda that returns the prompt
+string.  While waiting for input any timers, readable sources or signal watchers
+(see event-loop) are serviced and a lambda prompt is called again to redraw it
+after they run (so it can show a clock or background status for instance).  This
+stops at the first key press, they are not serviced while the line is edited.
 
 Section: shell
 
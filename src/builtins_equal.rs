@@ -0,0 +1,364 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::rc::Rc;
+
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::numeric::Num;
+use crate::types::*;
+
+// Values without identity, these are identical? when they are equal?.
+fn is_immediate(exp: &ExpEnum) -> bool {
+    matches!(
+        exp,
+        ExpEnum::Nil
+            | ExpEnum::True
+            | ExpEnum::False
+            | ExpEnum::Int(_)
+            | ExpEnum::Float(_)
+            | ExpEnum::Rational(_)
+            | ExpEnum::Decimal(_)
+            | ExpEnum::Char(_)
+            | ExpEnum::CodePoint(_)
+            | ExpEnum::Symbol(_, _)
+    )
+}
+
+// Position of a value's type in the compare ordering.
+fn type_rank(exp: &ExpEnum) -> usize {
+    match exp {
+        ExpEnum::Nil => 0,
+        ExpEnum::False => 1,
+        ExpEnum::True => 2,
+        ExpEnum::Int(_) | ExpEnum::Float(_) | ExpEnum::Rational(_) | ExpEnum::Decimal(_) => 3,
+        ExpEnum::Char(_) | ExpEnum::CodePoint(_) => 4,
+        ExpEnum::String(_, _) => 5,
+        ExpEnum::Symbol(_, _) => 6,
+        ExpEnum::Bytes(_) => 7,
+        ExpEnum::Regex(_) => 8,
+        ExpEnum::Vector(_) => 9,
+        ExpEnum::Pair(_, _) => 10,
+        ExpEnum::HashMap(_) => 11,
+        ExpEnum::Set(_) => 12,
+        ExpEnum::PVec(_) => 13,
+        ExpEnum::PMap(_) => 14,
+        ExpEnum::Record(_) => 15,
+        _ => 16,
+    }
+}
+
+// Compare the items of two vectors in order, then by length.
+fn compare_items(
+    a: &[Expression],
+    b: &[Expression],
+    seen: &mut HashSet<(usize, usize)>,
+) -> Ordering {
+    let mut ord = Ordering::Equal;
+    for (a, b) in a.iter().zip(b.iter()) {
+        ord = compare_inner(a, b, seen);
+        if ord != Ordering::Equal {
+            break;
+        }
+    }
+    ord.then(a.len().cmp(&b.len()))
+}
+
+// Compare a and b, pairs of containers already being compared (a cycle) are
+// treated as equal so cyclic structures terminate.
+fn compare_inner(a: &Expression, b: &Expression, seen: &mut HashSet<(usize, usize)>) -> Ordering {
+    if a.id() == b.id() {
+        return Ordering::Equal;
+    }
+    let a_d = a.get();
+    let b_d = b.get();
+    let (a_data, b_data) = (&a_d.data, &b_d.data);
+    // Unwrap values and analyzer wrappers.
+    match (a_data, b_data) {
+        (ExpEnum::Values(v), _) if !v.is_empty() => return compare_inner(&v[0], b, seen),
+        (_, ExpEnum::Values(v)) if !v.is_empty() => return compare_inner(a, &v[0], seen),
+        (ExpEnum::Wrapper(a), _) => return compare_inner(a, b, seen),
+        (_, ExpEnum::Wrapper(b)) => return compare_inner(a, b, seen),
+        _ => {}
+    }
+    let rank = type_rank(a_data).cmp(&type_rank(b_data));
+    if rank != Ordering::Equal {
+        return rank;
+    }
+    let is_container = matches!(
+        a_data,
+        ExpEnum::Vector(_)
+            | ExpEnum::Pair(_, _)
+            | ExpEnum::HashMap(_)
+            | ExpEnum::PVec(_)
+            | ExpEnum::PMap(_)
+            | ExpEnum::Record(_)
+    );
+    if is_container && !seen.insert((a.id(), b.id())) {
+        return Ordering::Equal;
+    }
+    let ord = match (a_data, b_data) {
+        (ExpEnum::Int(a), ExpEnum::Int(b)) => a.cmp(b),
+        (ExpEnum::Float(a), ExpEnum::Float(b)) => a.total_cmp(b),
+        // An Int sorts before a Float with the same value so 1 and 1.0 are not equal.
+        (ExpEnum::Int(a), ExpEnum::Float(b)) => (*a as f64).total_cmp(b).then(Ordering::Less),
+        (ExpEnum::Float(a), ExpEnum::Int(b)) => a.total_cmp(&(*b as f64)).then(Ordering::Greater),
+        // Rationals and decimals compare by value, equal values order by type.
+        _ if type_rank(a_data) == 3 => match (Num::from_data(a_data), Num::from_data(b_data)) {
+            (Some(a), Some(b)) => a.total_cmp(b),
+            _ => Ordering::Equal,
+        },
+        (ExpEnum::Char(a), ExpEnum::Char(b)) => a.cmp(b),
+        (ExpEnum::CodePoint(a), ExpEnum::CodePoint(b)) => a.cmp(b),
+        (ExpEnum::Char(a), ExpEnum::CodePoint(b)) => {
+            a[..].cmp(&b.to_string()[..]).then(Ordering::Less)
+        }
+        (ExpEnum::CodePoint(a), ExpEnum::Char(b)) => {
+            a.to_string()[..].cmp(&b[..]).then(Ordering::Greater)
+        }
+        (ExpEnum::String(a, _), ExpEnum::String(b, _)) => a.cmp(b),
+        (ExpEnum::Symbol(a, _), ExpEnum::Symbol(b, _)) => a.cmp(b),
+        (ExpEnum::Bytes(a), ExpEnum::Bytes(b)) => a.cmp(b),
+        (ExpEnum::Regex(a), ExpEnum::Regex(b)) => a.as_str().cmp(b.as_str()),
+        (ExpEnum::Vector(a), ExpEnum::Vector(b)) => compare_items(a, b, seen),
+        (ExpEnum::PVec(a), ExpEnum::PVec(b)) => compare_items(&a.to_vec(), &b.to_vec(), seen),
+        (ExpEnum::Pair(a_car, a_cdr), ExpEnum::Pair(b_car, b_cdr)) => {
+            compare_inner(a_car, b_car, seen).then_with(|| compare_inner(a_cdr, b_cdr, seen))
+        }
+        (ExpEnum::HashMap(a), ExpEnum::HashMap(b)) => {
+            let mut a_keys: Vec<&Cow<'static, str>> = a.keys().collect();
+            let mut b_keys: Vec<&Cow<'static, str>> = b.keys().collect();
+            a_keys.sort();
+            b_keys.sort();
+            let mut ord = a_keys.cmp(&b_keys);
+            if ord == Ordering::Equal {
+                for key in a_keys {
+                    ord = compare_inner(&a[key], &b[key], seen);
+                    if ord != Ordering::Equal {
+                        break;
+                    }
+                }
+            }
+            ord
+        }
+        (ExpEnum::PMap(a), ExpEnum::PMap(b)) => {
+            let mut a = a.entries();
+            let mut b = b.entries();
+            a.sort_by(|(x, _), (y, _)| x.cmp(y));
+            b.sort_by(|(x, _), (y, _)| x.cmp(y));
+            let mut ord = a.iter().map(|(k, _)| k).cmp(b.iter().map(|(k, _)| k));
+            if ord == Ordering::Equal {
+                for ((_, a), (_, b)) in a.iter().zip(b.iter()) {
+                    ord = compare_inner(a, b, seen);
+                    if ord != Ordering::Equal {
+                        break;
+                    }
+                }
+            }
+            ord
+        }
+        (ExpEnum::Set(a), ExpEnum::Set(b)) => {
+            // Members are equal? exactly when their keys are the same.
+            let mut a_keys: Vec<&str> = a.keys().map(|k| &k[..]).collect();
+            let mut b_keys: Vec<&str> = b.keys().map(|k| &k[..]).collect();
+            a_keys.sort();
+            b_keys.sort();
+            a_keys.cmp(&b_keys)
+        }
+        (ExpEnum::Record(a), ExpEnum::Record(b)) => {
+            let (a_type, b_type) = (a.record_type(), b.record_type());
+            let mut ord = a_type.name().cmp(b_type.name());
+            if ord == Ordering::Equal && !Rc::ptr_eq(a_type, b_type) {
+                ord = Rc::as_ptr(a_type).cmp(&Rc::as_ptr(b_type));
+            }
+            for (a, b) in a.fields().iter().zip(b.fields()) {
+                if ord != Ordering::Equal {
+                    break;
+                }
+                ord = compare_inner(&a.get(), &b.get(), seen);
+            }
+            ord
+        }
+        (ExpEnum::Nil, ExpEnum::Nil)
+        | (ExpEnum::True, ExpEnum::True)
+        | (ExpEnum::False, ExpEnum::False) => Ordering::Equal,
+        // No value ordering, order by type then by object.
+        _ => a
+            .display_type()
+            .cmp(&b.display_type())
+            .then(a.id().cmp(&b.id())),
+    };
+    if is_container {
+        seen.remove(&(a.id(), b.id()));
+    }
+    ord
+}
+
+/// Total ordering of any two values, values of different types are ordered by
+/// type (nil, #f, #t, numbers, chars, strings, symbols, bytes, regexes,
+/// vectors, lists, hash maps, sets, persistent vectors, persistent maps, records
+/// then everything else).  Ordering::Equal exactly when the values are equal?.
+pub fn compare_values(a: &Expression, b: &Expression) -> Ordering {
+    compare_inner(a, b, &mut HashSet::new())
+}
+
+/// Deep, type aware equality.
+pub fn values_equal(a: &Expression, b: &Expression) -> bool {
+    compare_values(a, b) == Ordering::Equal
+}
+
+/// True if a and b are the same object (or the same immediate value).
+pub fn values_identical(a: &Expression, b: &Expression) -> bool {
+    if a.id() == b.id() {
+        return true;
+    }
+    let a_d = a.get();
+    let b_d = b.get();
+    is_immediate(&a_d.data)
+        && is_immediate(&b_d.data)
+        && compare_inner(a, b, &mut HashSet::new()) == Ordering::Equal
+}
+
+fn builtin_equal_p(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "equal?";
+    let first = param_eval(environment, args, fn_name)?;
+    let mut res = true;
+    let mut count = 1;
+    while let Some(next) = param_eval_optional(environment, args)? {
+        res = res && values_equal(&first, &next);
+        count += 1;
+    }
+    if count < 2 {
+        return Err(LispError::new(format!(
+            "{}: takes at least two values",
+            fn_name
+        )));
+    }
+    Ok(if res {
+        Expression::make_true()
+    } else {
+        Expression::make_false()
+    })
+}
+
+fn builtin_identical_p(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "identical?";
+    let a = param_eval(environment, args, fn_name)?;
+    let b = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(if values_identical(&a, &b) {
+        Expression::make_true()
+    } else {
+        Expression::make_false()
+    })
+}
+
+fn builtin_compare(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "compare";
+    let a = param_eval(environment, args, fn_name)?;
+    let b = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let res = match compare_values(&a, &b) {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    };
+    Ok(Expression::alloc_data(ExpEnum::Int(res)))
+}
+
+pub fn add_equal_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("equal?"),
+        Expression::make_function(
+            builtin_equal_p,
+            r#"Usage: (equal? val0 val1 ...) -> t/nil
+
+True if all the values are structurally equal.  Unlike = values must have the
+same type to be equal (1 is not equal? to 1.0 or "1"), lists, vectors and hash
+maps are equal if their items are equal? (records if they have the same type
+and equal? fields), strings, chars, symbols and bytes by content.  Values with
+no content (functions, files, processes) are only equal to themselves.  Cyclic
+structures are fine.
+
+Section: equality
+
+Example:
+(test::assert-true (equal? '(1 (2 #(3 "x"))) (list 1 (list 2 (vec 3 "x")))))
+(test::assert-false (equal? '(1 2) "(1 2)"))
+(test::assert-true (= 1 1.0))
+(test::assert-false (equal? 1 1.0))
+(test::assert-false (equal? '(1 2) '#(1 2)))
+(test::assert-true (equal? (make-hash '((a . 1) (b . #(2)))) (make-hash '((b . #(2)) (a . 1)))))
+(test::assert-false (equal? (make-hash '((a . 1))) (make-hash '((a . 2)))))
+(test::assert-true (equal? #\a #\a))
+(test::assert-true (equal? :a :a :a))
+(test::assert-false (equal? :a :a :b))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("identical?"),
+        Expression::make_function(
+            builtin_identical_p,
+            r#"Usage: (identical? val0 val1) -> t/nil
+
+True if val0 and val1 are the same object, modifying one would modify the
+other.  Numbers, chars, symbols, nil, #t and #f have no identity and are
+identical? when they are equal?.
+
+Section: equality
+
+Example:
+(def test-ident (list 1 2))
+(test::assert-true (identical? test-ident test-ident))
+(test::assert-false (identical? test-ident (list 1 2)))
+(test::assert-true (equal? test-ident (list 1 2)))
+(test::assert-true (identical? 10 10))
+(test::assert-true (identical? :key :key))
+(test::assert-false (identical? "abc" (str "ab" "c")))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("compare"),
+        Expression::make_function(
+            builtin_compare,
+            r#"Usage: (compare val0 val1) -> Int
+
+Compare two values of any type and return -1 if val0 sorts first, 0 if they are
+equal? and 1 if val1 sorts first.  This is a total ordering, values of
+different types are ordered by type: nil, #f, #t, numbers (Int and Float by
+value), chars, strings, symbols, bytes, regexes, vectors, lists, hash maps,
+sets, persistent vectors, persistent maps, records then anything else.  Vectors
+and lists are compared item by item.
+This is the default ordering for sort and can be passed to sort as the
+comparator.
+
+Section: equality
+
+Example:
+(test::assert-equal -1 (compare 1 2))
+(test::assert-equal 1 (compare 2.5 2))
+(test::assert-equal 0 (compare '(1 #(2)) (list 1 (vec 2))))
+(test::assert-equal -1 (compare 1 1.0))
+(test::assert-equal -1 (compare 100 "1"))
+(test::assert-equal -1 (compare '(1 2) '(1 2 0)))
+(test::assert-equal '#(nil 1 2.5 "a" :b #(1)) (sort '#(#(1) :b "a" 2.5 nil 1) compare))
+"#,
+        ),
+    );
+}
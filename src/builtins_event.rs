@@ -0,0 +1,769 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::iter;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
+    libc,
+    poll::{poll, PollFd, PollFlags},
+    sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    unistd,
+};
+
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::eval::*;
+use crate::interner::*;
+use crate::signals::test_clear_sigint;
+use crate::types::*;
+
+// Self pipe used to wake poll when a watched signal arrives, the handler
+// records the signal in SIGNALS_PENDING (one bit per signal number).
+static SIGNAL_PIPE_READ: AtomicI32 = AtomicI32::new(-1);
+static SIGNAL_PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+static SIGNALS_PENDING: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn event_signal_handle(sig: libc::c_int) {
+    SIGNALS_PENDING.fetch_or(1 << sig, Ordering::SeqCst);
+    let fd = SIGNAL_PIPE_WRITE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let byte = [0_u8];
+        // If the pipe is full a wake up is already pending so ignore errors.
+        unsafe {
+            libc::write(fd, byte.as_ptr() as *const libc::c_void, 1);
+        }
+    }
+}
+
+fn signal_pipe() -> Result<RawFd, LispError> {
+    let read_fd = SIGNAL_PIPE_READ.load(Ordering::SeqCst);
+    if read_fd >= 0 {
+        return Ok(read_fd);
+    }
+    let (read_fd, write_fd) = unistd::pipe().map_err(|e| LispError::new(e.to_string()))?;
+    for fd in &[read_fd, write_fd] {
+        fcntl(*fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .and_then(|_| fcntl(*fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)))
+            .map_err(|e| LispError::new(e.to_string()))?;
+    }
+    SIGNAL_PIPE_WRITE.store(write_fd, Ordering::SeqCst);
+    SIGNAL_PIPE_READ.store(read_fd, Ordering::SeqCst);
+    Ok(read_fd)
+}
+
+fn drain_signal_pipe(read_fd: RawFd) -> u64 {
+    let mut buf = [0_u8; 64];
+    while let Ok(n) = unistd::read(read_fd, &mut buf) {
+        if n == 0 {
+            break;
+        }
+    }
+    SIGNALS_PENDING.swap(0, Ordering::SeqCst)
+}
+
+struct Timer {
+    id: i64,
+    due: Instant,
+    interval: Option<Duration>,
+    callback: Expression,
+}
+
+struct Readable {
+    id: i64,
+    fd: RawFd,
+    source: Expression,
+    callback: Expression,
+}
+
+struct SignalWatch {
+    id: i64,
+    signal: Signal,
+    callback: Expression,
+}
+
+// Timers, readable sources and signals waiting to be serviced by event-loop
+// (or by prompt while it waits for input).  Registrations are identified by
+// an integer id that can be passed to cancel.
+#[derive(Default)]
+pub struct EventLoop {
+    next_id: i64,
+    timers: Vec<Timer>,
+    readables: Vec<Readable>,
+    signals: Vec<SignalWatch>,
+    // Signal dispositions to restore once nothing is watching a signal.
+    saved_actions: HashMap<Signal, SigAction>,
+    stop: bool,
+}
+
+impl EventLoop {
+    pub fn has_work(&self) -> bool {
+        !self.timers.is_empty() || !self.readables.is_empty() || !self.signals.is_empty()
+    }
+
+    fn next_id(&mut self) -> i64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn add_timer(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        callback: Expression,
+    ) -> i64 {
+        let id = self.next_id();
+        self.timers.push(Timer {
+            id,
+            due: Instant::now() + delay,
+            interval,
+            callback,
+        });
+        id
+    }
+
+    fn add_readable(&mut self, fd: RawFd, source: Expression, callback: Expression) -> i64 {
+        let id = self.next_id();
+        self.readables.push(Readable {
+            id,
+            fd,
+            source,
+            callback,
+        });
+        id
+    }
+
+    fn add_signal(&mut self, signal: Signal, callback: Expression) -> Result<i64, LispError> {
+        signal_pipe()?;
+        if !self.signals.iter().any(|s| s.signal == signal) {
+            let action = SigAction::new(
+                SigHandler::Handler(event_signal_handle),
+                SaFlags::SA_RESTART,
+                SigSet::empty(),
+            );
+            let old = unsafe { sigaction(signal, &action) }
+                .map_err(|e| LispError::new(format!("on-signal: {}", e)))?;
+            self.saved_actions.entry(signal).or_insert(old);
+        }
+        let id = self.next_id();
+        self.signals.push(SignalWatch {
+            id,
+            signal,
+            callback,
+        });
+        Ok(id)
+    }
+
+    fn is_live(&self, id: i64) -> bool {
+        self.timers.iter().any(|t| t.id == id)
+            || self.readables.iter().any(|r| r.id == id)
+            || self.signals.iter().any(|s| s.id == id)
+    }
+
+    fn cancel(&mut self, id: i64) -> bool {
+        let before = self.timers.len() + self.readables.len() + self.signals.len();
+        self.timers.retain(|t| t.id != id);
+        self.readables.retain(|r| r.id != id);
+        let mut released = Vec::new();
+        self.signals.retain(|s| {
+            if s.id == id {
+                released.push(s.signal);
+                false
+            } else {
+                true
+            }
+        });
+        for signal in released {
+            if !self.signals.iter().any(|s| s.signal == signal) {
+                if let Some(old) = self.saved_actions.remove(&signal) {
+                    let _ = unsafe { sigaction(signal, &old) };
+                }
+            }
+        }
+        before != self.timers.len() + self.readables.len() + self.signals.len()
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.timers.iter().map(|t| t.due).min()
+    }
+}
+
+struct Ready {
+    id: i64,
+    // One shot timers are removed before their callback runs.
+    removed: bool,
+    callback: Expression,
+    arg: Option<Expression>,
+}
+
+fn collect_due_timers(event_loop: &mut EventLoop, ready: &mut Vec<Ready>) {
+    let now = Instant::now();
+    let mut due: Vec<&mut Timer> = event_loop
+        .timers
+        .iter_mut()
+        .filter(|t| t.due <= now)
+        .collect();
+    due.sort_by_key(|t| t.due);
+    for timer in due {
+        if let Some(interval) = timer.interval {
+            // Do not try to catch up on missed ticks, just run once and reschedule.
+            timer.due += interval;
+            if timer.due <= now {
+                timer.due = now + interval;
+            }
+        }
+        ready.push(Ready {
+            id: timer.id,
+            removed: timer.interval.is_none(),
+            callback: timer.callback.clone(),
+            arg: None,
+        });
+    }
+    event_loop
+        .timers
+        .retain(|t| t.interval.is_some() || t.due > now);
+}
+
+/// Callbacks that are ready to run, returned by poll_events.
+pub struct ReadyEvents(Vec<Ready>);
+
+impl ReadyEvents {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Run the callbacks in order.  An error from a callback cancels that
+    /// registration and is returned (skipping any remaining callbacks).
+    pub fn run(self, environment: &mut Environment) -> Result<(), LispError> {
+        for item in self.0 {
+            // An earlier callback may have cancelled this one.
+            if !item.removed && !environment.event_loop.is_live(item.id) {
+                continue;
+            }
+            let result = match item.arg {
+                Some(arg) => call_lambda(environment, item.callback, &mut iter::once(arg), false),
+                None => call_lambda(environment, item.callback, &mut iter::empty(), false),
+            };
+            if let Err(err) = result {
+                environment.event_loop.cancel(item.id);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wait for at most timeout (forever if None) for something registered with
+/// the event loop to become ready and return the callbacks to run.  If
+/// input_fd is provided it is also polled and None is returned as soon as it
+/// becomes readable.
+pub fn poll_events(
+    environment: &mut Environment,
+    timeout: Option<Duration>,
+    input_fd: Option<RawFd>,
+) -> Result<Option<ReadyEvents>, LispError> {
+    let event_loop = &mut environment.event_loop;
+    let mut wait = timeout;
+    if let Some(due) = event_loop.next_due() {
+        let until_due = due.saturating_duration_since(Instant::now());
+        wait = Some(wait.map_or(until_due, |w| w.min(until_due)));
+    }
+    // Input already read into a buffer will not wake poll so do not wait.
+    let buffered: Vec<bool> = event_loop
+        .readables
+        .iter()
+        .map(|readable| source_buffered(&readable.source))
+        .collect();
+    if buffered.iter().any(|b| *b) {
+        wait = Some(Duration::from_millis(0));
+    }
+    let mut fds = Vec::with_capacity(event_loop.readables.len() + 2);
+    if let Some(fd) = input_fd {
+        fds.push(PollFd::new(fd, PollFlags::POLLIN));
+    }
+    for readable in &event_loop.readables {
+        fds.push(PollFd::new(readable.fd, PollFlags::POLLIN));
+    }
+    let signal_fd = if event_loop.signals.is_empty() {
+        None
+    } else {
+        let fd = signal_pipe()?;
+        fds.push(PollFd::new(fd, PollFlags::POLLIN));
+        Some(fd)
+    };
+    let wait_ms = match wait {
+        // Round up so a timer is not polled for a moment before it is due.
+        Some(wait) => {
+            let mut ms = wait.as_millis();
+            if Duration::from_millis(ms as u64) < wait {
+                ms += 1;
+            }
+            ms.min(libc::c_int::MAX as u128) as libc::c_int
+        }
+        None => -1,
+    };
+    match poll(&mut fds, wait_ms) {
+        Ok(_) => {}
+        Err(Errno::EINTR) => {
+            if test_clear_sigint() {
+                return Err(LispError::new("event-loop: interrupted"));
+            }
+        }
+        Err(err) => return Err(LispError::new(format!("event-loop: {}", err))),
+    }
+    let revents = |fd: &PollFd| fd.revents().unwrap_or_else(PollFlags::empty);
+    let mut fds = fds.iter();
+    if input_fd.is_some() {
+        if let Some(fd) = fds.next() {
+            if !revents(fd).is_empty() {
+                return Ok(None);
+            }
+        }
+    }
+    let mut ready = Vec::new();
+    let mut closed = Vec::new();
+    for ((readable, fd), buffered) in event_loop.readables.iter().zip(&mut fds).zip(buffered) {
+        let revents = revents(fd);
+        if buffered || revents.contains(PollFlags::POLLIN) {
+            ready.push(Ready {
+                id: readable.id,
+                removed: false,
+                callback: readable.callback.clone(),
+                arg: Some(readable.source.clone()),
+            });
+        } else if !revents.is_empty() {
+            // Hung up or invalid with nothing left to read, stop watching it.
+            closed.push(readable.id);
+        }
+    }
+    if let (Some(signal_fd), Some(fd)) = (signal_fd, fds.next()) {
+        if !revents(fd).is_empty() {
+            let pending = drain_signal_pipe(signal_fd);
+            for watch in &event_loop.signals {
+                if pending & (1 << watch.signal as i32) != 0 {
+                    let name = watch.signal.as_str().to_lowercase();
+                    ready.push(Ready {
+                        id: watch.id,
+                        removed: false,
+                        callback: watch.callback.clone(),
+                        arg: Some(Expression::alloc_data(ExpEnum::Symbol(
+                            environment.interner.intern(&format!(":{}", name)),
+                            SymLoc::None,
+                        ))),
+                    });
+                }
+            }
+        }
+    }
+    let event_loop = &mut environment.event_loop;
+    for id in closed {
+        event_loop.cancel(id);
+    }
+    collect_due_timers(event_loop, &mut ready);
+    Ok(Some(ReadyEvents(ready)))
+}
+
+fn get_lambda(exp: Expression, fn_name: &str) -> Result<Expression, LispError> {
+    if let ExpEnum::Lambda(_) = &exp.get().data {
+        Ok(exp.clone())
+    } else {
+        Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Lambda",
+            &exp.display_type(),
+        )))
+    }
+}
+
+fn get_millis(exp: &Expression, fn_name: &str) -> Result<Duration, LispError> {
+    match &exp.get().data {
+        ExpEnum::Int(ms) if *ms >= 0 => Ok(Duration::from_millis(*ms as u64)),
+        _ => Err(LispError::new(format!(
+            "{}: milliseconds must be a non-negative integer",
+            fn_name
+        ))),
+    }
+}
+
+fn builtin_after(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "after";
+    let delay = get_millis(&param_eval(environment, args, fn_name)?, fn_name)?;
+    let callback = get_lambda(param_eval(environment, args, fn_name)?, fn_name)?;
+    params_done(args, fn_name)?;
+    let id = environment.event_loop.add_timer(delay, None, callback);
+    Ok(Expression::alloc_data(ExpEnum::Int(id)))
+}
+
+fn builtin_every(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "every";
+    let interval = get_millis(&param_eval(environment, args, fn_name)?, fn_name)?;
+    if interval.as_millis() == 0 {
+        return Err(LispError::new(
+            "every: interval must be at least 1 millisecond",
+        ));
+    }
+    let callback = get_lambda(param_eval(environment, args, fn_name)?, fn_name)?;
+    params_done(args, fn_name)?;
+    let id = environment
+        .event_loop
+        .add_timer(interval, Some(interval), callback);
+    Ok(Expression::alloc_data(ExpEnum::Int(id)))
+}
+
+// True if source has input buffered in the shell (so poll will not see it).
+fn source_buffered(source: &Expression) -> bool {
+    match &source.get().data {
+        ExpEnum::File(file) => match &*file.borrow() {
+            FileState::ReadBinary(reader) => !reader.buffer().is_empty(),
+            _ => false,
+        },
+        ExpEnum::Pty(pty) => pty.borrow().has_buffered(),
+        ExpEnum::FsWatcher(watcher) => watcher.borrow().has_pending(),
+        _ => false,
+    }
+}
+
+fn source_fd(
+    environment: &Environment,
+    source: &Expression,
+    fn_name: &str,
+) -> Result<RawFd, LispError> {
+    let fd = match &source.get().data {
+        ExpEnum::File(file) => match &*file.borrow() {
+            FileState::Stdin => Some(0),
+            FileState::Read(_, fd) => Some(*fd as RawFd),
+            FileState::ReadBinary(reader) => Some(reader.get_ref().as_raw_fd()),
+            _ => None,
+        },
+        ExpEnum::Process(ProcessState::Running(pid)) => match environment.procs.borrow().get(pid) {
+            Some((_, Some(fd))) => Some(*fd),
+            _ => None,
+        },
+        ExpEnum::Pty(pty) => pty.borrow().master_fd(),
+        ExpEnum::FsWatcher(watcher) => watcher.borrow().raw_fd(),
+        _ => None,
+    };
+    fd.ok_or_else(|| {
+        LispError::new(format!(
+            "{}: expected a readable file, a running process with captured output, a pty or a fs-watcher, got {}",
+            fn_name,
+            source.display_type()
+        ))
+    })
+}
+
+fn builtin_on_readable(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "on-readable";
+    let source = param_eval(environment, args, fn_name)?;
+    let callback = get_lambda(param_eval(environment, args, fn_name)?, fn_name)?;
+    params_done(args, fn_name)?;
+    let fd = source_fd(environment, &source, fn_name)?;
+    let id = environment.event_loop.add_readable(fd, source, callback);
+    Ok(Expression::alloc_data(ExpEnum::Int(id)))
+}
+
+fn builtin_on_signal(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "on-signal";
+    let signal = param_eval(environment, args, fn_name)?;
+    let callback = get_lambda(param_eval(environment, args, fn_name)?, fn_name)?;
+    params_done(args, fn_name)?;
+    let name = match &signal.get().data {
+        ExpEnum::Symbol(s, _) => s.trim_start_matches(':').to_uppercase(),
+        ExpEnum::String(s, _) => s.to_uppercase(),
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "Keyword or String",
+                &signal.display_type(),
+            )))
+        }
+    };
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    let signal = match Signal::from_str(&name) {
+        // SIGINT belongs to the shell (it interrupts the running form).
+        Ok(Signal::SIGINT) | Ok(Signal::SIGKILL) | Ok(Signal::SIGSTOP) => {
+            return Err(LispError::new(format!(
+                "{}: can not watch {}",
+                fn_name, name
+            )))
+        }
+        Ok(signal) => signal,
+        Err(_) => {
+            return Err(LispError::new(format!(
+                "{}: unknown signal {}",
+                fn_name, name
+            )))
+        }
+    };
+    let id = environment.event_loop.add_signal(signal, callback)?;
+    Ok(Expression::alloc_data(ExpEnum::Int(id)))
+}
+
+fn builtin_cancel(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "cancel";
+    let id = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let id = match &id.get().data {
+        ExpEnum::Int(id) => *id,
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "Int",
+                &id.display_type(),
+            )))
+        }
+    };
+    if environment.event_loop.cancel(id) {
+        Ok(Expression::make_true())
+    } else {
+        Ok(Expression::make_nil())
+    }
+}
+
+fn builtin_event_loop(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "event-loop";
+    let timeout = if let Some(ms) = param_eval_optional(environment, args)? {
+        Some(get_millis(&ms, fn_name)?)
+    } else {
+        None
+    };
+    params_done(args, fn_name)?;
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let old_stop = environment.event_loop.stop;
+    environment.event_loop.stop = false;
+    let mut result = Ok(Expression::make_nil());
+    while !environment.event_loop.stop && environment.event_loop.has_work() {
+        let remaining = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        if remaining == Some(Duration::from_millis(0)) {
+            break;
+        }
+        let ready = poll_events(environment, remaining, None)
+            .and_then(|ready| ready.map_or(Ok(()), |ready| ready.run(environment)));
+        if let Err(err) = ready {
+            result = Err(err);
+            break;
+        }
+    }
+    environment.event_loop.stop = old_stop;
+    result
+}
+
+fn builtin_event_loop_stop(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    params_done(args, "event-loop-stop")?;
+    environment.event_loop.stop = true;
+    Ok(Expression::make_nil())
+}
+
+pub fn add_event_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("after"),
+        Expression::make_function(
+            builtin_after,
+            r#"Usage: (after milliseconds lambda) -> timer-id
+
+Schedule lambda (called with no arguments) to run once after milliseconds have
+passed.  Timers only fire while event-loop is running or while prompt is waiting
+for input (before the first key of a line is typed).  Returns an id that can be passed to cancel.
+
+Section: system
+
+Example:
+(def after-test nil)
+(after 10 (fn () (set! after-test :fired)))
+(test::assert-false after-test)
+(event-loop)
+(test::assert-equal :fired after-test)
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("every"),
+        Expression::make_function(
+            builtin_every,
+            r#"Usage: (every milliseconds lambda) -> timer-id
+
+Schedule lambda (called with no arguments) to run repeatedly every milliseconds
+until cancelled.  Ticks missed because the loop was busy are not made up, the
+lambda runs once and the next tick is scheduled from then.  Returns an id that
+can be passed to cancel.
+
+Section: system
+
+Example:
+(def every-test 0)
+(def every-timer (every 5 (fn ()
+    (set! every-test (+ every-test 1))
+    (if (= every-test 3) (cancel every-timer)))))
+(event-loop)
+(test::assert-equal 3 every-test)
+(test::assert-false (cancel every-timer))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("on-readable"),
+        Expression::make_function(
+            builtin_on_readable,
+            r#"Usage: (on-readable source lambda) -> id
+
+Call lambda with source whenever source has input ready to read.  Source can be a
+file open for reading, a running process with captured output (for instance
+from a form ending in a pipe to nothing or run in the background with out>), a
+pty or a fs-watcher.  The lambda should consume the input or it will be called
+again immediately.  Once the other end hangs up and everything has been read the
+source is dropped automatically, otherwise use cancel with the returned id.
+Input already buffered by a binary file, pty or fs-watcher counts as ready, but
+a file opened for text reads ahead in a buffer that is not visible so read all
+the available lines in the lambda (a line left in that buffer will not trigger
+another call until more input arrives).
+
+Section: system
+
+Example:
+(def readable-test nil)
+(with-temp-file (fn (tmp)
+    (let ((out (open tmp :create :truncate)))
+        (write-line out "ready")
+        (close out))
+    (let ((in (open tmp :read)))
+        (def readable-id (on-readable in (fn (f)
+            (set! readable-test (read-line f))
+            (cancel readable-id))))
+        (event-loop 1000)
+        (close in))))
+(test::assert-equal "ready\n" readable-test)
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("on-signal"),
+        Expression::make_function(
+            builtin_on_signal,
+            r#"Usage: (on-signal signal lambda) -> id
+
+Call lambda with the signal (as a keyword, for instance :sigusr1) each time the
+shell receives signal.  Signal is a keyword or string and can be given with or
+without the sig prefix (:sigusr1, :usr1 and "SIGUSR1" are all the same).  The
+signal is handled until every watcher for it is cancelled, at which point its
+previous disposition is restored.  SIGINT, SIGKILL and SIGSTOP can not be
+watched.
+
+Section: system
+
+Example:
+(def signal-test nil)
+(def signal-id (on-signal :usr1 (fn (sig)
+    (set! signal-test sig)
+    (cancel signal-id))))
+(syscall 'sh "-c" "kill -USR1 \$PPID")
+(event-loop 1000)
+(test::assert-equal :sigusr1 signal-test)
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("cancel"),
+        Expression::make_function(
+            builtin_cancel,
+            r#"Usage: (cancel id) -> t/nil
+
+Cancel a timer, readable source or signal watcher using the id returned when it
+was registered.  Returns true if it was still active, nil otherwise.
+
+Section: system
+
+Example:
+(def cancel-test nil)
+(def cancel-id (after 5 (fn () (set! cancel-test t))))
+(test::assert-true (cancel cancel-id))
+(test::assert-false (cancel cancel-id))
+(event-loop 20)
+(test::assert-false cancel-test)
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("event-loop"),
+        Expression::make_function(
+            builtin_event_loop,
+            r#"Usage: (event-loop timeout?) -> nil
+
+Run timers, readable sources and signal watchers until nothing is left
+registered, event-loop-stop is called or timeout (in milliseconds) passes.
+Waiting is done with poll(2) so an idle loop uses no CPU.  If a callback raises
+an error it is cancelled and the error is raised from event-loop.  The REPL
+prompt also services the event loop while it waits for input, but only until the
+first key press, while a line is being edited nothing is serviced until it is
+entered.
+
+Section: system
+
+Example:
+(def event-loop-test (vec))
+(after 20 (fn () (vec-push! event-loop-test :second)))
+(after 10 (fn () (vec-push! event-loop-test :first)))
+(event-loop)
+(test::assert-equal '#(:first :second) event-loop-test)
+(def event-loop-timer (every 1000 (fn () nil)))
+(event-loop 10)
+(test::assert-true (cancel event-loop-timer))
+(after 1 (fn () (err "timer failed")))
+(test::assert-error (event-loop))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("event-loop-stop"),
+        Expression::make_function(
+            builtin_event_loop_stop,
+            r#"Usage: (event-loop-stop) -> nil
+
+Make the running event-loop return after the current callback.  Anything still
+registered stays registered and will run on the next event-loop.
+
+Section: system
+
+Example:
+(def stop-test 0)
+(def stop-timer (every 5 (fn ()
+    (set! stop-test (+ stop-test 1))
+    (event-loop-stop))))
+(event-loop)
+(test::assert-equal 1 stop-test)
+(test::assert-true (cancel stop-timer))
+"#,
+        ),
+    );
+}
@@ -6,7 +6,9 @@ use std::{env, fs, io};
 
 use glob::glob;
 
+use crate::builtins_digest::hash_path;
 use crate::builtins_util::*;
+use crate::digest::DIGEST_NAMES;
 use crate::environment::*;
 use crate::eval::*;
 use crate::interner::*;
@@ -367,16 +369,28 @@ fn is_same_file(path_0: String, path_1: String) -> LispResult<Expression> {
     }
 }
 
+fn invalid_crawl_option(fn_name: &str, opt: &Expression) -> LispError {
+    LispError::new(format!(
+        "{}: invalid option {}, expected a max depth, :follow-syms or one of :{}",
+        fn_name,
+        opt,
+        DIGEST_NAMES.join(" :")
+    ))
+}
+
 /// Usage: (fs-crawl /path/to/file/or/dir (fn (x) (println "found path" x) [max-depth]
-///              [:follow-syms])
+///              [:follow-syms] [hash-algorithm])
 ///
 /// If a directory is provided the path is recursively searched and every
 /// file and directory is called as an argument to the provided function.
 /// If a file is provided the path is provided as an argument to the provided
-/// function. Takes two optional arguments (in any order) an integer,
-/// representing max depth to traverse if file is a directory, or the
+/// function. Takes optional arguments (in any order) an integer,
+/// representing max depth to traverse if file is a directory, the
 /// symbol, :follow-syms, to follow symbol links when traversing if
-/// desired.
+/// desired, or a hash algorithm (:sha1, :sha256, :sha512, :blake3, :xxhash,
+/// :crc32 or :md5, see hash-file).  Any other argument is an error.  With a hash algorithm the function is called with
+/// the path and the hex digest of the file's contents (nil for directories and
+/// files that can not be read).
 ///
 ///
 /// Section: file
@@ -390,6 +404,17 @@ fn is_same_file(path_0: String, path_1: String) -> LispResult<Expression> {
 /// 		(set! cnt (+ 1 cnt))))
 /// 	(test::assert-equal 1 cnt)))
 ///
+/// (with-temp (fn (tmp-dir)
+/// 	(def tst-file (open (str tmp-dir "/a.txt") :create :truncate))
+/// 	(write-string tst-file "abc")
+/// 	(close tst-file)
+/// 	(def index (make-hash))
+/// 	(fs-crawl tmp-dir (fn (path hash) (when hash (hash-set! index path hash))) :sha256)
+/// 	(test::assert-equal 1 (length (hash-keys index)))
+/// 	(test::assert-equal (hash-bytes :sha256 "abc") (hash-get index (str tmp-dir "/a.txt")))
+/// 	(test::assert-error-msg (fs-crawl tmp-dir (fn (path hash) nil) :sha3)
+/// 		"fs-crawl: invalid option :sha3, expected a max depth, :follow-syms or one of :md5 :sha1 :sha256 :sha512 :blake3 :xxhash :crc32")))
+///
 /// (defn create-in (in-dir num-files visited)
 /// 	(dotimes-i i num-files
 /// 		 (hash-set! visited (get-temp-file in-dir) nil)))
@@ -466,13 +491,20 @@ fn fs_crawl(
     let file_or_dir = get_file(path);
     let mut depth = None;
     let mut sym_links = None;
+    let mut hash_algo = None;
     for depth_or_symlink in optional_depth_or_symlink {
         if let Ok(d) = depth_or_symlink.make_int(environment) {
             depth = Some(d);
         } else if let Ok(s) = depth_or_symlink.make_string(environment) {
             if s == ":follow-syms" {
                 sym_links = Some(true);
+            } else if DIGEST_NAMES.contains(&s.trim_start_matches(':')) {
+                hash_algo = Some(s.trim_start_matches(':').to_string());
+            } else {
+                return Err(invalid_crawl_option(fn_name, &depth_or_symlink));
             }
+        } else {
+            return Err(invalid_crawl_option(fn_name, &depth_or_symlink));
         }
     }
     let lambda_exp_d = &lambda_exp.get().data;
@@ -482,10 +514,26 @@ fn fs_crawl(
                 let mut cb = |entry: &DirEntry| -> LispResult<()> {
                     let path = entry.path();
                     if let Some(path) = path.to_str() {
-                        let path =
+                        let path_exp =
                             Expression::alloc_data(ExpEnum::String(path.to_string().into(), None));
-                        let mut args = iter::once(path);
-                        call_lambda(environment, lambda_exp.copy(), &mut args, true)?;
+                        if let Some(algo) = &hash_algo {
+                            let hash = if entry.file_type().is_file() {
+                                hash_path(algo, entry.path()).ok().flatten()
+                            } else {
+                                None
+                            };
+                            let hash = match hash {
+                                Some(hash) => {
+                                    Expression::alloc_data(ExpEnum::String(hash.into(), None))
+                                }
+                                None => Expression::make_nil(),
+                            };
+                            let mut args = vec![path_exp, hash].into_iter();
+                            call_lambda(environment, lambda_exp.copy(), &mut args, true)?;
+                        } else {
+                            let mut args = iter::once(path_exp);
+                            call_lambda(environment, lambda_exp.copy(), &mut args, true)?;
+                        }
                     }
                     Ok(())
                 };
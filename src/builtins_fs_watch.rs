@@ -0,0 +1,591 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor},
+};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+#[cfg(target_os = "linux")]
+use walkdir::WalkDir;
+
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::signals::test_clear_sigint;
+use crate::types::*;
+
+const FS_WATCH_DEFAULT_DEBOUNCE_MS: u64 = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsEventKind {
+    Created,
+    Deleted,
+    Modified,
+    MovedFrom,
+    MovedTo,
+    Attrib,
+}
+
+impl FsEventKind {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            FsEventKind::Created => ":created",
+            FsEventKind::Deleted => ":deleted",
+            FsEventKind::Modified => ":modified",
+            FsEventKind::MovedFrom => ":moved-from",
+            FsEventKind::MovedTo => ":moved-to",
+            FsEventKind::Attrib => ":attrib",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FsEvent {
+    pub path: PathBuf,
+    pub kind: FsEventKind,
+}
+
+// Watches a file or directory tree for changes.  Events are queued as they
+// are read from the kernel and handed out one at a time by next_event.
+pub struct FsWatcher {
+    #[cfg(target_os = "linux")]
+    inotify: Option<Inotify>,
+    #[cfg(target_os = "linux")]
+    watches: HashMap<WatchDescriptor, PathBuf>,
+    root: PathBuf,
+    recursive: bool,
+    debounce: Duration,
+    pending: VecDeque<FsEvent>,
+}
+
+impl FsWatcher {
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// True if events have been read but not yet handed out.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    // Drop an event that repeats the previous event for the same path (an
+    // editor saving a file will often produce several modifies for instance),
+    // other events for the path in between are kept so created, deleted,
+    // created is still reported in full.
+    fn coalesce(&mut self) {
+        let mut last_kind: HashMap<PathBuf, FsEventKind> = HashMap::new();
+        let mut coalesced: VecDeque<FsEvent> = VecDeque::with_capacity(self.pending.len());
+        for event in self.pending.drain(..) {
+            if last_kind.insert(event.path.clone(), event.kind) != Some(event.kind) {
+                coalesced.push_back(event);
+            }
+        }
+        self.pending = coalesced;
+    }
+
+    // Return the next event, waiting up to timeout for one (forever if None).
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<FsEvent>, LispError> {
+        if self.pending.is_empty() {
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+            while self.pending.is_empty() && !self.is_closed() {
+                let wait = match deadline {
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            break;
+                        }
+                        std::cmp::min(Duration::from_millis(100), deadline - now)
+                    }
+                    None => Duration::from_millis(100),
+                };
+                if test_clear_sigint() {
+                    return Err(LispError::new("fs-next-event: interrupted"));
+                }
+                self.read_events(wait)?;
+            }
+            if !self.pending.is_empty() && self.debounce > Duration::from_millis(0) {
+                // Give any related events a chance to arrive so they can be coalesced.
+                let deadline = Instant::now() + self.debounce;
+                let mut now = Instant::now();
+                while now < deadline {
+                    self.read_events(deadline - now)?;
+                    now = Instant::now();
+                }
+            }
+            self.coalesce();
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl FsWatcher {
+    /// True if watchers can be made (inotify can be initialized).
+    pub fn available() -> bool {
+        match Inotify::init(InitFlags::IN_CLOEXEC) {
+            Ok(inotify) => {
+                let _ = nix::unistd::close(inotify.as_raw_fd());
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn new(path: &Path, recursive: bool, debounce: Duration) -> Result<FsWatcher, LispError> {
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)
+            .map_err(|err| LispError::new(format!("fs-watch: inotify init failed: {}", err)))?;
+        let mut watcher = FsWatcher {
+            inotify: Some(inotify),
+            watches: HashMap::new(),
+            root: path.to_path_buf(),
+            recursive,
+            debounce,
+            pending: VecDeque::new(),
+        };
+        if let Err(err) = watcher.watch_path(path) {
+            watcher.close();
+            return Err(LispError::new(format!(
+                "fs-watch: unable to watch {}: {}",
+                path.display(),
+                err
+            )));
+        }
+        Ok(watcher)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inotify.is_none()
+    }
+
+    pub fn raw_fd(&self) -> Option<RawFd> {
+        self.inotify.as_ref().map(|inotify| inotify.as_raw_fd())
+    }
+
+    fn push_event(&mut self, path: PathBuf, kind: FsEventKind) {
+        self.pending.push_back(FsEvent { path, kind });
+    }
+
+    pub fn close(&mut self) {
+        if let Some(inotify) = self.inotify.take() {
+            if let Err(err) = nix::unistd::close(inotify.as_raw_fd()) {
+                eprintln!("Error closing fs watcher: {}", err);
+            }
+        }
+        self.watches.clear();
+    }
+
+    fn watch_mask() -> AddWatchFlags {
+        AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_DELETE_SELF
+            | AddWatchFlags::IN_MODIFY
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_ATTRIB
+            | AddWatchFlags::IN_DONT_FOLLOW
+    }
+
+    fn add_watch(&mut self, path: &Path) -> nix::Result<()> {
+        if let Some(inotify) = self.inotify {
+            let wd = inotify.add_watch(path, FsWatcher::watch_mask())?;
+            self.watches.insert(wd, path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    // Watch path and if recursive every directory under it.
+    fn watch_path(&mut self, path: &Path) -> nix::Result<()> {
+        self.add_watch(path)?;
+        if self.recursive && path.is_dir() {
+            for entry in WalkDir::new(path)
+                .min_depth(1)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_dir())
+            {
+                // The directory may already be gone, that is fine.
+                let _ = self.add_watch(entry.path());
+            }
+        }
+        Ok(())
+    }
+
+    // A new directory showed up, watch it and report anything that was created
+    // in it before the watch was in place.
+    fn watch_new_dir(&mut self, path: &Path) {
+        if self.watch_path(path).is_err() {
+            return;
+        }
+        for entry in WalkDir::new(path)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            // The new watch may have already reported it.
+            let event = FsEvent {
+                path: entry.path().to_path_buf(),
+                kind: FsEventKind::Created,
+            };
+            if !self.pending.contains(&event) {
+                self.pending.push_back(event);
+            }
+        }
+    }
+
+    // Stop watching path and everything under it (it was moved out from under us).
+    fn unwatch_tree(&mut self, path: &Path) {
+        let wds: Vec<WatchDescriptor> = self
+            .watches
+            .iter()
+            .filter(|(_, watch_path)| watch_path.starts_with(path))
+            .map(|(wd, _)| *wd)
+            .collect();
+        for wd in wds {
+            self.watches.remove(&wd);
+            if let Some(inotify) = self.inotify {
+                let _ = inotify.rm_watch(wd);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: InotifyEvent) {
+        let mask = event.mask;
+        if mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+            eprintln!("WARNING: fs watcher event queue overflowed, events were lost.");
+            return;
+        }
+        let dir = match self.watches.get(&event.wd) {
+            Some(dir) => dir.clone(),
+            None => return,
+        };
+        if mask.contains(AddWatchFlags::IN_IGNORED) {
+            self.watches.remove(&event.wd);
+            return;
+        }
+        let path = match &event.name {
+            Some(name) => dir.join(name),
+            None => dir,
+        };
+        let is_dir = mask.contains(AddWatchFlags::IN_ISDIR);
+        if mask.contains(AddWatchFlags::IN_CREATE) {
+            self.push_event(path.clone(), FsEventKind::Created);
+            if is_dir && self.recursive {
+                self.watch_new_dir(&path);
+            }
+        } else if mask.contains(AddWatchFlags::IN_DELETE) {
+            self.push_event(path, FsEventKind::Deleted);
+        } else if mask.contains(AddWatchFlags::IN_DELETE_SELF) {
+            // Everything but the root will also be reported by its parent.
+            if path == self.root {
+                self.push_event(path, FsEventKind::Deleted);
+            }
+        } else if mask.contains(AddWatchFlags::IN_MODIFY) {
+            self.push_event(path, FsEventKind::Modified);
+        } else if mask.contains(AddWatchFlags::IN_MOVED_FROM) {
+            if is_dir && self.recursive {
+                self.unwatch_tree(&path);
+            }
+            self.push_event(path, FsEventKind::MovedFrom);
+        } else if mask.contains(AddWatchFlags::IN_MOVED_TO) {
+            self.push_event(path.clone(), FsEventKind::MovedTo);
+            if is_dir && self.recursive {
+                let _ = self.watch_path(&path);
+            }
+        } else if mask.contains(AddWatchFlags::IN_ATTRIB) {
+            self.push_event(path, FsEventKind::Attrib);
+        }
+    }
+
+    // Wait up to timeout for events and queue anything read.
+    fn read_events(&mut self, timeout: Duration) -> Result<(), LispError> {
+        let inotify = match self.inotify {
+            Some(inotify) => inotify,
+            None => return Ok(()),
+        };
+        let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout.as_millis() as i32) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(nix::errno::Errno::EINTR) => return Ok(()),
+            Err(err) => return Err(LispError::new(format!("fs watcher: poll failed: {}", err))),
+        }
+        loop {
+            match inotify.read_events() {
+                Ok(events) => {
+                    for event in events {
+                        self.handle_event(event);
+                    }
+                }
+                Err(nix::errno::Errno::EAGAIN) => return Ok(()),
+                Err(nix::errno::Errno::EINTR) => return Ok(()),
+                Err(err) => {
+                    return Err(LispError::new(format!("fs watcher: read failed: {}", err)))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl FsWatcher {
+    pub fn available() -> bool {
+        false
+    }
+
+    pub fn new(
+        _path: &Path,
+        _recursive: bool,
+        _debounce: Duration,
+    ) -> Result<FsWatcher, LispError> {
+        Err(LispError::new(
+            "fs-watch: file system watching is not supported on this platform",
+        ))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        true
+    }
+
+    pub fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    pub fn close(&mut self) {}
+
+    fn read_events(&mut self, _timeout: Duration) -> Result<(), LispError> {
+        Ok(())
+    }
+}
+
+impl Drop for FsWatcher {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+fn get_watcher(exp: &Expression, fn_name: &str) -> Result<Rc<RefCell<FsWatcher>>, LispError> {
+    match &exp.get().data {
+        ExpEnum::FsWatcher(watcher) => Ok(watcher.clone()),
+        _ => Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "FsWatcher",
+            &exp.display_type(),
+        ))),
+    }
+}
+
+fn builtin_fs_watch(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "fs-watch";
+    let path = param_eval(environment, args, fn_name)?.as_string(environment)?;
+    let path = match expand_tilde(&path) {
+        Some(path) => path,
+        None => path,
+    };
+    let mut recursive = true;
+    let mut debounce = Duration::from_millis(FS_WATCH_DEFAULT_DEBOUNCE_MS);
+    let opts = make_args(environment, args)?;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match &opt.get().data {
+            ExpEnum::Symbol(":recursive", _) => recursive = true,
+            ExpEnum::Symbol(":flat", _) => recursive = false,
+            ExpEnum::Symbol(":debounce", _) => match opts.next().map(|ms| ms.get().data.clone()) {
+                Some(ExpEnum::Int(ms)) if ms >= 0 => debounce = Duration::from_millis(ms as u64),
+                _ => {
+                    return Err(LispError::new(format!(
+                        "{}: :debounce requires a non-negative integer (milliseconds)",
+                        fn_name
+                    )))
+                }
+            },
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: invalid option {}, expected :recursive, :flat or :debounce",
+                    fn_name, opt
+                )))
+            }
+        }
+    }
+    let path = Path::new(&path);
+    if !path.exists() {
+        return Err(LispError::new(format!(
+            "{}: {} does not exist",
+            fn_name,
+            path.display()
+        )));
+    }
+    let watcher = FsWatcher::new(path, recursive, debounce)?;
+    Ok(Expression::alloc_data(ExpEnum::FsWatcher(Rc::new(
+        RefCell::new(watcher),
+    ))))
+}
+
+fn builtin_fs_next_event(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "fs-next-event";
+    let watcher = get_watcher(&param_eval(environment, args, fn_name)?, fn_name)?;
+    let timeout = match param_eval_optional(environment, args)? {
+        Some(timeout) => match &timeout.get().data {
+            ExpEnum::Int(ms) if *ms >= 0 => Some(Duration::from_millis(*ms as u64)),
+            ExpEnum::Nil => None,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: timeout must be a non-negative integer (milliseconds)",
+                    fn_name
+                )))
+            }
+        },
+        None => None,
+    };
+    params_done(args, fn_name)?;
+    let event = watcher.borrow_mut().next_event(timeout)?;
+    match event {
+        Some(event) => {
+            let path = event.path.to_string_lossy().to_string();
+            Ok(Expression::alloc_data(ExpEnum::Vector(vec![
+                Expression::alloc_data(ExpEnum::String(path.into(), None)),
+                Expression::alloc_data(ExpEnum::Symbol(
+                    environment.interner.intern(event.kind.keyword()),
+                    SymLoc::None,
+                )),
+            ])))
+        }
+        None => Ok(Expression::make_nil()),
+    }
+}
+
+fn builtin_fs_watch_close(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "fs-watch-close";
+    let watcher = get_watcher(&param_eval(environment, args, fn_name)?, fn_name)?;
+    params_done(args, fn_name)?;
+    watcher.borrow_mut().close();
+    Ok(Expression::make_nil())
+}
+
+fn builtin_fs_watch_available(
+    _environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    params_done(args, "fs-watch-available?")?;
+    if FsWatcher::available() {
+        Ok(Expression::make_true())
+    } else {
+        Ok(Expression::make_false())
+    }
+}
+
+pub fn add_fs_watch_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("fs-watch"),
+        Expression::make_function(
+            builtin_fs_watch,
+            r#"Usage: (fs-watch path [:recursive | :flat] [:debounce milliseconds]) -> watcher
+
+Start watching a file or directory for changes (uses inotify so only available on
+Linux).  Directories are watched recursively (including directories created later)
+unless :flat is given.  Repeats of an event for a file that arrive within the
+debounce window (default 50ms) are reported once.  Use fs-next-event to retrieve events and
+fs-watch-close when done.
+
+Section: file
+
+Example:
+(with-temp (fn (tmp-dir)
+    (def watch-test (fs-watch tmp-dir :debounce 0))
+    (test::assert-true (fs-watcher? watch-test))
+    (test::assert-false (fs-next-event watch-test 10))
+    (fs-watch-close watch-test)))
+(test::assert-error (fs-watch "/does/not/exist/123"))
+(test::assert-error (fs-watch "/tmp" :bad-option))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("fs-next-event"),
+        Expression::make_function(
+            builtin_fs_next_event,
+            r#"Usage: (fs-next-event watcher timeout?) -> #(path event) or nil
+
+Wait for the next change from a watcher created with fs-watch.  Returns a vector
+of the path that changed and the kind of change, one of :created, :deleted,
+:modified, :moved-from, :moved-to or :attrib.  Blocks until an event arrives unless
+a timeout in milliseconds is provided, in which case nil is returned if nothing
+happened before it expired.
+
+Section: file
+
+Example:
+(with-temp (fn (tmp-dir)
+    (def watch-test (fs-watch tmp-dir))
+    (def watch-file (str tmp-dir "/watched"))
+    (syscall 'mkdir (str tmp-dir "/sub"))
+    (test::assert-equal (vec (str tmp-dir "/sub") :created) (fs-next-event watch-test 1000))
+    (let ((f (open watch-file :create :truncate)))
+        (write-string f "boop")
+        (close f))
+    (test::assert-equal (vec watch-file :created) (fs-next-event watch-test 1000))
+    (test::assert-equal (vec watch-file :modified) (fs-next-event watch-test 1000))
+    (test::assert-false (fs-next-event watch-test 10))
+    (let ((f (open (str tmp-dir "/sub/nested") :create)))
+        (close f))
+    (test::assert-equal (vec (str tmp-dir "/sub/nested") :created) (fs-next-event watch-test 1000))
+    (fs-rm watch-file)
+    (test::assert-equal (vec watch-file :deleted) (fs-next-event watch-test 1000))
+    (fs-watch-close watch-test)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("fs-watch-available?"),
+        Expression::make_function(
+            builtin_fs_watch_available,
+            r#"Usage: (fs-watch-available?) -> t/f
+
+True if fs-watch can be used on this system (inotify is available).
+
+Section: file
+
+Example:
+(test::assert-true (or (fs-watch-available?) (not (fs-watch-available?))))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("fs-watch-close"),
+        Expression::make_function(
+            builtin_fs_watch_close,
+            r#"Usage: (fs-watch-close watcher) -> nil
+
+Stop watching and release the resources held by a watcher.  Any further calls to
+fs-next-event will return nil.
+
+Section: file
+
+Example:
+(with-temp (fn (tmp-dir)
+    (def watch-test (fs-watch tmp-dir))
+    (fs-watch-close watch-test)
+    (test::assert-false (fs-next-event watch-test))))
+"#,
+        ),
+    );
+}
@@ -6,14 +6,109 @@ use std::hash::BuildHasher;
 use crate::environment::*;
 use crate::eval::*;
 use crate::interner::*;
+use crate::pretty_print::readable_string;
+use crate::reader::read;
 use crate::types::*;
 use crate::{try_inner_hash_map_mut, LispResult};
 
-#[allow(clippy::ptr_arg)]
-pub(crate) fn cow_to_ref(environment: &mut Environment, input: &Cow<'static, str>) -> &'static str {
-    match input {
-        Cow::Borrowed(s) => s,
-        Cow::Owned(s) => environment.interner.intern(s),
+/// The data of a hash map.  String, symbol and char keys are keyed by their
+/// text (so "a", 'a and #\a are the same key), any other value by a mark
+/// followed by its readable form.
+pub type LispMap = HashMap<Cow<'static, str>, Expression>;
+
+// Starts the key of a value that is not a string, symbol or char.
+const VALUE_KEY_MARK: char = '\u{1}';
+// Starts the key of a text that itself starts with a mark.
+const TEXT_KEY_MARK: char = '\u{2}';
+
+fn is_key_mark(ch: char) -> bool {
+    ch == VALUE_KEY_MARK || ch == TEXT_KEY_MARK
+}
+
+/// The hash map key for the text of a string, symbol or char.
+pub fn text_key(text: Cow<'static, str>) -> Cow<'static, str> {
+    if text.starts_with(is_key_mark) {
+        Cow::Owned(format!("{}{}", TEXT_KEY_MARK, text))
+    } else {
+        text
+    }
+}
+
+/// Get the value for the text key text, this does not allocate unless text
+/// starts with a mark (the reader does this for every char it reads).
+pub fn get_text_key<'a>(map: &'a LispMap, text: &str) -> Option<&'a Expression> {
+    if text.starts_with(is_key_mark) {
+        map.get(&format!("{}{}", TEXT_KEY_MARK, text)[..])
+    } else {
+        map.get(text)
+    }
+}
+
+/// The hash map key for the Int i.
+pub fn int_key(i: i64) -> Cow<'static, str> {
+    Cow::Owned(format!("{}{}", VALUE_KEY_MARK, i))
+}
+
+/// The key for a hash map key.  Strings, symbols and chars are keyed by their
+/// text, other values (numbers, lists, vectors, hash maps, etc) by their
+/// readable form so keys that are equal? find the same entry.
+pub fn hash_key(environment: &Environment, key: &Expression) -> LispResult<Cow<'static, str>> {
+    match &key.get().data {
+        ExpEnum::Symbol(sym, _) => Ok(text_key(Cow::Borrowed(sym))),
+        ExpEnum::String(s, _) => Ok(text_key(s.clone())),
+        ExpEnum::Char(ch) => Ok(text_key(ch.clone())),
+        ExpEnum::Int(i) => Ok(int_key(*i)),
+        _ => {
+            let mut text = readable_string(key, environment)?;
+            text.insert(0, VALUE_KEY_MARK);
+            Ok(Cow::Owned(text))
+        }
+    }
+}
+
+/// The text of a key (without its mark), value keys are their readable form.
+pub fn display_key(key: &str) -> &str {
+    if key.starts_with(is_key_mark) {
+        &key[1..]
+    } else {
+        key
+    }
+}
+
+/// A hash map key split into the type of the key and its text.
+pub enum KeyKind<'a> {
+    // A string, symbol or char.
+    Text(&'a str),
+    // The readable form of any other value.
+    Value(&'a str),
+}
+
+pub fn key_kind(key: &str) -> KeyKind<'_> {
+    let text = display_key(key);
+    if key.starts_with(VALUE_KEY_MARK) {
+        KeyKind::Value(text)
+    } else {
+        KeyKind::Text(text)
+    }
+}
+
+/// The key of a hash map entry as an expression.  Text keys are symbols and
+/// value keys are read back in safe mode (no reader macros) to their value.
+pub fn key_expression(environment: &mut Environment, key: &str) -> LispResult<Expression> {
+    match key_kind(key) {
+        KeyKind::Value(text) => {
+            let old_safe = environment.reader_state.safe;
+            environment.reader_state.safe = true;
+            let res = read(environment, text, None, false);
+            environment.reader_state.safe = old_safe;
+            res.map_err(|err| {
+                LispError::new(format!("invalid hash map key {}: {}", text, err.reason))
+            })
+        }
+        KeyKind::Text(sym) => Ok(Expression::alloc_data(ExpEnum::Symbol(
+            environment.interner.intern(sym),
+            SymLoc::None,
+        ))),
     }
 }
 
@@ -53,20 +148,11 @@ pub(crate) fn cow_to_ref(environment: &mut Environment, input: &Cow<'static, str
 /// (test::assert-equal 'tst-hash-val (hash-get tst-hash :keyv3))
 #[sl_sh_fn(fn_name = "make-hash", takes_env = true)]
 fn make_hash(environment: &mut Environment, assocs: Option<Expression>) -> LispResult<Expression> {
-    let mut map: HashMap<&'static str, Expression> = HashMap::new();
+    let mut map = LispMap::new();
     if let Some(assocs) = assocs {
         for key_val in assocs.iter() {
             if let ExpEnum::Pair(key, val) = &key_val.get().data {
-                match &key.get().data {
-                    ExpEnum::Symbol(sym, _) => map.insert(sym, val.clone()),
-                    ExpEnum::String(s, _) => map.insert(cow_to_ref(environment, s), val.clone()),
-                    ExpEnum::Char(ch) => map.insert(cow_to_ref(environment, ch), val.clone()),
-                    _ => {
-                        return Err(LispError::new(
-                            "make-hash key can only be a symbol or string",
-                        ))
-                    }
-                };
+                map.insert(hash_key(environment, key)?, val.clone());
             } else {
                 return Err(LispError::new(
                     "make-hash each association must be a pair (key . val)",
@@ -81,6 +167,10 @@ fn make_hash(environment: &mut Environment, assocs: Option<Expression>) -> LispR
 ///
 /// Add or update a hashmap key's value.  This is a destructive form!
 ///
+/// Keys are usually strings, symbols or chars (the string \"a\", the symbol a
+/// and the char #\\a are the same key) but any readable value (numbers, lists,
+/// vectors, hash maps, etc) can be a key, keys that are equal? are the same key.
+///
 /// Section: hashmap
 ///
 /// Example:
@@ -108,17 +198,32 @@ fn make_hash(environment: &mut Environment, assocs: Option<Expression>) -> LispR
 /// (test::assert-equal \"val two b\" (hash-get tst-hash 'key2))
 /// (test::assert-equal \"val three b\" (hash-get tst-hash \"key3\"))
 /// (test::assert-equal '(1 2 3) (hash-get tst-hash :new-key))
+/// (hash-set! tst-hash '(1 #(2)) \"list key\")
+/// (hash-set! tst-hash 10 \"int key\")
+/// (test::assert-equal \"list key\" (hash-get tst-hash (list 1 (vec 2))))
+/// (test::assert-equal \"int key\" (hash-get tst-hash 10))
+/// (test::assert-false (hash-get tst-hash 10.0))
+/// (test::assert-true (hash-haskey tst-hash '(1 #(2))))
+/// (test::assert-true (in? (hash-keys tst-hash) 10))
+/// (hash-remove! tst-hash 10)
+/// (test::assert-false (hash-haskey tst-hash 10))
+/// (hash-set! tst-hash \"a\" 1)
+/// (hash-set! tst-hash 'a 2)
+/// (test::assert-equal 2 (hash-get tst-hash #\\a))
+/// (hash-set! tst-hash \"\\x01a\" 3)
+/// (hash-set! tst-hash '(a) 4)
+/// (test::assert-equal '(2 3 4) (list (hash-get tst-hash \"a\") (hash-get tst-hash \"\\x01a\") (hash-get tst-hash '(a))))
 #[sl_sh_fn(fn_name = "hash-set!", takes_env = true)]
 fn hash_set(
     environment: &mut Environment,
     map: Expression,
-    key: &str,
+    key: Expression,
     val: Expression,
 ) -> LispResult<Expression> {
-    let s = environment.interner.intern(key);
+    let key = hash_key(environment, &key)?;
     let fn_name = "hash-set!";
     try_inner_hash_map_mut!(fn_name, map, map, {
-        map.insert(s, val);
+        map.insert(key, val);
     });
     Ok(map)
 }
@@ -150,9 +255,13 @@ fn hash_set(
 /// (test::assert-equal \"val S\" (hash-get tst-hash #\\S))
 /// (hash-remove! tst-hash #\\S)
 /// (test::assert-equal 0 (length (hash-keys tst-hash)))
-#[sl_sh_fn(fn_name = "hash-remove!")]
-fn hash_remove(map: &mut HashMap<&str, Expression>, to_val: &str) -> LispResult<Expression> {
-    let old = map.remove(to_val);
+#[sl_sh_fn(fn_name = "hash-remove!", takes_env = true)]
+fn hash_remove(
+    environment: &mut Environment,
+    map: &mut LispMap,
+    key: Expression,
+) -> LispResult<Expression> {
+    let old = map.remove(&hash_key(environment, &key)?[..]);
     if let Some(old) = old {
         Ok(old)
     } else {
@@ -166,7 +275,7 @@ fn builtin_hash_get(
 ) -> Result<Expression, LispError> {
     fn do_get(
         environment: &mut Environment,
-        map: &HashMap<&'static str, Expression>,
+        map: &LispMap,
         sym: &str,
         default: Option<Expression>,
     ) -> Result<Expression, LispError> {
@@ -187,14 +296,8 @@ fn builtin_hash_get(
                 let key = eval(environment, key)?;
                 let map_d = map.get();
                 if let ExpEnum::HashMap(map) = &map_d.data {
-                    return match &key.get().data {
-                        ExpEnum::Symbol(sym, _) => do_get(environment, map, sym, default),
-                        ExpEnum::String(s, _) => do_get(environment, map, s, default),
-                        ExpEnum::Char(ch) => do_get(environment, map, ch, default),
-                        _ => Err(LispError::new(
-                            "hash-get: key can only be a symbol or string",
-                        )),
-                    };
+                    let key = hash_key(environment, &key)?;
+                    return do_get(environment, map, &key, default);
                 }
             }
         }
@@ -224,9 +327,13 @@ fn builtin_hash_get(
 /// (test::assert-false (hash-haskey tst-hash :key1))
 /// (hash-set! tst-hash :key1 \"val one b\")
 /// (test::assert-true (hash-haskey tst-hash :key1))
-#[sl_sh_fn(fn_name = "hash-haskey")]
-fn hash_haskey(map: &mut HashMap<&str, Expression>, to_val: &str) -> LispResult<Expression> {
-    if map.contains_key(to_val) {
+#[sl_sh_fn(fn_name = "hash-haskey", takes_env = true)]
+fn hash_haskey(
+    environment: &mut Environment,
+    map: &mut LispMap,
+    key: Expression,
+) -> LispResult<Expression> {
+    if map.contains_key(&hash_key(environment, &key)?[..]) {
         Ok(Expression::make_true())
     } else {
         Ok(Expression::make_false())
@@ -237,6 +344,9 @@ fn hash_haskey(map: &mut HashMap<&str, Expression>, to_val: &str) -> LispResult<
 ///
 /// Returns a vector of all the hashmaps keys.  The keys will be unordered.
 ///
+/// String, symbol and char keys are returned as symbols, other keys as the
+/// value that was set.
+///
 /// Section: hashmap
 ///
 /// Example:
@@ -249,16 +359,10 @@ fn hash_haskey(map: &mut HashMap<&str, Expression>, to_val: &str) -> LispResult<
 /// (test::assert-true (in? (hash-keys tst-hash) 'key3) \" Test key3\")
 /// (test::assert-false (in? (hash-keys tst-hash) :key4))
 #[sl_sh_fn(fn_name = "hash-keys", takes_env = true)]
-fn hash_keys(
-    environment: &mut Environment,
-    map: &mut HashMap<&str, Expression>,
-) -> LispResult<Expression> {
+fn hash_keys(environment: &mut Environment, map: &mut LispMap) -> LispResult<Expression> {
     let mut key_list = Vec::with_capacity(map.len());
     for key in map.keys() {
-        key_list.push(Expression::alloc_data(ExpEnum::Symbol(
-            environment.interner.intern(key),
-            SymLoc::None,
-        )));
+        key_list.push(key_expression(environment, key)?);
     }
     Ok(Expression::with_list(key_list))
 }
@@ -283,7 +387,7 @@ fn hash_keys(
 /// (test::assert-false (hash-haskey tst-hash \"key3\"))
 /// (test::assert-false (hash-haskey tst-hash #\\S))
 #[sl_sh_fn(fn_name = "hash-clear!")]
-fn hash_clear(map: &mut HashMap<&str, Expression>) {
+fn hash_clear(map: &mut LispMap) {
     map.clear();
 }
 
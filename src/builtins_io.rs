@@ -14,10 +14,15 @@ extern crate unicode_reader;
 use unicode_reader::Graphemes;
 
 use crate::builtins_edit::read_prompt;
-use crate::builtins_util::expand_tilde;
+use crate::builtins_hashmap::{display_key, hash_key, text_key, LispMap};
+use crate::builtins_iterator::{make_iter_exp, SlIter};
+use crate::builtins_util::{
+    expand_tilde, make_args, param_eval, param_eval_optional, params_done, ErrorStrings,
+};
 use crate::environment::*;
 use crate::eval::*;
 use crate::interner::*;
+use crate::pretty_print::readable_string;
 use crate::reader::*;
 use crate::types::*;
 use crate::{get_file, LispResult};
@@ -60,6 +65,7 @@ fn builtin_open(
         let mut is_read = false;
         let mut is_write = false;
         let mut error_nil = false;
+        let mut is_binary = false;
         for a in args {
             let a = eval(environment, a)?;
             let a_d = a.get();
@@ -95,6 +101,9 @@ fn builtin_open(
                     ":on-error-nil" => {
                         error_nil = true;
                     }
+                    ":binary" => {
+                        is_binary = true;
+                    }
                     _ => {
                         let msg = format!("open: invalid directive, {}", sym);
                         return Err(LispError::new(msg));
@@ -126,7 +135,11 @@ fn builtin_open(
                 }
             }
         };
-        return if !is_write {
+        return if !is_write && is_binary {
+            Ok(Expression::alloc_data(ExpEnum::File(Rc::new(
+                RefCell::new(FileState::ReadBinary(BufReader::new(file))),
+            ))))
+        } else if !is_write {
             let fd: i64 = file.as_raw_fd() as i64;
             let file_iter: CharIter = Box::new(
                 Graphemes::from(BufReader::new(file))
@@ -256,15 +269,23 @@ fn builtin_read_line(
     Err(LispError::new("read-line takes one form (file)"))
 }
 
-fn builtin_read(
+fn read_next(
     environment: &mut Environment,
-    args: &mut dyn Iterator<Item = Expression>,
+    exp: Option<Expression>,
+    err_exp: Option<Expression>,
+    fn_name: &str,
 ) -> Result<Expression, LispError> {
     fn read_stdin(
         environment: &mut Environment,
         err_exp: Option<Expression>,
+        fn_name: &str,
     ) -> Result<Expression, LispError> {
-        let input = read_prompt(environment, "read> ", Some("read_history"), ":new")?;
+        let input = read_prompt(
+            environment,
+            &format!("{}> ", fn_name),
+            Some("read_history"),
+            ":new",
+        )?;
         let input = unsafe { &*(input.as_ref() as *const str) };
         let chars = Box::new(
             UnicodeSegmentation::graphemes(input, true)
@@ -282,87 +303,115 @@ fn builtin_read(
             }
         }
     }
-    let mut err_exp = None;
-    if let Some(exp) = args.next() {
-        err_exp = if let Some(exp) = args.next() {
-            Some(eval(environment, exp)?)
-        } else {
-            None
-        };
-        if args.next().is_none() {
-            let exp = eval(environment, exp)?;
-            let mut exp_d = exp.get_mut();
-            return match &mut exp_d.data {
-                ExpEnum::File(file) => match &mut *file.borrow_mut() {
-                    FileState::Read(file_iter, _) => {
-                        let iiter = file_iter.take().unwrap();
-                        match read_form_state(environment, iiter, true) {
-                            Ok((ast, i_iter)) => {
-                                file_iter.replace(i_iter);
-                                Ok(ast)
-                            }
-                            Err((err, i_iter)) => {
-                                file_iter.replace(i_iter);
-                                return if let Some(err_exp) = err_exp {
-                                    Ok(err_exp)
-                                } else {
-                                    Err(LispError::new(err.reason))
-                                };
-                            }
+    let exp = if let Some(exp) = exp {
+        exp
+    } else {
+        // No args, ask for input
+        return read_stdin(environment, err_exp, fn_name);
+    };
+    let mut exp_d = exp.get_mut();
+    match &mut exp_d.data {
+        ExpEnum::File(file) => match &mut *file.borrow_mut() {
+            FileState::Read(file_iter, _) => {
+                let iiter = file_iter.take().unwrap();
+                match read_form_state(environment, iiter, true) {
+                    Ok((ast, i_iter)) => {
+                        file_iter.replace(i_iter);
+                        Ok(ast)
+                    }
+                    Err((err, i_iter)) => {
+                        file_iter.replace(i_iter);
+                        if let Some(err_exp) = err_exp {
+                            Ok(err_exp)
+                        } else {
+                            Err(LispError::new(err.reason))
                         }
                     }
-                    FileState::Stdin => read_stdin(environment, err_exp),
-                    _ => Err(LispError::new(
-                        "read: requires a character file opened for reading or string",
-                    )),
-                },
-                ExpEnum::String(input, char_iter) => {
-                    if char_iter.is_none() {
-                        // This unsafe should be fine as long as the iterator is invalidated (set to None)
-                        // on ANY change to string.  See builtin_str_iter_start.
-                        let nstr = unsafe { &*(input.as_ref() as *const str) };
-                        *char_iter = Some(Box::new(
-                            UnicodeSegmentation::graphemes(nstr, true)
-                                .map(Cow::Borrowed)
-                                .peekable(),
-                        ));
+                }
+            }
+            FileState::Stdin => read_stdin(environment, err_exp, fn_name),
+            _ => Err(LispError::new(format!(
+                "{}: requires a character file opened for reading or string",
+                fn_name
+            ))),
+        },
+        ExpEnum::String(input, char_iter) => {
+            if char_iter.is_none() {
+                // This unsafe should be fine as long as the iterator is invalidated (set to None)
+                // on ANY change to string.  See builtin_str_iter_start.
+                let nstr = unsafe { &*(input.as_ref() as *const str) };
+                *char_iter = Some(Box::new(
+                    UnicodeSegmentation::graphemes(nstr, true)
+                        .map(Cow::Borrowed)
+                        .peekable(),
+                ));
+            }
+            if char_iter.is_some() {
+                let chars = char_iter.take().unwrap();
+                match read_form_state(environment, chars, true) {
+                    Ok((ast, ichars)) => {
+                        char_iter.replace(ichars);
+                        Ok(ast)
                     }
-                    if char_iter.is_some() {
-                        let chars = char_iter.take().unwrap();
-                        match read_form_state(environment, chars, true) {
-                            Ok((ast, ichars)) => {
-                                char_iter.replace(ichars);
-                                Ok(ast)
-                            }
-                            Err((err, _)) => {
-                                return if let Some(err_exp) = err_exp {
-                                    Ok(err_exp)
-                                } else {
-                                    Err(LispError::new(err.reason))
-                                };
-                            }
+                    Err((err, _)) => {
+                        if let Some(err_exp) = err_exp {
+                            Ok(err_exp)
+                        } else {
+                            Err(LispError::new(err.reason))
                         }
-                    } else {
-                        panic!("read: WTF, no char iter but just made one!");
                     }
                 }
-                _ => Err(LispError::new(
-                    "read: requires a character file opened for reading or string",
-                )),
-            };
-        } else {
-            return Err(LispError::new(
-                "read: requires 0-2 parameters ([file|string]? end-exp?)",
-            ));
+            } else {
+                panic!("read: WTF, no char iter but just made one!");
+            }
         }
+        _ => Err(LispError::new(format!(
+            "{}: requires a character file opened for reading or string",
+            fn_name
+        ))),
     }
-    // No args, ask for input
-    read_stdin(environment, err_exp)
 }
 
-fn builtin_read_all(
+fn read_args(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<(Option<Expression>, Option<Expression>), LispError> {
+    let exp = param_eval_optional(environment, args)?;
+    let err_exp = param_eval_optional(environment, args)?;
+    if args.next().is_some() {
+        return Err(LispError::new(format!(
+            "{}: requires 0-2 parameters ([file|string]? end-exp?)",
+            fn_name
+        )));
+    }
+    Ok((exp, err_exp))
+}
+
+fn builtin_read(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let (exp, err_exp) = read_args(environment, args, "read")?;
+    read_next(environment, exp, err_exp, "read")
+}
+
+fn builtin_read_data(
     environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let (exp, err_exp) = read_args(environment, args, "read-data")?;
+    let old_safe = environment.reader_state.safe;
+    environment.reader_state.safe = true;
+    let res = read_next(environment, exp, err_exp, "read-data");
+    environment.reader_state.safe = old_safe;
+    res
+}
+
+fn read_all(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
 ) -> Result<Expression, LispError> {
     fn do_read(
         environment: &mut Environment,
@@ -397,7 +446,10 @@ fn builtin_read_all(
                             let input: String = file_iter.collect();
                             do_read(environment, &input, empty_exp)
                         } else {
-                            Err(LispError::new("read-all: invalid read character iterator!"))
+                            Err(LispError::new(format!(
+                                "{}: invalid read character iterator!",
+                                fn_name
+                            )))
                         }
                     }
                     FileState::ReadBinary(file) => {
@@ -410,19 +462,22 @@ fn builtin_read_all(
                             read_prompt(environment, "read-all> ", Some("read_history"), ":new")?;
                         do_read(environment, &input, empty_exp)
                     }
-                    _ => Err(LispError::new(
-                        "read-all: requires a file opened for reading or string",
-                    )),
+                    _ => Err(LispError::new(format!(
+                        "{}: requires a file opened for reading or string",
+                        fn_name
+                    ))),
                 },
                 ExpEnum::String(input, _char_iter) => do_read(environment, input, empty_exp),
-                _ => Err(LispError::new(
-                    "read-all: requires a file opened for reading or string",
-                )),
+                _ => Err(LispError::new(format!(
+                    "{}: requires a file opened for reading or string",
+                    fn_name
+                ))),
             };
         } else {
-            return Err(LispError::new(
-                "read-all: requires 0-2 parameters ([file|string]? end-exp?).",
-            ));
+            return Err(LispError::new(format!(
+                "{}: requires 0-2 parameters ([file|string]? end-exp?).",
+                fn_name
+            )));
         }
     }
     // No args, ask for input
@@ -430,6 +485,24 @@ fn builtin_read_all(
     do_read(environment, &input, empty_exp)
 }
 
+fn builtin_read_all(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    read_all(environment, args, "read-all")
+}
+
+fn builtin_read_all_data(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let old_safe = environment.reader_state.safe;
+    environment.reader_state.safe = true;
+    let res = read_all(environment, args, "read-all-data");
+    environment.reader_state.safe = old_safe;
+    res
+}
+
 fn builtin_write_line(
     environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
@@ -504,6 +577,37 @@ fn builtin_write_string(
     ))
 }
 
+fn builtin_write_readable(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "write-readable";
+    let file = param_eval(environment, args, fn_name)?;
+    let value = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let text = readable_string(&value, environment)?;
+    let file_d = file.get();
+    if let ExpEnum::File(file) = &file_d.data {
+        match &mut *file.borrow_mut() {
+            FileState::Write(f) => f.write_all(text.as_bytes())?,
+            FileState::Stdout => io::stdout().write_all(text.as_bytes())?,
+            FileState::Stderr => io::stderr().write_all(text.as_bytes())?,
+            _ => {
+                return Err(LispError::new(
+                    "write-readable: requires a file opened for writing",
+                ))
+            }
+        }
+        Ok(Expression::make_nil())
+    } else {
+        Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "File",
+            &file.display_type(),
+        )))
+    }
+}
+
 /// Usage: (fs-rm \"/dir/or/file/to/remove\")
 ///
 /// Takes a file or directory as a string and removes it. Works recursively for directories.
@@ -542,6 +646,490 @@ fn fs_rm(path: String) -> LispResult<Expression> {
     }
 }
 
+// Read the next line from a file, None at end of file.
+pub fn file_read_line(file: &mut FileState, fn_name: &str) -> Result<Option<String>, LispError> {
+    let mut line = String::new();
+    match file {
+        FileState::Read(Some(f_iter), _) => {
+            for ch in f_iter {
+                line.push_str(&ch);
+                // "\r\n" is a single grapheme.
+                if ch == "\n" || ch == "\r\n" {
+                    break;
+                }
+            }
+        }
+        FileState::Read(None, _) => {}
+        FileState::ReadBinary(f) => {
+            f.read_line(&mut line)?;
+        }
+        FileState::Stdin => {
+            io::stdin().read_line(&mut line)?;
+        }
+        _ => {
+            return Err(LispError::new(format!(
+                "{}: requires a file opened for reading",
+                fn_name
+            )))
+        }
+    }
+    Ok(if line.is_empty() { None } else { Some(line) })
+}
+
+struct CsvOptions {
+    delim: char,
+    quote: char,
+    header: bool,
+    columns: Option<Vec<Cow<'static, str>>>,
+    row: bool,
+}
+
+fn csv_option_char(exp: Option<Expression>, fn_name: &str, opt: &str) -> LispResult<char> {
+    if let Some(exp) = exp {
+        let exp_d = exp.get();
+        let s = match &exp_d.data {
+            ExpEnum::String(s, _) => s.to_string(),
+            ExpEnum::Char(c) => c.to_string(),
+            ExpEnum::CodePoint(c) => c.to_string(),
+            _ => String::new(),
+        };
+        let mut chars = s.chars();
+        if let (Some(ch), None) = (chars.next(), chars.next()) {
+            return Ok(ch);
+        }
+    }
+    Err(LispError::new(format!(
+        "{}: {} requires a single character",
+        fn_name, opt
+    )))
+}
+
+fn csv_options(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> LispResult<CsvOptions> {
+    let mut opts = CsvOptions {
+        delim: ',',
+        quote: '"',
+        header: false,
+        columns: None,
+        row: false,
+    };
+    let mut args = make_args(environment, args)?.into_iter();
+    while let Some(opt) = args.next() {
+        let opt_d = opt.get();
+        match &opt_d.data {
+            ExpEnum::Symbol(":delim", _) => {
+                opts.delim = csv_option_char(args.next(), fn_name, ":delim")?
+            }
+            ExpEnum::Symbol(":quote", _) => {
+                opts.quote = csv_option_char(args.next(), fn_name, ":quote")?
+            }
+            ExpEnum::Symbol(":header", _) => opts.header = true,
+            ExpEnum::Symbol(":row", _) => opts.row = true,
+            ExpEnum::Symbol(":columns", _) => {
+                let cols = args.next().unwrap_or_else(Expression::make_nil);
+                let cols_d = cols.get();
+                match &cols_d.data {
+                    ExpEnum::Nil => opts.columns = None,
+                    ExpEnum::Vector(_) | ExpEnum::Pair(_, _) => {
+                        let mut columns = Vec::new();
+                        for col in cols.iter() {
+                            columns.push(hash_key(environment, &col)?);
+                        }
+                        opts.columns = Some(columns);
+                    }
+                    _ => {
+                        return Err(LispError::new(format!(
+                            "{}: :columns requires a vector of column names",
+                            fn_name
+                        )))
+                    }
+                }
+            }
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: invalid option {}, expected :delim, :quote, :header, :columns or :row",
+                    fn_name, opt
+                )))
+            }
+        }
+    }
+    if opts.delim == opts.quote || opts.delim == '\n' || opts.quote == '\n' {
+        return Err(LispError::new(format!(
+            "{}: invalid delimiter or quote character",
+            fn_name
+        )));
+    }
+    Ok(opts)
+}
+
+// Parse one record (which may span lines inside quotes), blank lines are skipped.
+fn csv_parse_record(
+    next_line: &mut dyn FnMut() -> LispResult<Option<String>>,
+    line_no: &mut usize,
+    opts: &CsvOptions,
+) -> LispResult<Option<Vec<String>>> {
+    let mut chars: Vec<char> = loop {
+        match next_line()? {
+            Some(line) => {
+                *line_no += 1;
+                if !line.trim_end_matches(['\n', '\r']).is_empty() {
+                    break line.chars().collect();
+                }
+            }
+            None => return Ok(None),
+        }
+    };
+    let start_line = *line_no;
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut after_quote = false;
+    let mut i = 0;
+    loop {
+        if i >= chars.len() {
+            if in_quotes {
+                match next_line()? {
+                    Some(line) => {
+                        *line_no += 1;
+                        chars = line.chars().collect();
+                        i = 0;
+                        continue;
+                    }
+                    None => {
+                        return Err(LispError::new(format!(
+                            "csv-read: unterminated quoted field starting at line {}",
+                            start_line
+                        )))
+                    }
+                }
+            }
+            break;
+        }
+        let ch = chars[i];
+        i += 1;
+        if in_quotes {
+            if ch == opts.quote {
+                if i < chars.len() && chars[i] == opts.quote {
+                    field.push(ch);
+                    i += 1;
+                } else {
+                    in_quotes = false;
+                    after_quote = true;
+                }
+            } else {
+                field.push(ch);
+            }
+        } else if ch == opts.delim {
+            fields.push(std::mem::take(&mut field));
+            after_quote = false;
+        } else if ch == '\n' || (ch == '\r' && (i >= chars.len() || chars[i] == '\n')) {
+            break;
+        } else if after_quote {
+            return Err(LispError::new(format!(
+                "csv-read: unexpected '{}' after closing quote at line {}",
+                ch, line_no
+            )));
+        } else if ch == opts.quote && field.is_empty() {
+            in_quotes = true;
+        } else {
+            field.push(ch);
+        }
+    }
+    fields.push(field);
+    Ok(Some(fields))
+}
+
+fn csv_record_exp(
+    fields: Vec<String>,
+    columns: &Option<Vec<Cow<'static, str>>>,
+    line_no: usize,
+) -> LispResult<Expression> {
+    let fields = fields
+        .into_iter()
+        .map(|f| Expression::alloc_data(ExpEnum::String(f.into(), None)));
+    if let Some(columns) = columns {
+        let fields: Vec<Expression> = fields.collect();
+        if fields.len() != columns.len() {
+            return Err(LispError::new(format!(
+                "csv-read: record at line {} has {} fields, expected {}",
+                line_no,
+                fields.len(),
+                columns.len()
+            )));
+        }
+        let map: LispMap = columns.iter().cloned().zip(fields).collect();
+        Ok(Expression::alloc_data(ExpEnum::HashMap(map)))
+    } else {
+        Ok(Expression::with_list(fields.collect()))
+    }
+}
+
+fn csv_header_columns(
+    environment: &mut Environment,
+    header: Vec<String>,
+) -> Vec<Cow<'static, str>> {
+    header
+        .iter()
+        .map(|h| text_key(Cow::Borrowed(environment.interner.intern(h))))
+        .collect()
+}
+
+fn csv_read_records(
+    environment: &mut Environment,
+    next_line: &mut dyn FnMut() -> LispResult<Option<String>>,
+    mut opts: CsvOptions,
+) -> LispResult<Expression> {
+    let mut line_no = 0;
+    if opts.row {
+        return match csv_parse_record(next_line, &mut line_no, &opts)? {
+            Some(fields) => csv_record_exp(fields, &opts.columns, line_no),
+            None => Ok(Expression::make_nil()),
+        };
+    }
+    if opts.header {
+        if let Some(header) = csv_parse_record(next_line, &mut line_no, &opts)? {
+            opts.columns = Some(csv_header_columns(environment, header));
+        }
+    }
+    let mut records = Vec::new();
+    while let Some(fields) = csv_parse_record(next_line, &mut line_no, &opts)? {
+        records.push(csv_record_exp(fields, &opts.columns, line_no)?);
+    }
+    Ok(Expression::with_list(records))
+}
+
+fn builtin_csv_read(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> LispResult<Expression> {
+    let fn_name = "csv-read";
+    let source = param_eval(environment, args, fn_name)?;
+    let opts = csv_options(environment, args, fn_name)?;
+    if opts.row && opts.header {
+        return Err(LispError::new(
+            "csv-read: :header can not be used with :row, use :columns",
+        ));
+    }
+    let source_d = source.get();
+    match &source_d.data {
+        ExpEnum::String(s, _) => {
+            if opts.row {
+                return Err(LispError::new("csv-read: :row requires a file"));
+            }
+            let text = s.to_string();
+            drop(source_d);
+            let mut lines = text.split_inclusive('\n');
+            let mut next_line = || Ok(lines.next().map(|l| l.to_string()));
+            csv_read_records(environment, &mut next_line, opts)
+        }
+        ExpEnum::File(file) => {
+            let file = file.clone();
+            drop(source_d);
+            let mut file = file.borrow_mut();
+            let mut next_line = || file_read_line(&mut file, fn_name);
+            csv_read_records(environment, &mut next_line, opts)
+        }
+        _ => Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "String or File",
+            &source.display_type(),
+        ))),
+    }
+}
+
+// Reads the records of a CSV file lazily for csv-iter.
+struct CsvIter {
+    file: Rc<RefCell<FileState>>,
+    opts: CsvOptions,
+    line_no: usize,
+}
+
+impl SlIter for CsvIter {
+    fn next(&mut self, _environment: &mut Environment) -> LispResult<Option<Expression>> {
+        let mut file = self.file.borrow_mut();
+        let mut next_line = || file_read_line(&mut file, "csv-iter");
+        match csv_parse_record(&mut next_line, &mut self.line_no, &self.opts)? {
+            Some(fields) => Ok(Some(csv_record_exp(
+                fields,
+                &self.opts.columns,
+                self.line_no,
+            )?)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn builtin_csv_iter(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> LispResult<Expression> {
+    let fn_name = "csv-iter";
+    let source = param_eval(environment, args, fn_name)?;
+    let mut opts = csv_options(environment, args, fn_name)?;
+    if opts.row {
+        return Err(LispError::new("csv-iter: :row is only valid for csv-read"));
+    }
+    let file = match &source.get().data {
+        ExpEnum::File(file) => file.clone(),
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "File",
+                &source.display_type(),
+            )))
+        }
+    };
+    let mut line_no = 0;
+    if opts.header {
+        let header = {
+            let mut file = file.borrow_mut();
+            let mut next_line = || file_read_line(&mut file, fn_name);
+            csv_parse_record(&mut next_line, &mut line_no, &opts)?
+        };
+        if let Some(header) = header {
+            opts.columns = Some(csv_header_columns(environment, header));
+        }
+    }
+    Ok(make_iter_exp(Box::new(CsvIter {
+        file,
+        opts,
+        line_no,
+    })))
+}
+
+fn csv_write_record(out: &mut dyn Write, fields: &[String], opts: &CsvOptions) -> LispResult<()> {
+    let mut line = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            line.push(opts.delim);
+        }
+        if field.contains([opts.delim, opts.quote, '\n', '\r']) {
+            line.push(opts.quote);
+            for ch in field.chars() {
+                if ch == opts.quote {
+                    line.push(ch);
+                }
+                line.push(ch);
+            }
+            line.push(opts.quote);
+        } else {
+            line.push_str(field);
+        }
+    }
+    line.push('\n');
+    out.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn csv_field(environment: &Environment, exp: &Expression) -> LispResult<String> {
+    if exp.is_nil() {
+        Ok(String::new())
+    } else {
+        exp.as_string(environment)
+    }
+}
+
+fn builtin_csv_write(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> LispResult<Expression> {
+    let fn_name = "csv-write";
+    let file = param_eval(environment, args, fn_name)?;
+    let rows = param_eval(environment, args, fn_name)?;
+    let mut opts = csv_options(environment, args, fn_name)?;
+    if opts.row || opts.header {
+        return Err(LispError::new(
+            "csv-write: :row and :header are only valid for csv-read",
+        ));
+    }
+    let file = if let ExpEnum::File(file) = &file.get().data {
+        file.clone()
+    } else {
+        return Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "File",
+            &file.display_type(),
+        )));
+    };
+    match &rows.get().data {
+        ExpEnum::Vector(_) | ExpEnum::Pair(_, _) | ExpEnum::Nil => {}
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "Vector or List",
+                &rows.display_type(),
+            )))
+        }
+    }
+    let mut file = file.borrow_mut();
+    let mut stdout;
+    let mut stderr;
+    let out: &mut dyn Write = match &mut *file {
+        FileState::Write(f) => f,
+        FileState::Stdout => {
+            stdout = io::stdout();
+            &mut stdout
+        }
+        FileState::Stderr => {
+            stderr = io::stderr();
+            &mut stderr
+        }
+        _ => {
+            return Err(LispError::new(
+                "csv-write: requires a file opened for writing",
+            ))
+        }
+    };
+    let mut wrote_header = false;
+    for row in rows.iter() {
+        let row_d = row.get();
+        let fields = match &row_d.data {
+            ExpEnum::HashMap(map) => {
+                // Without :columns use the sorted keys of the first map.
+                let columns = opts.columns.get_or_insert_with(|| {
+                    let mut keys: Vec<Cow<'static, str>> = map.keys().cloned().collect();
+                    keys.sort_unstable_by(|a, b| display_key(a).cmp(display_key(b)));
+                    keys
+                });
+                let mut fields = Vec::with_capacity(columns.len());
+                for col in columns.iter() {
+                    fields.push(match map.get(&col[..]) {
+                        Some(val) => csv_field(environment, val)?,
+                        None => String::new(),
+                    });
+                }
+                fields
+            }
+            ExpEnum::Vector(_) | ExpEnum::Pair(_, _) | ExpEnum::Nil => {
+                let mut fields = Vec::new();
+                for val in row.iter() {
+                    fields.push(csv_field(environment, &val)?);
+                }
+                fields
+            }
+            _ => {
+                return Err(LispError::new(format!(
+                    "csv-write: rows must be vectors, lists or hash maps, got {}",
+                    row.display_type()
+                )))
+            }
+        };
+        if !wrote_header {
+            wrote_header = true;
+            if let Some(columns) = &opts.columns {
+                let header: Vec<String> =
+                    columns.iter().map(|c| display_key(c).to_string()).collect();
+                csv_write_record(out, &header, &opts)?;
+            }
+        }
+        csv_write_record(out, &fields, &opts)?;
+    }
+    Ok(Expression::make_nil())
+}
+
 pub fn add_io_builtins<S: BuildHasher>(
     interner: &mut Interner,
     data: &mut HashMap<&'static str, (Expression, String), S>,
@@ -562,6 +1150,7 @@ Options are:
     :create
     :create-new
     :on-error-nil
+    :binary (read raw bytes with read-bytes instead of text)
 
 Section: file
 
@@ -631,6 +1220,8 @@ then returns that on the end condition.
 
 If no parameters are provided then read stdin.
 
+Reading can run code (#. and reader macros), use read-data for untrusted input.
+
 Section: file
 
 Example:
@@ -664,6 +1255,35 @@ Example:
 ",
         ),
     );
+    data.insert(
+        interner.intern("read-data"),
+        Expression::make_function(
+            builtin_read_data,
+            r#"Usage: (read-data [file|string]? end-exp?) -> expression
+
+Like read but only accepts literal data (numbers, symbols, strings, chars,
+lists, vectors, #h(...) hash maps, etc).  Read time evaluation (#.) and reader macros from
+*read-table* raise an error with the line and column instead of running, so this
+is safe to use on config or data files from untrusted sources.  Strings are read
+without *string-read-table* (so $ in a string is just a $ even in the shell).
+See read-all-data to read a whole file or string.
+
+Section: file
+
+Example:
+(test::assert-equal '(1 "two" #(3 x) #\a 5.0) (read-data "(1 \"two\" #(3 x) #\\a 5.0)"))
+(def test-str "(a b) :c")
+(test::assert-equal '(a b) (read-data test-str))
+(test::assert-equal :c (read-data test-str :done))
+(test::assert-equal :done (read-data test-str :done))
+(def read-data-side-effect nil)
+(test::assert-error-msg (read-data "(1\n 2 #.(set! read-data-side-effect #t))") "Reader macro #. not allowed when reading data: line 2, col: 4")
+(test::assert-false read-data-side-effect)
+(test::assert-equal "cost \$5" (read-data "\"cost \$5\""))
+(test::assert-equal '(+ 1 2) (read "(+ 1 #.(+ 1 1))" :done))
+"#,
+        ),
+    );
     data.insert(
         interner.intern("read-all"),
         Expression::make_function(
@@ -700,6 +1320,27 @@ Example:
 ",
         ),
     );
+    data.insert(
+        interner.intern("read-all-data"),
+        Expression::make_function(
+            builtin_read_all_data,
+            r#"Usage: (read-all-data [file|string]? empty-exp?) -> list|vec|empty-exp
+
+Like read-all but only accepts literal data, see read-data.
+
+Section: file
+
+Example:
+(test::assert-equal '(1 "two" #(3 x)) (read-all-data "1 \"two\" #(3 x)"))
+(test::assert-equal '#((a b) (c)) (read-all-data "(a b) (c)"))
+(test::assert-equal :empty (read-all-data ";(x y z)" :empty))
+(test::assert-equal '("\$HOME" x) (read-all-data "(\"\$HOME\" x)"))
+(def read-all-data-side-effect nil)
+(test::assert-error (read-all-data "1 #.(set! read-all-data-side-effect #t)"))
+(test::assert-false read-all-data-side-effect)
+"#,
+        ),
+    );
     data.insert(
         interner.intern("write-line"),
         Expression::make_function(
@@ -742,5 +1383,150 @@ Example:
 ",
         ),
     );
+    data.insert(
+        interner.intern("csv-read"),
+        Expression::make_function(
+            builtin_csv_read,
+            r#"Usage: (csv-read string-or-file option*) -> vector
+
+Read CSV (RFC 4180) records from a string or a file opened for reading.  Quoted
+fields may contain the delimiter, doubled quotes and newlines.  Blank lines are
+skipped.  Returns a vector with one vector of strings per record.
+
+Options are:
+    :delim char     field delimiter (default ",", use "\t" for TSV)
+    :quote char     quote character (default "\"")
+    :header         use the first record as column names, each record becomes
+                    a hash map of column name to field
+    :columns names  use the vector names as the column names (no header row
+                    is read)
+    :row            read only the next record from a file (nil at the end of
+                    the file), use with :columns instead of :header
+
+For large files csv-iter reads records lazily.
+
+Section: file
+
+Example:
+(def csv-test (csv-read "name,note\nsl-sh,\"a \"\"lisp\"\", shell\"\n\n\"multi\nline\",x\n"))
+(test::assert-equal 3 (length csv-test))
+(test::assert-equal '#("sl-sh" "a \"lisp\", shell") (vec-nth csv-test 1))
+(test::assert-equal "multi\nline" (vec-nth (vec-nth csv-test 2) 0))
+(def csv-test (csv-read "a\tb\n1\t2\n" :delim "\t" :header))
+(test::assert-equal "2" (hash-get (vec-nth csv-test 0) "b"))
+(test::assert-equal "x" (hash-get (vec-nth (csv-read "x;'y'" :delim ";" :quote "'" :columns '#(:c1 c2)) 0) ":c1"))
+(test::assert-error-msg (csv-read "a,b\n1,2,3\n" :header) "csv-read: record at line 2 has 3 fields, expected 2")
+(test::assert-error-msg (csv-read "a,\"b\nc") "csv-read: unterminated quoted field starting at line 1")
+(with-temp-file (fn (tmp)
+    (let ((out (open tmp :create :truncate)))
+        (write-string out "h1,h2\r\n1,2\r\n")
+        (close out))
+    (let ((in (open tmp :read)))
+        (test::assert-equal '#("h1" "h2") (csv-read in :row))
+        (test::assert-equal "2" (hash-get (csv-read in :row :columns '#(h1 h2)) "h2"))
+        (test::assert-false (csv-read in :row))
+        (close in))))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("csv-iter"),
+        Expression::make_function(
+            builtin_csv_iter,
+            r#"Usage: (csv-iter file option*) -> iterator
+
+Iterator over the CSV records of a file opened for reading, records are read
+lazily so large files are not loaded at once.  Takes the same options as
+csv-read (except :row), with :header the first record names the columns and
+each record is a hash map.
+
+Section: file
+
+Example:
+(with-temp-file (fn (tmp)
+    (def tst-file (open tmp :create :truncate))
+    (write-line tst-file "id,name")
+    (write-line tst-file "1,\"a,b\"")
+    (write-line tst-file "2,c")
+    (close tst-file)
+    (def test-iter (csv-iter (open tmp) :header))
+    (test::assert-true (iterator? test-iter))
+    (test::assert-false (test-iter :empty?))
+    (test::assert-equal "a,b" (hash-get (test-iter :next!) "name"))
+    (test::assert-equal "2" (hash-get (test-iter :next!) "id"))
+    (test::assert-true (test-iter :empty?))
+    (test::assert-equal '#(#("id" "name") #("1" "a,b") #("2" "c"))
+                  ((csv-iter (open tmp)) :collect-vec))
+    (test::assert-equal '("a,b" "c") ((iter-map (fn (r) (hash-get r "name")) (csv-iter (open tmp) :header)) :collect))))
+(test::assert-error (csv-iter "a,b"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("csv-write"),
+        Expression::make_function(
+            builtin_csv_write,
+            r#"Usage: (csv-write file rows option*) -> nil
+
+Write rows as CSV (RFC 4180) to a file opened for writing (or *stdout*/*stderr*).
+Rows is a vector or list of rows, each row is a vector or list of fields or a
+hash map.  Fields that contain the delimiter, quote or a newline are quoted, nil
+is written as an empty field and other values are written as with str.
+
+Options are:
+    :delim char     field delimiter (default ",", use "\t" for TSV)
+    :quote char     quote character (default "\"")
+    :columns names  write names as a header row first, for hash map rows this
+                    is also the field order (missing keys are empty fields)
+
+Hash map rows without :columns use the sorted keys of the first row as the
+header.
+
+Section: file
+
+Example:
+(with-temp-file (fn (tmp)
+    (let ((out (open tmp :create :truncate)))
+        (csv-write out '(#("a" "b,c") #(1 nil)) :columns '#(x y))
+        (csv-write out (list (make-hash '(("n" . "say \"hi\"") ("m" . 2)))))
+        (csv-write out '(#(1 2)) :delim "\t")
+        (close out))
+    (let ((in (open tmp :read)))
+        (test::assert-equal "x,y\na,\"b,c\"\n1,\nm,n\n2,\"say \"\"hi\"\"\"\n1\t2\n" (iterator::collect-str in))
+        (close in))
+    (let ((in (open tmp :read)))
+        (test::assert-equal '#("1" "") (vec-nth (csv-read in) 2))
+        (close in))))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("write-readable"),
+        Expression::make_function(
+            builtin_write_readable,
+            r#"Usage: (write-readable file value) -> nil
+
+Write value to a file opened for writing (or *stdout*/*stderr*) in a form that
+read will turn back into an equal value.  See str-readable for the format.  No
+newline is written.
+
+Section: file
+
+Example:
+(with-temp-file (fn (tmp)
+    (let ((out (open tmp :create :truncate)))
+        (write-readable out '("a \"b\"" #\( #(1.0 :x)))
+        (write-readable out " ")
+        (write-readable out (make-hash '((k . v))))
+        (close out))
+    (let ((in (open tmp :read)))
+        (test::assert-equal '("a \"b\"" #\( #(1.0 :x)) (read in))
+        (test::assert-equal " " (read in))
+        (test::assert-equal 'v (hash-get (read in) "k"))
+        (close in))))
+(test::assert-error (write-readable *stdout* (fn () nil)))
+"#,
+        ),
+    );
     intern_fs_rm(interner, data);
 }
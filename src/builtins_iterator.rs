@@ -0,0 +1,1360 @@
+use std::borrow::Cow;
+use std::cell::{RefCell, RefMut};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{BufRead, BufReader};
+use std::rc::Rc;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::builtins_equal::values_equal;
+use crate::builtins_hashmap::key_expression;
+use crate::builtins_io::file_read_line;
+use crate::builtins_persistent::pmap_pairs;
+use crate::builtins_record::call_record;
+use crate::builtins_set::sorted_members;
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::types::*;
+use crate::unix::{dup_fd, fd_to_file};
+
+type IterResult = Result<Option<Expression>, LispError>;
+
+/// A source of values for a native iterator.  Sources and adapters implement
+/// this, next_back is only called when double_ended is true.
+pub trait SlIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult;
+
+    fn next_back(&mut self, _environment: &mut Environment) -> IterResult {
+        Err(LispError::new("iterator is not double ended"))
+    }
+
+    fn double_ended(&self) -> bool {
+        false
+    }
+}
+
+/// The state of an Iterator expression.  One value can be peeked so :empty?
+/// works with sources that can only tell they are done by reading.
+pub struct IterState {
+    inner: Box<dyn SlIter>,
+    peeked: Option<Option<Expression>>,
+}
+
+impl IterState {
+    pub fn new(inner: Box<dyn SlIter>) -> IterState {
+        IterState {
+            inner,
+            peeked: None,
+        }
+    }
+
+    pub fn next(&mut self, environment: &mut Environment) -> IterResult {
+        match self.peeked.take() {
+            Some(val) => Ok(val),
+            None => self.inner.next(environment),
+        }
+    }
+
+    pub fn next_back(&mut self, environment: &mut Environment) -> IterResult {
+        if !self.inner.double_ended() {
+            return Err(LispError::new("iterator is not double ended"));
+        }
+        match self.inner.next_back(environment)? {
+            Some(val) => Ok(Some(val)),
+            // The peeked value is the last one left.
+            None => Ok(self.peeked.take().flatten()),
+        }
+    }
+
+    pub fn is_empty(&mut self, environment: &mut Environment) -> Result<bool, LispError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.inner.next(environment)?);
+        }
+        Ok(matches!(self.peeked, Some(None)))
+    }
+
+    pub fn double_ended(&self) -> bool {
+        self.inner.double_ended()
+    }
+}
+
+/// Wrap inner in a new Iterator expression.
+pub fn make_iter_exp(inner: Box<dyn SlIter>) -> Expression {
+    Expression::alloc_data(ExpEnum::Iterator(Rc::new(RefCell::new(IterState::new(
+        inner,
+    )))))
+}
+
+fn borrow_state(state: &RefCell<IterState>) -> Result<RefMut<'_, IterState>, LispError> {
+    state
+        .try_borrow_mut()
+        .map_err(|_| LispError::new("iterator: already in use (used inside itself?)"))
+}
+
+fn make_pair(car: Expression, cdr: Expression) -> Expression {
+    Expression::alloc_data(ExpEnum::Pair(car, cdr))
+}
+
+struct ListIter {
+    cur: Expression,
+    // The rest of the list, only made when it is read from the back.
+    rest: Option<VecDeque<Expression>>,
+}
+
+impl SlIter for ListIter {
+    fn next(&mut self, _environment: &mut Environment) -> IterResult {
+        if let Some(rest) = &mut self.rest {
+            return Ok(rest.pop_front());
+        }
+        let (car, cdr) = match &self.cur.get().data {
+            ExpEnum::Pair(car, cdr) => (car.clone(), cdr.clone()),
+            _ => return Ok(None),
+        };
+        self.cur = cdr;
+        Ok(Some(car))
+    }
+
+    fn next_back(&mut self, environment: &mut Environment) -> IterResult {
+        if self.rest.is_none() {
+            let mut rest = VecDeque::new();
+            while let Some(val) = self.next(environment)? {
+                rest.push_back(val);
+            }
+            self.rest = Some(rest);
+        }
+        Ok(self.rest.as_mut().and_then(|rest| rest.pop_back()))
+    }
+
+    fn double_ended(&self) -> bool {
+        true
+    }
+}
+
+struct VecIter {
+    vec: Expression,
+    front: usize,
+    back: usize,
+}
+
+impl VecIter {
+    fn nth(&self, idx: usize) -> Option<Expression> {
+        match &self.vec.get().data {
+            ExpEnum::Vector(v) => v.get(idx).cloned(),
+            _ => None,
+        }
+    }
+}
+
+impl SlIter for VecIter {
+    fn next(&mut self, _environment: &mut Environment) -> IterResult {
+        if self.front >= self.back {
+            return Ok(None);
+        }
+        self.front += 1;
+        Ok(self.nth(self.front - 1))
+    }
+
+    fn next_back(&mut self, _environment: &mut Environment) -> IterResult {
+        if self.front >= self.back {
+            return Ok(None);
+        }
+        self.back -= 1;
+        Ok(self.nth(self.back))
+    }
+
+    fn double_ended(&self) -> bool {
+        true
+    }
+}
+
+struct StrIter {
+    text: String,
+    pos: usize,
+}
+
+impl SlIter for StrIter {
+    fn next(&mut self, _environment: &mut Environment) -> IterResult {
+        match UnicodeSegmentation::graphemes(&self.text[self.pos..], true).next() {
+            Some(ch) => {
+                self.pos += ch.len();
+                Ok(Some(Expression::alloc_data(ExpEnum::Char(Cow::Owned(
+                    ch.to_string(),
+                )))))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+// Values that are already known (the entries of a hash map or set).
+struct ItemsIter(VecDeque<Expression>);
+
+impl SlIter for ItemsIter {
+    fn next(&mut self, _environment: &mut Environment) -> IterResult {
+        Ok(self.0.pop_front())
+    }
+
+    fn next_back(&mut self, _environment: &mut Environment) -> IterResult {
+        Ok(self.0.pop_back())
+    }
+
+    fn double_ended(&self) -> bool {
+        true
+    }
+}
+
+struct FileIter(Rc<RefCell<FileState>>);
+
+impl SlIter for FileIter {
+    fn next(&mut self, _environment: &mut Environment) -> IterResult {
+        let line = file_read_line(&mut self.0.borrow_mut(), "iterator")?;
+        Ok(line.map(|line| Expression::alloc_data(ExpEnum::String(line.into(), None))))
+    }
+}
+
+// Reads a dup of the process output fd so it stays open while the iterator lives.
+struct ProcessIter(BufReader<File>);
+
+impl SlIter for ProcessIter {
+    fn next(&mut self, _environment: &mut Environment) -> IterResult {
+        let mut line = Vec::new();
+        if self.0.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = String::from_utf8_lossy(&line).to_string();
+        Ok(Some(Expression::alloc_data(ExpEnum::String(
+            line.into(),
+            None,
+        ))))
+    }
+}
+
+struct RangeIter {
+    start: i64,
+    end: i64,
+    step: i64,
+}
+
+impl RangeIter {
+    // In i128 so ranges spanning most of i64 do not overflow.
+    fn len(&self) -> i128 {
+        let (start, end, step) = (self.start as i128, self.end as i128, self.step as i128);
+        if step > 0 && start < end {
+            (end - start + step - 1) / step
+        } else if step < 0 && start > end {
+            (start - end - step - 1) / -step
+        } else {
+            0
+        }
+    }
+}
+
+impl SlIter for RangeIter {
+    fn next(&mut self, _environment: &mut Environment) -> IterResult {
+        if self.len() == 0 {
+            return Ok(None);
+        }
+        let val = self.start;
+        // Past the end of i64 means the range is done.
+        self.start = self.start.checked_add(self.step).unwrap_or(self.end);
+        Ok(Some(Expression::alloc_data(ExpEnum::Int(val))))
+    }
+
+    fn next_back(&mut self, _environment: &mut Environment) -> IterResult {
+        let len = self.len();
+        if len == 0 {
+            return Ok(None);
+        }
+        // The last value is between start and end so fits in an i64.
+        self.end = (self.start as i128 + (len - 1) * self.step as i128) as i64;
+        Ok(Some(Expression::alloc_data(ExpEnum::Int(self.end))))
+    }
+
+    fn double_ended(&self) -> bool {
+        true
+    }
+}
+
+// Another native iterator used as a source, reading from it consumes it.
+struct SharedIter(Rc<RefCell<IterState>>);
+
+impl SlIter for SharedIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        borrow_state(&self.0)?.next(environment)
+    }
+
+    fn next_back(&mut self, environment: &mut Environment) -> IterResult {
+        borrow_state(&self.0)?.next_back(environment)
+    }
+
+    fn double_ended(&self) -> bool {
+        self.0
+            .try_borrow()
+            .map(|state| state.double_ended())
+            .unwrap_or(false)
+    }
+}
+
+// A Lisp struct that implements iterator::iterator.
+struct RecordIter {
+    record: Expression,
+    double_ended: bool,
+}
+
+fn send_message(
+    environment: &mut Environment,
+    record: &Expression,
+    message: &str,
+) -> Result<Expression, LispError> {
+    let message = Expression::alloc_data(ExpEnum::Symbol(
+        environment.interner.intern(message),
+        SymLoc::None,
+    ));
+    let old_sup = environment.supress_eval;
+    environment.supress_eval = true;
+    let ret = call_record(environment, record, &mut vec![message].into_iter());
+    environment.supress_eval = old_sup;
+    ret
+}
+
+impl RecordIter {
+    fn take(&mut self, environment: &mut Environment, message: &str) -> IterResult {
+        if send_message(environment, &self.record, ":empty?")?.is_falsey() {
+            Ok(Some(send_message(environment, &self.record, message)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl SlIter for RecordIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        self.take(environment, ":next!")
+    }
+
+    fn next_back(&mut self, environment: &mut Environment) -> IterResult {
+        self.take(environment, ":next-back!")
+    }
+
+    fn double_ended(&self) -> bool {
+        self.double_ended
+    }
+}
+
+struct MapIter {
+    inner: Box<dyn SlIter>,
+    func: Expression,
+}
+
+impl SlIter for MapIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        match self.inner.next(environment)? {
+            Some(val) => Ok(Some(call_fn(environment, &self.func, vec![val])?)),
+            None => Ok(None),
+        }
+    }
+
+    fn next_back(&mut self, environment: &mut Environment) -> IterResult {
+        match self.inner.next_back(environment)? {
+            Some(val) => Ok(Some(call_fn(environment, &self.func, vec![val])?)),
+            None => Ok(None),
+        }
+    }
+
+    fn double_ended(&self) -> bool {
+        self.inner.double_ended()
+    }
+}
+
+struct FilterIter {
+    inner: Box<dyn SlIter>,
+    pred: Expression,
+}
+
+impl SlIter for FilterIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        while let Some(val) = self.inner.next(environment)? {
+            if !call_fn(environment, &self.pred, vec![val.clone()])?.is_falsey() {
+                return Ok(Some(val));
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_back(&mut self, environment: &mut Environment) -> IterResult {
+        while let Some(val) = self.inner.next_back(environment)? {
+            if !call_fn(environment, &self.pred, vec![val.clone()])?.is_falsey() {
+                return Ok(Some(val));
+            }
+        }
+        Ok(None)
+    }
+
+    fn double_ended(&self) -> bool {
+        self.inner.double_ended()
+    }
+}
+
+struct TakeIter {
+    inner: Box<dyn SlIter>,
+    remaining: usize,
+}
+
+impl SlIter for TakeIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        // Do not touch inner once done, it may be infinite or shared.
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        self.inner.next(environment)
+    }
+}
+
+struct SkipIter {
+    inner: Box<dyn SlIter>,
+    skip: usize,
+}
+
+impl SlIter for SkipIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        while self.skip > 0 {
+            self.skip -= 1;
+            if self.inner.next(environment)?.is_none() {
+                return Ok(None);
+            }
+        }
+        self.inner.next(environment)
+    }
+}
+
+struct ZipIter {
+    first: Box<dyn SlIter>,
+    second: Box<dyn SlIter>,
+}
+
+impl SlIter for ZipIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        if let Some(first) = self.first.next(environment)? {
+            if let Some(second) = self.second.next(environment)? {
+                return Ok(Some(make_pair(first, second)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+struct ChainIter(VecDeque<Box<dyn SlIter>>);
+
+impl SlIter for ChainIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        while let Some(iter) = self.0.front_mut() {
+            if let Some(val) = iter.next(environment)? {
+                return Ok(Some(val));
+            }
+            self.0.pop_front();
+        }
+        Ok(None)
+    }
+
+    fn next_back(&mut self, environment: &mut Environment) -> IterResult {
+        while let Some(iter) = self.0.back_mut() {
+            if let Some(val) = iter.next_back(environment)? {
+                return Ok(Some(val));
+            }
+            self.0.pop_back();
+        }
+        Ok(None)
+    }
+
+    fn double_ended(&self) -> bool {
+        self.0.iter().all(|iter| iter.double_ended())
+    }
+}
+
+struct EnumerateIter {
+    inner: Box<dyn SlIter>,
+    idx: i64,
+}
+
+impl SlIter for EnumerateIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        match self.inner.next(environment)? {
+            Some(val) => {
+                self.idx += 1;
+                let idx = Expression::alloc_data(ExpEnum::Int(self.idx - 1));
+                Ok(Some(make_pair(idx, val)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+struct FlatMapIter {
+    inner: Box<dyn SlIter>,
+    func: Expression,
+    current: Option<Box<dyn SlIter>>,
+}
+
+impl SlIter for FlatMapIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        loop {
+            if let Some(current) = &mut self.current {
+                if let Some(val) = current.next(environment)? {
+                    return Ok(Some(val));
+                }
+                self.current = None;
+            }
+            match self.inner.next(environment)? {
+                Some(val) => {
+                    let items = call_fn(environment, &self.func, vec![val])?;
+                    self.current = Some(to_sl_iter(environment, &items, "iter-flat-map")?);
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+struct TakeWhileIter {
+    inner: Box<dyn SlIter>,
+    pred: Expression,
+    done: bool,
+}
+
+impl SlIter for TakeWhileIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        if self.done {
+            return Ok(None);
+        }
+        if let Some(val) = self.inner.next(environment)? {
+            if !call_fn(environment, &self.pred, vec![val.clone()])?.is_falsey() {
+                return Ok(Some(val));
+            }
+        }
+        self.done = true;
+        Ok(None)
+    }
+}
+
+struct ChunkIter {
+    inner: Box<dyn SlIter>,
+    size: usize,
+}
+
+impl SlIter for ChunkIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        let mut chunk = Vec::with_capacity(self.size);
+        while chunk.len() < self.size {
+            match self.inner.next(environment)? {
+                Some(val) => chunk.push(val),
+                None => break,
+            }
+        }
+        if chunk.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Expression::alloc_data(ExpEnum::Vector(chunk))))
+        }
+    }
+}
+
+struct WindowIter {
+    inner: Box<dyn SlIter>,
+    size: usize,
+    window: VecDeque<Expression>,
+}
+
+impl SlIter for WindowIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        if self.window.len() == self.size {
+            self.window.pop_front();
+        }
+        while self.window.len() < self.size {
+            match self.inner.next(environment)? {
+                Some(val) => self.window.push_back(val),
+                None => {
+                    self.window.clear();
+                    return Ok(None);
+                }
+            }
+        }
+        let window = self.window.iter().cloned().collect();
+        Ok(Some(Expression::alloc_data(ExpEnum::Vector(window))))
+    }
+}
+
+struct DedupIter {
+    inner: Box<dyn SlIter>,
+    last: Option<Expression>,
+}
+
+impl SlIter for DedupIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        while let Some(val) = self.inner.next(environment)? {
+            if let Some(last) = &self.last {
+                if values_equal(last, &val) {
+                    continue;
+                }
+            }
+            self.last = Some(val.clone());
+            return Ok(Some(val));
+        }
+        Ok(None)
+    }
+}
+
+struct ReverseIter(Box<dyn SlIter>);
+
+impl SlIter for ReverseIter {
+    fn next(&mut self, environment: &mut Environment) -> IterResult {
+        self.0.next_back(environment)
+    }
+
+    fn next_back(&mut self, environment: &mut Environment) -> IterResult {
+        self.0.next(environment)
+    }
+
+    fn double_ended(&self) -> bool {
+        true
+    }
+}
+
+/// Make an iterator source for exp, a list, vector, string, hash map, file,
+/// process (its output lines), native iterator or Lisp iterator struct.
+pub fn to_sl_iter(
+    environment: &mut Environment,
+    exp: &Expression,
+    fn_name: &str,
+) -> Result<Box<dyn SlIter>, LispError> {
+    match &exp.get().data {
+        ExpEnum::Iterator(state) => Ok(Box::new(SharedIter(state.clone()))),
+        ExpEnum::Pair(_, _) | ExpEnum::Nil => Ok(Box::new(ListIter {
+            cur: exp.clone(),
+            rest: None,
+        })),
+        ExpEnum::Vector(v) => Ok(Box::new(VecIter {
+            vec: exp.clone(),
+            front: 0,
+            back: v.len(),
+        })),
+        ExpEnum::String(s, _) => Ok(Box::new(StrIter {
+            text: s.to_string(),
+            pos: 0,
+        })),
+        ExpEnum::HashMap(map) => {
+            let entries: Vec<(Cow<'static, str>, Expression)> =
+                map.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+            let mut items = VecDeque::with_capacity(entries.len());
+            for (key, val) in entries {
+                items.push_back(make_pair(key_expression(environment, &key)?, val));
+            }
+            Ok(Box::new(ItemsIter(items)))
+        }
+        ExpEnum::Set(set) => Ok(Box::new(ItemsIter(sorted_members(set).into()))),
+        ExpEnum::PVec(v) => Ok(Box::new(ItemsIter(v.to_vec().into()))),
+        ExpEnum::PMap(map) => Ok(Box::new(ItemsIter(pmap_pairs(environment, map)?.into()))),
+        ExpEnum::File(file) => Ok(Box::new(FileIter(file.clone()))),
+        ExpEnum::Process(ProcessState::Running(pid))
+        | ExpEnum::Process(ProcessState::Over(pid, _)) => {
+            let fd = match environment.procs.borrow().get(pid) {
+                Some((_, Some(fd))) => *fd,
+                _ => {
+                    return Err(LispError::new(format!(
+                        "{}: process output is not captured",
+                        fn_name
+                    )))
+                }
+            };
+            let file = fd_to_file(dup_fd(fd)?);
+            Ok(Box::new(ProcessIter(BufReader::new(file))))
+        }
+        ExpEnum::Record(r) if r.record_type().has_tag(":trait-iterator") => {
+            let double_ended = r.record_type().has_tag(":trait-double-ended-iterator");
+            let record = exp.clone();
+            let double_ended =
+                double_ended && !send_message(environment, &record, ":double-ended?")?.is_falsey();
+            Ok(Box::new(RecordIter {
+                record,
+                double_ended,
+            }))
+        }
+        _ => Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "List, Vector, String, HashMap, Set, File, Process or Iterator",
+            &exp.display_type(),
+        ))),
+    }
+}
+
+fn param_items(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Expression, LispError> {
+    // Capture process output so it can be iterated.
+    let gpo = set_grab_proc_output(environment, true);
+    param_eval(gpo.environment, args, fn_name)
+}
+
+fn param_items_optional(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Option<Expression>, LispError> {
+    let gpo = set_grab_proc_output(environment, true);
+    param_eval_optional(gpo.environment, args)
+}
+
+fn param_iter(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Box<dyn SlIter>, LispError> {
+    let items = param_items(environment, args, fn_name)?;
+    to_sl_iter(environment, &items, fn_name)
+}
+
+fn param_count(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<usize, LispError> {
+    let n = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    if n < 0 {
+        return Err(LispError::new(format!(
+            "{}: count must not be negative, got {}",
+            fn_name, n
+        )));
+    }
+    Ok(n as usize)
+}
+
+fn drain(
+    environment: &mut Environment,
+    state: &RefCell<IterState>,
+) -> Result<Vec<Expression>, LispError> {
+    let mut state = borrow_state(state)?;
+    let mut items = Vec::new();
+    while let Some(val) = state.next(environment)? {
+        items.push(val);
+    }
+    Ok(items)
+}
+
+fn make_bool(b: bool) -> Expression {
+    if b {
+        Expression::make_true()
+    } else {
+        Expression::make_nil()
+    }
+}
+
+/// Send a message to a native iterator (it takes the same messages as a
+/// struct implementing iterator::iterator).
+pub fn call_iterator(
+    environment: &mut Environment,
+    iter: &Expression,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iterator";
+    let state = match &iter.get().data {
+        ExpEnum::Iterator(state) => state.clone(),
+        _ => return Err(LispError::new("call_iterator: requires an iterator")),
+    };
+    let msg = param_eval(environment, args, fn_name)?;
+    let msg_name = match &msg.get().data {
+        ExpEnum::Symbol(s, _) => *s,
+        _ => "",
+    };
+    let shared = || -> Box<dyn SlIter> { Box::new(SharedIter(state.clone())) };
+    match msg_name {
+        ":next!" => {
+            params_done(args, fn_name)?;
+            let val = borrow_state(&state)?.next(environment)?;
+            Ok(val.unwrap_or_else(Expression::make_nil))
+        }
+        ":next-back!" => {
+            params_done(args, fn_name)?;
+            let val = borrow_state(&state)?.next_back(environment)?;
+            Ok(val.unwrap_or_else(Expression::make_nil))
+        }
+        ":empty?" => {
+            params_done(args, fn_name)?;
+            let empty = borrow_state(&state)?.is_empty(environment)?;
+            Ok(make_bool(empty))
+        }
+        ":double-ended?" => {
+            params_done(args, fn_name)?;
+            Ok(make_bool(borrow_state(&state)?.double_ended()))
+        }
+        ":collect" => {
+            params_done(args, fn_name)?;
+            Ok(Expression::cons_from_vec(
+                &drain(environment, &state)?,
+                None,
+            ))
+        }
+        ":collect-vec" => {
+            params_done(args, fn_name)?;
+            Ok(Expression::alloc_data(ExpEnum::Vector(drain(
+                environment,
+                &state,
+            )?)))
+        }
+        ":collect-str" => {
+            params_done(args, fn_name)?;
+            let mut res = String::new();
+            for val in drain(environment, &state)? {
+                res.push_str(&val.as_string(environment)?);
+            }
+            Ok(Expression::alloc_data(ExpEnum::String(res.into(), None)))
+        }
+        ":count" => {
+            params_done(args, fn_name)?;
+            let mut state = borrow_state(&state)?;
+            let mut count = 0;
+            while state.next(environment)?.is_some() {
+                count += 1;
+            }
+            Ok(Expression::alloc_data(ExpEnum::Int(count)))
+        }
+        ":nth!" => {
+            let idx = param_count(environment, args, fn_name)?;
+            params_done(args, fn_name)?;
+            let mut state = borrow_state(&state)?;
+            for _ in 0..idx {
+                if state.next(environment)?.is_none() {
+                    return Ok(Expression::make_nil());
+                }
+            }
+            Ok(state
+                .next(environment)?
+                .unwrap_or_else(Expression::make_nil))
+        }
+        ":map" => {
+            let func = param_eval(environment, args, fn_name)?;
+            params_done(args, fn_name)?;
+            Ok(make_iter_exp(Box::new(MapIter {
+                inner: shared(),
+                func,
+            })))
+        }
+        ":filter" => {
+            let pred = param_eval(environment, args, fn_name)?;
+            params_done(args, fn_name)?;
+            Ok(make_iter_exp(Box::new(FilterIter {
+                inner: shared(),
+                pred,
+            })))
+        }
+        ":slice" => {
+            let start = param_count(environment, args, fn_name)?;
+            let end = if let Some(end) = param_eval_optional(environment, args)? {
+                Some(end.make_int(environment)?)
+            } else {
+                None
+            };
+            params_done(args, fn_name)?;
+            let skip = Box::new(SkipIter {
+                inner: shared(),
+                skip: start,
+            });
+            Ok(match end {
+                Some(end) => make_iter_exp(Box::new(TakeIter {
+                    inner: skip,
+                    remaining: (end - start as i64).max(0) as usize,
+                })),
+                None => make_iter_exp(skip),
+            })
+        }
+        ":reverse" => {
+            params_done(args, fn_name)?;
+            if !borrow_state(&state)?.double_ended() {
+                return Err(LispError::new("iterator :reverse: not double ended"));
+            }
+            Ok(make_iter_exp(Box::new(ReverseIter(shared()))))
+        }
+        _ => Err(LispError::new(format!(
+            "Invalid message ({}) to iterator",
+            msg
+        ))),
+    }
+}
+
+fn builtin_make_iter(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "make-iter";
+    let items = param_items(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    if let ExpEnum::Iterator(_) = &items.get().data {
+        return Ok(items.clone());
+    }
+    Ok(make_iter_exp(to_sl_iter(environment, &items, fn_name)?))
+}
+
+fn builtin_iter_range(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-range";
+    let first = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    let (start, end) = match param_eval_optional(environment, args)? {
+        Some(end) => (first, end.make_int(environment)?),
+        None => (0, first),
+    };
+    let step = match param_eval_optional(environment, args)? {
+        Some(step) => step.make_int(environment)?,
+        None => 1,
+    };
+    params_done(args, fn_name)?;
+    if step == 0 {
+        return Err(LispError::new("iter-range: step can not be 0"));
+    }
+    Ok(make_iter_exp(Box::new(RangeIter { start, end, step })))
+}
+
+fn builtin_iter_map(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-map";
+    let func = param_eval(environment, args, fn_name)?;
+    let inner = param_iter(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(MapIter { inner, func })))
+}
+
+fn builtin_iter_filter(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-filter";
+    let pred = param_eval(environment, args, fn_name)?;
+    let inner = param_iter(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(FilterIter { inner, pred })))
+}
+
+fn builtin_iter_take(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-take";
+    let inner = param_iter(environment, args, fn_name)?;
+    let remaining = param_count(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(TakeIter { inner, remaining })))
+}
+
+fn builtin_iter_skip(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-skip";
+    let inner = param_iter(environment, args, fn_name)?;
+    let skip = param_count(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(SkipIter { inner, skip })))
+}
+
+fn builtin_iter_zip(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-zip";
+    let first = param_iter(environment, args, fn_name)?;
+    let second = param_iter(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(ZipIter { first, second })))
+}
+
+fn builtin_iter_chain(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-chain";
+    let mut iters = VecDeque::new();
+    while let Some(items) = param_items_optional(environment, args)? {
+        iters.push_back(to_sl_iter(environment, &items, fn_name)?);
+    }
+    Ok(make_iter_exp(Box::new(ChainIter(iters))))
+}
+
+fn builtin_iter_enumerate(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-enumerate";
+    let inner = param_iter(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(EnumerateIter { inner, idx: 0 })))
+}
+
+fn builtin_iter_flat_map(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-flat-map";
+    let func = param_eval(environment, args, fn_name)?;
+    let inner = param_iter(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(FlatMapIter {
+        inner,
+        func,
+        current: None,
+    })))
+}
+
+fn builtin_iter_take_while(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-take-while";
+    let pred = param_eval(environment, args, fn_name)?;
+    let inner = param_iter(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(TakeWhileIter {
+        inner,
+        pred,
+        done: false,
+    })))
+}
+
+fn param_size(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<usize, LispError> {
+    let size = param_count(environment, args, fn_name)?;
+    if size == 0 {
+        return Err(LispError::new(format!(
+            "{}: size must be positive",
+            fn_name
+        )));
+    }
+    Ok(size)
+}
+
+fn builtin_iter_chunk(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-chunk";
+    let inner = param_iter(environment, args, fn_name)?;
+    let size = param_size(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(ChunkIter { inner, size })))
+}
+
+fn builtin_iter_window(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-window";
+    let inner = param_iter(environment, args, fn_name)?;
+    let size = param_size(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(WindowIter {
+        inner,
+        size,
+        window: VecDeque::with_capacity(size),
+    })))
+}
+
+fn builtin_iter_dedup(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iter-dedup";
+    let inner = param_iter(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(make_iter_exp(Box::new(DedupIter { inner, last: None })))
+}
+
+pub fn add_iterator_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("make-iter"),
+        Expression::make_function(
+            builtin_make_iter,
+            r#"Usage: (make-iter items) -> iterator
+
+Make a native iterator over items.  items can be a list, vector, string
+(produces chars), hash map or persistent map (produces (key . value) pairs),
+set (produces its members in compare order), persistent vector, file (produces
+lines), process (produces the lines of its captured output), a struct that
+implements iterator::iterator or an iterator (returned as is).
+
+Native iterators take the messages of iterator::iterator (:next!, :empty?,
+:collect, :collect-vec, :collect-str, :map, :filter, :slice, :count, :nth!,
+:double-ended?, :reverse and :next-back!) so they work anywhere a Lisp
+iterator does.  Iterators over lists, vectors, hash maps, sets, persistent
+collections and ranges are double ended.
+
+Section: iterator
+
+Example:
+(def test-iter (make-iter '(1 2 3)))
+(test::assert-true (iterator? test-iter))
+(test::assert-false (test-iter :empty?))
+(test::assert-equal 1 (test-iter :next!))
+(test::assert-equal 3 (test-iter :next-back!))
+(test::assert-equal 2 (test-iter :next!))
+(test::assert-true (test-iter :empty?))
+(test::assert-equal nil (test-iter :next!))
+(test::assert-equal '#(#\a #\b) ((make-iter "ab") :collect-vec))
+(test::assert-equal '(3 2 1) (((make-iter '#(1 2 3)) :reverse) :collect))
+(test::assert-equal '((:a . 1)) ((make-iter (make-hash '((:a . 1)))) :collect))
+(test::assert-equal '(((1 2) . 2)) ((make-iter (make-hash '(((1 2) . 2)))) :collect))
+(test::assert-equal '((a . 1)) ((make-iter (make-hash '(("a" . 1)))) :collect))
+(test::assert-equal "ABC" (((make-iter "abc") :map char-upper) :collect-str))
+(test::assert-equal 3 ((make-iter '(1 2 3)) :count))
+(test::assert-equal 2 ((make-iter '(1 2 3)) :nth! 1))
+(test::assert-equal '(1 2) (((make-iter '(0 1 2 3)) :slice 1 3) :collect))
+(def test-iter-file (open "/tmp/make-iter-test.txt" :create :truncate))
+(write-line test-iter-file "line 1")
+(write-string test-iter-file "line 2")
+(close test-iter-file)
+(test::assert-equal '("line 1\n" "line 2") ((make-iter (open "/tmp/make-iter-test.txt")) :collect))
+(test::assert-equal '("a\n" "b\n") ((make-iter (syscall 'printf "a\nb\n")) :collect))
+(test::assert-error (make-iter 1))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-range"),
+        Expression::make_function(
+            builtin_iter_range,
+            r#"Usage: (iter-range end) or (iter-range start end step?) -> iterator
+
+Iterator over the integers from start (0 if not provided) up to but not
+including end counting by step (1 if not provided, may be negative).  Double
+ended.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(0 1 2) ((iter-range 3) :collect))
+(test::assert-equal '(3 4 5) ((iter-range 3 6) :collect))
+(test::assert-equal '(0 3 6 9) ((iter-range 0 10 3) :collect))
+(test::assert-equal '(5 4 3) ((iter-range 5 2 -1) :collect))
+(test::assert-equal '(9 6 3 0) (((iter-range 0 10 3) :reverse) :collect))
+(test::assert-equal '() ((iter-range 3 3) :collect))
+(test::assert-equal '(-9223372036854775808 -1 9223372036854775806) ((iter-range -9223372036854775808 9223372036854775807 9223372036854775807) :collect))
+(test::assert-equal 9223372036854775806 (((iter-range -9223372036854775808 9223372036854775807 9223372036854775807) :reverse) :next!))
+(test::assert-error (iter-range 0 3 0))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-map"),
+        Expression::make_function(
+            builtin_iter_map,
+            r#"Usage: (iter-map function items) -> iterator
+
+Lazily apply function to each item of items (anything make-iter takes).  It is
+double ended if items is.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(2 3 4) ((iter-map (fn (x) (+ x 1)) '(1 2 3)) :collect))
+(test::assert-equal '(4 3 2) (((iter-map (fn (x) (+ x 1)) '#(1 2 3)) :reverse) :collect))
+(test::assert-equal '() ((iter-map (fn (x) (+ x 1)) nil) :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-filter"),
+        Expression::make_function(
+            builtin_iter_filter,
+            r#"Usage: (iter-filter predicate items) -> iterator
+
+Lazily produce only the items (anything make-iter takes) that predicate is
+true for.  It is double ended if items is.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(1 3) ((iter-filter (fn (x) (not (= x 2))) '(1 2 3)) :collect))
+(test::assert-equal '(2 4) ((iter-filter (fn (x) (= 0 (% x 2))) (iter-range 1 6)) :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-take"),
+        Expression::make_function(
+            builtin_iter_take,
+            r#"Usage: (iter-take items n) -> iterator
+
+Iterator over the first n items of items (anything make-iter takes).  items is
+not read past the nth item so it can be infinite.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(0 1) ((iter-take (iter-range 10) 2) :collect))
+(test::assert-equal '(0 1) ((iter-take '(0 1) 5) :collect))
+(def test-take-iter (make-iter '(0 1 2 3)))
+(test::assert-equal '(0 1) ((iter-take test-take-iter 2) :collect))
+(test::assert-equal '(2 3) (test-take-iter :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-skip"),
+        Expression::make_function(
+            builtin_iter_skip,
+            r#"Usage: (iter-skip items n) -> iterator
+
+Iterator over items (anything make-iter takes) after the first n.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(2 3) ((iter-skip '(0 1 2 3) 2) :collect))
+(test::assert-equal '() ((iter-skip '(0 1) 5) :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-zip"),
+        Expression::make_function(
+            builtin_iter_zip,
+            r#"Usage: (iter-zip first second) -> iterator
+
+Iterator over pairs (first-item . second-item) taken from first and second
+(anything make-iter takes), it ends when either one does.
+
+Section: iterator
+
+Example:
+(test::assert-equal '((1 . :a) (2 . :b)) ((iter-zip '(1 2 3) '#(:a :b)) :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-chain"),
+        Expression::make_function(
+            builtin_iter_chain,
+            r#"Usage: (iter-chain items*) -> iterator
+
+Iterator over each of the items (anything make-iter takes) in turn.  It is
+double ended if all the items are.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(0 1 2 3 4) ((iter-chain '(0 1) nil '#(2 3) (iter-range 4 5)) :collect))
+(test::assert-equal '(3 2 1 0) (((iter-chain '(0 1) '#(2 3)) :reverse) :collect))
+(test::assert-equal '() ((iter-chain) :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-enumerate"),
+        Expression::make_function(
+            builtin_iter_enumerate,
+            r#"Usage: (iter-enumerate items) -> iterator
+
+Iterator over pairs (index . item) for items (anything make-iter takes), the
+index starts at 0.
+
+Section: iterator
+
+Example:
+(test::assert-equal '((0 . #\a) (1 . #\b)) ((iter-enumerate "ab") :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-flat-map"),
+        Expression::make_function(
+            builtin_iter_flat_map,
+            r#"Usage: (iter-flat-map function items) -> iterator
+
+Apply function to each item of items (anything make-iter takes), function
+returns something make-iter takes and the resulting items are produced in turn.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(1 1 2 2) ((iter-flat-map (fn (x) (list x x)) '(1 2)) :collect))
+(test::assert-equal '(0 0 1) ((iter-flat-map (fn (x) (iter-range x)) '(0 1 2)) :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-take-while"),
+        Expression::make_function(
+            builtin_iter_take_while,
+            r#"Usage: (iter-take-while predicate items) -> iterator
+
+Iterator over items (anything make-iter takes) up to the first one predicate
+is not true for.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(1 2) ((iter-take-while (fn (x) (< x 3)) '(1 2 3 1)) :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-chunk"),
+        Expression::make_function(
+            builtin_iter_chunk,
+            r#"Usage: (iter-chunk items size) -> iterator
+
+Iterator over vectors of size items from items (anything make-iter takes), the
+last vector may be shorter.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(#(0 1) #(2 3) #(4)) ((iter-chunk (iter-range 5) 2) :collect))
+(test::assert-error (iter-chunk '(1) 0))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-window"),
+        Expression::make_function(
+            builtin_iter_window,
+            r#"Usage: (iter-window items size) -> iterator
+
+Iterator over vectors of each size consecutive items from items (anything
+make-iter takes), the window moves forward one item at a time.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(#(0 1 2) #(1 2 3)) ((iter-window (iter-range 4) 3) :collect))
+(test::assert-equal '() ((iter-window '(1 2) 3) :collect))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iter-dedup"),
+        Expression::make_function(
+            builtin_iter_dedup,
+            r#"Usage: (iter-dedup items) -> iterator
+
+Iterator over items (anything make-iter takes) with runs of equal? items
+reduced to the first one.
+
+Section: iterator
+
+Example:
+(test::assert-equal '(1 2 1 "a") ((iter-dedup '(1 1 2 1 "a" "a")) :collect))
+"#,
+        ),
+    );
+}
@@ -0,0 +1,695 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::hash::BuildHasher;
+use std::io::{self, Read, Write};
+
+use crate::builtins_hashmap::{display_key, text_key, LispMap};
+use crate::builtins_io::file_read_line;
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::types::*;
+
+const JSON_DEFAULT_INDENT: usize = 2;
+// Deepest nesting of arrays and objects read or written (both recurse, and a
+// value that contains itself would never finish writing).
+const JSON_MAX_DEPTH: usize = 512;
+
+struct JsonParser<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn new(text: &'a str) -> Self {
+        JsonParser {
+            text,
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    fn error(&self, msg: &str) -> LispError {
+        let consumed = &self.text[..self.pos];
+        let line = consumed.matches('\n').count() + 1;
+        let col = consumed.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        LispError::new(format!(
+            "json-read: {} at line {}, column {}",
+            msg, line, col
+        ))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(ch) = self.peek() {
+            if ch == ' ' || ch == '\t' || ch == '\n' || ch == '\r' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), LispError> {
+        match self.peek() {
+            Some(ch) if ch == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(ch) => Err(self.error(&format!("expected '{}' found '{}'", expected, ch))),
+            None => Err(self.error(&format!("expected '{}' found end of input", expected))),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Expression) -> Result<Expression, LispError> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+
+    fn parse_document(&mut self) -> Result<Expression, LispError> {
+        self.skip_ws();
+        let value = self.parse_value()?;
+        self.skip_ws();
+        if self.pos < self.text.len() {
+            Err(self.error("unexpected data after JSON value"))
+        } else {
+            Ok(value)
+        }
+    }
+
+    // Parse a nested array or object, limiting the depth so deeply nested input
+    // is an error rather than a stack overflow.
+    fn parse_nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Expression, LispError>,
+    ) -> Result<Expression, LispError> {
+        if self.depth >= JSON_MAX_DEPTH {
+            return Err(self.error(&format!("nesting deeper than {} levels", JSON_MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let res = parse(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn parse_value(&mut self) -> Result<Expression, LispError> {
+        match self.peek() {
+            Some('{') => self.parse_nested(Self::parse_object),
+            Some('[') => self.parse_nested(Self::parse_array),
+            Some('"') => {
+                let s = self.parse_string()?;
+                Ok(Expression::alloc_data(ExpEnum::String(s.into(), None)))
+            }
+            Some('t') => self.literal("true", Expression::make_true()),
+            Some('f') => self.literal("false", Expression::make_false()),
+            Some('n') => self.literal("null", Expression::make_nil()),
+            Some(ch) if ch == '-' || ch.is_ascii_digit() => self.parse_number(),
+            Some(ch) => Err(self.error(&format!("unexpected character '{}'", ch))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Expression, LispError> {
+        self.expect('{')?;
+        let mut map = LispMap::new();
+        self.skip_ws();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Expression::alloc_data(ExpEnum::HashMap(map)));
+        }
+        loop {
+            self.skip_ws();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_ws();
+            self.expect(':')?;
+            self.skip_ws();
+            let value = self.parse_value()?;
+            map.insert(text_key(key.into()), value);
+            self.skip_ws();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+        Ok(Expression::alloc_data(ExpEnum::HashMap(map)))
+    }
+
+    fn parse_array(&mut self) -> Result<Expression, LispError> {
+        self.expect('[')?;
+        let mut list = Vec::new();
+        self.skip_ws();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Expression::with_list(list));
+        }
+        loop {
+            self.skip_ws();
+            list.push(self.parse_value()?);
+            self.skip_ws();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+        Ok(Expression::with_list(list))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, LispError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.next_char().and_then(|ch| ch.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err(self.error("invalid \\u escape")),
+            }
+        }
+        Ok(code)
+    }
+
+    fn parse_string(&mut self) -> Result<String, LispError> {
+        self.expect('"')?;
+        let mut res = String::new();
+        loop {
+            let start = self.pos;
+            match self.next_char() {
+                Some('"') => break,
+                Some('\\') => match self.next_char() {
+                    Some('"') => res.push('"'),
+                    Some('\\') => res.push('\\'),
+                    Some('/') => res.push('/'),
+                    Some('b') => res.push('\u{0008}'),
+                    Some('f') => res.push('\u{000c}'),
+                    Some('n') => res.push('\n'),
+                    Some('r') => res.push('\r'),
+                    Some('t') => res.push('\t'),
+                    Some('u') => {
+                        let mut code = self.parse_hex4()?;
+                        // Surrogate pair, the low half must follow.
+                        if (0xd800..0xdc00).contains(&code) {
+                            if !self.text[self.pos..].starts_with("\\u") {
+                                return Err(self.error("unpaired surrogate in \\u escape"));
+                            }
+                            self.pos += 2;
+                            let low = self.parse_hex4()?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return Err(self.error("invalid surrogate pair in \\u escape"));
+                            }
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                        }
+                        match std::char::from_u32(code) {
+                            Some(ch) => res.push(ch),
+                            None => return Err(self.error("invalid \\u escape")),
+                        }
+                    }
+                    _ => {
+                        self.pos = start;
+                        return Err(self.error("invalid escape"));
+                    }
+                },
+                Some(ch) if (ch as u32) < 0x20 => {
+                    self.pos = start;
+                    return Err(self.error("control character in string"));
+                }
+                Some(ch) => res.push(ch),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        Ok(res)
+    }
+
+    fn parse_number(&mut self) -> Result<Expression, LispError> {
+        let start = self.pos;
+        let mut is_float = false;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        let digits = |p: &mut Self| {
+            let digits_start = p.pos;
+            while matches!(p.peek(), Some(ch) if ch.is_ascii_digit()) {
+                p.pos += 1;
+            }
+            p.pos - digits_start
+        };
+        let int_start = self.pos;
+        let int_digits = digits(self);
+        if int_digits == 0 || (int_digits > 1 && &self.text[int_start..int_start + 1] == "0") {
+            self.pos = start;
+            return Err(self.error("invalid number"));
+        }
+        if self.peek() == Some('.') {
+            is_float = true;
+            self.pos += 1;
+            if digits(self) == 0 {
+                return Err(self.error("expected digits after decimal point"));
+            }
+        }
+        if let Some('e') | Some('E') = self.peek() {
+            is_float = true;
+            self.pos += 1;
+            if let Some('+') | Some('-') = self.peek() {
+                self.pos += 1;
+            }
+            if digits(self) == 0 {
+                return Err(self.error("expected digits in exponent"));
+            }
+        }
+        let num = &self.text[start..self.pos];
+        if !is_float {
+            if let Ok(i) = num.parse::<i64>() {
+                return Ok(Expression::alloc_data(ExpEnum::Int(i)));
+            }
+        }
+        // Also catches integers too large for an Int.
+        match num.parse::<f64>() {
+            Ok(f) => Ok(Expression::alloc_data(ExpEnum::Float(f))),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error("invalid number"))
+            }
+        }
+    }
+}
+
+pub fn json_parse(text: &str) -> Result<Expression, LispError> {
+    JsonParser::new(text).parse_document()
+}
+
+fn json_write_str(s: &str, res: &mut String) {
+    res.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            '\u{0008}' => res.push_str("\\b"),
+            '\u{000c}' => res.push_str("\\f"),
+            _ if (ch as u32) < 0x20 => {
+                let _ = write!(res, "\\u{:04x}", ch as u32);
+            }
+            _ => res.push(ch),
+        }
+    }
+    res.push('"');
+}
+
+fn json_newline(indent: Option<usize>, level: usize, res: &mut String) {
+    if let Some(indent) = indent {
+        res.push('\n');
+        for _ in 0..indent * level {
+            res.push(' ');
+        }
+    }
+}
+
+fn json_check_depth(level: usize) -> Result<(), LispError> {
+    if level >= JSON_MAX_DEPTH {
+        Err(LispError::new(format!(
+            "json-write: nesting deeper than {} levels (does the value contain itself?)",
+            JSON_MAX_DEPTH
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn json_write_int(
+    exp: &Expression,
+    indent: Option<usize>,
+    level: usize,
+    res: &mut String,
+) -> Result<(), LispError> {
+    match &exp.get().data {
+        ExpEnum::Nil => res.push_str("null"),
+        ExpEnum::True => res.push_str("true"),
+        ExpEnum::False => res.push_str("false"),
+        ExpEnum::Int(i) => {
+            let _ = write!(res, "{}", i);
+        }
+        ExpEnum::Float(f) => {
+            if !f.is_finite() {
+                return Err(LispError::new(format!(
+                    "json-write: {} can not be represented in JSON",
+                    f
+                )));
+            }
+            // Debug keeps the decimal point (1.0 not 1).
+            let _ = write!(res, "{:?}", f);
+        }
+        ExpEnum::String(s, _) => json_write_str(s, res),
+        ExpEnum::Char(s) => json_write_str(s, res),
+        ExpEnum::CodePoint(c) => json_write_str(&c.to_string(), res),
+        ExpEnum::Symbol(s, _) => json_write_str(s.trim_start_matches(':'), res),
+        ExpEnum::Vector(_) | ExpEnum::Pair(_, _) => {
+            json_check_depth(level)?;
+            if let ExpEnum::Pair(_, _) = &exp.get().data {
+                if !is_proper_list(exp) {
+                    return Err(LispError::new("json-write: can not write an improper list"));
+                }
+            }
+            res.push('[');
+            let mut first = true;
+            for item in exp.iter() {
+                if !first {
+                    res.push(',');
+                }
+                first = false;
+                json_newline(indent, level + 1, res);
+                json_write_int(&item, indent, level + 1, res)?;
+            }
+            if !first {
+                json_newline(indent, level, res);
+            }
+            res.push(']');
+        }
+        ExpEnum::HashMap(map) => {
+            json_check_depth(level)?;
+            // Sorted so output is stable, keywords are written without the ':'.
+            let mut keys: Vec<(&str, &Cow<'static, str>)> = map
+                .keys()
+                .map(|key| (display_key(key).trim_start_matches(':'), key))
+                .collect();
+            keys.sort();
+            res.push('{');
+            let mut first = true;
+            for (name, key) in keys {
+                if !first {
+                    res.push(',');
+                }
+                first = false;
+                json_newline(indent, level + 1, res);
+                json_write_str(name, res);
+                res.push(':');
+                if indent.is_some() {
+                    res.push(' ');
+                }
+                json_write_int(&map[key], indent, level + 1, res)?;
+            }
+            if !first {
+                json_newline(indent, level, res);
+            }
+            res.push('}');
+        }
+        ExpEnum::Values(v) if !v.is_empty() => json_write_int(&v[0], indent, level, res)?,
+        ExpEnum::Wrapper(inner) => json_write_int(inner, indent, level, res)?,
+        _ => {
+            return Err(LispError::new(format!(
+                "json-write: {} can not be represented in JSON",
+                exp.display_type()
+            )))
+        }
+    }
+    Ok(())
+}
+
+pub fn json_string(exp: &Expression, indent: Option<usize>) -> Result<String, LispError> {
+    let mut res = String::new();
+    json_write_int(exp, indent, 0, &mut res)?;
+    Ok(res)
+}
+
+// Parse :pretty and :indent n options.
+fn json_indent(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Option<usize>, LispError> {
+    let mut indent = None;
+    let opts = make_args(environment, args)?;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match &opt.get().data {
+            ExpEnum::Symbol(":pretty", _) => {
+                indent.get_or_insert(JSON_DEFAULT_INDENT);
+            }
+            ExpEnum::Symbol(":indent", _) => match opts.next().map(|n| n.get().data.clone()) {
+                Some(ExpEnum::Int(n)) if n >= 0 => indent = Some(n as usize),
+                _ => {
+                    return Err(LispError::new(format!(
+                        "{}: :indent requires a non-negative integer",
+                        fn_name
+                    )))
+                }
+            },
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: invalid option {}, expected :pretty or :indent",
+                    fn_name, opt
+                )))
+            }
+        }
+    }
+    Ok(indent)
+}
+
+fn file_read_all(file: &mut FileState, fn_name: &str) -> Result<String, LispError> {
+    let mut text = String::new();
+    match file {
+        FileState::Read(Some(f_iter), _) => {
+            for ch in f_iter {
+                text.push_str(&ch);
+            }
+        }
+        FileState::Read(None, _) => {}
+        FileState::ReadBinary(f) => {
+            f.read_to_string(&mut text)?;
+        }
+        FileState::Stdin => {
+            io::stdin().read_to_string(&mut text)?;
+        }
+        _ => {
+            return Err(LispError::new(format!(
+                "{}: requires a file opened for reading",
+                fn_name
+            )))
+        }
+    }
+    Ok(text)
+}
+
+fn builtin_json_read(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "json-read";
+    let source = param_eval(environment, args, fn_name)?;
+    let ndjson = match param_eval_optional(environment, args)? {
+        Some(opt) => match &opt.get().data {
+            ExpEnum::Symbol(":ndjson", _) => true,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: invalid option {}, expected :ndjson",
+                    fn_name, opt
+                )))
+            }
+        },
+        None => false,
+    };
+    params_done(args, fn_name)?;
+    let source_d = source.get();
+    match &source_d.data {
+        ExpEnum::String(s, _) => {
+            let text = s.to_string();
+            drop(source_d);
+            json_parse(&text)
+        }
+        ExpEnum::File(file) => {
+            let file = file.clone();
+            drop(source_d);
+            let mut file = file.borrow_mut();
+            if ndjson {
+                // Skip blank lines, :eof at the end of the stream (JSON has no
+                // keywords so this can not be a value).
+                while let Some(line) = file_read_line(&mut file, fn_name)? {
+                    if !line.trim().is_empty() {
+                        return json_parse(&line);
+                    }
+                }
+                Ok(Expression::alloc_data(ExpEnum::Symbol(
+                    environment.interner.intern(":eof"),
+                    SymLoc::None,
+                )))
+            } else {
+                let text = file_read_all(&mut file, fn_name)?;
+                json_parse(&text)
+            }
+        }
+        _ => Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "String or File",
+            &source.display_type(),
+        ))),
+    }
+}
+
+fn builtin_json_str(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "json-str";
+    let value = param_eval(environment, args, fn_name)?;
+    let indent = json_indent(environment, args, fn_name)?;
+    let text = json_string(&value, indent)?;
+    Ok(Expression::alloc_data(ExpEnum::String(text.into(), None)))
+}
+
+fn builtin_json_write(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "json-write";
+    let file = param_eval(environment, args, fn_name)?;
+    let value = param_eval(environment, args, fn_name)?;
+    let indent = json_indent(environment, args, fn_name)?;
+    let mut text = json_string(&value, indent)?;
+    text.push('\n');
+    let file_d = file.get();
+    if let ExpEnum::File(file) = &file_d.data {
+        match &mut *file.borrow_mut() {
+            FileState::Write(f) => f.write_all(text.as_bytes())?,
+            FileState::Stdout => io::stdout().write_all(text.as_bytes())?,
+            FileState::Stderr => io::stderr().write_all(text.as_bytes())?,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: requires a file opened for writing",
+                    fn_name
+                )))
+            }
+        }
+        Ok(Expression::make_nil())
+    } else {
+        Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "File",
+            &file.display_type(),
+        )))
+    }
+}
+
+pub fn add_json_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("json-read"),
+        Expression::make_function(
+            builtin_json_read,
+            r#"Usage: (json-read string-or-file :ndjson?) -> value
+
+Parse JSON from a string or a file opened for reading.  Objects become hash maps
+(with string keys), arrays become vectors, numbers become Int (or Float if they
+have a fraction, exponent or do not fit an Int) and null/true/false become
+nil/#t/#f.  Malformed input raises an error with the line and column of the
+problem.
+
+With :ndjson only the next line of the file is parsed (newline delimited JSON,
+one value per line), blank lines are skipped and :eof is returned at the end of
+the file (a null line is nil).  Use this to stream large inputs one record at a
+time.  Arrays and objects nested more than 512 deep are an error.
+
+Section: file
+
+Example:
+(def json-test (json-read "{\"name\": \"sl-sh\", \"tags\": [1, 2.5, true, null], \"nested\": {\"ok\": false}}"))
+(test::assert-equal "sl-sh" (hash-get json-test "name"))
+(test::assert-equal '#(1 2.5 #t nil) (hash-get json-test "tags"))
+(test::assert-false (hash-get (hash-get json-test "nested") "ok"))
+(test::assert-equal "snow ☃ \n" (json-read "\"snow \\u2603 \\n\""))
+(test::assert-error-msg (json-read "{\"a\": 1,\n \"b\" 2}") "json-read: expected ':' found '2' at line 2, column 6")
+(with-temp-file (fn (tmp)
+    (let ((out (open tmp :create :truncate)))
+        (write-line out "{\"id\": 1}")
+        (write-line out "")
+        (write-line out "{\"id\": 2}")
+        (write-line out "null")
+        (close out))
+    (let ((in (open tmp :read)))
+        (test::assert-equal 1 (hash-get (json-read in :ndjson) "id"))
+        (test::assert-equal 2 (hash-get (json-read in :ndjson) "id"))
+        (test::assert-false (json-read in :ndjson))
+        (test::assert-equal :eof (json-read in :ndjson))
+        (close in))))
+(test::assert-error-msg (json-read (str-cat-list "" (make-vec 1000 "["))) "json-read: nesting deeper than 512 levels at line 1, column 513")
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("json-str"),
+        Expression::make_function(
+            builtin_json_str,
+            r#"Usage: (json-str value :pretty? :indent n?) -> string
+
+Return value as a JSON string.  Hash maps become objects (keys are sorted so the
+output is stable), vectors and lists become arrays, nil is null and #t/#f are
+true/false.  Strings, chars and symbols become strings (a keyword loses its
+leading colon).  Output is compact unless :pretty (two space indent) or :indent
+n is given.  Values with no JSON form (functions, processes, NaN, etc) or nested
+more than 512 levels deep (a value that contains itself) raise an error.
+
+Section: file
+
+Example:
+(test::assert-equal "{\"a\":[1,2.0,null,true],\"b\":\"x\\\"y\"}" (json-str (make-hash '(("b" . "x\"y") ("a" . #(1 2.0 nil #t))))))
+(test::assert-equal "{\n  \"a\": [\n    1,\n    :two\n  ]\n}" (str-replace (json-str (make-hash '(("a" . #(1 :two)))) :pretty) "\"two\"" ":two"))
+(test::assert-equal "[\n    {}\n]" (json-str (vec (make-hash)) :indent 4))
+(test::assert-equal (json-str (json-read "[1,{\"x\":[]}]")) "[1,{\"x\":[]}]")
+(test::assert-error (json-str (fn () nil)))
+(def json-cycle (vec 1))
+(vec-push! json-cycle json-cycle)
+(test::assert-error-msg (json-str json-cycle) "json-write: nesting deeper than 512 levels (does the value contain itself?)")
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("json-write"),
+        Expression::make_function(
+            builtin_json_write,
+            r#"Usage: (json-write file value :pretty? :indent n?) -> nil
+
+Write value as JSON followed by a newline to a file opened for writing (or
+*stdout*/*stderr*).  See json-str for how values are converted.  Writing one
+compact value per call produces newline delimited JSON that json-read can stream
+back with :ndjson.
+
+Section: file
+
+Example:
+(with-temp-file (fn (tmp)
+    (let ((out (open tmp :create :truncate)))
+        (json-write out (make-hash '(("id" . 1))))
+        (json-write out '#(1 2))
+        (close out))
+    (let ((in (open tmp :read)))
+        (test::assert-equal "{\"id\":1}\n" (read-line in))
+        (test::assert-equal '#(1 2) (json-read in :ndjson))
+        (close in))))
+"#,
+        ),
+    );
+}
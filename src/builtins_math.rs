@@ -1,10 +1,12 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::hash::BuildHasher;
 
 use crate::builtins_util::*;
 use crate::environment::*;
 use crate::eval::eval;
 use crate::interner::*;
+use crate::numeric::*;
 use crate::types::*;
 
 fn norm_value(arg: Expression) -> Expression {
@@ -21,6 +23,61 @@ fn norm_value(arg: Expression) -> Expression {
     }
 }
 
+// Fold the int args (at least one) with op.
+fn bit_fold(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+    op: fn(i64, i64) -> i64,
+) -> Result<Expression, LispError> {
+    let mut args = make_args(environment, args)?;
+    let ints = parse_list_of_ints(environment, &mut args)?;
+    match ints.split_first() {
+        Some((first, rest)) => Ok(Expression::alloc_data(ExpEnum::Int(
+            rest.iter().fold(*first, |acc, i| op(acc, *i)),
+        ))),
+        None => Err(LispError::new(format!(
+            "{}: expected at least one int",
+            fn_name
+        ))),
+    }
+}
+
+// The int and bit count (0-63) for a shift or rotate.
+fn shift_args(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<(i64, u32), LispError> {
+    let int = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    let bits = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    params_done(args, fn_name)?;
+    if !(0..64).contains(&bits) {
+        return Err(LispError::new(format!(
+            "{}: bit count must be between 0 and 63, got {}",
+            fn_name, bits
+        )));
+    }
+    Ok((int, bits as u32))
+}
+
+// The exact value of a number arg, a float is its shortest decimal form.
+fn exact_param(fn_name: &str, arg: &Expression) -> Result<ExactNum, LispError> {
+    match Num::from_exp(arg) {
+        Some(n) => n
+            .exact()
+            .or_else(|| float_exact(n.to_f64()))
+            .ok_or_else(|| {
+                LispError::new(format!("{}: {} has no exact value", fn_name, n.to_f64()))
+            }),
+        None => Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "number",
+            &arg.display_type(),
+        ))),
+    }
+}
+
 pub fn add_root_math_builtins<S: BuildHasher>(
     interner: &mut Interner,
     data: &mut HashMap<&'static str, (Expression, String), S>,
@@ -31,21 +88,12 @@ pub fn add_root_math_builtins<S: BuildHasher>(
             |environment: &mut Environment,
              args: &mut dyn Iterator<Item = Expression>|
              -> Result<Expression, LispError> {
-                let mut sum = 0;
-                let mut sum_float = 0.0;
-                let mut is_float = false;
+                let mut sum = Num::Int(0);
                 for arg in args {
                     let a = norm_value(eval(environment, arg)?);
-                    let a_d = a.get();
-                    match &a_d.data {
-                        ExpEnum::Int(i) if is_float => sum_float += *i as f64,
-                        ExpEnum::Int(i) => sum += i,
-                        ExpEnum::Float(f) if is_float => sum_float += f,
-                        ExpEnum::Float(f) => {
-                            is_float = true;
-                            sum_float = (sum as f64) + f;
-                        }
-                        _ => {
+                    match Num::from_exp(&a) {
+                        Some(n) => sum = sum.checked_add(n)?,
+                        None => {
                             return Err(LispError::new(format!(
                                 "Can only add numbers, got {}/{}.",
                                 a.display_type(),
@@ -54,11 +102,7 @@ pub fn add_root_math_builtins<S: BuildHasher>(
                         }
                     }
                 }
-                if is_float {
-                    Ok(Expression::alloc_data(ExpEnum::Float(sum_float)))
-                } else {
-                    Ok(Expression::alloc_data(ExpEnum::Int(sum)))
-                }
+                Ok(sum.into_exp())
             },
             "Usage: (+ number*)
 
@@ -78,6 +122,13 @@ Example:
 (test::assert-equal 6.5 (+ 1 5.5))
 (test::assert-equal 7 (+ 1 2 4))
 (test::assert-error (+ 1 2 4 \"5\"))
+(test::assert-equal \"5/6\" (str (+ 1/2 1/3)))
+(test::assert-equal 1 (+ 1/2 1/2))
+(test::assert-equal \"0.30\" (str (+ #d0.1 #d0.20)))
+(test::assert-equal \"Decimal\" (type (+ 1 #d0.5)))
+(test::assert-equal \"Rational\" (type (+ 1/3 #d0.5)))
+(test::assert-equal \"Float\" (type (+ 1/3 0.5)))
+(test::assert-error-msg (+ 9223372036854775807/2 9223372036854775807/3) \"rational overflow\")
 ",
         ),
     );
@@ -88,41 +139,23 @@ Example:
             |environment: &mut Environment,
              args: &mut dyn Iterator<Item = Expression>|
              -> Result<Expression, LispError> {
-                let mut res = 0;
-                let mut res_float = 0.0;
-                let mut is_float = false;
-                if let Ok(a) = param_eval(environment, args, "multiply") {
-                    match norm_value(a).get().data {
-                        ExpEnum::Int(i) => res = i,
-                        ExpEnum::Float(f) => {
-                            is_float = true;
-                            res_float = f;
-                        }
-                        _ => return Err(LispError::new("Can only multiply numbers.")),
+                let mut res = if let Ok(a) = param_eval(environment, args, "multiply") {
+                    match Num::from_exp(&a) {
+                        Some(n) => n,
+                        None => return Err(LispError::new("Can only multiply numbers.")),
                     }
                 } else {
                     // Missing args so return 1.
                     return Ok(Expression::alloc_data(ExpEnum::Int(1)));
-                }
+                };
                 for a in args {
                     let a = norm_value(eval(environment, a)?);
-                    let a_d = a.get();
-                    match &a_d.data {
-                        ExpEnum::Int(i) if is_float => res_float *= *i as f64,
-                        ExpEnum::Int(i) => res *= i,
-                        ExpEnum::Float(f) if is_float => res_float *= f,
-                        ExpEnum::Float(f) => {
-                            is_float = true;
-                            res_float = (res as f64) * f;
-                        }
-                        _ => return Err(LispError::new("Can only multiply numbers.")),
+                    match Num::from_exp(&a) {
+                        Some(n) => res = res.checked_mul(n)?,
+                        None => return Err(LispError::new("Can only multiply numbers.")),
                     }
                 }
-                if is_float {
-                    Ok(Expression::alloc_data(ExpEnum::Float(res_float)))
-                } else {
-                    Ok(Expression::alloc_data(ExpEnum::Int(res)))
-                }
+                Ok(res.into_exp())
             },
             "Usage: (* number*)
 
@@ -145,6 +178,8 @@ Example:
 (test::assert-equal 16.0 (* 2.0 2.0 4.0))
 (test::assert-equal 55.0000000001 (* 100 0.55))
 (test::assert-error (* 1 2 4 \"5\"))
+(test::assert-equal \"1/6\" (str (* 1/2 1/3)))
+(test::assert-equal \"2.5000\" (str (* #d1.25 #d2.00)))
 ",
         ),
     );
@@ -155,46 +190,23 @@ Example:
             |environment: &mut Environment,
              args: &mut dyn Iterator<Item = Expression>|
              -> Result<Expression, LispError> {
-                let mut res = 0;
-                let mut res_float = 0.0;
-                let mut is_float = false;
+                let mut res = match Num::from_exp(&param_eval(environment, args, "subtract")?) {
+                    Some(n) => n,
+                    None => return Err(LispError::new("Can only subtract numbers.")),
+                };
                 let mut has_two = false;
-                match norm_value(param_eval(environment, args, "subtract")?)
-                    .get()
-                    .data
-                {
-                    ExpEnum::Int(i) => res = i,
-                    ExpEnum::Float(f) => {
-                        is_float = true;
-                        res_float = f;
-                    }
-                    _ => return Err(LispError::new("Can only subtract numbers.")),
-                }
                 for a in args {
                     has_two = true;
                     let a = norm_value(eval(environment, a)?);
-                    let a_d = a.get();
-                    match &a_d.data {
-                        ExpEnum::Int(i) if is_float => res_float -= *i as f64,
-                        ExpEnum::Int(i) => res -= i,
-                        ExpEnum::Float(f) if is_float => res_float -= f,
-                        ExpEnum::Float(f) => {
-                            is_float = true;
-                            res_float = (res as f64) - f;
-                        }
-                        _ => return Err(LispError::new("Can only subtract numbers.")),
+                    match Num::from_exp(&a) {
+                        Some(n) => res = res.checked_sub(n)?,
+                        None => return Err(LispError::new("Can only subtract numbers.")),
                     }
                 }
-                if is_float {
-                    if has_two {
-                        Ok(Expression::alloc_data(ExpEnum::Float(res_float)))
-                    } else {
-                        Ok(Expression::alloc_data(ExpEnum::Float(-res_float)))
-                    }
-                } else if has_two {
-                    Ok(Expression::alloc_data(ExpEnum::Int(res)))
+                if has_two {
+                    Ok(res.into_exp())
                 } else {
-                    Ok(Expression::alloc_data(ExpEnum::Int(-res)))
+                    Ok(res.negate()?.into_exp())
                 }
             },
             "Usage: (- number+)
@@ -213,6 +225,11 @@ Example:
 (test::assert-equal -4.5 (- 1 5.5))
 (test::assert-equal 4 (- 10 2 4))
 (test::assert-equal 4.9 (- 10.9 2 4))
+(test::assert-equal \"-1/2\" (str (- 1/2)))
+(test::assert-equal \"1/6\" (str (- 1/2 1/3)))
+(test::assert-equal \"#d-0.75\" (str-readable (- #d0.25 1)))
+(test::assert-equal \"-1/3\" (str-readable (- 1/3)))
+(test::assert-error-msg (- -9223372036854775808) \"integer overflow\")
 ",
         ),
     );
@@ -223,53 +240,30 @@ Example:
             |environment: &mut Environment,
              args: &mut dyn Iterator<Item = Expression>|
              -> Result<Expression, LispError> {
-                let mut res = 0;
-                let mut res_float = 0.0;
-                let mut is_float = false;
+                let mut res = match Num::from_exp(&param_eval(environment, args, "divide")?) {
+                    Some(n) => n,
+                    None => return Err(LispError::new("Can only divide numbers.")),
+                };
                 let mut has_two = false;
-                match norm_value(param_eval(environment, args, "divide")?)
-                    .get()
-                    .data
-                {
-                    ExpEnum::Int(i) => res = i,
-                    ExpEnum::Float(f) => {
-                        is_float = true;
-                        res_float = f;
-                    }
-                    _ => return Err(LispError::new("Can only divide numbers.")),
-                }
                 for a in args {
                     has_two = true;
                     let a = norm_value(eval(environment, a)?);
-                    let a_d = a.get();
-                    match &a_d.data {
-                        ExpEnum::Int(i) if *i == 0 => {
-                            return Err(LispError::new("Can not divide by 0."))
-                        }
-                        ExpEnum::Int(i) if is_float => res_float /= *i as f64,
-                        ExpEnum::Int(i) => res /= i,
-                        ExpEnum::Float(f) if *f == 0.0 => {
-                            return Err(LispError::new("Can not divide by 0.0."))
-                        }
-                        ExpEnum::Float(f) if is_float => res_float /= f,
-                        ExpEnum::Float(f) => {
-                            is_float = true;
-                            res_float = (res as f64) / f;
-                        }
-                        _ => return Err(LispError::new("Can only divide numbers.")),
+                    match Num::from_exp(&a) {
+                        Some(n) => res = res.checked_div(n)?,
+                        None => return Err(LispError::new("Can only divide numbers.")),
                     }
                 }
-                if !has_two {
-                    Err(LispError::new("divide requires at least two numbers."))
-                } else if is_float {
-                    Ok(Expression::alloc_data(ExpEnum::Float(res_float)))
+                if has_two {
+                    Ok(res.into_exp())
                 } else {
-                    Ok(Expression::alloc_data(ExpEnum::Int(res)))
+                    Err(LispError::new("divide requires at least two numbers."))
                 }
             },
             "Usage: (/ number+)
 
-Divide a sequence of numbers.  Requires at least two numbers.
+Divide a sequence of numbers.  Requires at least two numbers.  Ints truncate,
+if any number is a rational the result is exact (see rational) and dividing
+decimals gives a decimal when it is exact or else a rational.
 
 Section: math
 Example:
@@ -287,6 +281,10 @@ Example:
 (test::assert-error (/ 1 0))
 (test::assert-error (/ 10 5 0))
 (test::assert-error (/ 10 \"5\" 2))
+(test::assert-equal \"1/3\" (str (/ (rational 1 2) 3 1/2)))
+(test::assert-equal \"2.50\" (str (/ #d10.00 4)))
+(test::assert-equal \"1/3\" (str (/ #d1.00 3)))
+(test::assert-error (/ 1/2 0))
 ",
         ),
     );
@@ -325,6 +323,190 @@ Example:
 (test::assert-error (% 1))
 (test::assert-error (% 1 2 3))
 (test::assert-error (% 1 2.0))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("rational"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let fn_name = "rational";
+                let num = param_eval(environment, args, fn_name)?;
+                let den = param_eval_optional(environment, args)?;
+                params_done(args, fn_name)?;
+                let res = if let Some(den) = den {
+                    let num = num.make_int(environment)?;
+                    let den = den.make_int(environment)?;
+                    if den == 0 {
+                        return Err(LispError::new("rational: denominator can not be 0"));
+                    }
+                    ExactNum::new(num as i128, den as i128)
+                } else {
+                    exact_param(fn_name, &num)?
+                };
+                Ok(res.to_num()?.into_exp())
+            },
+            "Usage: (rational int int) or (rational number)
+
+Exact fraction.  Given two ints returns their exact quotient (/ on ints
+truncates), given one number returns its exact value (a float is its shortest
+decimal form so 0.1 is 1/10).  Whole values are ints.  Rationals can also be
+read as n/d literals (1/3, -2/5) written in lowest terms, anything else (2021/06
+or 10/4) is a symbol, prefix with #r to read any fraction (#r10/4 is 5/2).
+
+Section: math
+
+Example:
+(test::assert-equal \"1/3\" (str (rational 1 3)))
+(test::assert-equal \"1/3\" (str (rational 2 6)))
+(test::assert-equal \"-1/2\" (str (rational 1 -2)))
+(test::assert-equal \"Int\" (type (rational 4 2)))
+(test::assert-equal 1/10 (rational 0.1))
+(test::assert-equal 3/4 (rational #d0.75))
+(test::assert-equal 5 (rational 5))
+(test::assert-error (rational 1 0))
+(test::assert-error (rational 1.5 2))
+(test::assert-error (rational \"1/2\"))
+(test::assert-equal \"Rational\" (type (read \"2/3\")))
+(test::assert-equal \"Symbol\" (type (read \"2021/06\")))
+(test::assert-equal \"Symbol\" (type (read \"10/4\")))
+(test::assert-equal 5/2 (read \"#r10/4\"))
+(test::assert-error (read \"#r1/0\"))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("numerator"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let fn_name = "numerator";
+                let arg = param_eval(environment, args, fn_name)?;
+                params_done(args, fn_name)?;
+                let num = exact_param(fn_name, &arg)?.numerator();
+                Ok(Expression::alloc_data(ExpEnum::Int(num as i64)))
+            },
+            "Usage: (numerator number)
+
+Numerator of the number as a fraction in lowest terms.
+
+Section: math
+
+Example:
+(test::assert-equal 1 (numerator #r2/6))
+(test::assert-equal -3 (numerator -3/4))
+(test::assert-equal 5 (numerator 5))
+(test::assert-equal 5 (numerator #d1.25))
+(test::assert-error (numerator \"1/2\"))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("denominator"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let fn_name = "denominator";
+                let arg = param_eval(environment, args, fn_name)?;
+                params_done(args, fn_name)?;
+                let den = exact_param(fn_name, &arg)?.denominator();
+                Ok(Expression::alloc_data(ExpEnum::Int(den as i64)))
+            },
+            "Usage: (denominator number)
+
+Denominator (always positive) of the number as a fraction in lowest terms.
+
+Section: math
+
+Example:
+(test::assert-equal 3 (denominator #r2/6))
+(test::assert-equal 4 (denominator -3/4))
+(test::assert-equal 1 (denominator 5))
+(test::assert-equal 4 (denominator #d1.25))
+(test::assert-error (denominator \"1/2\"))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("decimal"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let fn_name = "decimal";
+                let value = param_eval(environment, args, fn_name)?;
+                let places = param_eval_optional(environment, args)?;
+                params_done(args, fn_name)?;
+                // Decimals and strings keep their places ("1.50" is #d1.50).
+                let as_decimal = match &value.get().data {
+                    ExpEnum::Decimal(d) => Some(*d),
+                    ExpEnum::String(s, _) => Some(Decimal::parse(s.trim()).ok_or_else(|| {
+                        LispError::new(format!("{}: {} is not a decimal number", fn_name, s))
+                    })?),
+                    _ => None,
+                };
+                let res = match (places, as_decimal) {
+                    (Some(places), _) => {
+                        let places = places.make_int(environment)?;
+                        let places = u32::try_from(places).map_err(|_| {
+                            LispError::new(format!(
+                                "{}: places must be positive, got {}",
+                                fn_name, places
+                            ))
+                        })?;
+                        let exact = match as_decimal {
+                            Some(d) => Num::Decimal(d).exact().unwrap(),
+                            None => exact_param(fn_name, &value)?,
+                        };
+                        Decimal::round(exact, places)?
+                    }
+                    (None, Some(d)) => d,
+                    (None, None) => {
+                        let exact = exact_param(fn_name, &value)?;
+                        Decimal::exactly(exact, 0).ok_or_else(|| {
+                            LispError::new(format!(
+                                "{}: {} has no exact decimal value, give the places to round to",
+                                fn_name, value
+                            ))
+                        })?
+                    }
+                };
+                Ok(Num::Decimal(res).into_exp())
+            },
+            "Usage: (decimal number-or-string places?)
+
+Exact decimal number, these keep their places through arithmetic (#d1.50 + 1 is
+#d2.50) so suit money.  Without places the value must have an exact decimal form
+(1/4 but not 1/3), with places it is rounded (halves away from zero) to that many
+places.  A float is its shortest decimal form (2.675 rounds to 2.68).  Decimals
+can also be read as literals with #d (#d19.99), without the #d 10M is a symbol.
+
+Section: math
+
+Example:
+(test::assert-equal \"Decimal\" (type (decimal 5)))
+(test::assert-equal \"0.25\" (str (decimal 1/4)))
+(test::assert-equal \"0.33\" (str (decimal 1/3 2)))
+(test::assert-equal \"-0.67\" (str (decimal -2/3 2)))
+(test::assert-equal \"2.68\" (str (decimal 2.675 2)))
+(test::assert-equal \"19.90\" (str (decimal \"19.90\")))
+(test::assert-equal \"5.000\" (str (decimal 5 3)))
+(test::assert-equal \"1.3\" (str (decimal #d1.25 1)))
+(test::assert-equal #d19.99 (decimal 19.99))
+(test::assert-error (decimal 1/3))
+(test::assert-error (decimal \"abc\"))
+(test::assert-error (decimal 1 -1))
+(test::assert-equal \"Decimal\" (type (read \"#d-0.50\")))
+(test::assert-equal \"Symbol\" (type (read \"10M\")))
+(test::assert-error (read \"#d1.2.3\"))
 ",
         ),
     );
@@ -967,6 +1149,260 @@ Section: math
 Example:
 (ns-import 'math)
 (test::assert-equal 0 (- *pi* (to-radians 180)))
+",
+        ),
+    );
+    data.insert(
+        interner.intern("bit-and"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                bit_fold(environment, args, "bit-and", |a, b| a & b)
+            },
+            "Usage: (bit-and int+)
+
+Bitwise and of the ints.
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal #x0f (bit-and #xff #x0f))
+(test::assert-equal #b100 (bit-and #b110 #b101 #b111))
+(test::assert-equal #xffffff00 (bit-and #xffffffff (bit-not #xff)))
+(test::assert-equal 5 (bit-and 5))
+(test::assert-error (bit-and))
+(test::assert-error (bit-and 1 1.0))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("bit-or"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                bit_fold(environment, args, "bit-or", |a, b| a | b)
+            },
+            "Usage: (bit-or int+)
+
+Bitwise or of the ints.
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal #o755 (bit-or #o700 #o050 #o005))
+(test::assert-equal #xff (bit-or #xf0 #x0f))
+(test::assert-error (bit-or))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("bit-xor"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                bit_fold(environment, args, "bit-xor", |a, b| a ^ b)
+            },
+            "Usage: (bit-xor int+)
+
+Bitwise exclusive or of the ints.
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal #b011 (bit-xor #b110 #b101))
+(test::assert-equal #b100 (bit-xor #b110 #b101 #b111))
+(test::assert-equal 0 (bit-xor #xabc #xabc))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("bit-not"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let int = param_eval(environment, args, "bit-not")?.make_int(environment)?;
+                params_done(args, "bit-not")?;
+                Ok(Expression::alloc_data(ExpEnum::Int(!int)))
+            },
+            "Usage: (bit-not int)
+
+Bitwise not (complement) of int, ints are 64 bit two's complement so
+(bit-not n) is (- -1 n).
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal -1 (bit-not 0))
+(test::assert-equal 0 (bit-not -1))
+(test::assert-equal -6 (bit-not 5))
+(test::assert-equal #o022 (bit-and (bit-not #o755) #o777))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("bit-shift-left"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let (int, bits) = shift_args(environment, args, "bit-shift-left")?;
+                Ok(Expression::alloc_data(ExpEnum::Int(int << bits)))
+            },
+            "Usage: (bit-shift-left int bits)
+
+Shift int left by bits (0 to 63), bits shifted past the top are lost.
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal #b1000 (bit-shift-left 1 3))
+(test::assert-equal #xff00 (bit-shift-left #xff 8))
+(test::assert-equal (bit-shift-left 1 63) (- -9223372036854775807 1))
+(test::assert-error (bit-shift-left 1 64))
+(test::assert-error (bit-shift-left 1 -1))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("bit-shift-right"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let (int, bits) = shift_args(environment, args, "bit-shift-right")?;
+                Ok(Expression::alloc_data(ExpEnum::Int(int >> bits)))
+            },
+            "Usage: (bit-shift-right int bits)
+
+Arithmetic shift of int right by bits (0 to 63), the sign bit is copied in
+from the top so negative ints stay negative.
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal #xf (bit-shift-right #xff 4))
+(test::assert-equal -4 (bit-shift-right -16 2))
+(test::assert-equal -1 (bit-shift-right -1 63))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("bit-shift-right-logical"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let (int, bits) = shift_args(environment, args, "bit-shift-right-logical")?;
+                Ok(Expression::alloc_data(ExpEnum::Int(
+                    ((int as u64) >> bits) as i64,
+                )))
+            },
+            "Usage: (bit-shift-right-logical int bits)
+
+Logical shift of int right by bits (0 to 63), zeros are shifted in from the
+top.
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal #xf (bit-shift-right-logical #xff 4))
+(test::assert-equal 1 (bit-shift-right-logical -1 63))
+(test::assert-equal #x3fffffffffffffff (bit-shift-right-logical -1 2))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("bit-rotate-left"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let (int, bits) = shift_args(environment, args, "bit-rotate-left")?;
+                Ok(Expression::alloc_data(ExpEnum::Int(int.rotate_left(bits))))
+            },
+            "Usage: (bit-rotate-left int bits)
+
+Rotate the 64 bits of int left by bits (0 to 63), bits shifted past the top
+come back in at the bottom.
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal #b10 (bit-rotate-left 1 1))
+(test::assert-equal 1 (bit-rotate-left (bit-shift-left 1 63) 1))
+(test::assert-equal #xff0 (bit-rotate-left #xff 4))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("bit-rotate-right"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let (int, bits) = shift_args(environment, args, "bit-rotate-right")?;
+                Ok(Expression::alloc_data(ExpEnum::Int(int.rotate_right(bits))))
+            },
+            "Usage: (bit-rotate-right int bits)
+
+Rotate the 64 bits of int right by bits (0 to 63), bits shifted past the
+bottom come back in at the top.
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal (bit-shift-left 1 63) (bit-rotate-right 1 1))
+(test::assert-equal #xf (bit-rotate-right #xf0 4))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("bit-count"),
+        Expression::make_function(
+            |environment: &mut Environment,
+             args: &mut dyn Iterator<Item = Expression>|
+             -> Result<Expression, LispError> {
+                let int = param_eval(environment, args, "bit-count")?.make_int(environment)?;
+                params_done(args, "bit-count")?;
+                Ok(Expression::alloc_data(
+                    ExpEnum::Int(int.count_ones() as i64),
+                ))
+            },
+            "Usage: (bit-count int)
+
+Number of bits set in int (population count), a negative int counts its two's
+complement bits.
+
+Section: math
+
+Example:
+(ns-import 'math)
+(test::assert-equal 0 (bit-count 0))
+(test::assert-equal 8 (bit-count #xff))
+(test::assert-equal 24 (bit-count #xffffff00))
+(test::assert-equal 64 (bit-count -1))
 ",
         ),
     );
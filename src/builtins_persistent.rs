@@ -0,0 +1,486 @@
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use crate::builtins_hashmap::{hash_key, key_expression};
+use crate::builtins_iterator::to_sl_iter;
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::persistent::{PMap, PVec};
+use crate::types::*;
+use crate::LispResult;
+
+// An index into a persistent vector of length len (len itself if allow_end).
+fn vec_index(
+    idx: &Expression,
+    len: usize,
+    allow_end: bool,
+    fn_name: &str,
+) -> Result<usize, LispError> {
+    let idx = match &idx.get().data {
+        ExpEnum::Int(i) => *i,
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "Int",
+                &idx.display_type(),
+            )))
+        }
+    };
+    if idx < 0 || idx as usize > len || (idx as usize == len && !allow_end) {
+        return Err(LispError::new(format!(
+            "{}: index {} out of range for length {}",
+            fn_name, idx, len
+        )));
+    }
+    Ok(idx as usize)
+}
+
+fn not_persistent(fn_name: &str, coll: &Expression) -> LispError {
+    LispError::new(ErrorStrings::mismatched_type(
+        fn_name,
+        "PersistentVector or PersistentMap",
+        &coll.display_type(),
+    ))
+}
+
+// Coll with key set to val, a new version (coll is not changed).
+fn assoc(
+    environment: &mut Environment,
+    coll: &Expression,
+    key: &Expression,
+    val: Expression,
+    fn_name: &str,
+) -> Result<Expression, LispError> {
+    let data = match &coll.get().data {
+        ExpEnum::PVec(v) => {
+            let idx = vec_index(key, v.len(), true, fn_name)?;
+            if idx == v.len() {
+                ExpEnum::PVec(v.push(val))
+            } else {
+                ExpEnum::PVec(v.set(idx, val))
+            }
+        }
+        ExpEnum::PMap(map) => ExpEnum::PMap(map.insert(hash_key(environment, key)?, val)),
+        _ => return Err(not_persistent(fn_name, coll)),
+    };
+    Ok(Expression::alloc_data(data))
+}
+
+// The value at key in coll, None if not there.
+fn lookup(
+    environment: &mut Environment,
+    coll: &Expression,
+    key: &Expression,
+    fn_name: &str,
+) -> Result<Option<Expression>, LispError> {
+    match &coll.get().data {
+        ExpEnum::PVec(v) => match &key.get().data {
+            ExpEnum::Int(i) if *i >= 0 => Ok(v.get(*i as usize).cloned()),
+            ExpEnum::Int(_) => Ok(None),
+            _ => Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "Int",
+                &key.display_type(),
+            ))),
+        },
+        ExpEnum::PMap(map) => Ok(map.get(&hash_key(environment, key)?).cloned()),
+        _ => Err(not_persistent(fn_name, coll)),
+    }
+}
+
+fn builtin_persistent_vec(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let items = make_args(environment, args)?;
+    Ok(Expression::alloc_data(ExpEnum::PVec(items.into())))
+}
+
+fn builtin_persistent_map(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "persistent-map";
+    let items = make_args(environment, args)?;
+    if items.len() % 2 == 1 {
+        return Err(LispError::new(format!(
+            "{}: requires a value for every key",
+            fn_name
+        )));
+    }
+    let mut map = PMap::new();
+    for pair in items.chunks(2) {
+        map = map.insert(hash_key(environment, &pair[0])?, pair[1].clone());
+    }
+    Ok(Expression::alloc_data(ExpEnum::PMap(map)))
+}
+
+fn builtin_to_persistent(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "to-persistent";
+    let coll = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let data = match &coll.get().data {
+        ExpEnum::PVec(_) | ExpEnum::PMap(_) => None,
+        ExpEnum::Vector(v) => Some(ExpEnum::PVec(v.clone().into())),
+        ExpEnum::HashMap(map) => {
+            let mut pmap = PMap::new();
+            for (key, val) in map.iter() {
+                pmap = pmap.insert(key.clone(), val.clone());
+            }
+            Some(ExpEnum::PMap(pmap))
+        }
+        _ => {
+            let mut items = to_sl_iter(environment, &coll, fn_name)?;
+            let mut pvec = PVec::new();
+            while let Some(item) = items.next(environment)? {
+                pvec = pvec.push(item);
+            }
+            Some(ExpEnum::PVec(pvec))
+        }
+    };
+    Ok(data.map(Expression::alloc_data).unwrap_or(coll))
+}
+
+fn builtin_to_mutable(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "to-mutable";
+    let coll = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let data = match &coll.get().data {
+        ExpEnum::PVec(v) => ExpEnum::Vector(v.to_vec()),
+        ExpEnum::PMap(map) => ExpEnum::HashMap(map.entries().into_iter().collect()),
+        _ => return Err(not_persistent(fn_name, &coll)),
+    };
+    Ok(Expression::alloc_data(data))
+}
+
+fn builtin_get(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "get";
+    let coll = param_eval(environment, args, fn_name)?;
+    let key = param_eval(environment, args, fn_name)?;
+    let default = param_eval_optional(environment, args)?;
+    params_done(args, fn_name)?;
+    Ok(lookup(environment, &coll, &key, fn_name)?
+        .or(default)
+        .unwrap_or_else(Expression::make_nil))
+}
+
+fn builtin_assoc(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "assoc";
+    let mut coll = param_eval(environment, args, fn_name)?;
+    let items = make_args(environment, args)?;
+    if items.is_empty() || items.len() % 2 == 1 {
+        return Err(LispError::new(format!(
+            "{}: requires one or more key and value",
+            fn_name
+        )));
+    }
+    for pair in items.chunks(2) {
+        coll = assoc(environment, &coll, &pair[0], pair[1].clone(), fn_name)?;
+    }
+    Ok(coll)
+}
+
+fn builtin_dissoc(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "dissoc";
+    let coll = param_eval(environment, args, fn_name)?;
+    let mut map = match &coll.get().data {
+        ExpEnum::PMap(map) => map.clone(),
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "PersistentMap",
+                &coll.display_type(),
+            )))
+        }
+    };
+    for key in make_args(environment, args)? {
+        map = map.remove(&hash_key(environment, &key)?);
+    }
+    Ok(Expression::alloc_data(ExpEnum::PMap(map)))
+}
+
+fn builtin_conj(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "conj";
+    let mut coll = param_eval(environment, args, fn_name)?;
+    for item in make_args(environment, args)? {
+        let data = match (&coll.get().data, &item.get().data) {
+            (ExpEnum::PVec(v), _) => ExpEnum::PVec(v.push(item.clone())),
+            (ExpEnum::PMap(map), ExpEnum::Pair(key, val)) => {
+                ExpEnum::PMap(map.insert(hash_key(environment, key)?, val.clone()))
+            }
+            (ExpEnum::PMap(_), _) => {
+                return Err(LispError::new(ErrorStrings::mismatched_type(
+                    fn_name,
+                    "Pair",
+                    &item.display_type(),
+                )))
+            }
+            _ => return Err(not_persistent(fn_name, &coll)),
+        };
+        coll = Expression::alloc_data(data);
+    }
+    Ok(coll)
+}
+
+fn builtin_update(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "update";
+    let coll = param_eval(environment, args, fn_name)?;
+    let key = param_eval(environment, args, fn_name)?;
+    let func = param_eval(environment, args, fn_name)?;
+    let mut func_args =
+        vec![lookup(environment, &coll, &key, fn_name)?.unwrap_or_else(Expression::make_nil)];
+    func_args.append(&mut make_args(environment, args)?);
+    let val = call_fn(environment, &func, func_args)?;
+    assoc(environment, &coll, &key, val, fn_name)
+}
+
+/// The entries of a persistent map as (key . value) pairs.
+pub fn pmap_pairs(environment: &mut Environment, map: &PMap) -> LispResult<Vec<Expression>> {
+    let mut pairs = Vec::new();
+    for (key, val) in map.entries() {
+        let key = key_expression(environment, &key)?;
+        pairs.push(Expression::alloc_data(ExpEnum::Pair(key, val)));
+    }
+    Ok(pairs)
+}
+
+pub fn add_persistent_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("persistent-vec"),
+        Expression::make_function(
+            builtin_persistent_vec,
+            r##"Usage: (persistent-vec item*) -> persistent-vector
+
+Make a new persistent vector containing the items.  A persistent vector can not
+be changed, assoc, conj and update return a new version that shares most of its
+structure with the old one (so they are cheap) and leave the old one as it was.
+Use to-mutable to get a vector copy.  A persistent vector literal is written
+#pv(item*), the items are not evaluated.
+
+Section: persistent
+
+Example:
+(def test-pvec (persistent-vec 1 2 3))
+(test::assert-true (persistent-vec? test-pvec))
+(test::assert-equal 3 (length test-pvec))
+(test::assert-equal 2 (get test-pvec 1))
+(test::assert-equal 0 (length (persistent-vec)))
+(test::assert-equal "(persistent-vec 1 2 3)" (str test-pvec))
+(test::assert-equal '(1 2 3) (iterator::collect test-pvec))
+(test::assert-equal test-pvec #pv(1 2 3))
+(test::assert-equal "#pv(1 \"two\" #(3))" (str-readable (persistent-vec 1 "two" '#(3))))
+"##,
+        ),
+    );
+    data.insert(
+        interner.intern("persistent-map"),
+        Expression::make_function(
+            builtin_persistent_map,
+            r##"Usage: (persistent-map [key value]*) -> persistent-map
+
+Make a new persistent map from alternating keys and values.  Keys work like
+hash map keys.  A persistent map can not be changed, assoc, dissoc, conj and
+update return a new version that shares most of its structure with the old one
+(so they are cheap) and leave the old one as it was.  Use to-mutable to get a
+hash map copy.  A persistent map literal is written #pm(key value ...), the keys
+and values are not evaluated.
+
+Section: persistent
+
+Example:
+(def test-pmap (persistent-map :a 1 "b" 2 '(3) 3))
+(test::assert-true (persistent-map? test-pmap))
+(test::assert-equal 3 (length test-pmap))
+(test::assert-equal 1 (get test-pmap :a))
+(test::assert-equal 2 (get test-pmap 'b))
+(test::assert-equal 3 (get test-pmap '(3)))
+(test::assert-equal 0 (length (persistent-map)))
+(test::assert-equal "(persistent-map :a 1)" (str (persistent-map :a 1)))
+(test::assert-error (persistent-map :a 1 :b))
+(test::assert-equal test-pmap #pm(:a 1 "b" 2 (3) 3))
+(test::assert-equal "#pm(\":a\" 1 \"b\" 2)" (str-readable (persistent-map :a 1 'b 2)))
+(test::assert-equal test-pmap (read (str-readable test-pmap)))
+"##,
+        ),
+    );
+    data.insert(
+        interner.intern("to-persistent"),
+        Expression::make_function(
+            builtin_to_persistent,
+            r#"Usage: (to-persistent collection) -> persistent-vector/persistent-map
+
+Return a persistent copy of collection.  A hash map becomes a persistent map,
+anything else make-iter takes becomes a persistent vector of its items.  A
+persistent collection is returned as is.
+
+Section: persistent
+
+Example:
+(def test-hash (make-hash '((:a . 1))))
+(def test-pmap (to-persistent test-hash))
+(hash-set! test-hash :a 2)
+(test::assert-equal 1 (get test-pmap :a))
+(test::assert-true (equal? (persistent-vec 1 2) (to-persistent '#(1 2))))
+(test::assert-true (equal? (persistent-vec 1 2) (to-persistent '(1 2))))
+(test::assert-true (identical? test-pmap (to-persistent test-pmap)))
+(test::assert-error (to-persistent 1))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("to-mutable"),
+        Expression::make_function(
+            builtin_to_mutable,
+            r#"Usage: (to-mutable persistent) -> vector/hash-map
+
+Return a mutable copy of a persistent collection, a persistent vector becomes a
+vector and a persistent map a hash map.
+
+Section: persistent
+
+Example:
+(def test-pvec (persistent-vec 1 2))
+(def test-vec (to-mutable test-pvec))
+(vec-push! test-vec 3)
+(test::assert-equal '#(1 2 3) test-vec)
+(test::assert-equal 2 (length test-pvec))
+(def test-hash (to-mutable (persistent-map :a 1)))
+(test::assert-true (hash? test-hash))
+(test::assert-equal 1 (hash-get test-hash :a))
+(test::assert-error (to-mutable '#(1 2)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("get"),
+        Expression::make_function(
+            builtin_get,
+            r#"Usage: (get persistent key default?) -> value
+
+Get the value at key (an index for a persistent vector) from a persistent
+collection.  Returns default (or nil) if key is not present.
+
+Section: persistent
+
+Example:
+(test::assert-equal 1 (get (persistent-vec 1 2) 0))
+(test::assert-equal nil (get (persistent-vec 1 2) 2))
+(test::assert-equal :none (get (persistent-vec 1 2) -1 :none))
+(test::assert-equal "one" (get (persistent-map :a "one") :a))
+(test::assert-equal :none (get (persistent-map :a "one") :b :none))
+(test::assert-error (get (make-hash) :a))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("assoc"),
+        Expression::make_function(
+            builtin_assoc,
+            r#"Usage: (assoc persistent key value [key value]*) -> persistent
+
+Return a new version of a persistent collection with each key set to its value.
+For a persistent vector key is an index, it can be the length to append.
+
+Section: persistent
+
+Example:
+(def test-pmap (persistent-map :a 1))
+(def test-pmap2 (assoc test-pmap :a 2 :b 3))
+(test::assert-equal 1 (get test-pmap :a))
+(test::assert-equal 2 (get test-pmap2 :a))
+(test::assert-equal 3 (get test-pmap2 :b))
+(test::assert-equal 1 (length test-pmap))
+(def test-pvec (persistent-vec 1 2))
+(test::assert-true (equal? (persistent-vec 1 5 3) (assoc test-pvec 1 5 2 3)))
+(test::assert-true (equal? (persistent-vec 1 2) test-pvec))
+(test::assert-error (assoc test-pvec 3 1))
+(test::assert-error (assoc test-pmap :a))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("dissoc"),
+        Expression::make_function(
+            builtin_dissoc,
+            r#"Usage: (dissoc persistent-map key*) -> persistent-map
+
+Return a new version of a persistent map without the keys.
+
+Section: persistent
+
+Example:
+(def test-pmap (persistent-map :a 1 :b 2 :c 3))
+(def test-pmap2 (dissoc test-pmap :a :c :d))
+(test::assert-true (equal? (persistent-map :b 2) test-pmap2))
+(test::assert-equal 3 (length test-pmap))
+(test::assert-error (dissoc (persistent-vec 1) 0))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("conj"),
+        Expression::make_function(
+            builtin_conj,
+            r#"Usage: (conj persistent item*) -> persistent
+
+Return a new version of a persistent collection with the items added, appended
+to a persistent vector or (key . value) pairs set in a persistent map.
+
+Section: persistent
+
+Example:
+(def test-pvec (persistent-vec 1))
+(test::assert-true (equal? (persistent-vec 1 2 3) (conj test-pvec 2 3)))
+(test::assert-equal 1 (length test-pvec))
+(test::assert-true (equal? (persistent-map :a 1 :b 2) (conj (persistent-map :a 0) '(:a . 1) '(:b . 2))))
+(test::assert-error (conj (persistent-map) :a))
+(test::assert-error (conj '#(1) 2))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("update"),
+        Expression::make_function(
+            builtin_update,
+            r#"Usage: (update persistent key function arg*) -> persistent
+
+Return a new version of a persistent collection with the value at key replaced
+by (function old-value arg*), old-value is nil if key is not present.
+
+Section: persistent
+
+Example:
+(def test-pmap (persistent-map :count 1))
+(test::assert-equal 2 (get (update test-pmap :count + 1) :count))
+(test::assert-equal 1 (get test-pmap :count))
+(test::assert-equal 5 (get (update test-pmap :new (fn (old v) (if old (+ old v) v)) 5) :new))
+(test::assert-true (equal? (persistent-vec 1 20) (update (persistent-vec 1 2) 1 * 10)))
+"#,
+        ),
+    );
+}
@@ -0,0 +1,590 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use nix::{
+    libc,
+    poll::{poll, PollFd, PollFlags},
+    sys::{
+        signal::{kill, Signal},
+        wait::{self, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
+
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::eval::*;
+use crate::interner::*;
+use crate::process::add_arg_s;
+use crate::signals::test_clear_sigint;
+use crate::types::*;
+use crate::unix::*;
+
+const PTY_DEFAULT_ROWS: u16 = 24;
+const PTY_DEFAULT_COLS: u16 = 80;
+
+// A process running on the slave side of a pseudo-terminal.  Output read from
+// the master is buffered until consumed by expect or read-available.
+pub struct PtyState {
+    master: Option<File>,
+    pid: u32,
+    buffer: Vec<u8>,
+    eof: bool,
+    exit_status: Option<i32>,
+}
+
+enum PtyPattern {
+    Text(String),
+    Regex(regex::bytes::Regex),
+}
+
+impl PtyPattern {
+    // Returns the (start, end) of the first match in buf.
+    fn find(&self, buf: &[u8]) -> Option<(usize, usize)> {
+        match self {
+            PtyPattern::Text(text) => {
+                let text = text.as_bytes();
+                if text.is_empty() {
+                    Some((0, 0))
+                } else {
+                    buf.windows(text.len())
+                        .position(|w| w == text)
+                        .map(|start| (start, start + text.len()))
+                }
+            }
+            PtyPattern::Regex(regex) => regex.find(buf).map(|m| (m.start(), m.end())),
+        }
+    }
+}
+
+impl PtyState {
+    pub fn new(master: File, pid: u32) -> PtyState {
+        PtyState {
+            master: Some(master),
+            pid,
+            buffer: Vec::new(),
+            eof: false,
+            exit_status: None,
+        }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.master.is_none()
+    }
+
+    /// True if output has already been read from the pty but not consumed.
+    pub fn has_buffered(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    pub fn master_fd(&self) -> Option<RawFd> {
+        self.master.as_ref().map(|master| master.as_raw_fd())
+    }
+
+    fn master(&mut self, fn_name: &str) -> Result<&mut File, LispError> {
+        match &mut self.master {
+            Some(master) => Ok(master),
+            None => Err(LispError::new(format!("{}: pty is closed", fn_name))),
+        }
+    }
+
+    // Wait up to timeout_ms for output and add anything read to the buffer.
+    // Returns true if data was read.
+    fn fill(&mut self, timeout_ms: i32) -> Result<bool, LispError> {
+        if self.eof {
+            return Ok(false);
+        }
+        let master = match &mut self.master {
+            Some(master) => master,
+            None => return Ok(false),
+        };
+        let mut fds = [PollFd::new(master.as_raw_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout_ms) {
+            Ok(0) => return Ok(false),
+            Ok(_) => {}
+            Err(nix::errno::Errno::EINTR) => return Ok(false),
+            Err(err) => return Err(LispError::new(format!("pty: poll failed: {}", err))),
+        }
+        let mut buf = [0; 4096];
+        match master.read(&mut buf) {
+            Ok(0) => {
+                self.eof = true;
+                Ok(false)
+            }
+            Ok(n) => {
+                self.buffer.extend_from_slice(&buf[..n]);
+                Ok(true)
+            }
+            // Linux returns EIO on the master once the slave side is gone.
+            Err(err) if err.raw_os_error() == Some(libc::EIO) => {
+                self.eof = true;
+                Ok(false)
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn take_buffer(&mut self, end: usize) -> String {
+        // Leave a UTF-8 sequence split across reads in the buffer to be
+        // completed by the next read.
+        let end = match std::str::from_utf8(&self.buffer[..end]) {
+            Err(err) if err.error_len().is_none() && !self.eof => err.valid_up_to(),
+            _ => end,
+        };
+        let rest = self.buffer.split_off(end);
+        let taken = std::mem::replace(&mut self.buffer, rest);
+        String::from_utf8_lossy(&taken).to_string()
+    }
+
+    fn read_available(&mut self) -> Result<String, LispError> {
+        while self.fill(0)? {}
+        let end = self.buffer.len();
+        Ok(self.take_buffer(end))
+    }
+
+    fn expect(
+        &mut self,
+        pattern: &PtyPattern,
+        timeout: Option<Duration>,
+    ) -> Result<Option<String>, LispError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some((_start, end)) = pattern.find(&self.buffer) {
+                return Ok(Some(self.take_buffer(end)));
+            }
+            if self.eof || self.master.is_none() {
+                return Ok(None);
+            }
+            let wait_ms = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    std::cmp::min(100, (deadline - now).as_millis()) as i32
+                }
+                None => 100,
+            };
+            if test_clear_sigint() {
+                return Err(LispError::new("pty-expect: interrupted"));
+            }
+            self.fill(wait_ms)?;
+        }
+    }
+
+    fn try_reap(&mut self, block: bool) -> Result<Option<i32>, LispError> {
+        if self.exit_status.is_some() {
+            return Ok(self.exit_status);
+        }
+        let opts = if block {
+            None
+        } else {
+            Some(WaitPidFlag::WNOHANG)
+        };
+        match wait::waitpid(Pid::from_raw(self.pid as i32), opts) {
+            Ok(WaitStatus::Exited(_, status)) => {
+                self.exit_status = Some(status);
+                Ok(self.exit_status)
+            }
+            Ok(WaitStatus::Signaled(_, signal, _)) => {
+                self.exit_status = Some(128 + signal as i32);
+                Ok(self.exit_status)
+            }
+            Ok(_) => Ok(None),
+            Err(nix::errno::Errno::ECHILD) => {
+                self.exit_status = Some(-1);
+                Ok(self.exit_status)
+            }
+            Err(err) => Err(LispError::new(format!(
+                "pty: error waiting for pid {}: {}",
+                self.pid, err
+            ))),
+        }
+    }
+
+    fn wait(&mut self) -> Result<Option<i32>, LispError> {
+        let mut int_cnt = 0;
+        loop {
+            if let Some(status) = self.try_reap(false)? {
+                return Ok(Some(status));
+            }
+            if test_clear_sigint() {
+                let sig = if int_cnt == 0 {
+                    Signal::SIGINT
+                } else if int_cnt == 1 {
+                    Signal::SIGTERM
+                } else {
+                    Signal::SIGKILL
+                };
+                if let Err(err) = kill(Pid::from_raw(self.pid as i32), sig) {
+                    eprintln!("ERROR sending {} to pty process {}, {}", sig, self.pid, err);
+                }
+                int_cnt += 1;
+            }
+            // Keep draining output so the child can not block on a full pty.
+            if self.eof || self.master.is_none() {
+                std::thread::sleep(Duration::from_millis(10));
+            } else {
+                self.fill(10)?;
+            }
+        }
+    }
+
+    fn close(&mut self) -> Result<(), LispError> {
+        // Closing the master will hang up the child, give it a moment to exit
+        // then kill it so it can be reaped (not left a zombie).
+        self.master = None;
+        for _ in 0..10 {
+            if self.try_reap(false)?.is_some() {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if self.try_reap(false)?.is_none() {
+            if let Err(err) = kill(Pid::from_raw(self.pid as i32), Signal::SIGKILL) {
+                if err != nix::errno::Errno::ESRCH {
+                    return Err(LispError::new(format!(
+                        "pty: error killing pid {}: {}",
+                        self.pid, err
+                    )));
+                }
+            }
+            self.try_reap(true)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PtyState {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            eprintln!("Error closing pty for pid {}: {}", self.pid, err);
+        }
+    }
+}
+
+fn get_pty(exp: &Expression, fn_name: &str) -> Result<Rc<RefCell<PtyState>>, LispError> {
+    match &exp.get().data {
+        ExpEnum::Pty(pty) => Ok(pty.clone()),
+        _ => Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Pty",
+            &exp.display_type(),
+        ))),
+    }
+}
+
+fn get_timeout(exp: Option<Expression>, fn_name: &str) -> Result<Option<Duration>, LispError> {
+    if let Some(exp) = exp {
+        match &exp.get().data {
+            ExpEnum::Int(ms) if *ms >= 0 => Ok(Some(Duration::from_millis(*ms as u64))),
+            ExpEnum::Nil => Ok(None),
+            _ => Err(LispError::new(format!(
+                "{}: timeout must be a non-negative integer (milliseconds)",
+                fn_name
+            ))),
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+fn builtin_pty_spawn(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "pty-spawn";
+    let command = param_eval(environment, args, fn_name)?;
+    let command = match &command.get().data {
+        ExpEnum::Symbol(s, _) => s.to_string(),
+        ExpEnum::String(s, _) => s.to_string(),
+        _ => {
+            return Err(LispError::new(format!(
+                "{}: command must be a string or symbol",
+                fn_name
+            )))
+        }
+    };
+    let mut cmd_args = Vec::new();
+    for arg in args {
+        add_arg_s(&mut cmd_args, eval(environment, arg)?)?;
+    }
+    match forkpty_exec(PTY_DEFAULT_ROWS, PTY_DEFAULT_COLS, &command, &cmd_args) {
+        Ok((master, pid)) => Ok(Expression::alloc_data(ExpEnum::Pty(Rc::new(RefCell::new(
+            PtyState::new(master, pid),
+        ))))),
+        Err(err) => Err(LispError::new(format!(
+            "{}: failed to execute [{}]: {}",
+            fn_name, command, err
+        ))),
+    }
+}
+
+fn builtin_pty_send(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "pty-send";
+    let pty = get_pty(&param_eval(environment, args, fn_name)?, fn_name)?;
+    let text = param_eval(environment, args, fn_name)?.as_string(environment)?;
+    params_done(args, fn_name)?;
+    let mut pty = pty.borrow_mut();
+    let master = pty.master(fn_name)?;
+    master.write_all(text.as_bytes())?;
+    master.flush()?;
+    Ok(Expression::alloc_data(ExpEnum::Int(text.len() as i64)))
+}
+
+fn builtin_pty_expect(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "pty-expect";
+    let pty = get_pty(&param_eval(environment, args, fn_name)?, fn_name)?;
+    let pattern = param_eval(environment, args, fn_name)?;
+    let timeout = get_timeout(param_eval_optional(environment, args)?, fn_name)?;
+    params_done(args, fn_name)?;
+    let pattern = match &pattern.get().data {
+        ExpEnum::String(s, _) => PtyPattern::Text(s.to_string()),
+        ExpEnum::Regex(regex) => match regex::bytes::Regex::new(regex.as_str()) {
+            Ok(regex) => PtyPattern::Regex(regex),
+            Err(err) => return Err(LispError::new(format!("{}: {}", fn_name, err))),
+        },
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "String or Regex",
+                &pattern.display_type(),
+            )))
+        }
+    };
+    let res = pty.borrow_mut().expect(&pattern, timeout)?;
+    match res {
+        Some(text) => Ok(Expression::alloc_data(ExpEnum::String(text.into(), None))),
+        None => Ok(Expression::make_nil()),
+    }
+}
+
+fn builtin_pty_read_available(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "pty-read-available";
+    let pty = get_pty(&param_eval(environment, args, fn_name)?, fn_name)?;
+    params_done(args, fn_name)?;
+    let text = pty.borrow_mut().read_available()?;
+    Ok(Expression::alloc_data(ExpEnum::String(text.into(), None)))
+}
+
+fn builtin_pty_resize(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "pty-resize";
+    let pty = get_pty(&param_eval(environment, args, fn_name)?, fn_name)?;
+    let rows = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    let cols = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    params_done(args, fn_name)?;
+    if rows <= 0 || rows > u16::MAX as i64 || cols <= 0 || cols > u16::MAX as i64 {
+        return Err(LispError::new(format!(
+            "{}: rows and cols must be positive integers",
+            fn_name
+        )));
+    }
+    let mut pty = pty.borrow_mut();
+    let master = pty.master(fn_name)?.as_raw_fd();
+    pty_resize(master, rows as u16, cols as u16)?;
+    Ok(Expression::make_nil())
+}
+
+fn builtin_pty_wait(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "pty-wait";
+    let pty = get_pty(&param_eval(environment, args, fn_name)?, fn_name)?;
+    params_done(args, fn_name)?;
+    let status = pty.borrow_mut().wait()?;
+    match status {
+        Some(status) => Ok(Expression::alloc_data(ExpEnum::Int(i64::from(status)))),
+        None => Ok(Expression::make_nil()),
+    }
+}
+
+fn builtin_pty_close(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "pty-close";
+    let pty = get_pty(&param_eval(environment, args, fn_name)?, fn_name)?;
+    params_done(args, fn_name)?;
+    pty.borrow_mut().close()?;
+    Ok(Expression::make_nil())
+}
+
+pub fn add_pty_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("pty-spawn"),
+        Expression::make_function(
+            builtin_pty_spawn,
+            r#"Usage: (pty-spawn command arg0 ... argN) -> pty
+
+Run command with the supplied arguments on a new pseudo-terminal and return a
+pty handle.  Use this instead of syscall for programs that behave differently
+when not connected to a terminal.  Args are evaluated and lists are flattened
+(like syscall).  The terminal starts as 24 rows by 80 columns.
+
+Section: system
+
+Example:
+(def pty-test (pty-spawn "cat"))
+(test::assert-true (pty? pty-test))
+(pty-send pty-test "hello pty\n")
+(test::assert-true (str-contains "hello pty" (pty-expect pty-test "hello pty" 5000)))
+(pty-close pty-test)
+(test::assert-equal 3 (pty-wait (pty-spawn "sh" "-c" "exit 3")))
+(test::assert-error (pty-spawn "/does/not/exist/123"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("pty-send"),
+        Expression::make_function(
+            builtin_pty_send,
+            r#"Usage: (pty-send pty string) -> int
+
+Write string to the pty (as if typed at the terminal) and return the number of
+bytes written.  No newline is added.
+
+Section: system
+
+Example:
+(def pty-test (pty-spawn "cat"))
+(test::assert-equal 4 (pty-send pty-test "one\n"))
+(test::assert-true (string? (pty-expect pty-test "one" 5000)))
+(pty-close pty-test)
+(test::assert-error (pty-send pty-test "two\n"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("pty-expect"),
+        Expression::make_function(
+            builtin_pty_expect,
+            r#"Usage: (pty-expect pty pattern timeout?) -> string or nil
+
+Wait for output from the pty matching pattern (a string or regex) and return
+all the output up to and including the match, this output is consumed.  Returns
+nil if timeout (milliseconds) expires or the pty reaches end of file before a
+match, in this case no output is consumed.  If timeout is not provided (or nil)
+then wait until there is a match or end of file.
+
+Section: system
+
+Example:
+(def pty-test (pty-spawn "sh"))
+(pty-send pty-test "echo answer-42\n")
+(test::assert-true (string? (pty-expect pty-test #/answer-\d+/ 5000)))
+(test::assert-false (pty-expect pty-test "not going to happen" 100))
+(pty-send pty-test "exit 0\n")
+(test::assert-equal 0 (pty-wait pty-test))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("pty-read-available"),
+        Expression::make_function(
+            builtin_pty_read_available,
+            r#"Usage: (pty-read-available pty) -> string
+
+Return (and consume) all the output from the pty that is available without
+blocking.  This includes output buffered by a previous pty-expect that was not
+part of its match.
+
+Section: system
+
+Example:
+(def pty-test (pty-spawn "sh" "-c" "printf 'one two'; sleep 1"))
+(test::assert-true (string? (pty-expect pty-test "one" 5000)))
+(test::assert-equal " two" (pty-read-available pty-test))
+(test::assert-equal "" (pty-read-available pty-test))
+(pty-wait pty-test)
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("pty-resize"),
+        Expression::make_function(
+            builtin_pty_resize,
+            r#"Usage: (pty-resize pty rows cols) -> nil
+
+Set the terminal size of the pty, the process will receive a SIGWINCH.
+
+Section: system
+
+Example:
+(def pty-test (pty-spawn "sh"))
+(pty-resize pty-test 50 132)
+(pty-send pty-test "stty size\n")
+(test::assert-true (string? (pty-expect pty-test "50 132" 5000)))
+(pty-send pty-test "exit\n")
+(pty-wait pty-test)
+(test::assert-error (pty-resize pty-test 0 80))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("pty-wait"),
+        Expression::make_function(
+            builtin_pty_wait,
+            r#"Usage: (pty-wait pty) -> int
+
+Wait for the pty process to exit and return its exit status (128 plus the
+signal number if it was killed by a signal).  Output produced while waiting is
+buffered and can be retrieved with pty-read-available.  Can be called multiple
+times.
+
+Section: system
+
+Example:
+(def pty-test (pty-spawn "sh" "-c" "echo done; exit 7"))
+(test::assert-equal 7 (pty-wait pty-test))
+(test::assert-equal 7 (pty-wait pty-test))
+(test::assert-true (str-contains "done" (pty-read-available pty-test)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("pty-close"),
+        Expression::make_function(
+            builtin_pty_close,
+            r#"Usage: (pty-close pty) -> nil
+
+Close the pty, this hangs up the terminal (the process will get a SIGHUP).  A
+process still running shortly after the hang up is killed and reaped.  Use
+pty-wait afterwards to get the exit status.
+
+Section: system
+
+Example:
+(def pty-test (pty-spawn "cat"))
+(pty-close pty-test)
+(test::assert-true (int? (pty-wait pty-test)))
+"#,
+        ),
+    );
+}
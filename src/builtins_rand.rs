@@ -1,36 +1,51 @@
-use rand::distributions::{Alphanumeric, Distribution};
-use rand::Rng;
+use rand::distributions::{Alphanumeric, Distribution, WeightedIndex};
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::hash::BuildHasher;
 use std::iter;
+use std::rc::Rc;
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::builtins_sort::{make_like, param_seq};
 use crate::builtins_util::*;
 use crate::environment::*;
+use crate::eval::*;
 use crate::interner::*;
 use crate::types::*;
-use rand::rngs::ThreadRng;
 use std::borrow::Cow;
 
+/// Call f with the generator the random builtins use, the one set by with-rng
+/// or with-rng-seed if any else the thread rng.
+pub fn with_rng<T>(environment: &Environment, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    match &environment.rng {
+        Some(rng) => f(&mut *rng.borrow_mut()),
+        None => f(&mut rand::thread_rng()),
+    }
+}
+
 fn builtin_random(
     environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
 ) -> Result<Expression, LispError> {
     let next_arg = param_eval(environment, args, "random")?;
     let next_arg_d = next_arg.get();
-    let mut rng = rand::thread_rng();
     params_done(args, "random")?;
     match &next_arg_d.data {
         ExpEnum::Int(i) => match i {
-            positive if positive > &0 => {
-                Ok(Expression::alloc_data(ExpEnum::Int(rng.gen_range(0..*i))))
-            }
+            positive if positive > &0 => Ok(Expression::alloc_data(ExpEnum::Int(with_rng(
+                environment,
+                |rng| rng.gen_range(0..*i),
+            )))),
             _ => Err(LispError::new("Expected positive number")),
         },
         ExpEnum::Float(f) => match f {
-            positive if positive > &0.0 => Ok(Expression::alloc_data(ExpEnum::Float(
-                rng.gen_range(0.0..*f),
-            ))),
+            positive if positive > &0.0 => Ok(Expression::alloc_data(ExpEnum::Float(with_rng(
+                environment,
+                |rng| rng.gen_range(0.0..*f),
+            )))),
             _ => Err(LispError::new("Expected positive number")),
         },
         _ => Err(LispError::new("Expected positive number, float or int")),
@@ -53,7 +68,7 @@ fn builtin_get_random_str(
     }
 }
 
-pub fn rand_alphanumeric_str(len: u64, rng: &mut ThreadRng) -> Cow<'static, str> {
+pub fn rand_alphanumeric_str<R: Rng + ?Sized>(len: u64, rng: &mut R) -> Cow<'static, str> {
     iter::repeat(())
         .map(|()| rng.sample(Alphanumeric))
         .map(char::from)
@@ -68,9 +83,8 @@ fn get_random_str(
 ) -> Result<Expression, LispError> {
     let charset_arg = param_eval(environment, args, "random-str")?;
     let charset_arg_d = charset_arg.get();
-    let mut rng = rand::thread_rng();
     params_done(args, "random-str")?;
-    match &charset_arg_d.data {
+    with_rng(environment, |rng| match &charset_arg_d.data {
         ExpEnum::Symbol(sym, _) => match *sym {
             ":ascii" => Ok(Expression::alloc_data(ExpEnum::String(
                 iter::repeat(())
@@ -81,7 +95,7 @@ fn get_random_str(
                 None,
             ))),
             ":alnum" => Ok(Expression::alloc_data(ExpEnum::String(
-                rand_alphanumeric_str(len, &mut rng),
+                rand_alphanumeric_str(len, rng),
                 None,
             ))),
             ":hex" => Ok(Expression::alloc_data(ExpEnum::String(
@@ -105,7 +119,7 @@ fn get_random_str(
             )))
         }
         _ => Err(LispError::new("Second argument must be keyword or string")),
-    }
+    })
 }
 
 #[derive(Debug)]
@@ -198,7 +212,7 @@ fn builtin_probool(
         Some((_, 0)) => Err(LispError::new("Denominator can not be zero")),
         Some((i, j)) => match i / j {
             improper if improper > 1 => Ok(Expression::alloc_data(ExpEnum::True)),
-            _ => match rand::thread_rng().gen_ratio(i, j) {
+            _ => match with_rng(environment, |rng| rng.gen_ratio(i, j)) {
                 true => Ok(Expression::alloc_data(ExpEnum::True)),
                 false => Ok(Expression::alloc_data(ExpEnum::Nil)),
             },
@@ -206,6 +220,270 @@ fn builtin_probool(
     }
 }
 
+fn builtin_make_rng(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "make-rng";
+    let rng = match param_eval_optional(environment, args)? {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed.make_int(environment)? as u64),
+        None => ChaCha8Rng::from_entropy(),
+    };
+    params_done(args, fn_name)?;
+    Ok(Expression::alloc_data(ExpEnum::Rng(Rc::new(RefCell::new(
+        rng,
+    )))))
+}
+
+// Evaluate body with rng as the generator for the random builtins, the
+// previous generator is restored even on error.
+fn eval_with_rng(
+    environment: &mut Environment,
+    rng: Rc<RefCell<ChaCha8Rng>>,
+    body: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let old_rng = environment.rng.replace(rng);
+    let mut res = Ok(Expression::make_nil());
+    for exp in body {
+        res = eval(environment, exp);
+        if res.is_err() {
+            break;
+        }
+    }
+    environment.rng = old_rng;
+    res
+}
+
+fn builtin_with_rng(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "with-rng";
+    let rng = param_eval(environment, args, fn_name)?;
+    let rng = match &rng.get().data {
+        ExpEnum::Rng(rng) => rng.clone(),
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "Rng",
+                &rng.display_type(),
+            )))
+        }
+    };
+    eval_with_rng(environment, rng, args)
+}
+
+fn builtin_with_rng_seed(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "with-rng-seed";
+    let seed = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    let rng = Rc::new(RefCell::new(ChaCha8Rng::seed_from_u64(seed as u64)));
+    eval_with_rng(environment, rng, args)
+}
+
+fn builtin_shuffle(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "shuffle";
+    let seq = param_seq(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let mut items: Vec<Expression> = seq.iter().collect();
+    with_rng(environment, |rng| items.shuffle(rng));
+    Ok(make_like(&seq, items))
+}
+
+fn builtin_sample(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "sample";
+    let seq = param_seq(environment, args, fn_name)?;
+    let n = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    params_done(args, fn_name)?;
+    if n < 0 {
+        return Err(LispError::new(format!(
+            "{}: count must not be negative",
+            fn_name
+        )));
+    }
+    let items: Vec<Expression> = seq.iter().collect();
+    let picked = with_rng(environment, |rng| {
+        items
+            .choose_multiple(rng, n as usize)
+            .cloned()
+            .collect::<Vec<Expression>>()
+    });
+    Ok(make_like(&seq, picked))
+}
+
+fn builtin_choose(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "choose";
+    let seq = param_seq(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let items: Vec<Expression> = seq.iter().collect();
+    match with_rng(environment, |rng| items.choose(rng).cloned()) {
+        Some(item) => Ok(item),
+        None => Err(LispError::new(format!(
+            "{}: no items to choose from",
+            fn_name
+        ))),
+    }
+}
+
+fn builtin_choose_weighted(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "choose-weighted";
+    let seq = param_seq(environment, args, fn_name)?;
+    let weights = param_seq(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let items: Vec<Expression> = seq.iter().collect();
+    let mut weights: Vec<Expression> = weights.iter().collect();
+    if items.len() != weights.len() {
+        return Err(LispError::new(format!(
+            "{}: requires a weight for each item",
+            fn_name
+        )));
+    }
+    let weights = parse_list_of_floats(environment, &mut weights)?;
+    let dist = WeightedIndex::new(&weights)
+        .map_err(|err| LispError::new(format!("{}: {}", fn_name, err)))?;
+    let idx = with_rng(environment, |rng| dist.sample(rng));
+    Ok(items[idx].clone())
+}
+
+// An optional float param, default if not provided.
+fn param_float_optional(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    default: f64,
+) -> Result<f64, LispError> {
+    match param_eval_optional(environment, args)? {
+        Some(exp) => exp.make_float(environment),
+        None => Ok(default),
+    }
+}
+
+// A uniform float in (0, 1], safe to take the log of.
+fn open_unit(rng: &mut dyn RngCore) -> f64 {
+    1.0 - rng.gen::<f64>()
+}
+
+/// A standard normal draw (Box-Muller).
+fn sample_normal(rng: &mut dyn RngCore) -> f64 {
+    let u1 = open_unit(rng);
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+// ln(k!), exact for small k else Stirling's series.
+fn ln_factorial(k: f64) -> f64 {
+    if k < 10.0 {
+        (2..=k as u64).map(|i| (i as f64).ln()).sum()
+    } else {
+        let k1 = k + 1.0;
+        (k1 - 0.5) * k1.ln() - k1 + 0.5 * (2.0 * std::f64::consts::PI).ln() + 1.0 / (12.0 * k1)
+            - 1.0 / (360.0 * k1 * k1 * k1)
+    }
+}
+
+// A Poisson draw, Knuth's method for small lambda else Hörmann's PTRS
+// (transformed rejection with squeeze).
+fn sample_poisson(rng: &mut dyn RngCore, lambda: f64) -> i64 {
+    if lambda < 30.0 {
+        let limit = (-lambda).exp();
+        let mut k = 0;
+        let mut p: f64 = rng.gen();
+        while p > limit {
+            k += 1;
+            p *= rng.gen::<f64>();
+        }
+        return k;
+    }
+    let slam = lambda.sqrt();
+    let loglam = lambda.ln();
+    let b = 0.931 + 2.53 * slam;
+    let a = -0.059 + 0.02483 * b;
+    let invalpha = 1.1239 + 1.1328 / (b - 3.4);
+    let vr = 0.9277 - 3.6224 / (b - 2.0);
+    loop {
+        let u = rng.gen::<f64>() - 0.5;
+        let v = open_unit(rng);
+        let us = 0.5 - u.abs();
+        let k = ((2.0 * a / us + b) * u + lambda + 0.43).floor();
+        if us >= 0.07 && v <= vr {
+            return k as i64;
+        }
+        if k < 0.0 || (us < 0.013 && v > us) {
+            continue;
+        }
+        if v.ln() + invalpha.ln() - (a / (us * us) + b).ln()
+            <= -lambda + k * loglam - ln_factorial(k)
+        {
+            return k as i64;
+        }
+    }
+}
+
+fn builtin_random_normal(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "random-normal";
+    let mean = param_float_optional(environment, args, 0.0)?;
+    let std_dev = param_float_optional(environment, args, 1.0)?;
+    params_done(args, fn_name)?;
+    if std_dev < 0.0 || !std_dev.is_finite() {
+        return Err(LispError::new(format!(
+            "{}: std-dev must be a finite, non-negative number",
+            fn_name
+        )));
+    }
+    let val = with_rng(environment, sample_normal);
+    Ok(Expression::alloc_data(ExpEnum::Float(mean + std_dev * val)))
+}
+
+fn builtin_random_exp(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "random-exp";
+    let rate = param_float_optional(environment, args, 1.0)?;
+    params_done(args, fn_name)?;
+    if rate <= 0.0 || !rate.is_finite() {
+        return Err(LispError::new(format!(
+            "{}: rate must be a finite, positive number",
+            fn_name
+        )));
+    }
+    let val = with_rng(environment, |rng| -open_unit(rng).ln() / rate);
+    Ok(Expression::alloc_data(ExpEnum::Float(val)))
+}
+
+fn builtin_random_poisson(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "random-poisson";
+    let lambda = param_eval(environment, args, fn_name)?.make_float(environment)?;
+    params_done(args, fn_name)?;
+    if lambda < 0.0 || !lambda.is_finite() {
+        return Err(LispError::new(format!(
+            "{}: lambda must be a finite, non-negative number",
+            fn_name
+        )));
+    }
+    let val = with_rng(environment, |rng| sample_poisson(rng, lambda));
+    Ok(Expression::alloc_data(ExpEnum::Int(val)))
+}
+
 pub fn add_rand_builtins<S: BuildHasher>(
     interner: &mut Interner,
     data: &mut HashMap<&'static str, (Expression, String), S>,
@@ -281,6 +559,222 @@ Example:
 (test::assert-true (and (> rand-float 0) (< rand-float 1)))
 (test::assert-error-msg (random -1) \"Expected positive integer\")
 (test::assert-error-msg (random 1 2) \"Expected zero or one integers\")
+",
+        ),
+    );
+    data.insert(
+        interner.intern("make-rng"),
+        Expression::make_function(
+            builtin_make_rng,
+            "Usage: (make-rng seed?) -> rng
+
+Make a random number generator, seeded with the integer seed if provided (the
+same seed always produces the same numbers) otherwise from system entropy.  Use
+it with with-rng.
+
+Section: random
+
+Example:
+(test::assert-true (rng? (make-rng 42)))
+(test::assert-true (rng? (make-rng)))
+(test::assert-error (make-rng \"seed\"))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("with-rng"),
+        Expression::make_special(
+            builtin_with_rng,
+            "Usage: (with-rng rng body*) -> value
+
+Evaluate body with rng (from make-rng) as the generator for all the random
+builtins (random, random-str, probool, shuffle, sample, choose, choose-weighted,
+random-normal, random-exp and random-poisson) and return the value of the last
+form.  The generator keeps its state between uses so a series of with-rng
+forms using the same rng continue one sequence.
+
+Section: random
+
+Example:
+(def test-rng0 (make-rng 7))
+(def test-rng1 (make-rng 7))
+(def test-rolls0 (with-rng test-rng0 (list (random 100) (random 100))))
+(def test-rolls1 (with-rng test-rng1 (list (random 100) (random 100))))
+(test::assert-equal test-rolls0 test-rolls1)
+(test::assert-equal (with-rng test-rng0 (random 100)) (with-rng test-rng1 (random 100)))
+(test::assert-error (with-rng 7 (random 100)))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("with-rng-seed"),
+        Expression::make_special(
+            builtin_with_rng_seed,
+            "Usage: (with-rng-seed seed body*) -> value
+
+Evaluate body with a new generator seeded with the integer seed for all the
+random builtins (see with-rng) and return the value of the last form.  The same
+seed always produces the same results (the generator is ChaCha8 so this holds
+across versions and platforms), use this for reproducible tests and simulations.
+
+Section: random
+
+Example:
+(test::assert-equal (with-rng-seed 42 (random 1000)) (with-rng-seed 42 (random 1000)))
+(test::assert-equal 681 (with-rng-seed 42 (random 1000)))
+(test::assert-equal '(2 4 5 3 1) (with-rng-seed 3 (shuffle '(1 2 3 4 5))))
+(test::assert-equal (with-rng-seed 1 (random-str 8 :alnum)) (with-rng-seed 1 (random-str 8 :alnum)))
+(test::assert-equal (with-rng-seed 3 (shuffle '(1 2 3 4 5))) (with-rng-seed 3 (shuffle '(1 2 3 4 5))))
+(test::assert-equal 8 (length (with-rng-seed 1 (random-str 8 :hex))))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("shuffle"),
+        Expression::make_function(
+            builtin_shuffle,
+            "Usage: (shuffle sequence) -> sequence
+
+Return a new list or vector (the same kind as sequence) with the items of
+sequence in random order.
+
+Section: random
+
+Example:
+(def test-shuffled (shuffle '(1 2 3 4 5)))
+(test::assert-true (list? test-shuffled))
+(test::assert-equal '(1 2 3 4 5) (sort test-shuffled))
+(test::assert-true (vec? (shuffle '#(1 2 3))))
+(test::assert-equal nil (shuffle '()))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("sample"),
+        Expression::make_function(
+            builtin_sample,
+            "Usage: (sample sequence n) -> sequence
+
+Return a new list or vector (the same kind as sequence) of n items picked at
+random from sequence without replacement (all of them if there are fewer than
+n).
+
+Section: random
+
+Example:
+(def test-sample (sample '#(1 2 3 4 5) 3))
+(test::assert-equal 3 (length test-sample))
+(test::assert-true (vec? test-sample))
+(test::assert-equal 3 (length (make-set test-sample)))
+(test::assert-equal '(1 2) (sort (sample '(1 2) 5)))
+(test::assert-error (sample '(1 2) -1))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("choose"),
+        Expression::make_function(
+            builtin_choose,
+            "Usage: (choose sequence) -> item
+
+Return an item picked at random from sequence, an error if it is empty.
+
+Section: random
+
+Example:
+(test::assert-true (contains? #s(1 2 3) (choose '(1 2 3))))
+(test::assert-equal :only (choose '#(:only)))
+(test::assert-error (choose '()))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("choose-weighted"),
+        Expression::make_function(
+            builtin_choose_weighted,
+            "Usage: (choose-weighted sequence weights) -> item
+
+Return an item picked at random from sequence, each item is picked with
+probability proportional to its weight (a non-negative number in the same
+position of the weights list or vector).
+
+Section: random
+
+Example:
+(test::assert-equal :b (choose-weighted '(:a :b :c) '(0 1 0)))
+(test::assert-true (contains? #s(:a :b) (choose-weighted '#(:a :b :c) '#(1 2.5 0))))
+(test::assert-error (choose-weighted '(:a :b) '(1)))
+(test::assert-error (choose-weighted '(:a :b) '(0 0)))
+(test::assert-error (choose-weighted '(:a :b) '(1 -1)))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("random-normal"),
+        Expression::make_function(
+            builtin_random_normal,
+            "Usage: (random-normal mean? std-dev?) -> float
+
+Return a random float from the normal distribution with mean (default 0.0) and
+standard deviation std-dev (default 1.0).
+
+Section: random
+
+Example:
+(test::assert-true (float? (random-normal)))
+(test::assert-equal 5.0 (random-normal 5 0))
+(def test-normal (with-rng-seed 1 (iterator::collect (iterator::map (fn (_) (random-normal 10 2)) (iterator::range 1 1000)))))
+(test::assert-true (< 9.5 (apply stats::mean test-normal) 10.5))
+(test::assert-error (random-normal 0 -1))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("random-exp"),
+        Expression::make_function(
+            builtin_random_exp,
+            "Usage: (random-exp rate?) -> float
+
+Return a random float from the exponential distribution with rate (default
+1.0), the mean is 1/rate.
+
+Section: random
+
+Example:
+(test::assert-true (>= (random-exp) 0.0))
+(def test-exp (with-rng-seed 1 (iterator::collect (iterator::map (fn (_) (random-exp 4)) (iterator::range 1 1000)))))
+(test::assert-true (< 0.2 (apply stats::mean test-exp) 0.3))
+(test::assert-error (random-exp 0))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("random-poisson"),
+        Expression::make_function(
+            builtin_random_poisson,
+            "Usage: (random-poisson lambda) -> int
+
+Return a random integer from the Poisson distribution with mean lambda.
+
+Section: random
+
+Example:
+(test::assert-true (int? (random-poisson 3)))
+(test::assert-equal 0 (random-poisson 0))
+(def test-poisson (with-rng-seed 1 (iterator::collect (iterator::map (fn (_) (random-poisson 4)) (iterator::range 1 1000)))))
+(test::assert-true (< 3.7 (apply stats::mean test-poisson) 4.3))
+(def test-poisson (with-rng-seed 1 (iterator::collect (iterator::map (fn (_) (random-poisson 100)) (iterator::range 1 1000)))))
+(test::assert-true (< 98 (apply stats::mean test-poisson) 102))
+(test::assert-error (random-poisson -1))
 ",
         ),
     );
@@ -0,0 +1,488 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::rc::Rc;
+
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::eval::*;
+use crate::interner::*;
+use crate::symbols::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldAccess {
+    Private,
+    ReadWrite,
+    ReadOnly,
+    WriteOnly,
+}
+
+// What a message sent to a record does.
+enum Message {
+    Get(usize),
+    Set(usize),
+    Method(Expression),
+    Type,
+}
+
+/// The shared description of a record (defstruct) type, it is also the
+/// constructor for instances of the type.
+pub struct RecordType {
+    name: &'static str,
+    // The name with its namespace, used to find the type when reading #S(...).
+    full_name: &'static str,
+    fields: Vec<&'static str>,
+    access: Vec<FieldAccess>,
+    field_idx: HashMap<&'static str, usize>,
+    messages: HashMap<&'static str, Message>,
+    tags: HashSet<&'static str>,
+    // Lambda returning the default field values for a new instance.
+    defaults: Expression,
+}
+
+impl RecordType {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn full_name(&self) -> &'static str {
+        self.full_name
+    }
+
+    pub fn fields(&self) -> &[&'static str] {
+        &self.fields
+    }
+
+    /// True if field idx can be read from outside the record (:rw or :ro).
+    pub fn is_readable(&self, idx: usize) -> bool {
+        matches!(
+            self.access[idx],
+            FieldAccess::ReadWrite | FieldAccess::ReadOnly
+        )
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(tag)
+    }
+}
+
+/// An instance of a record type.  Fields are bindings so they can be shared
+/// with the stack frame of a running method.
+#[derive(Clone)]
+pub struct Record {
+    rtype: Rc<RecordType>,
+    fields: Vec<Binding>,
+}
+
+impl Record {
+    pub fn record_type(&self) -> &Rc<RecordType> {
+        &self.rtype
+    }
+
+    pub fn fields(&self) -> &[Binding] {
+        &self.fields
+    }
+
+    pub fn copy(&self) -> Record {
+        Record {
+            rtype: self.rtype.clone(),
+            fields: self
+                .fields
+                .iter()
+                .map(|f| Binding::with_expression(f.get()))
+                .collect(),
+        }
+    }
+}
+
+// Call a lambda with evaluation on, apply turns it off to pass evaluated args.
+fn call_lambda_eval(
+    environment: &mut Environment,
+    lambda: Expression,
+    bound: &[Binding],
+    args: Vec<Expression>,
+) -> Result<Expression, LispError> {
+    let old_sup = environment.supress_eval;
+    environment.supress_eval = false;
+    let ret = call_lambda_bound(environment, lambda, bound, &mut args.into_iter(), false);
+    environment.supress_eval = old_sup;
+    ret
+}
+
+/// Create a new instance of rtype, args are optional :field value pairs that
+/// override the defaults.
+pub fn new_record(
+    environment: &mut Environment,
+    rtype: &Rc<RecordType>,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let name = rtype.name;
+    let defaults = call_lambda_eval(environment, rtype.defaults.clone(), &[], Vec::new())?;
+    let mut fields: Vec<Binding> = defaults.iter().map(Binding::with_expression).collect();
+    if fields.len() != rtype.fields.len() {
+        return Err(LispError::new(format!(
+            "{}: expected {} default values got {}",
+            name,
+            rtype.fields.len(),
+            fields.len()
+        )));
+    }
+    while let Some(key) = param_eval_optional(environment, args)? {
+        let idx = match &key.get().data {
+            ExpEnum::Symbol(s, _) => rtype.field_idx.get(s.trim_start_matches(':')).copied(),
+            _ => None,
+        };
+        let idx = idx.ok_or_else(|| LispError::new(format!("{}: {} is not a field", name, key)))?;
+        let val = param_eval(environment, args, name)?;
+        fields[idx] = Binding::with_expression(val);
+    }
+    Ok(Expression::alloc_data(ExpEnum::Record(Record {
+        rtype: rtype.clone(),
+        fields,
+    })))
+}
+
+/// Build a record from the items of a #S(type :field value ...) literal.
+/// Nothing is evaluated so every field needs a value.
+pub fn read_record(
+    environment: &Environment,
+    items: &[Expression],
+) -> Result<Expression, LispError> {
+    let type_name = match items.first().map(|t| t.get().data.clone()) {
+        Some(ExpEnum::Symbol(s, _)) => s,
+        _ => return Err(LispError::new("record literal must start with a type name")),
+    };
+    let rtype = match lookup_expression(environment, type_name) {
+        Some(exp) => match &exp.get().data {
+            ExpEnum::RecordType(t) => t.clone(),
+            _ => {
+                return Err(LispError::new(format!(
+                    "{} is not a record type",
+                    type_name
+                )))
+            }
+        },
+        None => {
+            return Err(LispError::new(format!(
+                "record type {} not found",
+                type_name
+            )))
+        }
+    };
+    let name = rtype.name;
+    let pairs = items[1..].chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(LispError::new(format!(
+            "{}: record literal needs a value for every field",
+            name
+        )));
+    }
+    let mut fields: Vec<Option<Binding>> = vec![None; rtype.fields.len()];
+    for pair in pairs {
+        let idx = match &pair[0].get().data {
+            ExpEnum::Symbol(s, _) => rtype.field_idx.get(s.trim_start_matches(':')).copied(),
+            _ => None,
+        };
+        let idx =
+            idx.ok_or_else(|| LispError::new(format!("{}: {} is not a field", name, pair[0])))?;
+        fields[idx] = Some(Binding::with_expression(pair[1].clone()));
+    }
+    let fields = fields
+        .into_iter()
+        .enumerate()
+        .map(|(idx, field)| {
+            field.ok_or_else(|| {
+                LispError::new(format!("{}: missing field {}", name, rtype.fields[idx]))
+            })
+        })
+        .collect::<Result<Vec<Binding>, LispError>>()?;
+    Ok(Expression::alloc_data(ExpEnum::Record(Record {
+        rtype,
+        fields,
+    })))
+}
+
+/// Send a message (a keyword naming an accessor, setter or method) to a
+/// record, args are unevaluated.
+pub fn call_record(
+    environment: &mut Environment,
+    record: &Expression,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let (rtype, fields) = match &record.get().data {
+        ExpEnum::Record(r) => (r.rtype.clone(), r.fields.clone()),
+        _ => return Err(LispError::new("call_record: requires a record")),
+    };
+    let msg = param_eval(environment, args, rtype.name)?;
+    let message = match &msg.get().data {
+        ExpEnum::Symbol(s, _) => rtype.messages.get(s),
+        _ => None,
+    };
+    match message {
+        Some(Message::Get(idx)) => {
+            params_done(args, rtype.name)?;
+            Ok(fields[*idx].get())
+        }
+        Some(Message::Set(idx)) => {
+            let val = param_eval(environment, args, rtype.name)?;
+            params_done(args, rtype.name)?;
+            fields[*idx].replace(val.clone());
+            Ok(val)
+        }
+        Some(Message::Method(method)) => {
+            let mut margs = vec![record.clone()];
+            for a in args {
+                margs.push(eval(environment, a)?);
+            }
+            call_lambda_eval(environment, method.clone(), &fields, margs)
+        }
+        Some(Message::Type) => {
+            params_done(args, rtype.name)?;
+            Ok(Expression::alloc_data(ExpEnum::String(
+                rtype.name.into(),
+                None,
+            )))
+        }
+        None => Err(LispError::new(format!(
+            "Invalid message ({}) to struct: {}",
+            msg, rtype.name
+        ))),
+    }
+}
+
+fn param_symbols(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Vec<&'static str>, LispError> {
+    let syms = param_eval(environment, args, fn_name)?;
+    let mut res = Vec::new();
+    for s in syms.iter() {
+        match &s.get().data {
+            ExpEnum::Symbol(s, _) => res.push(*s),
+            _ => {
+                return Err(LispError::new(ErrorStrings::mismatched_type(
+                    fn_name,
+                    "Symbol",
+                    &s.display_type(),
+                )))
+            }
+        }
+    }
+    Ok(res)
+}
+
+fn builtin_make_record_type(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "make-record-type";
+    let name = param_eval(environment, args, fn_name)?;
+    let name = match &name.get().data {
+        ExpEnum::Symbol(s, _) => *s,
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "Symbol",
+                &name.display_type(),
+            )))
+        }
+    };
+    let fields = param_symbols(environment, args, fn_name)?;
+    let access_exp = param_eval(environment, args, fn_name)?;
+    let tags = param_symbols(environment, args, fn_name)?;
+    let defaults = param_eval(environment, args, fn_name)?;
+    if !matches!(defaults.get().data, ExpEnum::Lambda(_)) {
+        return Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Lambda",
+            &defaults.display_type(),
+        )));
+    }
+    let methods = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+
+    let mut field_idx = HashMap::new();
+    let mut messages = HashMap::new();
+    let mut field_access = Vec::with_capacity(fields.len());
+    let mut access_iter = access_exp.iter();
+    for (idx, field) in fields.iter().enumerate() {
+        if field_idx.insert(*field, idx).is_some() {
+            return Err(LispError::new(format!(
+                "{}: duplicate field {}",
+                fn_name, field
+            )));
+        }
+        let access = match access_iter.next() {
+            Some(a) => match &a.get().data {
+                ExpEnum::Nil => FieldAccess::Private,
+                ExpEnum::Symbol(":rw", _) => FieldAccess::ReadWrite,
+                ExpEnum::Symbol(":ro", _) => FieldAccess::ReadOnly,
+                ExpEnum::Symbol(":wo", _) => FieldAccess::WriteOnly,
+                _ => {
+                    return Err(LispError::new(format!(
+                        "{}: invalid field access key {} (valid are :rw, :ro and :wo)",
+                        fn_name, a
+                    )))
+                }
+            },
+            None => FieldAccess::Private,
+        };
+        field_access.push(access);
+        if access == FieldAccess::ReadWrite || access == FieldAccess::ReadOnly {
+            let key = environment.interner.intern(&format!(":{}", field));
+            messages.insert(key, Message::Get(idx));
+        }
+        if access == FieldAccess::ReadWrite || access == FieldAccess::WriteOnly {
+            let key = environment.interner.intern(&format!(":set-{}", field));
+            messages.insert(key, Message::Set(idx));
+        }
+    }
+    // Earlier methods win so struct methods take precedence over traits.
+    for method in methods.iter() {
+        let (msg, lambda) = match &method.get().data {
+            ExpEnum::Pair(msg, lambda) => (msg.clone(), lambda.clone()),
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: methods are (:name . lambda) pairs",
+                    fn_name
+                )))
+            }
+        };
+        let msg = match &msg.get().data {
+            ExpEnum::Symbol(s, _) => *s,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: method name must be a symbol, got {}",
+                    fn_name, msg
+                )))
+            }
+        };
+        match &lambda.get().data {
+            ExpEnum::Lambda(l) if l.num_params > fields.len() => {}
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: method {} must be a lambda taking the fields then self",
+                    fn_name, msg
+                )))
+            }
+        }
+        messages.entry(msg).or_insert(Message::Method(lambda));
+    }
+    messages.entry(":type").or_insert(Message::Type);
+    let full_name = format!("{}::{}", environment.namespace.borrow().name(), name);
+    Ok(Expression::alloc_data(ExpEnum::RecordType(Rc::new(
+        RecordType {
+            name,
+            full_name: environment.interner.intern(&full_name),
+            fields,
+            access: field_access,
+            field_idx,
+            messages,
+            tags: tags.into_iter().collect(),
+            defaults,
+        },
+    ))))
+}
+
+fn builtin_record_of_p(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "record-of?";
+    let val = param_eval(environment, args, fn_name)?;
+    let rtype = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let rtype = match &rtype.get().data {
+        ExpEnum::RecordType(t) => t.clone(),
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "RecordType",
+                &rtype.display_type(),
+            )))
+        }
+    };
+    let res = match &val.get().data {
+        ExpEnum::Record(r) => Rc::ptr_eq(&r.rtype, &rtype),
+        _ => false,
+    };
+    Ok(if res {
+        Expression::make_true()
+    } else {
+        Expression::make_false()
+    })
+}
+
+pub fn add_record_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("make-record-type"),
+        Expression::make_function(
+            builtin_make_record_type,
+            r##"Usage: (make-record-type name fields access tags defaults methods) -> RecordType
+
+Create a native record type, this is what struct::defstruct expands into.
+fields is a vector of field names, access a vector with nil (private), :rw,
+:ro or :wo for each field, tags a vector of symbols for meta-tag? and defaults
+a lambda returning a list of the initial field values.  methods is a list of
+(:name . lambda) pairs, each lambda takes the fields followed by self and any
+arguments (a set! on a field parameter updates the record).  The first method
+with a name wins.
+
+Call the record type to make an instance, optionally with :field value pairs
+to override defaults.  Call an instance with :field to read a :rw or :ro
+field, :set-field value to write a :rw or :wo field, :type for the type name
+or the name of a method.  Instances print their :rw and :ro fields, str-readable
+writes #S(type :field value ...) with every field which read turns back into an
+equal record (the type is looked up by its name when read, so def it with the
+same name, and nothing is evaluated).
+
+Section: struct
+
+Example:
+(def test-rec-type (make-record-type 'test-rec '#(a b) '#(:rw nil) '#(:tag-rec)
+                                     (fn () (list 1 2))
+                                     (list (join :sum (fn (a b self) (+ a b)))
+                                           (join :bump (fn (a b self n) (set! b (+ b n)))))))
+(def test-rec (test-rec-type))
+(test::assert-equal 1 (test-rec :a))
+(test::assert-equal 3 (test-rec :sum))
+(test-rec :bump 10)
+(test::assert-equal 13 (test-rec :sum))
+(test-rec :set-a 2)
+(test::assert-equal 14 (test-rec :sum))
+(test::assert-equal "test-rec" (test-rec :type))
+(test::assert-error (test-rec :b))
+(test::assert-equal 12 ((test-rec-type :a 10) :sum))
+(test::assert-true (meta-tag? test-rec :tag-rec))
+(test::assert-true (record-of? test-rec test-rec-type))
+(test::assert-equal "#<test-rec :a 2>" (str test-rec))
+(test::assert-error-msg (read "#S(test-rec :a 1)") "test-rec is not a record type: line 1, col: 3")
+(test::assert-error (make-record-type 'bad '#(a) '#(:rx) '#() (fn () (list 1)) nil))
+"##,
+        ),
+    );
+    data.insert(
+        interner.intern("record-of?"),
+        Expression::make_function(
+            builtin_record_of_p,
+            r#"Usage: (record-of? val record-type) -> t/nil
+
+True if val is an instance of record-type (see make-record-type and
+struct::defstruct).
+
+Section: struct
+
+Example:
+(def test-rec-of-type (make-record-type 'test-rec-of '#(a) '#() '#() (fn () (list 1)) nil))
+(def test-rec-of-type2 (make-record-type 'test-rec-of '#(a) '#() '#() (fn () (list 1)) nil))
+(test::assert-true (record-of? (test-rec-of-type) test-rec-of-type))
+(test::assert-false (record-of? (test-rec-of-type) test-rec-of-type2))
+(test::assert-false (record-of? '#(1) test-rec-of-type))
+"#,
+        ),
+    );
+}
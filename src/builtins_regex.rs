@@ -1,11 +1,17 @@
 use regex::{Captures, Regex};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::hash::{BuildHasher, Hash, Hasher};
+use std::rc::Rc;
 
+use crate::builtins_hashmap::{int_key, text_key, LispMap};
+use crate::builtins_io::file_read_line;
+use crate::builtins_iterator::{make_iter_exp, SlIter};
 use crate::environment::*;
+use crate::eval::call_lambda;
 use crate::interner::*;
 use crate::types::*;
-use crate::{param_eval, params_done};
+use crate::{param_eval, param_eval_optional, params_done};
 use std::collections::hash_map::DefaultHasher;
 
 const FOREGROUND_DEFAULT: &str = "\x1b[39m";
@@ -160,6 +166,260 @@ fn builtin_make_regex(
     }
 }
 
+fn get_regex(regex: &Expression, fn_name: &str) -> Result<Regex, LispError> {
+    match &regex.get().data {
+        ExpEnum::Regex(regex) => Ok(regex.clone()),
+        ExpEnum::String(regex, _) => Regex::new(regex).map_err(|e| {
+            LispError::new(format!(
+                "{} requires a valid regular expression.\n{}",
+                fn_name, e
+            ))
+        }),
+        _ => Err(LispError::new(format!(
+            "{} takes a regex or regex string, got {}",
+            fn_name,
+            regex.display_type()
+        ))),
+    }
+}
+
+// Build the re-captures hash map, span(i) is the byte span of group i.
+fn captures_to_hash(
+    regex: &Regex,
+    text: &str,
+    span: impl Fn(usize) -> Option<(usize, usize)>,
+) -> Expression {
+    let int = |i: usize| Expression::alloc_data(ExpEnum::Int(i as i64));
+    let mut map = LispMap::new();
+    let mut spans = LispMap::new();
+    let mut groups = Vec::new();
+    for (i, name) in regex.capture_names().enumerate() {
+        let (value, span_exp) = match span(i) {
+            Some((start, end)) => (
+                Expression::alloc_data(ExpEnum::String(text[start..end].to_string().into(), None)),
+                Expression::with_list(vec![int(start), int(end)]),
+            ),
+            None => (Expression::make_nil(), Expression::make_nil()),
+        };
+        spans.insert(int_key(i as i64), span_exp.clone());
+        if let Some(name) = name {
+            let name = text_key(name.to_string().into());
+            map.insert(name.clone(), value.clone());
+            spans.insert(name, span_exp);
+        }
+        groups.push(value);
+    }
+    if let Some((start, end)) = span(0) {
+        map.insert(":start".into(), int(start));
+        map.insert(":end".into(), int(end));
+    }
+    map.insert(":match".into(), groups[0].clone());
+    map.insert(":groups".into(), Expression::with_list(groups));
+    map.insert(
+        ":spans".into(),
+        Expression::alloc_data(ExpEnum::HashMap(spans)),
+    );
+    Expression::alloc_data(ExpEnum::HashMap(map))
+}
+
+fn param_string(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<String, LispError> {
+    let exp = param_eval(environment, args, fn_name)?;
+    let exp_d = exp.get();
+    if let ExpEnum::String(s, _) = &exp_d.data {
+        Ok(s.to_string())
+    } else {
+        Err(LispError::new(format!(
+            "{}: requires a string, got {}",
+            fn_name,
+            exp.display_type()
+        )))
+    }
+}
+
+fn param_usize(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Option<usize>, LispError> {
+    match param_eval_optional(environment, args)? {
+        Some(exp) => match &exp.get().data {
+            ExpEnum::Int(i) if *i >= 0 => Ok(Some(*i as usize)),
+            _ => Err(LispError::new(format!(
+                "{}: requires a non-negative int, got {}",
+                fn_name, exp
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn builtin_regex_captures(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "re-captures";
+    let regex = param_eval(environment, args, fn_name)?;
+    let regex = get_regex(&regex, fn_name)?;
+    let text_exp = param_eval(environment, args, fn_name)?;
+    let mut start = param_usize(environment, args, fn_name)?.unwrap_or(0);
+    params_done(args, fn_name)?;
+    // Borrow the text, callers loop over the matches with a start offset so
+    // copying it would make iterating a long string quadratic.
+    let text_d = text_exp.get();
+    let text = if let ExpEnum::String(s, _) = &text_d.data {
+        s
+    } else {
+        return Err(LispError::new(format!(
+            "{}: requires a string, got {}",
+            fn_name,
+            text_exp.display_type()
+        )));
+    };
+    if start > text.len() {
+        return Ok(Expression::make_nil());
+    }
+    // Let iterators step past an empty match one byte at a time.
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    let mut locs = regex.capture_locations();
+    if regex.captures_read_at(&mut locs, text, start).is_some() {
+        Ok(captures_to_hash(&regex, text, |i| locs.get(i)))
+    } else {
+        Ok(Expression::make_nil())
+    }
+}
+
+// The matches of a regex in a string, or in a file line by line, for re-iter.
+struct RegexIter {
+    regex: Regex,
+    file: Option<Rc<RefCell<FileState>>>,
+    text: Option<String>,
+    pos: usize,
+    line: usize,
+}
+
+impl SlIter for RegexIter {
+    fn next(&mut self, _environment: &mut Environment) -> Result<Option<Expression>, LispError> {
+        loop {
+            if let Some(text) = &self.text {
+                let mut start = self.pos;
+                while start < text.len() && !text.is_char_boundary(start) {
+                    start += 1;
+                }
+                let mut locs = self.regex.capture_locations();
+                if start <= text.len()
+                    && self
+                        .regex
+                        .captures_read_at(&mut locs, text, start)
+                        .is_some()
+                {
+                    let (match_start, match_end) = locs.get(0).expect("group 0 always matches");
+                    // Step past an empty match so it is not found again.
+                    self.pos = if match_start == match_end {
+                        match_end + 1
+                    } else {
+                        match_end
+                    };
+                    let caps = captures_to_hash(&self.regex, text, |i| locs.get(i));
+                    if self.file.is_some() {
+                        if let ExpEnum::HashMap(map) = &mut caps.get_mut().data {
+                            let line = Expression::alloc_data(ExpEnum::Int(self.line as i64));
+                            map.insert(":line".into(), line);
+                        }
+                    }
+                    return Ok(Some(caps));
+                }
+            }
+            self.text = match &self.file {
+                Some(file) => file_read_line(&mut file.borrow_mut(), "re-iter")?,
+                None => None,
+            };
+            if self.text.is_none() {
+                return Ok(None);
+            }
+            self.pos = 0;
+            self.line += 1;
+        }
+    }
+}
+
+fn builtin_regex_iter(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "re-iter";
+    let regex = param_eval(environment, args, fn_name)?;
+    let regex = get_regex(&regex, fn_name)?;
+    let source = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let (file, text) = match &source.get().data {
+        ExpEnum::String(s, _) => (None, Some(s.to_string())),
+        ExpEnum::File(file) => (Some(file.clone()), None),
+        _ => {
+            return Err(LispError::new(format!(
+                "{}: requires a string or file, got {}",
+                fn_name,
+                source.display_type()
+            )))
+        }
+    };
+    Ok(make_iter_exp(Box::new(RegexIter {
+        regex,
+        file,
+        text,
+        pos: 0,
+        line: 0,
+    })))
+}
+
+fn builtin_regex_split(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "re-split";
+    let regex = param_eval(environment, args, fn_name)?;
+    let regex = get_regex(&regex, fn_name)?;
+    let text = param_string(environment, args, fn_name)?;
+    let limit = param_usize(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let to_exp = |s: &str| Expression::alloc_data(ExpEnum::String(s.to_string().into(), None));
+    let parts: Vec<Expression> = match limit {
+        Some(limit) => regex.splitn(&text, limit).map(to_exp).collect(),
+        None => regex.split(&text).map(to_exp).collect(),
+    };
+    Ok(Expression::with_list(parts))
+}
+
+fn regex_replace_fn(
+    environment: &mut Environment,
+    regex: &Regex,
+    text: &str,
+    callback: Expression,
+) -> Result<Expression, LispError> {
+    let mut res = String::with_capacity(text.len());
+    let mut last = 0;
+    for caps in regex.captures_iter(text) {
+        let whole = caps.get(0).expect("group 0 always matches");
+        let caps_exp = captures_to_hash(regex, text, |i| caps.get(i).map(|m| (m.start(), m.end())));
+        let replacement = call_lambda(
+            environment,
+            callback.clone(),
+            &mut std::iter::once(caps_exp),
+            false,
+        )?;
+        res.push_str(&text[last..whole.start()]);
+        res.push_str(&replacement.as_string(environment)?);
+        last = whole.end();
+    }
+    res.push_str(&text[last..]);
+    Ok(Expression::alloc_data(ExpEnum::String(res.into(), None)))
+}
+
 fn regex_replace(sample: &str, regex: &Regex, replacement: &str) -> Expression {
     let replaced = regex.replace_all(sample, replacement);
     Expression::alloc_data(ExpEnum::String(replaced.to_string().into(), None))
@@ -170,13 +430,17 @@ fn builtin_regex_replace(
     args: &mut dyn Iterator<Item = Expression>,
 ) -> Result<Expression, LispError> {
     let fn_name = "re-replace";
-    let regex = param_eval(environment, args, fn_name)?;
-    let regex = &regex.get().data;
+    let regex_exp = param_eval(environment, args, fn_name)?;
+    let regex = &regex_exp.get().data;
     let string = param_eval(environment, args, fn_name)?;
     let string = &string.get().data;
-    let replacement = param_eval(environment, args, fn_name)?;
-    let replacement = &replacement.get().data;
+    let replacement_exp = param_eval(environment, args, fn_name)?;
+    let replacement = &replacement_exp.get().data;
     params_done(args, fn_name)?;
+    if let (ExpEnum::String(string, _), ExpEnum::Lambda(_)) = (string, replacement) {
+        let regex = get_regex(&regex_exp, fn_name)?;
+        return regex_replace_fn(environment, &regex, string, replacement_exp.clone());
+    }
     match (regex, string, replacement) {
         (
             ExpEnum::String(regex, _),
@@ -383,6 +647,9 @@ syntax. The replacement string syntax is borrowed from the Rust regex library
 and is specified [here](https://docs.rs/regex/latest/regex/struct.Regex.html#replacement-string-syntax).
 The regex argument can either be a regex string or a regex type
 obtained from [make-regex](#regex::make-regex).
+If replacement is a lambda it is called for each match with the captures hash
+map (see [re-captures](#regex::re-captures)) and the string it returns replaces
+the match.
 
 Section: regex
 
@@ -396,6 +663,107 @@ Example:
 (test::assert-equal
     "10-20-2020 and then again on 12-18-2021 but not on 11-20-2020"
     (re-replace (make-regex "(?P<y>\d{4})-(?P<m>\d{2})-(?P<d>\d{2})") "2020-10-20 and then again on 2021-12-18 but not on 2020-11-20" "\$m-\$d-\$y"))
+(test::assert-equal
+    "a=2 b=4"
+    (re-replace "(?P<n>\d+)" "a=1 b=2" (fn (caps) (* 2 (str->int (hash-get caps "n"))))))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("re-captures"),
+        Expression::make_function(
+            builtin_regex_captures,
+            r#"Usage: (re-captures regex string start?) -> HashMap or nil
+
+Find the first match of regex in string at or after the byte offset start
+(default 0) and return its capture groups as a hash map, nil if there is no
+match.  The hash map contains:
+- each named group as a string key mapped to the matched text (nil if the group
+  did not participate in the match)
+- :match the text of the whole match
+- :start and :end the byte offsets of the whole match (usable with str-sub)
+- :groups a vector of every group's text by index, group 0 is the whole match
+- :spans a hash map of #(start end) byte spans keyed by group name and by group
+  index (an Int)
+The regex argument can either be a regex string or a regex type obtained from
+[make-regex](#regex::make-regex).
+
+Section: regex
+
+Example:
+(def caps (re-captures "(?P<y>\d{4})-(?P<m>\d{2})(-(?P<d>\d{2}))?" "on 2020-10 and 2021-12-18"))
+(test::assert-equal "2020" (hash-get caps "y"))
+(test::assert-equal "10" (hash-get caps "m"))
+(test::assert-false (hash-get caps "d"))
+(test::assert-equal "2020-10" (hash-get caps :match))
+(test::assert-equal 3 (hash-get caps :start))
+(test::assert-equal 10 (hash-get caps :end))
+(test::assert-equal '#("2020-10" "2020" "10" nil nil) (hash-get caps :groups))
+(test::assert-equal '#(8 10) (hash-get (hash-get caps :spans) "m"))
+(test::assert-equal '#(3 10) (hash-get (hash-get caps :spans) 0))
+(test::assert-equal '#(8 10) (hash-get (hash-get caps :spans) 2))
+(def caps (re-captures "(?P<y>\d{4})-(?P<m>\d{2})(-(?P<d>\d{2}))?" "on 2020-10 and 2021-12-18" 10))
+(test::assert-equal "18" (hash-get caps "d"))
+(test::assert-false (re-captures "\d" "abc"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("re-iter"),
+        Expression::make_function(
+            builtin_regex_iter,
+            r#"Usage: (re-iter regex string-or-file) -> iterator
+
+Iterator over the matches of regex in a string or a file opened for reading,
+matches are found lazily so large strings and files are not scanned at once.
+Each item is the hash map re-captures returns for the match, when iterating a
+file the matches are found line by line and each item also has the one based
+line number under :line (spans are relative to the line).
+
+Section: regex
+
+Example:
+(def test-iter (re-iter "(?P<k>\w+)=(?P<v>\d+)" "a=1, b=22,c=333"))
+(test::assert-true (iterator? test-iter))
+(test::assert-false (test-iter :empty?))
+(test::assert-equal "1" (hash-get (test-iter :next!) "v"))
+(def test-caps (test-iter :next!))
+(test::assert-equal "b" (hash-get test-caps "k"))
+(test::assert-equal 5 (hash-get test-caps :start))
+(test::assert-equal "c=333" (hash-get (test-iter :next!) :match))
+(test::assert-true (test-iter :empty?))
+(test::assert-equal '#("" "" "") (((re-iter "x*" "ab") :map (fn (c) (hash-get c :match))) :collect-vec))
+(test::assert-equal '("1" "2") ((iter-map (fn (c) (hash-get c :match)) (re-iter "\d" "a1é2")) :collect))
+(with-temp-file (fn (tmp)
+    (def tst-file (open tmp :create :truncate))
+    (write-line tst-file "one 1")
+    (write-line tst-file "none")
+    (write-line tst-file "2 and 3")
+    (close tst-file)
+    (test::assert-equal '#(#(1 "1") #(3 "2") #(3 "3"))
+                  (((re-iter (make-regex "\d") (open tmp))
+                     :map (fn (c) (vec (hash-get c :line) (hash-get c :match)))) :collect-vec))))
+(test::assert-error (re-iter "x" 1))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("re-split"),
+        Expression::make_function(
+            builtin_regex_split,
+            r#"Usage: (re-split regex string limit?) -> Vector
+
+Split string on every match of regex and return the pieces as a vector of
+strings.  If limit is given return at most limit pieces, the last holding the
+unsplit remainder.  The regex argument can either be a regex string or a regex
+type obtained from [make-regex](#regex::make-regex).
+
+Section: regex
+
+Example:
+(test::assert-equal '#("a" "b" "c" "d") (re-split "\s*,\s*" "a, b ,c,d"))
+(test::assert-equal '#("a" "b ,c,d") (re-split "\s*,\s*" "a, b ,c,d" 2))
+(test::assert-equal '#("" "a" "") (re-split "-" "-a-"))
 "#,
         ),
     );
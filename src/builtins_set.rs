@@ -0,0 +1,409 @@
+use std::borrow::Cow;
+use std::cell::Ref;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use crate::builtins_equal::compare_values;
+use crate::builtins_hashmap::hash_key;
+use crate::builtins_iterator::to_sl_iter;
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::types::*;
+
+/// Members of a set by key.  Keys are owned (not interned) so removed members
+/// do not leave their keys behind.
+pub type SetMap = HashMap<Cow<'static, str>, Expression>;
+
+/// The key val is stored under in a set.  Strings, symbols and chars are keyed
+/// by their type and text, other values like hash map keys (by their readable
+/// form) so only values that are equal? are the same member.
+pub fn set_key(
+    environment: &Environment,
+    val: &Expression,
+) -> Result<Cow<'static, str>, LispError> {
+    Ok(Cow::Owned(match &val.get().data {
+        ExpEnum::Symbol(sym, _) => format!("y{}", sym),
+        ExpEnum::String(s, _) => format!("s{}", s),
+        ExpEnum::Char(ch) => format!("c{}", ch),
+        _ => return hash_key(environment, val),
+    }))
+}
+
+/// The members of a set in compare order (so output is stable).
+pub fn sorted_members(set: &SetMap) -> Vec<Expression> {
+    let mut members: Vec<Expression> = set.values().cloned().collect();
+    members.sort_by(compare_values);
+    members
+}
+
+// The members of set, an error if set is not a set.
+fn set_map<'a>(set: &'a Expression, fn_name: &str) -> Result<Ref<'a, SetMap>, LispError> {
+    Ref::filter_map(set.get(), |obj| match &obj.data {
+        ExpEnum::Set(set) => Some(set),
+        _ => None,
+    })
+    .map_err(|_| {
+        LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Set",
+            &set.display_type(),
+        ))
+    })
+}
+
+fn param_set(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Expression, LispError> {
+    let set = param_eval(environment, args, fn_name)?;
+    set_map(&set, fn_name)?;
+    Ok(set)
+}
+
+fn builtin_make_set(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "make-set";
+    let mut set = SetMap::new();
+    if let Some(items) = param_eval_optional(environment, args)? {
+        params_done(args, fn_name)?;
+        let mut items = to_sl_iter(environment, &items, fn_name)?;
+        while let Some(item) = items.next(environment)? {
+            set.insert(set_key(environment, &item)?, item);
+        }
+    }
+    Ok(Expression::alloc_data(ExpEnum::Set(set)))
+}
+
+fn builtin_set_add(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "set-add!";
+    let set = param_set(environment, args, fn_name)?;
+    for item in make_args(environment, args)? {
+        let key = set_key(environment, &item)?;
+        if let ExpEnum::Set(set) = &mut set.get_mut().data {
+            set.insert(key, item);
+        }
+    }
+    Ok(set)
+}
+
+fn builtin_set_remove(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "set-remove!";
+    let set = param_set(environment, args, fn_name)?;
+    for item in make_args(environment, args)? {
+        let key = set_key(environment, &item)?;
+        if let ExpEnum::Set(set) = &mut set.get_mut().data {
+            set.remove(&key);
+        }
+    }
+    Ok(set)
+}
+
+fn builtin_contains(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "contains?";
+    let set = param_set(environment, args, fn_name)?;
+    let item = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let key = set_key(environment, &item)?;
+    if set_map(&set, fn_name)?.contains_key(&key) {
+        Ok(Expression::make_true())
+    } else {
+        Ok(Expression::make_false())
+    }
+}
+
+fn param_sets(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Vec<Expression>, LispError> {
+    let sets = make_args(environment, args)?;
+    for set in &sets {
+        set_map(set, fn_name)?;
+    }
+    Ok(sets)
+}
+
+// A new set with the members of set that are (or are not if in_all is false)
+// in all of the other sets.
+fn filter_set(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+    in_all: bool,
+) -> Result<Expression, LispError> {
+    let set = param_set(environment, args, fn_name)?;
+    let others = param_sets(environment, args, fn_name)?;
+    let others = others
+        .iter()
+        .map(|other| set_map(other, fn_name))
+        .collect::<Result<Vec<_>, LispError>>()?;
+    let res: SetMap = set_map(&set, fn_name)?
+        .iter()
+        .filter(|(key, _)| {
+            if in_all {
+                others.iter().all(|other| other.contains_key(*key))
+            } else {
+                !others.iter().any(|other| other.contains_key(*key))
+            }
+        })
+        .map(|(key, val)| (key.clone(), val.clone()))
+        .collect();
+    Ok(Expression::alloc_data(ExpEnum::Set(res)))
+}
+
+fn builtin_set_union(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "set-union";
+    let mut res = SetMap::new();
+    for set in param_sets(environment, args, fn_name)? {
+        for (key, val) in set_map(&set, fn_name)?.iter() {
+            res.insert(key.clone(), val.clone());
+        }
+    }
+    Ok(Expression::alloc_data(ExpEnum::Set(res)))
+}
+
+fn builtin_set_intersection(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    filter_set(environment, args, "set-intersection", true)
+}
+
+fn builtin_set_difference(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    filter_set(environment, args, "set-difference", false)
+}
+
+// True if every member of sub is a member of set.
+fn is_subset(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+    sub_first: bool,
+) -> Result<Expression, LispError> {
+    let first = param_set(environment, args, fn_name)?;
+    let second = param_set(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let (sub, set) = if sub_first {
+        (first, second)
+    } else {
+        (second, first)
+    };
+    let set = set_map(&set, fn_name)?;
+    if set_map(&sub, fn_name)?
+        .keys()
+        .all(|key| set.contains_key(key))
+    {
+        Ok(Expression::make_true())
+    } else {
+        Ok(Expression::make_false())
+    }
+}
+
+fn builtin_is_subset(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    is_subset(environment, args, "subset?", true)
+}
+
+fn builtin_is_superset(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    is_subset(environment, args, "superset?", false)
+}
+
+pub fn add_set_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("make-set"),
+        Expression::make_function(
+            builtin_make_set,
+            r##"Usage: (make-set items?) -> set
+
+Make a new set, with the members of items (anything make-iter takes) if
+provided.  A set literal is written #s(member*), the members are not
+evaluated.
+
+Members are kept with the same hashing as hash map keys, members that are
+equal? are the same member (so "a", 'a and #\a are three members).  Sets print in a form that reads back as an equal? set
+(members in compare order) and can be iterated (in the same order).
+
+Section: set
+
+Example:
+(def test-set (make-set '(1 2 2 "three" (4 5))))
+(test::assert-true (set? test-set))
+(test::assert-equal 4 (length test-set))
+(test::assert-true (contains? test-set '(4 5)))
+(test::assert-equal 0 (length (make-set)))
+(test::assert-true (equal? #s(1 2) (make-set '#(2 1 1))))
+(test::assert-equal "#s(1 2 \"three\" (4 5))" (str test-set))
+(test::assert-true (equal? test-set (read (str test-set))))
+(test::assert-equal '(1 2 "three" (4 5)) (iterator::collect test-set))
+(test::assert-equal '(#\a #\b) (iterator::collect (make-set "abba")))
+(test::assert-equal 3 (length (make-set '("a" a #\a "a"))))
+(test::assert-error (make-set 1))
+"##,
+        ),
+    );
+    data.insert(
+        interner.intern("set-add!"),
+        Expression::make_function(
+            builtin_set_add,
+            r#"Usage: (set-add! set item*) -> set
+
+Add each item to set and return set.  This is a destructive form!
+
+Section: set
+
+Example:
+(def test-set (make-set))
+(set-add! test-set 1 "two" :three)
+(set-add! test-set 1)
+(test::assert-equal 3 (length test-set))
+(test::assert-true (contains? test-set "two"))
+(test::assert-error (set-add! '(1) 2))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("set-remove!"),
+        Expression::make_function(
+            builtin_set_remove,
+            r#"Usage: (set-remove! set item*) -> set
+
+Remove each item from set (if a member) and return set.  This is a destructive
+form!
+
+Section: set
+
+Example:
+(def test-set (make-set '(1 2 3)))
+(set-remove! test-set 1 3 4)
+(test::assert-true (equal? #s(2) test-set))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("contains?"),
+        Expression::make_function(
+            builtin_contains,
+            r#"Usage: (contains? set item) -> t/f
+
+True if item is a member of set.
+
+Section: set
+
+Example:
+(test::assert-true (contains? #s(1 2 "a") 2))
+(test::assert-true (contains? #s(1 2 "a") "a"))
+(test::assert-false (contains? #s(1 2 "a") 3))
+(test::assert-true (contains? #s(#(1 2)) '#(1 2)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("set-union"),
+        Expression::make_function(
+            builtin_set_union,
+            r#"Usage: (set-union set*) -> set
+
+Return a new set with the members of all the sets.
+
+Section: set
+
+Example:
+(test::assert-true (equal? #s(1 2 3) (set-union #s(1 2) #s(2 3))))
+(test::assert-true (equal? #s() (set-union)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("set-intersection"),
+        Expression::make_function(
+            builtin_set_intersection,
+            r#"Usage: (set-intersection set set*) -> set
+
+Return a new set with the members of set that are in all the other sets.
+
+Section: set
+
+Example:
+(test::assert-true (equal? #s(2) (set-intersection #s(1 2) #s(2 3))))
+(test::assert-true (equal? #s(2) (set-intersection #s(1 2 3) #s(2 3) #s(2))))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("set-difference"),
+        Expression::make_function(
+            builtin_set_difference,
+            r#"Usage: (set-difference set set*) -> set
+
+Return a new set with the members of set that are not in any of the other sets.
+
+Section: set
+
+Example:
+(test::assert-true (equal? #s(1) (set-difference #s(1 2) #s(2 3))))
+(test::assert-true (equal? #s(1 4) (set-difference #s(1 2 3 4) #s(2) #s(3))))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("subset?"),
+        Expression::make_function(
+            builtin_is_subset,
+            r#"Usage: (subset? sub set) -> t/f
+
+True if every member of sub is a member of set.
+
+Section: set
+
+Example:
+(test::assert-true (subset? #s(1 2) #s(1 2 3)))
+(test::assert-true (subset? #s() #s(1)))
+(test::assert-true (subset? #s(1) #s(1)))
+(test::assert-false (subset? #s(1 4) #s(1 2 3)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("superset?"),
+        Expression::make_function(
+            builtin_is_superset,
+            r#"Usage: (superset? set sub) -> t/f
+
+True if every member of sub is a member of set.
+
+Section: set
+
+Example:
+(test::assert-true (superset? #s(1 2 3) #s(1 2)))
+(test::assert-false (superset? #s(1 2) #s(1 2 3)))
+"#,
+        ),
+    );
+}
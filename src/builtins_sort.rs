@@ -0,0 +1,418 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+use crate::builtins_equal::compare_values;
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::types::*;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Order {
+    Default,
+    Natural,
+    Numeric,
+}
+
+struct SortOpts {
+    less: Option<Expression>,
+    desc: bool,
+    order: Order,
+}
+
+fn is_callable(exp: &Expression) -> bool {
+    match &exp.get().data {
+        ExpEnum::Lambda(_) => true,
+        ExpEnum::Function(f) => !f.is_special_form,
+        _ => false,
+    }
+}
+
+fn param_sort_opts(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<SortOpts, LispError> {
+    let mut opts = SortOpts {
+        less: None,
+        desc: false,
+        order: Order::Default,
+    };
+    while let Some(opt) = param_eval_optional(environment, args)? {
+        if is_callable(&opt) && opts.less.is_none() {
+            opts.less = Some(opt);
+            continue;
+        }
+        match &opt.get().data {
+            ExpEnum::Symbol(":desc", _) => opts.desc = true,
+            ExpEnum::Symbol(":natural", _) => opts.order = Order::Natural,
+            ExpEnum::Symbol(":numeric", _) => opts.order = Order::Numeric,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: invalid option {}, expected a comparator, :desc, :natural or :numeric",
+                    fn_name, opt
+                )))
+            }
+        }
+    }
+    if opts.less.is_some() && opts.order != Order::Default {
+        return Err(LispError::new(format!(
+            "{}: :natural and :numeric can not be used with a comparator",
+            fn_name
+        )));
+    }
+    Ok(opts)
+}
+
+// Compare runs of digits by value and everything else by character.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (a_ch, b_ch) = match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_ch), Some(b_ch)) => (a_ch, b_ch),
+        };
+        if a_ch.is_ascii_digit() && b_ch.is_ascii_digit() {
+            let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+            let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+            let a_num = a[..a_end].trim_start_matches('0');
+            let b_num = b[..b_end].trim_start_matches('0');
+            let ord = a_num
+                .len()
+                .cmp(&b_num.len())
+                .then_with(|| a_num.cmp(b_num))
+                .then_with(|| a_end.cmp(&b_end));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            a = &a[a_end..];
+            b = &b[b_end..];
+        } else {
+            if a_ch != b_ch {
+                return a_ch.cmp(&b_ch);
+            }
+            a = &a[a_ch.len_utf8()..];
+            b = &b[b_ch.len_utf8()..];
+        }
+    }
+}
+
+// The number at the start of s (after whitespace) or 0.0, like sort -n.
+fn leading_number(s: &str) -> f64 {
+    let s = s.trim_start();
+    let bytes = s.as_bytes();
+    let digits = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    };
+    let mut end = if s.starts_with('-') || s.starts_with('+') {
+        1
+    } else {
+        0
+    };
+    end = digits(end);
+    if end < bytes.len() && bytes[end] == b'.' {
+        end = digits(end + 1);
+    }
+    s[..end].parse().unwrap_or(0.0)
+}
+
+fn numeric_value(environment: &mut Environment, exp: &Expression) -> Result<f64, LispError> {
+    match &exp.get().data {
+        ExpEnum::Int(i) => Ok(*i as f64),
+        ExpEnum::Float(f) => Ok(*f),
+        _ => Ok(leading_number(&exp.as_string(environment)?)),
+    }
+}
+
+// Ordering used when no comparator is given.
+fn default_cmp(
+    environment: &mut Environment,
+    a: &Expression,
+    b: &Expression,
+    order: Order,
+) -> Result<Ordering, LispError> {
+    match order {
+        Order::Natural => {
+            let a = a.as_string(environment)?;
+            let b = b.as_string(environment)?;
+            return Ok(natural_cmp(&a, &b));
+        }
+        Order::Numeric => {
+            let a = numeric_value(environment, a)?;
+            let b = numeric_value(environment, b)?;
+            return Ok(a.partial_cmp(&b).unwrap_or(Ordering::Equal));
+        }
+        Order::Default => {}
+    }
+    Ok(compare_values(a, b))
+}
+
+// A comparator returns true for less than or an Int < 0 (like compare).
+fn is_less(res: &Expression) -> bool {
+    match &res.get().data {
+        ExpEnum::Int(i) => *i < 0,
+        _ => !res.is_falsey(),
+    }
+}
+
+// Stable merge sort with a fallible less than, right is only taken before left
+// when it is strictly less so equal items keep their order.
+fn merge_sort<T: Clone>(
+    items: &mut [T],
+    less: &mut dyn FnMut(&T, &T) -> Result<bool, LispError>,
+) -> Result<(), LispError> {
+    if items.len() <= 8 {
+        // Insertion sort for short runs.
+        for i in 1..items.len() {
+            let mut j = i;
+            while j > 0 && less(&items[j], &items[j - 1])? {
+                items.swap(j, j - 1);
+                j -= 1;
+            }
+        }
+        return Ok(());
+    }
+    let mid = items.len() / 2;
+    merge_sort(&mut items[..mid], less)?;
+    merge_sort(&mut items[mid..], less)?;
+    if !less(&items[mid], &items[mid - 1])? {
+        // Already in order.
+        return Ok(());
+    }
+    let left = items[..mid].to_vec();
+    let (mut l, mut r, mut out) = (0, mid, 0);
+    while l < left.len() && r < items.len() {
+        if less(&items[r], &left[l])? {
+            items[out] = items[r].clone();
+            r += 1;
+        } else {
+            items[out] = left[l].clone();
+            l += 1;
+        }
+        out += 1;
+    }
+    while l < left.len() {
+        items[out] = left[l].clone();
+        l += 1;
+        out += 1;
+    }
+    Ok(())
+}
+
+fn sort_items(
+    environment: &mut Environment,
+    items: &mut [Expression],
+    opts: &SortOpts,
+) -> Result<(), LispError> {
+    let desc = opts.desc;
+    match &opts.less {
+        Some(less_fn) => merge_sort(items, &mut |a, b| {
+            let (a, b) = if desc { (b, a) } else { (a, b) };
+            let res = call_fn(environment, less_fn, vec![a.clone(), b.clone()])?;
+            Ok(is_less(&res))
+        }),
+        None => merge_sort(items, &mut |a, b| {
+            let (a, b) = if desc { (b, a) } else { (a, b) };
+            Ok(default_cmp(environment, a, b, opts.order)? == Ordering::Less)
+        }),
+    }
+}
+
+pub fn param_seq(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Expression, LispError> {
+    let seq = param_eval(environment, args, fn_name)?;
+    match &seq.get().data {
+        ExpEnum::Vector(_) | ExpEnum::Pair(_, _) | ExpEnum::Nil => {}
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "list or vector",
+                &seq.display_type(),
+            )))
+        }
+    }
+    Ok(seq)
+}
+
+// Return the sorted items as the same kind of sequence as seq.
+pub fn make_like(seq: &Expression, items: Vec<Expression>) -> Expression {
+    match &seq.get().data {
+        ExpEnum::Vector(_) => Expression::with_list(items),
+        _ => Expression::cons_from_vec(&items, None),
+    }
+}
+
+fn builtin_sort(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "sort";
+    let seq = param_seq(environment, args, fn_name)?;
+    let opts = param_sort_opts(environment, args, fn_name)?;
+    let mut items: Vec<Expression> = seq.iter().collect();
+    sort_items(environment, &mut items, &opts)?;
+    Ok(make_like(&seq, items))
+}
+
+fn builtin_sort_bang(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "sort!";
+    let seq = param_seq(environment, args, fn_name)?;
+    let opts = param_sort_opts(environment, args, fn_name)?;
+    let mut items: Vec<Expression> = seq.iter().collect();
+    sort_items(environment, &mut items, &opts)?;
+    let mut seq_d = seq.get_mut();
+    if let ExpEnum::Vector(v) = &mut seq_d.data {
+        *v = items;
+        drop(seq_d);
+        return Ok(seq);
+    }
+    drop(seq_d);
+    // A list is sorted in place by replacing the car of each pair.
+    let mut pair = seq.clone();
+    for item in items {
+        let next = match &mut pair.get_mut().data {
+            ExpEnum::Pair(car, cdr) => {
+                *car = item;
+                cdr.clone()
+            }
+            _ => break,
+        };
+        pair = next;
+    }
+    Ok(seq)
+}
+
+fn builtin_sort_by(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "sort-by";
+    let key_fn = param_eval(environment, args, fn_name)?;
+    if !is_callable(&key_fn) {
+        return Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "key function (lambda or builtin)",
+            &key_fn.display_type(),
+        )));
+    }
+    let seq = param_seq(environment, args, fn_name)?;
+    let opts = param_sort_opts(environment, args, fn_name)?;
+    // Compute each key once, then sort the (key, item) pairs by key.
+    let mut keyed = Vec::new();
+    for item in seq.iter() {
+        keyed.push((call_fn(environment, &key_fn, vec![item.clone()])?, item));
+    }
+    let desc = opts.desc;
+    match &opts.less {
+        Some(less_fn) => merge_sort(&mut keyed, &mut |a, b| {
+            let (a, b) = if desc { (b, a) } else { (a, b) };
+            let res = call_fn(environment, less_fn, vec![a.0.clone(), b.0.clone()])?;
+            Ok(is_less(&res))
+        })?,
+        None => merge_sort(&mut keyed, &mut |a, b| {
+            let (a, b) = if desc { (b, a) } else { (a, b) };
+            Ok(default_cmp(environment, &a.0, &b.0, opts.order)? == Ordering::Less)
+        })?,
+    }
+    Ok(make_like(
+        &seq,
+        keyed.into_iter().map(|(_, item)| item).collect(),
+    ))
+}
+
+pub fn add_sort_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("sort"),
+        Expression::make_function(
+            builtin_sort,
+            r#"Usage: (sort sequence comparator? :desc? :natural? :numeric?) -> sequence
+
+Return a sorted copy of sequence (a list or vector, the result is the same
+kind).  The sort is stable (equal items keep their order) and O(n log n).
+
+comparator is an optional lambda or builtin that takes two items and returns
+true if the first sorts before the second (like <) or an Int less than 0 (like
+compare).  Without one items are ordered with compare so any values can be
+sorted, numbers by value and strings, chars and symbols by content.  :desc reverses the order (equal items still keep their order).
+:natural compares strings with runs of digits by value so "file2" sorts before
+"file10" and :numeric compares by the number at the start of each item (0 if
+there is none) like sort -n.
+
+Section: sequence
+
+Example:
+(test::assert-equal '(1 2 3) (sort '(2 3 1)))
+(test::assert-equal '#(1 1.5 2 3) (sort '#(2 3 1.5 1)))
+(test::assert-equal '#(3 2 1) (sort '#(2 3 1) :desc))
+(test::assert-equal '#(3 2 1) (sort '#(2 3 1) >))
+(test::assert-equal '#("aaa" "aab" "ba") (sort '#("ba" "aab" "aaa")))
+(test::assert-equal '#("file1" "file2" "file10") (sort '#("file10" "file2" "file1") :natural))
+(test::assert-equal '#("file1" "file10" "file2") (sort '#("file10" "file2" "file1")))
+(test::assert-equal '#("x" "2 b" "10 a") (sort '#("10 a" "x" "2 b") :numeric))
+(test::assert-equal '#(#(1 "b") #(1 "a") #(2 "c"))
+                    (sort '#(#(2 "c") #(1 "b") #(1 "a")) (fn (a b) (< (vec-nth a 0) (vec-nth b 0)))))
+(test::assert-equal '() (sort '()))
+(test::assert-equal '#(1 "a" :b) (sort '#(:b "a" 1)))
+(test::assert-equal '#(3 2 1) (sort '#(2 3 1) (fn (a b) (compare b a))))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("sort!"),
+        Expression::make_function(
+            builtin_sort_bang,
+            r#"Usage: (sort! sequence comparator? :desc? :natural? :numeric?) -> sequence
+
+Sort sequence (a list or vector) in place and return it, takes the same
+options as sort.  The items are sorted in a temporary vector (the comparator
+may use sequence while it runs) and then stored back into sequence.
+
+Section: sequence
+
+Example:
+(def test-sort-vec (vec 3 1 2))
+(sort! test-sort-vec)
+(test::assert-equal '#(1 2 3) test-sort-vec)
+(def test-sort-list (list "b" "c" "a"))
+(test::assert-equal '("c" "b" "a") (sort! test-sort-list :desc))
+(test::assert-equal '("c" "b" "a") test-sort-list)
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("sort-by"),
+        Expression::make_function(
+            builtin_sort_by,
+            r#"Usage: (sort-by key-fn sequence comparator? :desc? :natural? :numeric?) -> sequence
+
+Return a copy of sequence (a list or vector) sorted by the result of calling
+key-fn on each item, key-fn is called once per item.  The options are the same
+as sort and apply to the keys.  The sort is stable.
+
+Section: sequence
+
+Example:
+(test::assert-equal '#("a" "bb" "ccc") (sort-by length '#("ccc" "a" "bb")))
+(test::assert-equal '#("ccc" "bb" "a") (sort-by length '#("bb" "ccc" "a") :desc))
+(def people '#(#("sam" 40) #("ann" 31) #("bob" 40)))
+(test::assert-equal '#(#("ann" 31) #("sam" 40) #("bob" 40)) (sort-by (fn (p) (vec-nth p 1)) people))
+(test::assert-equal '(" v2.txt" "v10.txt") (sort-by str-trim '("v10.txt" " v2.txt") :natural))
+"#,
+        ),
+    );
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::hash::BuildHasher;
 
+use crate::builtins_hashmap::LispMap;
 use crate::builtins_util::*;
 use crate::environment::*;
 use crate::interner::*;
@@ -228,17 +229,17 @@ fn builtin_summary_stats(
     let mut stats = SummaryStats::new(&mut floats)?;
     stats.calculate();
 
-    let mut map: HashMap<&'static str, Expression> = HashMap::new();
+    let mut map = LispMap::new();
 
-    map.insert(":mean", float_to_expr(stats.calc_mean()));
-    map.insert(":sd", float_to_expr(stats.calc_std_dev()));
-    map.insert(":mode", floats_to_expr(stats.calc_mode()));
-    map.insert(":min", float_to_expr(stats.calc_min()));
-    map.insert(":q1", float_to_expr(stats.calc_q1()));
-    map.insert(":med", float_to_expr(stats.calc_median()));
-    map.insert(":q3", float_to_expr(stats.calc_q3()));
-    map.insert(":max", float_to_expr(stats.calc_max()));
-    map.insert(":vec", floats_to_expr(Vec::from(stats.vec)));
+    map.insert(":mean".into(), float_to_expr(stats.calc_mean()));
+    map.insert(":sd".into(), float_to_expr(stats.calc_std_dev()));
+    map.insert(":mode".into(), floats_to_expr(stats.calc_mode()));
+    map.insert(":min".into(), float_to_expr(stats.calc_min()));
+    map.insert(":q1".into(), float_to_expr(stats.calc_q1()));
+    map.insert(":med".into(), float_to_expr(stats.calc_median()));
+    map.insert(":q3".into(), float_to_expr(stats.calc_q3()));
+    map.insert(":max".into(), float_to_expr(stats.calc_max()));
+    map.insert(":vec".into(), floats_to_expr(Vec::from(stats.vec)));
 
     Ok(Expression::alloc_data(ExpEnum::HashMap(map)))
 }
@@ -335,6 +336,313 @@ fn builtin_mean(
     Ok(Expression::alloc_data(ExpEnum::Float(mean)))
 }
 
+// The numbers in a list or vector as floats, read directly from the items.
+fn seq_floats(seq: &Expression, fn_name: &str) -> Result<Vec<f64>, LispError> {
+    match &seq.get().data {
+        ExpEnum::Vector(_) | ExpEnum::Pair(_, _) | ExpEnum::Nil => {}
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "list or vector",
+                &seq.display_type(),
+            )))
+        }
+    }
+    let mut floats = Vec::new();
+    for item in seq.iter() {
+        match &item.get().data {
+            ExpEnum::Int(i) => floats.push(*i as f64),
+            ExpEnum::Float(f) => floats.push(*f),
+            _ => {
+                return Err(LispError::new(ErrorStrings::mismatched_type(
+                    fn_name,
+                    "Int or Float",
+                    &item.display_type(),
+                )))
+            }
+        }
+    }
+    Ok(floats)
+}
+
+fn param_floats(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Vec<f64>, LispError> {
+    let seq = param_eval(environment, args, fn_name)?;
+    let floats = seq_floats(&seq, fn_name)?;
+    if floats.is_empty() {
+        return Err(LispError::new(format!(
+            "{}: expected at least one number",
+            fn_name
+        )));
+    }
+    Ok(floats)
+}
+
+// Two sequences of numbers with the same length.
+fn param_float_pairs(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<(Vec<f64>, Vec<f64>), LispError> {
+    let xs = param_floats(environment, args, fn_name)?;
+    let ys = param_floats(environment, args, fn_name)?;
+    if xs.len() != ys.len() {
+        return Err(LispError::new(format!(
+            "{}: sequences have different lengths ({} and {})",
+            fn_name,
+            xs.len(),
+            ys.len()
+        )));
+    }
+    Ok((xs, ys))
+}
+
+// The divisor for variance like stats, n - 1 for a :sample (default) or n for
+// the whole :population.
+fn param_divisor(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    count: usize,
+    fn_name: &str,
+) -> Result<f64, LispError> {
+    let population = match param_eval_optional(environment, args)? {
+        Some(kind) => match &kind.get().data {
+            ExpEnum::Symbol(":sample", _) => false,
+            ExpEnum::Symbol(":population", _) => true,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: expected :sample or :population, got {}",
+                    fn_name, kind
+                )))
+            }
+        },
+        None => false,
+    };
+    params_done(args, fn_name)?;
+    if population {
+        Ok(count as f64)
+    } else if count < 2 {
+        Err(LispError::new(format!(
+            "{}: a sample needs at least two numbers",
+            fn_name
+        )))
+    } else {
+        Ok((count - 1) as f64)
+    }
+}
+
+fn mean_of(vec: &[f64]) -> f64 {
+    vec.iter().sum::<f64>() / vec.len() as f64
+}
+
+// Sum of (x - mean x) * (y - mean y).
+fn co_moment(xs: &[f64], ys: &[f64]) -> f64 {
+    let (x_mean, y_mean) = (mean_of(xs), mean_of(ys));
+    xs.iter()
+        .zip(ys.iter())
+        .map(|(x, y)| (x - x_mean) * (y - y_mean))
+        .sum()
+}
+
+// The p-th percentile of sorted, linear interpolation between closest ranks.
+fn percentile_of(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let lo = rank.floor() as usize;
+    let hi = rank.ceil() as usize;
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+}
+
+fn builtin_percentile(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "percentile";
+    let mut floats = param_floats(environment, args, fn_name)?;
+    let p = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    floats.sort_by(|a, b| a.total_cmp(b));
+    let get = |p: f64| {
+        if (0.0..=100.0).contains(&p) {
+            Ok(percentile_of(&floats, p))
+        } else {
+            Err(LispError::new(format!(
+                "{}: percentile {} not between 0 and 100",
+                fn_name, p
+            )))
+        }
+    };
+    let p_d = p.get();
+    match &p_d.data {
+        ExpEnum::Vector(_) | ExpEnum::Pair(_, _) => {
+            let mut res = Vec::new();
+            for p in seq_floats(&p, fn_name)? {
+                res.push(get(p)?);
+            }
+            Ok(floats_to_expr(res))
+        }
+        _ => {
+            drop(p_d);
+            Ok(float_to_expr(get(p.make_float(environment)?)?))
+        }
+    }
+}
+
+fn builtin_variance(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "variance";
+    let floats = param_floats(environment, args, fn_name)?;
+    let divisor = param_divisor(environment, args, floats.len(), fn_name)?;
+    Ok(float_to_expr(co_moment(&floats, &floats) / divisor))
+}
+
+fn builtin_covariance(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "covariance";
+    let (xs, ys) = param_float_pairs(environment, args, fn_name)?;
+    let divisor = param_divisor(environment, args, xs.len(), fn_name)?;
+    Ok(float_to_expr(co_moment(&xs, &ys) / divisor))
+}
+
+fn builtin_correlation(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "correlation";
+    let (xs, ys) = param_float_pairs(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let spread = (co_moment(&xs, &xs) * co_moment(&ys, &ys)).sqrt();
+    if spread == 0.0 {
+        return Err(LispError::new(format!(
+            "{}: undefined for a sequence with no variance",
+            fn_name
+        )));
+    }
+    Ok(float_to_expr(co_moment(&xs, &ys) / spread))
+}
+
+fn builtin_linear_regression(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "linear-regression";
+    let (xs, ys) = param_float_pairs(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let (sxx, syy, sxy) = (
+        co_moment(&xs, &xs),
+        co_moment(&ys, &ys),
+        co_moment(&xs, &ys),
+    );
+    if sxx == 0.0 {
+        return Err(LispError::new(format!(
+            "{}: undefined when all x values are the same",
+            fn_name
+        )));
+    }
+    let slope = sxy / sxx;
+    let intercept = mean_of(&ys) - slope * mean_of(&xs);
+    // A perfect fit when there is no y variance to explain.
+    let r2 = if syy == 0.0 {
+        1.0
+    } else {
+        sxy * sxy / (sxx * syy)
+    };
+    let mut map = LispMap::new();
+    map.insert(":slope".into(), float_to_expr(slope));
+    map.insert(":intercept".into(), float_to_expr(intercept));
+    map.insert(":r2".into(), float_to_expr(r2));
+    Ok(Expression::alloc_data(ExpEnum::HashMap(map)))
+}
+
+fn builtin_histogram(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "histogram";
+    let floats = param_floats(environment, args, fn_name)?;
+    let bins = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    let lo = match param_eval_optional(environment, args)? {
+        Some(lo) => lo.make_float(environment)?,
+        None => floats.iter().cloned().fold(f64::INFINITY, f64::min),
+    };
+    let hi = match param_eval_optional(environment, args)? {
+        Some(hi) => hi.make_float(environment)?,
+        None => floats.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+    };
+    params_done(args, fn_name)?;
+    if bins < 1 {
+        return Err(LispError::new(format!(
+            "{}: expected at least one bin",
+            fn_name
+        )));
+    }
+    if hi < lo {
+        return Err(LispError::new(format!(
+            "{}: max {} is less than min {}",
+            fn_name, hi, lo
+        )));
+    }
+    let bins = bins as usize;
+    let width = (hi - lo) / bins as f64;
+    let mut counts = vec![0_i64; bins];
+    for f in floats {
+        if f < lo || f > hi {
+            continue;
+        }
+        // The last bin includes max.
+        let bin = if width == 0.0 {
+            0
+        } else {
+            (((f - lo) / width) as usize).min(bins - 1)
+        };
+        counts[bin] += 1;
+    }
+    let edges = (0..=bins).map(|i| lo + width * i as f64).collect();
+    let counts = counts
+        .into_iter()
+        .map(|c| Expression::alloc_data(ExpEnum::Int(c)))
+        .collect();
+    let mut map = LispMap::new();
+    map.insert(
+        ":counts".into(),
+        Expression::alloc_data(ExpEnum::Vector(counts)),
+    );
+    map.insert(":edges".into(), floats_to_expr(edges));
+    Ok(Expression::alloc_data(ExpEnum::HashMap(map)))
+}
+
+fn builtin_moving_average(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "moving-average";
+    let floats = param_floats(environment, args, fn_name)?;
+    let window = param_eval(environment, args, fn_name)?.make_int(environment)?;
+    params_done(args, fn_name)?;
+    if window < 1 || window as usize > floats.len() {
+        return Err(LispError::new(format!(
+            "{}: window must be between 1 and the number of values ({})",
+            fn_name,
+            floats.len()
+        )));
+    }
+    let window = window as usize;
+    let mut averages = Vec::with_capacity(floats.len() - window + 1);
+    let mut sum: f64 = floats[..window].iter().sum();
+    averages.push(sum / window as f64);
+    for i in window..floats.len() {
+        sum += floats[i] - floats[i - window];
+        averages.push(sum / window as f64);
+    }
+    Ok(floats_to_expr(averages))
+}
+
 pub fn add_stats_builtins<S: BuildHasher>(
     interner: &mut Interner,
     data: &mut HashMap<&'static str, (Expression, String), S>,
@@ -512,4 +820,171 @@ Example:
             ",
         ),
     );
+    data.insert(
+        interner.intern("percentile"),
+        Expression::make_function(
+            builtin_percentile,
+            "Usage: (percentile numbers p) -> float
+
+Returns the p-th percentile (0 to 100) of a list or vector of numbers,
+interpolating linearly between the closest ranks.  If p is a list or vector
+returns a vector with each percentile (sorting the numbers once).
+
+Section: stats
+
+Example:
+(ns-import 'stats)
+(test::assert-equal 5.5 (percentile '(10 9 8 7 6 5 4 3 2 1) 50))
+(test::assert-equal 1 (percentile '#(3 1 2) 0))
+(test::assert-equal 3 (percentile '#(3 1 2) 100))
+(test::assert-equal 95.05 (percentile (collect-vec (range 1 100)) 95))
+(test::assert-equal '#(2.5 4.5) (percentile '(1 2 3 4 5) '(37.5 87.5)))
+(test::assert-error-msg (percentile '() 50) \"percentile: expected at least one number\")
+(test::assert-error (percentile '(1 2) 101))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("variance"),
+        Expression::make_function(
+            builtin_variance,
+            "Usage: (variance numbers :sample/:population?) -> float
+
+Returns the variance of a list or vector of numbers, treating them as a
+:sample (the default, divides by n - 1 like std-dev) or the whole
+:population (divides by n).
+
+Section: stats
+
+Example:
+(ns-import 'stats)
+(test::assert-equal 2.5 (variance '(1 2 3 4 5)))
+(test::assert-equal 2.5 (variance '#(1 2 3 4 5) :sample))
+(test::assert-equal 2 (variance '#(1 2 3 4 5) :population))
+(test::assert-equal 0 (variance '(7) :population))
+(test::assert-error (variance '(7)))
+(test::assert-error (variance '(1 2) :other))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("covariance"),
+        Expression::make_function(
+            builtin_covariance,
+            "Usage: (covariance xs ys :sample/:population?) -> float
+
+Returns the covariance of two lists or vectors of numbers with the same length,
+as a :sample (the default, divides by n - 1) or the whole :population (divides
+by n).
+
+Section: stats
+
+Example:
+(ns-import 'stats)
+(test::assert-equal 5 (covariance '(1 2 3 4 5) '(2 4 6 8 10)))
+(test::assert-equal 4 (covariance '(1 2 3 4 5) '(2 4 6 8 10) :population))
+(test::assert-equal -2.5 (covariance '(1 2 3 4 5) '#(5 4 3 2 1)))
+(test::assert-error (covariance '(1 2 3) '(1 2)))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("correlation"),
+        Expression::make_function(
+            builtin_correlation,
+            "Usage: (correlation xs ys) -> float
+
+Returns the Pearson correlation coefficient (-1 to 1) of two lists or vectors
+of numbers with the same length.
+
+Section: stats
+
+Example:
+(ns-import 'stats)
+(test::assert-equal 1 (correlation '(1 2 3 4 5) '(2 4 6 8 10)))
+(test::assert-equal -1 (correlation '(1 2 3 4 5) '(5 4 3 2 1)))
+(test::assert-equal 0 (correlation '(1 2 3 4 5) '(1 2 3 2 1)))
+(test::assert-error (correlation '(1 2 3) '(4 4 4)))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("linear-regression"),
+        Expression::make_function(
+            builtin_linear_regression,
+            "Usage: (linear-regression xs ys) -> hash-map
+
+Fits the line y = slope * x + intercept to two lists or vectors of numbers with
+the same length by least squares.  Returns a hash map with :slope, :intercept
+and :r2 (the coefficient of determination, 1 is a perfect fit).
+
+Section: stats
+
+Example:
+(ns-import 'stats)
+(def fit (linear-regression '(1 2 3 4) '(3 5 7 9)))
+(test::assert-equal 2 (hash-get fit :slope))
+(test::assert-equal 1 (hash-get fit :intercept))
+(test::assert-equal 1 (hash-get fit :r2))
+(def fit (linear-regression '#(1 2 3) '#(1 3 2)))
+(test::assert-equal 0.5 (hash-get fit :slope))
+(test::assert-equal 0.25 (hash-get fit :r2))
+(test::assert-error (linear-regression '(2 2) '(1 3)))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("histogram"),
+        Expression::make_function(
+            builtin_histogram,
+            "Usage: (histogram numbers bins min? max?) -> hash-map
+
+Counts a list or vector of numbers into bins equal width bins between min and
+max (default to the smallest and largest number).  Each bin includes its lower
+edge, the last bin also includes max and numbers outside min to max are not
+counted.  Returns a hash map with :counts (a vector of bins counts) and :edges
+(a vector of the bins + 1 bin edges).
+
+Section: stats
+
+Example:
+(ns-import 'stats)
+(def hist (histogram '(1 2 2 3 3 3 4 4 4 4) 3))
+(test::assert-equal '#(1 2 7) (hash-get hist :counts))
+(test::assert-equal '#(1 2 3 4) (hash-get hist :edges))
+(def hist (histogram '#(-1 0 5 9.9 10 11) 2 0 10))
+(test::assert-equal '#(1 3) (hash-get hist :counts))
+(test::assert-equal '#(0 5 10) (hash-get hist :edges))
+(test::assert-equal '#(2) (hash-get (histogram '(5 5) 1) :counts))
+(test::assert-error (histogram '(1 2) 0))
+",
+        ),
+    );
+
+    data.insert(
+        interner.intern("moving-average"),
+        Expression::make_function(
+            builtin_moving_average,
+            "Usage: (moving-average numbers window) -> vector
+
+Returns a vector of the averages of each run of window consecutive numbers in a
+list or vector of numbers (the simple moving average).
+
+Section: stats
+
+Example:
+(ns-import 'stats)
+(test::assert-equal '#(2 3 4) (moving-average '(1 2 3 4 5) 3))
+(test::assert-equal '#(1 2 3) (moving-average '#(1 2 3) 1))
+(test::assert-equal '#(2) (moving-average '(1 2 3) 3))
+(test::assert-error (moving-average '(1 2 3) 4))
+(test::assert-error (moving-average '(1 2 3) 0))
+",
+        ),
+    );
 }
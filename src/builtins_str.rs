@@ -9,6 +9,7 @@ use unicode_segmentation::UnicodeSegmentation;
 use crate::environment::*;
 use crate::eval::*;
 use crate::interner::*;
+use crate::pretty_print::readable_string;
 use crate::types::*;
 use crate::{param_eval, params_done};
 use std::collections::hash_map::DefaultHasher;
@@ -381,6 +382,16 @@ fn builtin_str(
     Ok(Expression::alloc_data(ExpEnum::String(res.into(), None)))
 }
 
+fn builtin_str_readable(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let value = param_eval(environment, args, "str-readable")?;
+    params_done(args, "str-readable")?;
+    let text = readable_string(&value, environment)?;
+    Ok(Expression::alloc_data(ExpEnum::String(text.into(), None)))
+}
+
 pub fn builtin_do_unstr(
     environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
@@ -1048,6 +1059,39 @@ Example:
 "#,
         ),
     );
+    data.insert(
+        interner.intern("str-readable"),
+        Expression::make_function(
+            builtin_str_readable,
+            r##"Usage: (str-readable value) -> string
+
+Return a string that read turns back into a value equal to value (str and print
+lose the difference between a string and a symbol for instance).  Strings are
+quoted with escapes, chars use #\ literals, hash maps use the #h(key value ...)
+literal, persistent vectors and maps the #pv(item ...) and #pm(key value ...)
+literals, records (from struct::defstruct) the #S(type :field value ...) literal
+and lists that loop back on themselves (made with xdr! for instance) use #n=
+labels and #n# references.  Values with no readable form (functions, files,
+processes, NaN, etc) raise an error.
+
+Section: string
+
+Example:
+(test::assert-equal "(\"a\\nb\" #\\space sym :key 1 1.0 #t nil)" (str-readable '("a\nb" #\space sym :key 1 1.0 #t nil)))
+(test::assert-equal "#h(\"a\" #(1 2) \"b\" \"x\")" (str-readable (make-hash '((b . "x") ("a" . #(1 2))))))
+(test::assert-equal "x" (hash-get (read (str-readable (make-hash '((k . "x"))))) "k"))
+(test::assert-equal "#h(1 \"i\" \"1\" \"s\")" (str-readable (make-hash '((1 . "i") ("1" . "s"))))))
+(test::assert-equal "\"a\\x01\u{85}\"" (str-readable "a\x01\u{85}"))
+(def str-readable-cycle (list 1 2))
+(xdr! (cdr str-readable-cycle) str-readable-cycle)
+(test::assert-equal "#1=(1 2 . #1#)" (str-readable str-readable-cycle))
+(def str-readable-cycle (read (str-readable str-readable-cycle)))
+(test::assert-equal 1 (car (cdr (cdr str-readable-cycle))))
+(test::assert-equal '(1 . #(1.5 (a . b))) (read (str-readable '(1 . #(1.5 (a . b))))))
+(test::assert-error (str-readable (fn () nil)))
+"##,
+        ),
+    );
     data.insert(
         interner.intern("str"),
         Expression::make_function(
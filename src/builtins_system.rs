@@ -1,23 +1,31 @@
 use nix::{
     libc,
+    poll::{poll, PollFd, PollFlags},
     sys::{
         signal::{self, Signal},
         stat::Mode,
         termios,
+        wait::{self, WaitStatus},
     },
     unistd::{self, Pid},
 };
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::env;
 use std::hash::BuildHasher;
 use std::io::{self, BufReader, Read, Write};
+use std::iter;
 use std::{thread, time};
+use unicode_segmentation::UnicodeSegmentation;
 
+use crate::builtins_hashmap::LispMap;
 use crate::builtins_util::*;
 use crate::environment::*;
 use crate::eval::*;
 use crate::interner::*;
 use crate::process::*;
+use crate::reader::read_form_state;
+use crate::signals::test_clear_sigint;
 use crate::types::*;
 use crate::unix::*;
 use crate::{to_octal_string, with_umask};
@@ -115,7 +123,7 @@ fn builtin_export(
                 .into(),
             None,
         ),
-        ExpEnum::Process(ProcessState::Over(_pid, _exit_status)) => ExpEnum::String(
+        ExpEnum::Process(ProcessState::Over(_pid, _status)) => ExpEnum::String(
             val.as_string(environment)
                 .unwrap_or_else(|_| "PROCESS FAILED".to_string())
                 .into(),
@@ -316,6 +324,245 @@ fn builtin_fork(
     ))
 }
 
+// Wait for a fork-value child and turn what it sent back into its value (or
+// the error it raised).
+fn fork_value_result(
+    environment: &mut Environment,
+    pid: u32,
+    text: &[u8],
+    fn_name: &str,
+) -> Result<Expression, LispError> {
+    let status = loop {
+        match wait::waitpid(Pid::from_raw(pid as i32), None) {
+            Err(nix::errno::Errno::EINTR) => continue,
+            status => break status,
+        }
+    };
+    if text.is_empty() {
+        let how = match status {
+            Ok(WaitStatus::Exited(_, code)) => format!("exited with status {}", code),
+            Ok(WaitStatus::Signaled(_, sig, _)) => format!("was killed by {}", sig.as_str()),
+            _ => "went away".to_string(),
+        };
+        return Err(LispError::new(format!(
+            "{}: child {} without returning a value",
+            fn_name, how
+        )));
+    }
+    let text = String::from_utf8_lossy(text);
+    let invalid = || {
+        LispError::new(format!(
+            "{}: invalid result from child {}: {}",
+            fn_name, pid, text
+        ))
+    };
+    // This unsafe should be fine since text outlives the iterator.
+    let ntext = unsafe { &*(text.as_ref() as *const str) };
+    let chars: CharIter = Box::new(
+        UnicodeSegmentation::graphemes(ntext, true)
+            .map(Cow::Borrowed)
+            .peekable(),
+    );
+    // The child's output is data, do not let it run reader macros.
+    let old_safe = environment.reader_state.safe;
+    environment.reader_state.safe = true;
+    let result = read_form_state(environment, chars, true);
+    environment.reader_state.safe = old_safe;
+    let result = match result {
+        Ok((result, _)) => result,
+        Err(_) => return Err(invalid()),
+    };
+    let mut items = result.iter();
+    match (items.next(), items.next(), items.next()) {
+        (Some(tag), Some(value), backtrace) => match (&tag.get().data, &value.get().data) {
+            (ExpEnum::Symbol(":ok", _), _) => Ok(value.clone()),
+            // Raise the child's error as is (same reason and backtrace).
+            (ExpEnum::Symbol(":error", _), ExpEnum::String(reason, _)) => Err(LispError {
+                reason: reason.to_string(),
+                backtrace: backtrace
+                    .filter(|b| !b.is_nil())
+                    .map(|b| b.iter().collect()),
+            }),
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+fn builtin_fork_value(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "fork-value";
+    let exp = if let Some(exp) = args.next() {
+        exp
+    } else {
+        return Err(LispError::new(format!(
+            "{}: requires one form to evaluate in a child",
+            fn_name
+        )));
+    };
+    params_done(args, fn_name)?;
+    let (pid, fd) = fork_value(environment, |environment| eval(environment, exp))?;
+    let mut text = Vec::new();
+    let res = fd_to_file(fd).read_to_end(&mut text);
+    let result = fork_value_result(environment, pid, &text, fn_name);
+    res?;
+    result
+}
+
+struct PmapChild {
+    pid: u32,
+    fd: i32,
+    index: usize,
+    text: Vec<u8>,
+}
+
+fn pmap_kill(children: &mut Vec<PmapChild>) {
+    for child in children.drain(..) {
+        let _ = signal::kill(Pid::from_raw(child.pid as i32), Signal::SIGTERM);
+        let _ = close_fd(child.fd);
+        let _ = wait::waitpid(Pid::from_raw(child.pid as i32), None);
+    }
+}
+
+// Read from any children with output ready, return the index (into children)
+// of one that has finished sending its result.
+fn pmap_next_done(children: &mut [PmapChild]) -> Result<Option<usize>, LispError> {
+    let mut fds: Vec<PollFd> = children
+        .iter()
+        .map(|child| PollFd::new(child.fd, PollFlags::POLLIN))
+        .collect();
+    match poll(&mut fds, -1) {
+        Ok(_) => {}
+        Err(nix::errno::Errno::EINTR) => {
+            if test_clear_sigint() {
+                return Err(LispError::new("pmap: interrupted"));
+            }
+            return Ok(None);
+        }
+        Err(err) => return Err(LispError::new(format!("pmap: {}", err))),
+    }
+    let mut buf = [0_u8; 8192];
+    for (i, fd) in fds.iter().enumerate() {
+        if !fd.revents().unwrap_or_else(PollFlags::empty).is_empty() {
+            match unistd::read(children[i].fd, &mut buf) {
+                Ok(0) => return Ok(Some(i)),
+                Ok(n) => children[i].text.extend_from_slice(&buf[..n]),
+                Err(nix::errno::Errno::EINTR) | Err(nix::errno::Errno::EAGAIN) => {}
+                Err(err) => return Err(LispError::new(format!("pmap: {}", err))),
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn builtin_pmap(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "pmap";
+    let func = param_eval(environment, args, fn_name)?;
+    if !matches!(&func.get().data, ExpEnum::Lambda(_) | ExpEnum::Function(_)) {
+        return Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Lambda or Function",
+            &func.display_type(),
+        )));
+    }
+    let seq = param_eval(environment, args, fn_name)?;
+    let items: Vec<Expression> = match &seq.get().data {
+        ExpEnum::Vector(_) | ExpEnum::Pair(_, _) | ExpEnum::Nil => seq.iter().collect(),
+        _ => {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "List or Vector",
+                &seq.display_type(),
+            )))
+        }
+    };
+    let mut jobs = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) }.max(1) as usize;
+    let opts = make_args(environment, args)?;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match (&opt.get().data, opts.next().map(|n| n.get().data.clone())) {
+            (ExpEnum::Symbol(":jobs", _), Some(ExpEnum::Int(n))) if n > 0 => jobs = n as usize,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{}: invalid option {}, expected :jobs followed by a positive integer",
+                    fn_name, opt
+                )))
+            }
+        }
+    }
+    let mut results: Vec<Option<Expression>> = vec![None; items.len()];
+    let mut children: Vec<PmapChild> = Vec::with_capacity(jobs);
+    let mut pending = items.into_iter().enumerate();
+    loop {
+        while children.len() < jobs {
+            if let Some((index, item)) = pending.next() {
+                let func = func.clone();
+                let spawned = fork_value(environment, move |environment| {
+                    let mut args = iter::once(item);
+                    let data = func.get().data.clone();
+                    match data {
+                        ExpEnum::Function(f) => {
+                            // Item is already a value, do not evaluate it again.
+                            environment.supress_eval = true;
+                            (f.func)(environment, &mut args)
+                        }
+                        _ => call_lambda(environment, func, &mut args, false),
+                    }
+                });
+                match spawned {
+                    Ok((pid, fd)) => children.push(PmapChild {
+                        pid,
+                        fd,
+                        index,
+                        text: Vec::new(),
+                    }),
+                    Err(err) => {
+                        pmap_kill(&mut children);
+                        return Err(err);
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+        if children.is_empty() {
+            break;
+        }
+        match pmap_next_done(&mut children) {
+            Ok(Some(i)) => {
+                let child = children.swap_remove(i);
+                let _ = close_fd(child.fd);
+                match fork_value_result(environment, child.pid, &child.text, fn_name) {
+                    Ok(value) => results[child.index] = Some(value),
+                    Err(err) => {
+                        pmap_kill(&mut children);
+                        return Err(LispError {
+                            reason: format!("{} (index {})", err.reason, child.index),
+                            backtrace: err.backtrace,
+                        });
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                pmap_kill(&mut children);
+                return Err(err);
+            }
+        }
+    }
+    Ok(Expression::with_list(
+        results
+            .into_iter()
+            .map(|r| r.unwrap_or_else(Expression::make_nil))
+            .collect(),
+    ))
+}
+
 fn builtin_sleep(
     environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
@@ -340,17 +587,37 @@ fn builtin_time(
     environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
 ) -> Result<Expression, LispError> {
-    if let Some(form) = args.next() {
+    let mut form = args.next();
+    // :verbose (not evaluated) asks for a report on a timed external command.
+    let verbose = matches!(&form, Some(f) if matches!(&f.get().data, ExpEnum::Symbol(s, _) if *s == ":verbose"));
+    if verbose {
+        form = args.next();
+    }
+    if let Some(form) = form {
         if args.next().is_none() {
             let now = time::Instant::now();
-            eval(environment, form)?;
-            return Ok(Expression::alloc_data(ExpEnum::Float(
-                now.elapsed().as_secs_f64(),
-            )));
+            let result = eval(environment, form)?;
+            let elapsed = now.elapsed().as_secs_f64();
+            if verbose {
+                if let ExpEnum::Process(ProcessState::Over(_pid, status)) = &result.get().data {
+                    eprintln!("real\t{:.3}s", elapsed);
+                    eprintln!("user\t{:.3}s", status.user_time.as_secs_f64());
+                    eprintln!("sys\t{:.3}s", status.system_time.as_secs_f64());
+                    eprintln!("maxrss\t{}KB", status.max_rss);
+                    if let Some(signal) = status.signal_name() {
+                        if status.core_dumped {
+                            eprintln!("signal\t{} (core dumped)", signal);
+                        } else {
+                            eprintln!("signal\t{}", signal);
+                        }
+                    }
+                }
+            }
+            return Ok(Expression::alloc_data(ExpEnum::Float(elapsed)));
         }
     }
     Err(LispError::new(
-        "time: can only have one argument (form to time)",
+        "time: can only have one argument (form to time) after an optional :verbose",
     ))
 }
 
@@ -528,22 +795,22 @@ fn builtin_wait(
                     let pid = *pid;
                     drop(arg0_d);
                     match wait_pid(environment, pid, None) {
-                        Some(exit_status) => {
-                            Ok(Expression::alloc_data(ExpEnum::Int(i64::from(exit_status))))
-                        }
+                        Some(status) => Ok(Expression::alloc_data(ExpEnum::Int(i64::from(
+                            status.exit_status,
+                        )))),
                         None => Ok(Expression::make_nil()),
                     }
                 }
-                ExpEnum::Process(ProcessState::Over(_pid, exit_status)) => Ok(
-                    Expression::alloc_data(ExpEnum::Int(i64::from(*exit_status))),
-                ),
+                ExpEnum::Process(ProcessState::Over(_pid, status)) => Ok(Expression::alloc_data(
+                    ExpEnum::Int(i64::from(status.exit_status)),
+                )),
                 ExpEnum::Int(pid) => {
                     let pid = *pid;
                     drop(arg0_d);
                     match wait_pid(environment, pid as u32, None) {
-                        Some(exit_status) => {
-                            Ok(Expression::alloc_data(ExpEnum::Int(i64::from(exit_status))))
-                        }
+                        Some(status) => Ok(Expression::alloc_data(ExpEnum::Int(i64::from(
+                            status.exit_status,
+                        )))),
                         None => Ok(Expression::make_nil()),
                     }
                 }
@@ -565,7 +832,7 @@ fn builtin_pid(
                 ExpEnum::Process(ProcessState::Running(pid)) => {
                     Ok(Expression::alloc_data(ExpEnum::Int(i64::from(pid))))
                 }
-                ExpEnum::Process(ProcessState::Over(pid, _exit_status)) => {
+                ExpEnum::Process(ProcessState::Over(pid, _status)) => {
                     Ok(Expression::alloc_data(ExpEnum::Int(i64::from(pid))))
                 }
                 _ => Err(LispError::new("pid error: not a process")),
@@ -575,6 +842,94 @@ fn builtin_pid(
     Err(LispError::new("pid takes one form (a process)"))
 }
 
+fn builtin_proc_info(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "proc-info";
+    let arg = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let state = if let ExpEnum::Process(state) = &arg.get().data {
+        *state
+    } else {
+        return Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Process",
+            &arg.display_type(),
+        )));
+    };
+    let (pid, status) = match state {
+        ProcessState::Running(pid) => {
+            // Pick up the status if it finished since we last looked.
+            if let (true, Some(status)) = try_wait_pid(environment, pid) {
+                arg.get_mut()
+                    .data
+                    .replace(ExpEnum::Process(ProcessState::Over(pid, status)));
+                (pid, Some(status))
+            } else {
+                (pid, None)
+            }
+        }
+        ProcessState::Over(pid, status) => (pid, Some(status)),
+    };
+    let keyword = |environment: &mut Environment, key: &str| {
+        Expression::alloc_data(ExpEnum::Symbol(
+            environment.interner.intern(key),
+            SymLoc::None,
+        ))
+    };
+    let mut map = LispMap::new();
+    map.insert(
+        ":pid".into(),
+        Expression::alloc_data(ExpEnum::Int(i64::from(pid))),
+    );
+    match status {
+        Some(status) => {
+            let state = if status.signal.is_some() {
+                ":signaled"
+            } else {
+                ":exited"
+            };
+            map.insert(":state".into(), keyword(environment, state));
+            map.insert(
+                ":exit-status".into(),
+                Expression::alloc_data(ExpEnum::Int(i64::from(status.exit_status))),
+            );
+            map.insert(
+                ":signal".into(),
+                match status.signal_name() {
+                    Some(name) => Expression::alloc_data(ExpEnum::String(name.into(), None)),
+                    None => Expression::make_nil(),
+                },
+            );
+            map.insert(
+                ":core-dumped".into(),
+                if status.core_dumped {
+                    Expression::make_true()
+                } else {
+                    Expression::make_false()
+                },
+            );
+            map.insert(
+                ":user-time".into(),
+                Expression::alloc_data(ExpEnum::Float(status.user_time.as_secs_f64())),
+            );
+            map.insert(
+                ":system-time".into(),
+                Expression::alloc_data(ExpEnum::Float(status.system_time.as_secs_f64())),
+            );
+            map.insert(
+                ":max-rss".into(),
+                Expression::alloc_data(ExpEnum::Int(status.max_rss)),
+            );
+        }
+        None => {
+            map.insert(":state".into(), keyword(environment, ":running"));
+        }
+    }
+    Ok(Expression::alloc_data(ExpEnum::HashMap(map)))
+}
+
 fn builtin_get_pid(
     _environment: &mut Environment,
     args: &mut dyn Iterator<Item = Expression>,
@@ -722,7 +1077,8 @@ Example:
             builtin_epoch,
             r#"Usage: (epoch)
 
-Prints system time in milliseconds.
+Prints system time in milliseconds.  See date-now and date-from-epoch for
+working with dates and times.
 
 Section: shell
 
@@ -802,6 +1158,55 @@ Example:
 (test::assert-equal 57 (wait fork-test))
 (def fork-time (time (wait (fork (sleep 1000)))))
 (test::assert-true (> fork-time 1.0))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("fork-value"),
+        Expression::make_special(
+            builtin_fork_value,
+            r#"Usage: (fork-value exp) -> value
+
+Evaluate exp in a forked child process and return its value.  The child sends
+the value back over a pipe in readable form so it must be something the reader
+can produce (numbers, strings, chars, symbols, lists, vectors, etc).  Changes the
+child makes to its environment are not seen by the parent.  If exp raises an
+error then fork-value raises that same error (reason and backtrace), if the
+value can not be sent back or the child dies it raises an error saying so.  The
+child's output is read as data (no reader macros).
+
+Section: system
+
+Example:
+(def fork-value-x 1)
+(test::assert-equal '(2 "two" #(:three 3.0)) (fork-value (do (set! fork-value-x 2) (list fork-value-x "two" (vec :three 3.0)))))
+(test::assert-equal 1 fork-value-x)
+(test::assert-equal "with \"quotes\" and \$\$ kept" (fork-value (str "with \"quotes\" and \$\$ kept")))
+(test::assert-error-msg (fork-value (err "child failed")) "child failed")
+(test::assert-equal :error (car (get-error (fork-value (err "child failed")))))
+(test::assert-error (fork-value (fn () nil)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("pmap"),
+        Expression::make_function(
+            builtin_pmap,
+            r#"Usage: (pmap function sequence :jobs n) -> vector
+
+Parallel map, call function on each item in sequence (a list or vector) with
+each call run in a child process (see fork-value) and return a vector of the
+results in order.  At most n children (default is the number of CPUs) run at
+once.  If any call raises an error the remaining children are killed and pmap
+raises the error (with the index of the item that failed).
+
+Section: system
+
+Example:
+(test::assert-equal '#(1 4 9 16 25) (pmap (fn (x) (* x x)) '(1 2 3 4 5) :jobs 2))
+(test::assert-equal '#("A" "B") (pmap str-upper '#("a" "b")))
+(test::assert-equal '#() (pmap (fn (x) x) nil))
+(test::assert-error-msg (pmap (fn (x) (if (= x 3) (err "bad item") x)) '(1 2 3 4)) "bad item (index 2)")
 "#,
         ),
     );
@@ -844,15 +1249,20 @@ Example:
         interner.intern("time"),
         Expression::make_function(
             builtin_time,
-            r#"Usage: (time form) -> eval-time
+            r#"Usage: (time :verbose? form) -> eval-time
 
 Evalutes the provided form and returns the seconds it ran for (as float with fractional part).
+With :verbose, if the form evaluates to a completed process (for instance an external
+command) then the user and system CPU time, max resident set size and terminating
+signal (if any) of that process are also reported on stderr (see proc-info to get
+these as values).
 
 Section: system
 
 Example:
 (def test-sleep-var (time (sleep 1100)))
 (assert-true (> test-sleep-var 1.1))
+(assert-true (float? (time :verbose (syscall 'true))))
 "#,
         ),
     );
@@ -995,6 +1405,41 @@ Example:
 (test::assert-true (int? (pid pid-test)))
 (test::assert-true (int? (pid (fork ((fn () nil))))))
 (test::assert-error (pid 1))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("proc-info"),
+        Expression::make_function(
+            builtin_proc_info,
+            r#"Usage: (proc-info proc) -> hash-map
+
+Return a hash map describing a process.  Always contains :pid and :state, state
+is one of :running, :exited or :signaled.  Once a process is complete the map also
+contains :exit-status (128 + signal number if killed by a signal), :signal (name
+of the signal or nil), :core-dumped, :user-time and :system-time (seconds of CPU
+used as floats) and :max-rss (max resident set size in kilobytes).
+
+Section: system
+
+Example:
+(def proc-test (proc-info (syscall 'true)))
+(test::assert-equal :exited (hash-get proc-test :state))
+(test::assert-equal 0 (hash-get proc-test :exit-status))
+(test::assert-false (hash-get proc-test :signal))
+(test::assert-false (hash-get proc-test :core-dumped))
+(test::assert-true (float? (hash-get proc-test :user-time)))
+(test::assert-true (float? (hash-get proc-test :system-time)))
+(test::assert-true (int? (hash-get proc-test :max-rss)))
+(def proc-test (proc-info (syscall 'sh "-c" "kill -TERM \$\$")))
+(test::assert-equal :signaled (hash-get proc-test :state))
+(test::assert-equal "SIGTERM" (hash-get proc-test :signal))
+(test::assert-equal 143 (hash-get proc-test :exit-status))
+(def proc-test (fork (sleep 200)))
+(test::assert-equal :running (hash-get (proc-info proc-test) :state))
+(wait proc-test)
+(test::assert-equal :exited (hash-get (proc-info proc-test) :state))
+(test::assert-error (proc-info 1))
 "#,
         ),
     );
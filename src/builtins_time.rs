@@ -0,0 +1,1180 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::hash::BuildHasher;
+use std::time::SystemTime;
+
+use nix::libc;
+
+use crate::builtins_hashmap::LispMap;
+use crate::builtins_util::*;
+use crate::environment::*;
+use crate::interner::*;
+use crate::types::*;
+
+extern "C" {
+    // Not exposed by the libc crate on unix, re-reads TZ for localtime_r/mktime.
+    fn tzset();
+}
+
+// Datetimes are hash maps (so they print, serialize and destructure like any
+// other map), :epoch (milliseconds) is the source of truth and the other
+// fields are the broken down time in the zone they were made for.
+#[derive(Clone, Copy)]
+enum Zone {
+    // The system zone (TZ or /etc/localtime), re-resolved after arithmetic.
+    Local,
+    // A fixed offset from UTC in seconds, 0 is UTC.
+    Fixed(i64),
+}
+
+fn zone_name(offset: i64) -> String {
+    if offset == 0 {
+        "UTC".to_string()
+    } else {
+        let sign = if offset < 0 { '-' } else { '+' };
+        let offset = offset.abs();
+        format!("{}{:02}:{:02}", sign, offset / 3600, (offset % 3600) / 60)
+    }
+}
+
+#[allow(clippy::unnecessary_cast)] // c_long is not i64 on all targets.
+fn gmtoff(tm: &libc::tm) -> i64 {
+    tm.tm_gmtoff as i64
+}
+
+fn new_tm() -> libc::tm {
+    // All zero is a valid tm (pointers null).
+    unsafe { std::mem::zeroed() }
+}
+
+// Break secs down for zone, returns the tm, UTC offset and zone name.
+fn to_tm(secs: i64, zone: Zone, fn_name: &str) -> Result<(libc::tm, i64, String), LispError> {
+    let mut tm = new_tm();
+    match zone {
+        Zone::Local => {
+            let t = secs as libc::time_t;
+            let res = unsafe {
+                tzset();
+                libc::localtime_r(&t, &mut tm)
+            };
+            if res.is_null() {
+                return Err(out_of_range(fn_name));
+            }
+            let name = if tm.tm_zone.is_null() {
+                zone_name(gmtoff(&tm))
+            } else {
+                unsafe { CStr::from_ptr(tm.tm_zone) }
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            let offset = gmtoff(&tm);
+            Ok((tm, offset, name))
+        }
+        Zone::Fixed(offset) => {
+            let t = secs
+                .checked_add(offset)
+                .ok_or_else(|| out_of_range(fn_name))? as libc::time_t;
+            if unsafe { libc::gmtime_r(&t, &mut tm) }.is_null() {
+                return Err(out_of_range(fn_name));
+            }
+            tm.tm_gmtoff = offset as libc::c_long;
+            Ok((tm, offset, zone_name(offset)))
+        }
+    }
+}
+
+fn make_datetime(millis: i64, zone: Zone, fn_name: &str) -> Result<Expression, LispError> {
+    let (tm, offset, name) = to_tm(millis.div_euclid(1000), zone, fn_name)?;
+    let int = |i: i64| Expression::alloc_data(ExpEnum::Int(i));
+    let mut map = LispMap::new();
+    map.insert(":epoch".into(), int(millis));
+    map.insert(":year".into(), int(tm.tm_year as i64 + 1900));
+    map.insert(":month".into(), int(tm.tm_mon as i64 + 1));
+    map.insert(":day".into(), int(tm.tm_mday as i64));
+    map.insert(":hour".into(), int(tm.tm_hour as i64));
+    map.insert(":minute".into(), int(tm.tm_min as i64));
+    map.insert(":second".into(), int(tm.tm_sec as i64));
+    map.insert(":millisecond".into(), int(millis.rem_euclid(1000)));
+    // ISO weekday, Monday is 1 and Sunday is 7.
+    map.insert(
+        ":weekday".into(),
+        int(if tm.tm_wday == 0 {
+            7
+        } else {
+            tm.tm_wday as i64
+        }),
+    );
+    map.insert(":yearday".into(), int(tm.tm_yday as i64 + 1));
+    map.insert(":offset".into(), int(offset));
+    map.insert(
+        ":zone".into(),
+        Expression::alloc_data(ExpEnum::String(name.into(), None)),
+    );
+    map.insert(
+        ":local".into(),
+        if let Zone::Local = zone {
+            Expression::make_true()
+        } else {
+            Expression::make_false()
+        },
+    );
+    Ok(Expression::alloc_data(ExpEnum::HashMap(map)))
+}
+
+fn get_datetime(exp: &Expression, fn_name: &str) -> Result<(i64, Zone), LispError> {
+    let err = || {
+        LispError::new(format!(
+            "{}: expected a datetime (hash map from date-now, date-make, etc), got {}",
+            fn_name,
+            exp.display_type()
+        ))
+    };
+    let exp_d = exp.get();
+    if let ExpEnum::HashMap(map) = &exp_d.data {
+        let int = |key: &str| match map.get(key).map(|v| v.get().data.clone()) {
+            Some(ExpEnum::Int(i)) => Some(i),
+            _ => None,
+        };
+        let millis = int(":epoch").ok_or_else(err)?;
+        let zone = match map.get(":local") {
+            Some(local) if !local.is_falsey() => Zone::Local,
+            _ => Zone::Fixed(int(":offset").ok_or_else(err)?),
+        };
+        Ok((millis, zone))
+    } else {
+        Err(err())
+    }
+}
+
+fn param_datetime(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<(i64, Zone), LispError> {
+    let exp = param_eval(environment, args, fn_name)?;
+    get_datetime(&exp, fn_name)
+}
+
+fn param_int(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<i64, LispError> {
+    let exp = param_eval(environment, args, fn_name)?;
+    let exp_d = exp.get();
+    if let ExpEnum::Int(i) = &exp_d.data {
+        Ok(*i)
+    } else {
+        Err(LispError::new(ErrorStrings::mismatched_type(
+            fn_name,
+            "Int",
+            &exp.display_type(),
+        )))
+    }
+}
+
+fn zone_option(exp: Option<Expression>, fn_name: &str) -> Result<Option<Zone>, LispError> {
+    match exp {
+        None => Ok(None),
+        Some(exp) => match &exp.get().data {
+            ExpEnum::Symbol(":utc", _) => Ok(Some(Zone::Fixed(0))),
+            ExpEnum::Symbol(":local", _) => Ok(Some(Zone::Local)),
+            _ => Err(LispError::new(format!(
+                "{}: invalid option {}, expected :utc or :local",
+                fn_name, exp
+            ))),
+        },
+    }
+}
+
+fn param_zone(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+) -> Result<Zone, LispError> {
+    let zone = zone_option(param_eval_optional(environment, args)?, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(zone.unwrap_or(Zone::Local))
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn out_of_range(fn_name: &str) -> LispError {
+    LispError::new(format!("{}: time out of range", fn_name))
+}
+
+// True if a strftime/strptime format uses %z (a literal %% does not count).
+fn format_has_offset(fmt: &str) -> bool {
+    let mut chars = fmt.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '%' && chars.next() == Some('z') {
+            return true;
+        }
+    }
+    false
+}
+
+// Convert a civil time (year month day hour minute second milli) to epoch
+// milliseconds, offset None means local time.
+fn civil_to_epoch(fields: &[i64; 7], offset: Option<i64>, fn_name: &str) -> Result<i64, LispError> {
+    let [year, month, day, hour, minute, second, millis] = *fields;
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || !(0..=23).contains(&hour)
+        || !(0..=59).contains(&minute)
+        // Allow a leap second.
+        || !(0..=60).contains(&second)
+        || !(0..=999).contains(&millis)
+    {
+        return Err(LispError::new(format!(
+            "{}: invalid date/time {:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+            fn_name, year, month, day, hour, minute, second, millis
+        )));
+    }
+    let mut tm = new_tm();
+    tm.tm_year = year
+        .checked_sub(1900)
+        .and_then(|year| libc::c_int::try_from(year).ok())
+        .ok_or_else(|| out_of_range(fn_name))?;
+    tm.tm_mon = (month - 1) as libc::c_int;
+    tm.tm_mday = day as libc::c_int;
+    tm.tm_hour = hour as libc::c_int;
+    tm.tm_min = minute as libc::c_int;
+    tm.tm_sec = second as libc::c_int;
+    let secs = match offset {
+        Some(offset) => unsafe { libc::timegm(&mut tm) as i64 - offset },
+        None => {
+            // Let mktime work out if DST applies.
+            tm.tm_isdst = -1;
+            unsafe {
+                tzset();
+                libc::mktime(&mut tm) as i64
+            }
+        }
+    };
+    secs.checked_mul(1000)
+        .and_then(|ms| ms.checked_add(millis))
+        .ok_or_else(|| out_of_range(fn_name))
+}
+
+fn epoch_now(fn_name: &str) -> Result<i64, LispError> {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(elapsed) => Ok(elapsed.as_millis() as i64),
+        Err(err) => Err(LispError::new(format!("{}: failed {}", fn_name, err))),
+    }
+}
+
+fn builtin_date_now(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date-now";
+    let zone = param_zone(environment, args, fn_name)?;
+    make_datetime(epoch_now(fn_name)?, zone, fn_name)
+}
+
+fn builtin_date_from_epoch(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date-from-epoch";
+    let millis = param_int(environment, args, fn_name)?;
+    let zone = param_zone(environment, args, fn_name)?;
+    make_datetime(millis, zone, fn_name)
+}
+
+fn builtin_date_make(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date-make";
+    let mut args = make_args(environment, args)?;
+    let zone = if let Some(ExpEnum::Symbol(_, _)) = args.last().map(|a| a.get().data.clone()) {
+        zone_option(args.pop(), fn_name)?.unwrap_or(Zone::Local)
+    } else {
+        Zone::Local
+    };
+    if args.len() < 3 || args.len() > 7 {
+        return Err(LispError::new(
+            "date-make: takes year month day and optionally hour minute second millisecond",
+        ));
+    }
+    let mut fields = [1, 1, 1, 0, 0, 0, 0];
+    for (i, arg) in args.iter().enumerate() {
+        if let ExpEnum::Int(v) = &arg.get().data {
+            fields[i] = *v;
+        } else {
+            return Err(LispError::new(ErrorStrings::mismatched_type(
+                fn_name,
+                "Int",
+                &arg.display_type(),
+            )));
+        }
+    }
+    let offset = match zone {
+        Zone::Local => None,
+        Zone::Fixed(offset) => Some(offset),
+    };
+    let millis = civil_to_epoch(&fields, offset, fn_name)?;
+    make_datetime(millis, zone, fn_name)
+}
+
+fn builtin_date_to_local(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date->local";
+    let (millis, _) = param_datetime(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    make_datetime(millis, Zone::Local, fn_name)
+}
+
+fn builtin_date_to_utc(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date->utc";
+    let (millis, _) = param_datetime(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    make_datetime(millis, Zone::Fixed(0), fn_name)
+}
+
+fn builtin_date_format(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date-format";
+    let (millis, zone) = param_datetime(environment, args, fn_name)?;
+    let fmt = param_eval(environment, args, fn_name)?.as_string(environment)?;
+    params_done(args, fn_name)?;
+    // Handle the %L (milliseconds) extension before strftime sees the format.
+    let mut fmt_ms = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '%' {
+            match chars.next() {
+                Some('L') => fmt_ms.push_str(&format!("{:03}", millis.rem_euclid(1000))),
+                Some(next) => {
+                    fmt_ms.push('%');
+                    fmt_ms.push(next);
+                }
+                None => fmt_ms.push('%'),
+            }
+        } else {
+            fmt_ms.push(ch);
+        }
+    }
+    if fmt_ms.is_empty() {
+        return Ok(Expression::alloc_data(ExpEnum::String("".into(), None)));
+    }
+    let (mut tm, _, name) = to_tm(millis.div_euclid(1000), zone, fn_name)?;
+    // Keep the zone name alive while strftime may reference it (%Z).
+    let name = CString::new(name).unwrap_or_default();
+    tm.tm_zone = name.as_ptr();
+    let c_fmt = CString::new(fmt_ms.clone())
+        .map_err(|_| LispError::new("date-format: format can not contain a NUL"))?;
+    let mut size = fmt_ms.len() * 4 + 64;
+    loop {
+        let mut buf = vec![0u8; size];
+        let len = unsafe {
+            libc::strftime(
+                buf.as_mut_ptr() as *mut libc::c_char,
+                size,
+                c_fmt.as_ptr(),
+                &tm,
+            )
+        };
+        // 0 means the buffer was too small (or the result was really empty).
+        if len > 0 || size > 64 * 1024 {
+            buf.truncate(len);
+            let res = String::from_utf8_lossy(&buf).into_owned();
+            return Ok(Expression::alloc_data(ExpEnum::String(res.into(), None)));
+        }
+        size *= 4;
+    }
+}
+
+fn builtin_date_parse(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date-parse";
+    let text = param_eval(environment, args, fn_name)?.as_string(environment)?;
+    let fmt = param_eval(environment, args, fn_name)?.as_string(environment)?;
+    let zone = param_zone(environment, args, fn_name)?;
+    let c_text = CString::new(text.clone())
+        .map_err(|_| LispError::new("date-parse: string can not contain a NUL"))?;
+    let c_fmt = CString::new(fmt.clone())
+        .map_err(|_| LispError::new("date-parse: format can not contain a NUL"))?;
+    let mut tm = new_tm();
+    tm.tm_mday = 1;
+    let rest = unsafe { libc::strptime(c_text.as_ptr(), c_fmt.as_ptr(), &mut tm) };
+    if rest.is_null() {
+        return Err(LispError::new(format!(
+            "date-parse: '{}' does not match format '{}'",
+            text, fmt
+        )));
+    }
+    let rest = unsafe { CStr::from_ptr(rest) }.to_string_lossy();
+    if !rest.is_empty() {
+        return Err(LispError::new(format!(
+            "date-parse: unexpected '{}' after format '{}'",
+            rest, fmt
+        )));
+    }
+    let fields = [
+        tm.tm_year as i64 + 1900,
+        tm.tm_mon as i64 + 1,
+        tm.tm_mday as i64,
+        tm.tm_hour as i64,
+        tm.tm_min as i64,
+        tm.tm_sec as i64,
+        0,
+    ];
+    if format_has_offset(&fmt) {
+        // The text has its own offset, keep it.
+        let offset = gmtoff(&tm);
+        let millis = civil_to_epoch(&fields, Some(offset), fn_name)?;
+        make_datetime(millis, Zone::Fixed(offset), fn_name)
+    } else {
+        let offset = match zone {
+            Zone::Local => None,
+            Zone::Fixed(offset) => Some(offset),
+        };
+        let millis = civil_to_epoch(&fields, offset, fn_name)?;
+        make_datetime(millis, zone, fn_name)
+    }
+}
+
+fn builtin_date_to_iso(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date->iso";
+    let (millis, zone) = param_datetime(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let (tm, offset, _) = to_tm(millis.div_euclid(1000), zone, fn_name)?;
+    let mut res = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        tm.tm_year as i64 + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    );
+    if millis.rem_euclid(1000) != 0 {
+        res.push_str(&format!(".{:03}", millis.rem_euclid(1000)));
+    }
+    if offset == 0 {
+        res.push('Z');
+    } else {
+        res.push_str(&zone_name(offset));
+    }
+    Ok(Expression::alloc_data(ExpEnum::String(res.into(), None)))
+}
+
+// Parse ISO-8601/RFC-3339 (extended format), returns the civil fields and
+// offset (None if the text has no offset).
+fn parse_iso(text: &str) -> Option<([i64; 7], Option<i64>)> {
+    let bytes = text.trim().as_bytes();
+    let mut pos = 0;
+    let num = |pos: &mut usize, len: usize| -> Option<i64> {
+        let digits = bytes.get(*pos..*pos + len)?;
+        if !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        *pos += len;
+        std::str::from_utf8(digits).ok()?.parse().ok()
+    };
+    let expect = |pos: &mut usize, chars: &[u8]| -> bool {
+        match bytes.get(*pos) {
+            Some(ch) if chars.contains(ch) => {
+                *pos += 1;
+                true
+            }
+            _ => false,
+        }
+    };
+    let mut fields = [0, 0, 0, 0, 0, 0, 0];
+    fields[0] = num(&mut pos, 4)?;
+    if !expect(&mut pos, b"-") {
+        return None;
+    }
+    fields[1] = num(&mut pos, 2)?;
+    if !expect(&mut pos, b"-") {
+        return None;
+    }
+    fields[2] = num(&mut pos, 2)?;
+    if pos == bytes.len() {
+        return Some((fields, None));
+    }
+    if !expect(&mut pos, b"Tt ") {
+        return None;
+    }
+    fields[3] = num(&mut pos, 2)?;
+    if !expect(&mut pos, b":") {
+        return None;
+    }
+    fields[4] = num(&mut pos, 2)?;
+    if expect(&mut pos, b":") {
+        fields[5] = num(&mut pos, 2)?;
+        if expect(&mut pos, b".,") {
+            // Keep milliseconds, extra precision is truncated.
+            let start = pos;
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+            if pos == start {
+                return None;
+            }
+            let frac = std::str::from_utf8(&bytes[start..pos]).ok()?;
+            let frac = format!("{:0<3}", &frac[..frac.len().min(3)]);
+            fields[6] = frac.parse().ok()?;
+        }
+    }
+    if pos == bytes.len() {
+        return Some((fields, None));
+    }
+    if expect(&mut pos, b"Zz") {
+        return if pos == bytes.len() {
+            Some((fields, Some(0)))
+        } else {
+            None
+        };
+    }
+    let sign = match bytes[pos] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    pos += 1;
+    let hours = num(&mut pos, 2)?;
+    let minutes = if pos == bytes.len() {
+        0
+    } else {
+        expect(&mut pos, b":");
+        num(&mut pos, 2)?
+    };
+    if pos != bytes.len() || hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some((fields, Some(sign * (hours * 3600 + minutes * 60))))
+}
+
+fn builtin_iso_to_date(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "iso->date";
+    let text = param_eval(environment, args, fn_name)?.as_string(environment)?;
+    let zone = param_zone(environment, args, fn_name)?;
+    let (fields, offset) = parse_iso(&text).ok_or_else(|| {
+        LispError::new(format!("iso->date: invalid ISO-8601 date/time '{}'", text))
+    })?;
+    match offset {
+        Some(offset) => {
+            let millis = civil_to_epoch(&fields, Some(offset), fn_name)?;
+            make_datetime(millis, Zone::Fixed(offset), fn_name)
+        }
+        None => {
+            let offset = match zone {
+                Zone::Local => None,
+                Zone::Fixed(offset) => Some(offset),
+            };
+            let millis = civil_to_epoch(&fields, offset, fn_name)?;
+            make_datetime(millis, zone, fn_name)
+        }
+    }
+}
+
+fn builtin_duration(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "duration";
+    let args = make_args(environment, args)?;
+    if args.len() % 2 != 0 {
+        return Err(LispError::new(
+            "duration: takes pairs of a unit and an amount",
+        ));
+    }
+    let mut total: i64 = 0;
+    for pair in args.chunks(2) {
+        let scale = match &pair[0].get().data {
+            ExpEnum::Symbol(":weeks", _) => 7 * 24 * 60 * 60 * 1000,
+            ExpEnum::Symbol(":days", _) => 24 * 60 * 60 * 1000,
+            ExpEnum::Symbol(":hours", _) => 60 * 60 * 1000,
+            ExpEnum::Symbol(":minutes", _) => 60 * 1000,
+            ExpEnum::Symbol(":seconds", _) => 1000,
+            ExpEnum::Symbol(":millis", _) => 1,
+            _ => {
+                return Err(LispError::new(format!(
+                    "duration: invalid unit {}, expected one of :weeks :days :hours :minutes :seconds :millis",
+                    pair[0]
+                )))
+            }
+        };
+        let amount = match &pair[1].get().data {
+            ExpEnum::Int(i) => *i as f64,
+            ExpEnum::Float(f) => *f,
+            _ => {
+                return Err(LispError::new(ErrorStrings::mismatched_type(
+                    fn_name,
+                    "Int or Float",
+                    &pair[1].display_type(),
+                )))
+            }
+        };
+        let millis = (amount * scale as f64).round();
+        if !millis.is_finite() || millis.abs() >= i64::MAX as f64 {
+            return Err(out_of_range(fn_name));
+        }
+        total = total
+            .checked_add(millis as i64)
+            .ok_or_else(|| out_of_range(fn_name))?;
+    }
+    Ok(Expression::alloc_data(ExpEnum::Int(total)))
+}
+
+fn builtin_date_add(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date-add";
+    let (millis, zone) = param_datetime(environment, args, fn_name)?;
+    let duration = param_int(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let millis = millis
+        .checked_add(duration)
+        .ok_or_else(|| out_of_range(fn_name))?;
+    make_datetime(millis, zone, fn_name)
+}
+
+fn builtin_date_diff(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date-diff";
+    let (millis1, _) = param_datetime(environment, args, fn_name)?;
+    let (millis2, _) = param_datetime(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    let diff = millis1
+        .checked_sub(millis2)
+        .ok_or_else(|| out_of_range(fn_name))?;
+    Ok(Expression::alloc_data(ExpEnum::Int(diff)))
+}
+
+fn builtin_date_compare(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    let fn_name = "date-compare";
+    let (millis1, _) = param_datetime(environment, args, fn_name)?;
+    let (millis2, _) = param_datetime(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    Ok(Expression::alloc_data(ExpEnum::Int(
+        millis1.cmp(&millis2) as i64
+    )))
+}
+
+fn date_field(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+    fn_name: &str,
+    key: &str,
+) -> Result<Expression, LispError> {
+    let exp = param_eval(environment, args, fn_name)?;
+    params_done(args, fn_name)?;
+    get_datetime(&exp, fn_name)?;
+    let exp_d = exp.get();
+    match &exp_d.data {
+        ExpEnum::HashMap(map) => match map.get(key) {
+            Some(val) => Ok(val.clone()),
+            None => Err(LispError::new(format!(
+                "{}: datetime has no {}",
+                fn_name, key
+            ))),
+        },
+        _ => Err(LispError::new(format!("{}: expected a datetime", fn_name))),
+    }
+}
+
+fn builtin_date_year(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    date_field(environment, args, "date-year", ":year")
+}
+
+fn builtin_date_month(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    date_field(environment, args, "date-month", ":month")
+}
+
+fn builtin_date_day(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    date_field(environment, args, "date-day", ":day")
+}
+
+fn builtin_date_hour(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    date_field(environment, args, "date-hour", ":hour")
+}
+
+fn builtin_date_minute(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    date_field(environment, args, "date-minute", ":minute")
+}
+
+fn builtin_date_second(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    date_field(environment, args, "date-second", ":second")
+}
+
+fn builtin_date_weekday(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    date_field(environment, args, "date-weekday", ":weekday")
+}
+
+fn builtin_date_yearday(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    date_field(environment, args, "date-yearday", ":yearday")
+}
+
+fn builtin_date_epoch(
+    environment: &mut Environment,
+    args: &mut dyn Iterator<Item = Expression>,
+) -> Result<Expression, LispError> {
+    date_field(environment, args, "date-epoch", ":epoch")
+}
+
+pub fn add_time_builtins<S: BuildHasher>(
+    interner: &mut Interner,
+    data: &mut HashMap<&'static str, (Expression, String), S>,
+) {
+    data.insert(
+        interner.intern("date-now"),
+        Expression::make_function(
+            builtin_date_now,
+            r#"Usage: (date-now :utc|:local?) -> datetime
+
+Return the current time as a datetime in the local zone (default) or UTC.
+
+A datetime is a hash map with these keys:
+    :epoch        milliseconds since 1970-01-01T00:00:00Z
+    :year :month :day :hour :minute :second :millisecond
+    :weekday      1 (Monday) to 7 (Sunday)
+    :yearday      1 to 366
+    :offset       seconds east of UTC
+    :zone         zone name (for instance "UTC", "CET" or "+05:30")
+    :local        true if the datetime follows the system zone (TZ)
+The :epoch is what counts, the other fields are derived from it.  Local time
+uses the system tz database (TZ or /etc/localtime).
+
+Section: time
+
+Example:
+(def tst-now (date-now :utc))
+(test::assert-equal "UTC" (hash-get tst-now :zone))
+(test::assert-true (< (abs (- (epoch) (date-epoch tst-now))) 10000))
+(test::assert-true (hash-get (date-now) :local))
+(test::assert-error (date-now :gmt))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-from-epoch"),
+        Expression::make_function(
+            builtin_date_from_epoch,
+            r#"Usage: (date-from-epoch milliseconds :utc|:local?) -> datetime
+
+Return the datetime for milliseconds since the epoch (as returned by epoch) in
+the local zone (default) or UTC.
+
+Section: time
+
+Example:
+(def tst-date (date-from-epoch 1000000000123 :utc))
+(test::assert-equal "2001-09-09T01:46:40.123Z" (date->iso tst-date))
+(test::assert-equal 7 (date-weekday tst-date))
+(test::assert-equal 252 (date-yearday tst-date))
+(test::assert-equal 123 (hash-get tst-date :millisecond))
+(test::assert-equal "1969-12-31T23:59:59.500Z" (date->iso (date-from-epoch -500 :utc)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-make"),
+        Expression::make_function(
+            builtin_date_make,
+            r#"Usage: (date-make year month day hour? minute? second? millisecond? :utc|:local?) -> datetime
+
+Make a datetime from its parts in the local zone (default) or UTC.  Month and day
+start at 1, invalid dates (for instance February 30) are an error.
+
+Section: time
+
+Example:
+(test::assert-equal "2021-03-04T05:06:07.008Z" (date->iso (date-make 2021 3 4 5 6 7 8 :utc)))
+(test::assert-equal "2020-02-29T00:00:00Z" (date->iso (date-make 2020 2 29 :utc)))
+(test::assert-equal 2021 (date-year (date-make 2021 3 4)))
+(test::assert-error-msg (date-make 2021 2 29 :utc) "date-make: invalid date/time 2021-02-29 00:00:00.000")
+(test::assert-error (date-make 2021 3))
+(test::assert-error-msg (date-make 9999999999 1 1 :utc) "date-make: time out of range")
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date->local"),
+        Expression::make_function(
+            builtin_date_to_local,
+            r#"Usage: (date->local datetime) -> datetime
+
+Return datetime converted to the local zone (same instant).
+
+Section: time
+
+Example:
+(def tst-date (date-make 2021 6 1 12 0 0 :utc))
+(test::assert-true (hash-get (date->local tst-date) :local))
+(test::assert-equal (date-epoch tst-date) (date-epoch (date->local tst-date)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date->utc"),
+        Expression::make_function(
+            builtin_date_to_utc,
+            r#"Usage: (date->utc datetime) -> datetime
+
+Return datetime converted to UTC (same instant).
+
+Section: time
+
+Example:
+(def tst-date (iso->date "2021-06-01T12:00:00+02:00"))
+(test::assert-equal 12 (date-hour tst-date))
+(test::assert-equal 10 (date-hour (date->utc tst-date)))
+(test::assert-equal "2021-06-01T10:00:00Z" (date->iso (date->utc tst-date)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-format"),
+        Expression::make_function(
+            builtin_date_format,
+            r#"Usage: (date-format datetime format) -> string
+
+Format datetime with a strftime(3) format string (%Y, %m, %d, %H, %M, %S, %a,
+%b, %z, %Z, etc).  %L is also supported for zero padded milliseconds.
+
+Section: time
+
+Example:
+(def tst-date (date-make 2021 3 4 5 6 7 89 :utc))
+(test::assert-equal "2021-03-04 05:06:07.089" (date-format tst-date "%Y-%m-%d %H:%M:%S.%L"))
+(test::assert-equal "Thu Mar  4 UTC +0000" (date-format tst-date "%a %b %e %Z %z"))
+(test::assert-equal "05:06 +0530" (date-format (iso->date "2021-03-04T05:06:00+05:30") "%H:%M %z"))
+(test::assert-equal "100%" (date-format tst-date "100%%"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-parse"),
+        Expression::make_function(
+            builtin_date_parse,
+            r#"Usage: (date-parse string format :utc|:local?) -> datetime
+
+Parse string with a strptime(3) format into a datetime.  If the format includes
+%z the parsed offset is used, otherwise the time is taken to be local (default)
+or UTC.  The whole string must match.
+
+Section: time
+
+Example:
+(test::assert-equal "2021-03-04T05:06:07Z" (date->iso (date-parse "04/03/2021 05:06:07" "%d/%m/%Y %H:%M:%S" :utc)))
+(test::assert-equal "2021-03-04T05:06:00-07:00" (date->iso (date-parse "2021-03-04 05:06 -0700" "%Y-%m-%d %H:%M %z")))
+(test::assert-equal 3 (date-month (date-parse "2021-03-04" "%Y-%m-%d")))
+(test::assert-equal "2021-03-04T05:06:00Z" (date->iso (date-parse "2021-03-04 05:06 %z" "%Y-%m-%d %H:%M %%z" :utc)))
+(test::assert-error-msg (date-parse "2021-03-04x" "%Y-%m-%d") "date-parse: unexpected 'x' after format '%Y-%m-%d'")
+(test::assert-error (date-parse "March" "%Y"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date->iso"),
+        Expression::make_function(
+            builtin_date_to_iso,
+            r#"Usage: (date->iso datetime) -> string
+
+Format datetime as ISO-8601/RFC-3339 with the datetime's offset (Z for UTC),
+milliseconds are included if not zero.  Reads back with iso->date.
+
+Section: time
+
+Example:
+(test::assert-equal "2021-03-04T05:06:07Z" (date->iso (date-make 2021 3 4 5 6 7 :utc)))
+(test::assert-equal "2021-03-04T05:06:07.500+01:00" (date->iso (iso->date "2021-03-04T05:06:07.5+01:00")))
+(def tst-date (date-now))
+(test::assert-equal (date-epoch tst-date) (date-epoch (iso->date (date->iso tst-date))))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("iso->date"),
+        Expression::make_function(
+            builtin_iso_to_date,
+            r#"Usage: (iso->date string :utc|:local?) -> datetime
+
+Parse an ISO-8601/RFC-3339 date or date and time (YYYY-MM-DD,
+YYYY-MM-DDTHH:MM[:SS[.fff]] with an optional Z or +HH:MM offset).  A string with
+an offset keeps that offset, one without is local time (default) or UTC.
+
+Section: time
+
+Example:
+(test::assert-equal "2021-03-04T05:06:07.123Z" (date->iso (iso->date "2021-03-04T05:06:07.123456Z")))
+(test::assert-equal -16200 (hash-get (iso->date "2021-03-04 05:06-04:30") :offset))
+(test::assert-equal "2021-03-04T00:00:00Z" (date->iso (iso->date "2021-03-04" :utc)))
+(test::assert-equal 1614816000000 (date-epoch (iso->date "2021-03-04T00:00:00Z")))
+(test::assert-error-msg (iso->date "2021-3-4") "iso->date: invalid ISO-8601 date/time '2021-3-4'")
+(test::assert-error (iso->date "2021-02-30T00:00:00Z"))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("duration"),
+        Expression::make_function(
+            builtin_duration,
+            r#"Usage: (duration unit amount ...) -> int
+
+Return a duration in milliseconds (the unit of epoch and date-diff).  Units are
+:weeks, :days, :hours, :minutes, :seconds and :millis, amounts can be floats.
+
+Section: time
+
+Example:
+(test::assert-equal 90000 (duration :minutes 1 :seconds 30))
+(test::assert-equal 129600000 (duration :days 1.5))
+(test::assert-equal -1000 (duration :seconds -1))
+(test::assert-error (duration :years 1))
+(test::assert-error-msg (duration :weeks 1e300) "duration: time out of range")
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-add"),
+        Expression::make_function(
+            builtin_date_add,
+            r#"Usage: (date-add datetime milliseconds) -> datetime
+
+Return datetime moved by a duration in milliseconds (see duration), it keeps
+the zone of datetime.
+
+Section: time
+
+Example:
+(def tst-date (date-make 2020 2 28 23 0 0 :utc))
+(test::assert-equal "2020-02-29T01:30:00Z" (date->iso (date-add tst-date (duration :hours 2 :minutes 30))))
+(test::assert-equal "2020-02-27T23:00:00Z" (date->iso (date-add tst-date (duration :days -1))))
+(test::assert-error-msg (date-add tst-date 9223372036854775807) "date-add: time out of range")
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-diff"),
+        Expression::make_function(
+            builtin_date_diff,
+            r#"Usage: (date-diff datetime1 datetime2) -> int
+
+Return the milliseconds from datetime2 to datetime1 (datetime1 - datetime2).
+
+Section: time
+
+Example:
+(test::assert-equal (duration :days 1) (date-diff (date-make 2021 3 1 :utc) (date-make 2021 2 28 :utc)))
+(test::assert-equal 0 (date-diff (iso->date "2021-03-01T01:00:00+01:00") (iso->date "2021-03-01T00:00:00Z")))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-compare"),
+        Expression::make_function(
+            builtin_date_compare,
+            r#"Usage: (date-compare datetime1 datetime2) -> int
+
+Compare two datetimes, returns -1 if datetime1 is earlier, 0 if the same
+instant (the zones may differ) and 1 if later.
+
+Section: time
+
+Example:
+(test::assert-equal -1 (date-compare (date-make 2021 1 1 :utc) (date-make 2021 1 2 :utc)))
+(test::assert-equal 0 (date-compare (iso->date "2021-03-01T01:00:00+01:00") (iso->date "2021-03-01T00:00:00Z")))
+(test::assert-equal 1 (date-compare (date-now) (date-make 2021 1 1)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-year"),
+        Expression::make_function(
+            builtin_date_year,
+            r#"Usage: (date-year datetime) -> int
+
+Return the year of datetime.
+
+Section: time
+
+Example:
+(test::assert-equal 2021 (date-year (date-make 2021 3 4 :utc)))
+(test::assert-error (date-year (make-hash)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-month"),
+        Expression::make_function(
+            builtin_date_month,
+            r#"Usage: (date-month datetime) -> int
+
+Return the month (1-12) of datetime.
+
+Section: time
+
+Example:
+(test::assert-equal 3 (date-month (date-make 2021 3 4 :utc)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-day"),
+        Expression::make_function(
+            builtin_date_day,
+            r#"Usage: (date-day datetime) -> int
+
+Return the day of the month (1-31) of datetime.
+
+Section: time
+
+Example:
+(test::assert-equal 4 (date-day (date-make 2021 3 4 :utc)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-hour"),
+        Expression::make_function(
+            builtin_date_hour,
+            r#"Usage: (date-hour datetime) -> int
+
+Return the hour (0-23) of datetime.
+
+Section: time
+
+Example:
+(test::assert-equal 5 (date-hour (date-make 2021 3 4 5 6 7 :utc)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-minute"),
+        Expression::make_function(
+            builtin_date_minute,
+            r#"Usage: (date-minute datetime) -> int
+
+Return the minute (0-59) of datetime.
+
+Section: time
+
+Example:
+(test::assert-equal 6 (date-minute (date-make 2021 3 4 5 6 7 :utc)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-second"),
+        Expression::make_function(
+            builtin_date_second,
+            r#"Usage: (date-second datetime) -> int
+
+Return the second (0-60) of datetime.
+
+Section: time
+
+Example:
+(test::assert-equal 7 (date-second (date-make 2021 3 4 5 6 7 :utc)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-weekday"),
+        Expression::make_function(
+            builtin_date_weekday,
+            r#"Usage: (date-weekday datetime) -> int
+
+Return the ISO day of the week of datetime, 1 (Monday) to 7 (Sunday).
+
+Section: time
+
+Example:
+(test::assert-equal 4 (date-weekday (date-make 2021 3 4 :utc)))
+(test::assert-equal 7 (date-weekday (date-make 2021 3 7 :utc)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-yearday"),
+        Expression::make_function(
+            builtin_date_yearday,
+            r#"Usage: (date-yearday datetime) -> int
+
+Return the day of the year (1-366) of datetime.
+
+Section: time
+
+Example:
+(test::assert-equal 63 (date-yearday (date-make 2021 3 4 :utc)))
+(test::assert-equal 366 (date-yearday (date-make 2020 12 31 :utc)))
+"#,
+        ),
+    );
+    data.insert(
+        interner.intern("date-epoch"),
+        Expression::make_function(
+            builtin_date_epoch,
+            r#"Usage: (date-epoch datetime) -> int
+
+Return datetime as milliseconds since the epoch (like epoch).
+
+Section: time
+
+Example:
+(test::assert-equal 1000 (date-epoch (date-make 1970 1 1 0 0 1 :utc)))
+"#,
+        ),
+    );
+}
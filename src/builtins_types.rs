@@ -1,7 +1,7 @@
 use sl_sh_proc_macros::sl_sh_fn;
 use std::collections::HashMap;
 use std::hash::BuildHasher;
-use std::num::{ParseFloatError, ParseIntError};
+use std::num::ParseFloatError;
 
 use crate::builtins_util::*;
 use crate::environment::*;
@@ -18,18 +18,30 @@ use crate::{LispError, LispResult, VarArgs};
 ///     False
 ///     Float
 ///     Int
+///     Rational
+///     Decimal
 ///     Symbol
 ///     String
 ///     Char
 ///     Lambda
 ///     Macro
 ///     Process
+///     Pty
+///     FsWatcher
+///     Rng
+///     RecordType
+///     Record
+///     Iterator
 ///     SpecialForm
 ///     Function
 ///     Vector
 ///     Pair
 ///     Nil
 ///     HashMap
+///     Set
+///     PersistentVector
+///     PersistentMap
+///     Bytes
 ///     File
 ///
 /// Section: type
@@ -39,6 +51,8 @@ use crate::{LispError, LispResult, VarArgs};
 /// (test::assert-equal "False" (type #f))
 /// (test::assert-equal "Float" (type 1.1))
 /// (test::assert-equal "Int" (type 1))
+/// (test::assert-equal "Rational" (type 1/3))
+/// (test::assert-equal "Decimal" (type #d1.50))
 /// (test::assert-equal "Symbol" (type 'symbol))
 /// (def type-sym 'symbol)
 /// (test::assert-equal "Symbol" (type type-sym))
@@ -57,6 +71,10 @@ use crate::{LispError, LispResult, VarArgs};
 /// (test::assert-equal "Nil" (type nil))
 /// (test::assert-equal "Nil" (type '()))
 /// (test::assert-equal "HashMap" (type (make-hash)))
+/// (test::assert-equal "Set" (type #s(1)))
+/// (test::assert-equal "PersistentVector" (type (persistent-vec 1)))
+/// (test::assert-equal "PersistentMap" (type (persistent-map :a 1)))
+/// (test::assert-equal "Bytes" (type #u8(1 2)))
 /// (test::assert-equal "File" (type (open :stdin)))
 #[sl_sh_fn(fn_name = "type")]
 fn to_type(exp: Expression) -> String {
@@ -230,6 +248,37 @@ fn is_int(exp: Expression) -> bool {
     matches!(exp.get().data, ExpEnum::Int(_))
 }
 
+/// Usage: (rational? expression)
+///
+/// True if the expression is a rational (not an int), false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (test::assert-true (rational? 1/3))
+/// (test::assert-true (rational? (rational 1 3)))
+/// (test::assert-false (rational? #r2/2))
+/// (test::assert-false (rational? 0.5))
+#[sl_sh_fn(fn_name = "rational?")]
+fn is_rational(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::Rational(_))
+}
+
+/// Usage: (decimal? expression)
+///
+/// True if the expression is an exact decimal, false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (test::assert-true (decimal? #d19.99))
+/// (test::assert-true (decimal? (decimal 1)))
+/// (test::assert-false (decimal? 19.99))
+#[sl_sh_fn(fn_name = "decimal?")]
+fn is_decimal(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::Decimal(_))
+}
+
 /// Usage: (symbol? expression)
 ///
 /// True if the expression is a symbol, false otherwise.
@@ -384,6 +433,165 @@ fn is_process(exp: Expression) -> bool {
     matches!(exp.get().data, ExpEnum::Process(_))
 }
 
+/// Usage: (pty? expression)
+///
+/// True if the expression is a pty (from pty-spawn), false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (def pty?-test (pty-spawn "cat"))
+/// (test::assert-true (pty? pty?-test))
+/// (pty-close pty?-test)
+/// (test::assert-false (pty? (syscall 'true)))
+/// (test::assert-false (pty? 1))
+#[sl_sh_fn(fn_name = "pty?")]
+fn is_pty(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::Pty(_))
+}
+
+/// Usage: (bytes? expression)
+///
+/// True if the expression is a byte vector, false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (test::assert-true (bytes? #u8(1 2 255)))
+/// (test::assert-true (bytes? (str->bytes "abc")))
+/// (test::assert-false (bytes? "abc"))
+/// (test::assert-false (bytes? '#(1 2)))
+#[sl_sh_fn(fn_name = "bytes?")]
+fn is_bytes(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::Bytes(_))
+}
+
+/// Usage: (fs-watcher? expression)
+///
+/// True if the expression is a file system watcher (from fs-watch), false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (def fs-watcher?-test (fs-watch "/tmp" :flat))
+/// (test::assert-true (fs-watcher? fs-watcher?-test))
+/// (fs-watch-close fs-watcher?-test)
+/// (test::assert-false (fs-watcher? "/tmp"))
+/// (test::assert-false (fs-watcher? 1))
+#[sl_sh_fn(fn_name = "fs-watcher?")]
+fn is_fs_watcher(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::FsWatcher(_))
+}
+
+/// Usage: (rng? expression)
+///
+/// True if the expression is a random number generator (see make-rng), false
+/// otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (test::assert-true (rng? (make-rng 1)))
+/// (test::assert-false (rng? 1))
+#[sl_sh_fn(fn_name = "rng?")]
+fn is_rng(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::Rng(_))
+}
+
+/// Usage: (record? expression)
+///
+/// True if the expression is an instance of a record type (see struct::defstruct), false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (struct::defstruct record?-test (a 1 :ro))
+/// (test::assert-true (record? (record?-test)))
+/// (test::assert-false (record? record?-test))
+/// (test::assert-false (record? (make-hash)))
+/// (test::assert-false (record? 1))
+#[sl_sh_fn(fn_name = "record?")]
+fn is_record(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::Record(_))
+}
+
+/// Usage: (record-type? expression)
+///
+/// True if the expression is a record type (made by struct::defstruct, calling it makes an instance), false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (struct::defstruct record-type?-test (a 1 :ro))
+/// (test::assert-true (record-type? record-type?-test))
+/// (test::assert-false (record-type? (record-type?-test)))
+/// (test::assert-false (record-type? (fn () ())))
+#[sl_sh_fn(fn_name = "record-type?")]
+fn is_record_type(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::RecordType(_))
+}
+
+/// Usage: (set? expression)
+///
+/// True if the expression is a set, false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (test::assert-true (set? #s(1 2)))
+/// (test::assert-true (set? (make-set)))
+/// (test::assert-false (set? '#(1 2)))
+/// (test::assert-false (set? (make-hash)))
+#[sl_sh_fn(fn_name = "set?")]
+fn is_set(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::Set(_))
+}
+
+/// Usage: (persistent-vec? expression)
+///
+/// True if the expression is a persistent vector, false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (test::assert-true (persistent-vec? (persistent-vec 1 2)))
+/// (test::assert-false (persistent-vec? '#(1 2)))
+/// (test::assert-false (persistent-vec? (persistent-map)))
+#[sl_sh_fn(fn_name = "persistent-vec?")]
+fn is_persistent_vec(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::PVec(_))
+}
+
+/// Usage: (persistent-map? expression)
+///
+/// True if the expression is a persistent map, false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (test::assert-true (persistent-map? (persistent-map :a 1)))
+/// (test::assert-false (persistent-map? (make-hash)))
+/// (test::assert-false (persistent-map? (persistent-vec)))
+#[sl_sh_fn(fn_name = "persistent-map?")]
+fn is_persistent_map(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::PMap(_))
+}
+
+/// Usage: (iterator? expression)
+///
+/// True if the expression is a native iterator (see make-iter), false otherwise.
+///
+/// Section: type
+///
+/// Example:
+/// (test::assert-true (iterator? (make-iter '(1 2))))
+/// (test::assert-true (iterator? (iter-range 3)))
+/// (test::assert-false (iterator? '(1 2)))
+#[sl_sh_fn(fn_name = "iterator?")]
+fn is_iterator(exp: Expression) -> bool {
+    matches!(exp.get().data, ExpEnum::Iterator(_))
+}
+
 /// "Usage: (file? expression)
 ///
 /// True if the expression is a file, false otherwise.
@@ -435,9 +643,11 @@ fn is_list(exp: Expression) -> bool {
     exp.is_nil() || is_proper_list(&exp)
 }
 
-/// Usage: (str->int string) -> int
+/// Usage: (str->int string radix?) -> int
 ///
 /// If string is a valid representation of an integer return that int.  Error if not.
+/// If radix (2 to 36) is provided string is read in that base, a leading #x/0x
+/// (radix 16), #o/0o (radix 8) or #b/0b (radix 2) prefix after any sign is allowed.
 ///
 /// Section: type
 ///
@@ -448,15 +658,105 @@ fn is_list(exp: Expression) -> bool {
 /// (test::assert-error (str->int "not int"))
 /// (test::assert-error (str->int "10.0"))
 /// (test::assert-error (str->int "--10"))
+/// (test::assert-equal 255 (str->int "ff" 16))
+/// (test::assert-equal 255 (str->int "0xFF" 16))
+/// (test::assert-equal -255 (str->int "-#xff" 16))
+/// (test::assert-equal 493 (str->int "755" 8))
+/// (test::assert-equal 5 (str->int "0b101" 2))
+/// (test::assert-equal 35 (str->int "z" 36))
+/// (test::assert-error (str->int "0x12" 10))
+/// (test::assert-error (str->int "12" 2))
+/// (test::assert-error (str->int "12" 37))
 #[sl_sh_fn(fn_name = "str->int")]
-fn str_to_int(istr: &str) -> LispResult<i64> {
-    let potential_int: Result<i64, ParseIntError> = istr.parse();
-    match potential_int {
+fn str_to_int(istr: &str, radix: Option<i64>) -> LispResult<i64> {
+    let radix = match radix {
+        Some(radix) => radix_param("str->int", radix)?,
+        None => 10,
+    };
+    let (sign, digits) = match istr.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", istr.strip_prefix('+').unwrap_or(istr)),
+    };
+    let prefix = match radix {
+        16 => "x",
+        8 => "o",
+        2 => "b",
+        _ => "",
+    };
+    let digits = if prefix.is_empty() {
+        digits
+    } else {
+        digits
+            .strip_prefix('#')
+            .or_else(|| digits.strip_prefix('0'))
+            .and_then(|d| d.strip_prefix(prefix))
+            .unwrap_or(digits)
+    };
+    // Sign was stripped above so a second sign is an error.
+    if digits.starts_with(&['-', '+'][..]) {
+        return Err(LispError::new("str->int: string is not a valid integer"));
+    }
+    match i64::from_str_radix(&format!("{}{}", sign, digits), radix) {
         Ok(v) => Ok(v),
         Err(_) => Err(LispError::new("str->int: string is not a valid integer")),
     }
 }
 
+fn radix_param(fn_name: &str, radix: i64) -> LispResult<u32> {
+    if (2..=36).contains(&radix) {
+        Ok(radix as u32)
+    } else {
+        Err(LispError::new(format!(
+            "{}: radix must be between 2 and 36, got {}",
+            fn_name, radix
+        )))
+    }
+}
+
+/// Usage: (int->str int radix? width?) -> string
+///
+/// Format int as a string in radix (2 to 36, default 10) using lowercase
+/// letters for digits above 9.  If width is provided the digits are zero padded
+/// to at least width (after any - sign).
+///
+/// Section: type
+///
+/// Example:
+/// (test::assert-equal "255" (int->str 255))
+/// (test::assert-equal "ff" (int->str 255 16))
+/// (test::assert-equal "-ff" (int->str -255 16))
+/// (test::assert-equal "755" (int->str #o755 8))
+/// (test::assert-equal "00000101" (int->str 5 2 8))
+/// (test::assert-equal "-0005" (int->str -5 10 4))
+/// (test::assert-equal "z" (int->str 35 36))
+/// (test::assert-equal "-8000000000000000" (int->str (- -9223372036854775807 1) 16))
+/// (test::assert-equal 1234 (str->int (int->str 1234 7) 7))
+/// (test::assert-error (int->str 1 1))
+#[sl_sh_fn(fn_name = "int->str")]
+fn int_to_str(int: i64, radix: Option<i64>, width: Option<i64>) -> LispResult<String> {
+    let radix = match radix {
+        Some(radix) => radix_param("int->str", radix)?,
+        None => 10,
+    };
+    let mut n = int.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        digits.push(std::char::from_digit((n % radix as u64) as u32, radix).unwrap());
+        n /= radix as u64;
+        if n == 0 {
+            break;
+        }
+    }
+    let width = width.unwrap_or(0).max(0) as usize;
+    while digits.len() < width {
+        digits.push('0');
+    }
+    if int < 0 {
+        digits.push('-');
+    }
+    Ok(digits.into_iter().rev().collect())
+}
+
 /// Usage: (str->float string) -> float
 ///
 /// If string is a valid representation of a float return that float.  Error if not.
@@ -603,6 +903,8 @@ pub fn add_type_builtins<S: BuildHasher>(
     intern_is_float(interner, data);
     intern_is_regex(interner, data);
     intern_is_int(interner, data);
+    intern_is_rational(interner, data);
+    intern_is_decimal(interner, data);
     intern_is_symbol(interner, data);
     intern_is_string(interner, data);
     intern_is_char(interner, data);
@@ -612,10 +914,21 @@ pub fn add_type_builtins<S: BuildHasher>(
     intern_is_pair(interner, data);
     intern_is_builtin(interner, data);
     intern_is_process(interner, data);
+    intern_is_pty(interner, data);
+    intern_is_fs_watcher(interner, data);
+    intern_is_rng(interner, data);
+    intern_is_record(interner, data);
+    intern_is_record_type(interner, data);
+    intern_is_iterator(interner, data);
+    intern_is_set(interner, data);
+    intern_is_persistent_vec(interner, data);
+    intern_is_persistent_map(interner, data);
+    intern_is_bytes(interner, data);
     intern_is_file(interner, data);
     intern_is_hash(interner, data);
     intern_is_list(interner, data);
     intern_str_to_int(interner, data);
+    intern_int_to_str(interner, data);
     intern_str_to_float(interner, data);
     intern_int_to_float(interner, data);
     intern_float_to_int(interner, data);
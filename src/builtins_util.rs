@@ -1,5 +1,6 @@
 use crate::environment::*;
 use crate::eval::*;
+use crate::numeric::{ExactNum, Num};
 use crate::types::*;
 use std::borrow::Cow;
 
@@ -100,6 +101,13 @@ pub fn parse_list_of_floats(
     Ok(list)
 }
 
+/// The exact values of args, None unless all are ints, rationals or decimals.
+pub fn parse_list_of_exact(args: &[Expression]) -> Option<Vec<ExactNum>> {
+    args.iter()
+        .map(|arg| Num::from_exp(arg).and_then(Num::exact))
+        .collect()
+}
+
 pub fn parse_list_of_strings(
     environment: &mut Environment,
     args: &mut [Expression],
@@ -185,6 +193,32 @@ pub fn make_args(
     Ok(list)
 }
 
+// Call a lambda or builtin with already evaluated args.
+pub fn call_fn(
+    environment: &mut Environment,
+    func: &Expression,
+    args: Vec<Expression>,
+) -> Result<Expression, LispError> {
+    let func_d = func.get();
+    if let ExpEnum::Function(f) = &func_d.data {
+        let f = f.func;
+        drop(func_d);
+        let old_sup = environment.supress_eval;
+        environment.supress_eval = true;
+        let ret = f(environment, &mut args.into_iter());
+        environment.supress_eval = old_sup;
+        ret
+    } else {
+        drop(func_d);
+        // We may be called from apply, the lambda body must still be evaluated.
+        let old_sup = environment.supress_eval;
+        environment.supress_eval = false;
+        let ret = call_lambda(environment, func.clone(), &mut args.into_iter(), false);
+        environment.supress_eval = old_sup;
+        ret
+    }
+}
+
 pub struct ErrorStrings {}
 
 impl ErrorStrings {
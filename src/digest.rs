@@ -0,0 +1,1114 @@
+// Streaming message digests and checksums for hash-bytes and hash-file.  Each
+// algorithm is fed data with update and produces its digest bytes with finish.
+
+pub trait Digest {
+    fn update(&mut self, data: &[u8]);
+    fn finish(&mut self) -> Vec<u8>;
+}
+
+/// Names of the supported algorithms, as accepted by new_digest.
+pub const DIGEST_NAMES: [&str; 7] = [
+    "md5", "sha1", "sha256", "sha512", "blake3", "xxhash", "crc32",
+];
+
+pub fn new_digest(name: &str) -> Option<Box<dyn Digest>> {
+    match name {
+        "md5" => Some(Box::new(Md5::new())),
+        "sha1" => Some(Box::new(Sha1::new())),
+        "sha256" => Some(Box::new(Sha256::new())),
+        "sha512" => Some(Box::new(Sha512::new())),
+        "blake3" => Some(Box::new(Blake3::new())),
+        "xxhash" => Some(Box::new(Xxh64::new(0))),
+        "crc32" => Some(Box::new(Crc32::new())),
+        _ => None,
+    }
+}
+
+// Buffers input into fixed size blocks for the Merkle–Damgård hashes.
+struct Blocks {
+    buf: Vec<u8>,
+    size: usize,
+    total: u128,
+}
+
+impl Blocks {
+    fn new(size: usize) -> Self {
+        Blocks {
+            buf: Vec::with_capacity(size),
+            size,
+            total: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8], mut compress: impl FnMut(&[u8])) {
+        self.total += data.len() as u128;
+        if !self.buf.is_empty() {
+            let take = (self.size - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buf.len() < self.size {
+                return;
+            }
+            compress(&self.buf);
+            self.buf.clear();
+        }
+        let blocks = data.chunks_exact(self.size);
+        let rest = blocks.remainder();
+        for block in blocks {
+            compress(block);
+        }
+        self.buf.extend_from_slice(rest);
+    }
+
+    // Append the 0x80 marker, zeros and the message length in bits.
+    fn pad(&mut self, len_bytes: usize, big_endian: bool, mut compress: impl FnMut(&[u8])) {
+        let bits = self.total.wrapping_mul(8);
+        let mut tail = std::mem::take(&mut self.buf);
+        tail.push(0x80);
+        let used = (tail.len() + len_bytes) % self.size;
+        if used > 0 {
+            tail.resize(tail.len() + self.size - used, 0);
+        }
+        if big_endian {
+            tail.extend_from_slice(&bits.to_be_bytes()[16 - len_bytes..]);
+        } else {
+            tail.extend_from_slice(&bits.to_le_bytes()[..len_bytes]);
+        }
+        for block in tail.chunks(self.size) {
+            compress(block);
+        }
+    }
+}
+
+fn words_be(block: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    block
+        .chunks_exact(4)
+        .map(|w| u32::from_be_bytes([w[0], w[1], w[2], w[3]]))
+}
+
+struct Md5 {
+    state: [u32; 4],
+    blocks: Blocks,
+}
+
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+const MD5_K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+impl Md5 {
+    fn new() -> Self {
+        Md5 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476],
+            blocks: Blocks::new(64),
+        }
+    }
+
+    fn compress(state: &mut [u32; 4], block: &[u8]) {
+        let mut m = [0u32; 16];
+        for (m, w) in m.iter_mut().zip(block.chunks_exact(4)) {
+            *m = u32::from_le_bytes([w[0], w[1], w[2], w[3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = *state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(MD5_K[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[(i / 16) * 4 + i % 4]));
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+}
+
+impl Digest for Md5 {
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |b| Md5::compress(state, b));
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let state = &mut self.state;
+        self.blocks.pad(8, false, |b| Md5::compress(state, b));
+        state.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+}
+
+struct Sha1 {
+    state: [u32; 5],
+    blocks: Blocks,
+}
+
+impl Sha1 {
+    fn new() -> Self {
+        Sha1 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            blocks: Blocks::new(64),
+        }
+    }
+
+    fn compress(state: &mut [u32; 5], block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (w, word) in w.iter_mut().zip(words_be(block)) {
+            *w = word;
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = *state;
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i / 20 {
+                0 => ((b & c) | (!b & d), 0x5a827999),
+                1 => (b ^ c ^ d, 0x6ed9eba1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+}
+
+impl Digest for Sha1 {
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |b| Sha1::compress(state, b));
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let state = &mut self.state;
+        self.blocks.pad(8, true, |b| Sha1::compress(state, b));
+        state.iter().flat_map(|s| s.to_be_bytes()).collect()
+    }
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+struct Sha256 {
+    state: [u32; 8],
+    blocks: Blocks,
+}
+
+impl Sha256 {
+    fn new() -> Self {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            blocks: Blocks::new(64),
+        }
+    }
+
+    fn compress(state: &mut [u32; 8], block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (w, word) in w.iter_mut().zip(words_be(block)) {
+            *w = word;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for (k, w) in SHA256_K.iter().zip(w.iter()) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(*w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+}
+
+impl Digest for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |b| Sha256::compress(state, b));
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let state = &mut self.state;
+        self.blocks.pad(8, true, |b| Sha256::compress(state, b));
+        state.iter().flat_map(|s| s.to_be_bytes()).collect()
+    }
+}
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+struct Sha512 {
+    state: [u64; 8],
+    blocks: Blocks,
+}
+
+impl Sha512 {
+    fn new() -> Self {
+        Sha512 {
+            state: [
+                0x6a09e667f3bcc908,
+                0xbb67ae8584caa73b,
+                0x3c6ef372fe94f82b,
+                0xa54ff53a5f1d36f1,
+                0x510e527fade682d1,
+                0x9b05688c2b3e6c1f,
+                0x1f83d9abfb41bd6b,
+                0x5be0cd19137e2179,
+            ],
+            blocks: Blocks::new(128),
+        }
+    }
+
+    fn compress(state: &mut [u64; 8], block: &[u8]) {
+        let mut w = [0u64; 80];
+        for (w, word) in w.iter_mut().zip(block.chunks_exact(8)) {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(word);
+            *w = u64::from_be_bytes(bytes);
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for (k, w) in SHA512_K.iter().zip(w.iter()) {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(*w);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+}
+
+impl Digest for Sha512 {
+    fn update(&mut self, data: &[u8]) {
+        let state = &mut self.state;
+        self.blocks.update(data, |b| Sha512::compress(state, b));
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let state = &mut self.state;
+        self.blocks.pad(16, true, |b| Sha512::compress(state, b));
+        state.iter().flat_map(|s| s.to_be_bytes()).collect()
+    }
+}
+
+/// BLAKE3 (hash mode, 32 byte output).
+struct Blake3 {
+    chunk: Blake3Chunk,
+    // Chaining values of completed subtrees, one per set bit of the chunk count.
+    stack: Vec<[u32; 8]>,
+}
+
+const BLAKE3_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+const BLAKE3_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+const BLAKE3_CHUNK_LEN: usize = 1024;
+const BLAKE3_CHUNK_START: u32 = 1;
+const BLAKE3_CHUNK_END: u32 = 2;
+const BLAKE3_PARENT: u32 = 4;
+const BLAKE3_ROOT: u32 = 8;
+
+fn blake3_g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(x);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(y);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+fn blake3_compress(
+    cv: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+) -> [u32; 8] {
+    let mut state = [0u32; 16];
+    state[..8].copy_from_slice(cv);
+    state[8..12].copy_from_slice(&BLAKE3_IV[..4]);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+    state[14] = block_len;
+    state[15] = flags;
+    let mut m = *block;
+    for round in 0..7 {
+        blake3_g(&mut state, 0, 4, 8, 12, m[0], m[1]);
+        blake3_g(&mut state, 1, 5, 9, 13, m[2], m[3]);
+        blake3_g(&mut state, 2, 6, 10, 14, m[4], m[5]);
+        blake3_g(&mut state, 3, 7, 11, 15, m[6], m[7]);
+        blake3_g(&mut state, 0, 5, 10, 15, m[8], m[9]);
+        blake3_g(&mut state, 1, 6, 11, 12, m[10], m[11]);
+        blake3_g(&mut state, 2, 7, 8, 13, m[12], m[13]);
+        blake3_g(&mut state, 3, 4, 9, 14, m[14], m[15]);
+        if round < 6 {
+            let mut permuted = [0u32; 16];
+            for (p, i) in permuted.iter_mut().zip(BLAKE3_PERMUTATION.iter()) {
+                *p = m[*i];
+            }
+            m = permuted;
+        }
+    }
+    let mut out = [0u32; 8];
+    for (i, o) in out.iter_mut().enumerate() {
+        *o = state[i] ^ state[i + 8];
+    }
+    out
+}
+
+fn blake3_parent(left: &[u32; 8], right: &[u32; 8], flags: u32) -> [u32; 8] {
+    let mut block = [0u32; 16];
+    block[..8].copy_from_slice(left);
+    block[8..].copy_from_slice(right);
+    blake3_compress(&BLAKE3_IV, &block, 0, 64, BLAKE3_PARENT | flags)
+}
+
+// The state of the 1024 byte chunk currently being hashed.
+struct Blake3Chunk {
+    cv: [u32; 8],
+    counter: u64,
+    buf: Vec<u8>,
+    blocks: usize,
+}
+
+impl Blake3Chunk {
+    fn new(counter: u64) -> Self {
+        Blake3Chunk {
+            cv: BLAKE3_IV,
+            counter,
+            buf: Vec::with_capacity(64),
+            blocks: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.blocks * 64 + self.buf.len()
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks == 0 {
+            BLAKE3_CHUNK_START
+        } else {
+            0
+        }
+    }
+
+    fn block_words(&self) -> [u32; 16] {
+        let mut block = [0u8; 64];
+        block[..self.buf.len()].copy_from_slice(&self.buf);
+        let mut words = [0u32; 16];
+        for (w, b) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *w = u32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+        words
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // The last block is kept back, it is compressed with CHUNK_END.
+            if self.buf.len() == 64 {
+                let flags = self.start_flag();
+                self.cv = blake3_compress(&self.cv, &self.block_words(), self.counter, 64, flags);
+                self.blocks += 1;
+                self.buf.clear();
+            }
+            let take = (64 - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+        }
+    }
+
+    fn finish(&self, flags: u32) -> [u32; 8] {
+        blake3_compress(
+            &self.cv,
+            &self.block_words(),
+            self.counter,
+            self.buf.len() as u32,
+            self.start_flag() | BLAKE3_CHUNK_END | flags,
+        )
+    }
+}
+
+impl Blake3 {
+    fn new() -> Self {
+        Blake3 {
+            chunk: Blake3Chunk::new(0),
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl Digest for Blake3 {
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // Only finish a chunk once more input arrives, the last one is the root.
+            if self.chunk.len() == BLAKE3_CHUNK_LEN {
+                let mut cv = self.chunk.finish(0);
+                let mut chunks = self.chunk.counter + 1;
+                while chunks & 1 == 0 {
+                    let left = self.stack.pop().expect("a subtree for each set bit");
+                    cv = blake3_parent(&left, &cv, 0);
+                    chunks >>= 1;
+                }
+                self.stack.push(cv);
+                self.chunk = Blake3Chunk::new(self.chunk.counter + 1);
+            }
+            let take = (BLAKE3_CHUNK_LEN - self.chunk.len()).min(data.len());
+            self.chunk.update(&data[..take]);
+            data = &data[take..];
+        }
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let out = if self.stack.is_empty() {
+            self.chunk.finish(BLAKE3_ROOT)
+        } else {
+            let mut cv = self.chunk.finish(0);
+            for (i, left) in self.stack.iter().enumerate().rev() {
+                let flags = if i == 0 { BLAKE3_ROOT } else { 0 };
+                cv = blake3_parent(left, &cv, flags);
+            }
+            cv
+        };
+        out.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+}
+
+const XXH_P1: u64 = 0x9e3779b185ebca87;
+const XXH_P2: u64 = 0xc2b2ae3d27d4eb4f;
+const XXH_P3: u64 = 0x165667b19e3779f9;
+const XXH_P4: u64 = 0x85ebca77c2b2ae63;
+const XXH_P5: u64 = 0x27d4eb2f165667c5;
+
+/// 64 bit xxHash (XXH64).
+struct Xxh64 {
+    seed: u64,
+    acc: [u64; 4],
+    buf: Vec<u8>,
+    total: u64,
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(b)
+}
+
+fn xxh_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(XXH_P2))
+        .rotate_left(31)
+        .wrapping_mul(XXH_P1)
+}
+
+fn xxh_merge(acc: u64, val: u64) -> u64 {
+    (acc ^ xxh_round(0, val))
+        .wrapping_mul(XXH_P1)
+        .wrapping_add(XXH_P4)
+}
+
+impl Xxh64 {
+    fn new(seed: u64) -> Self {
+        Xxh64 {
+            seed,
+            acc: [
+                seed.wrapping_add(XXH_P1).wrapping_add(XXH_P2),
+                seed.wrapping_add(XXH_P2),
+                seed,
+                seed.wrapping_sub(XXH_P1),
+            ],
+            buf: Vec::with_capacity(32),
+            total: 0,
+        }
+    }
+
+    fn stripe(acc: &mut [u64; 4], stripe: &[u8]) {
+        for (i, acc) in acc.iter_mut().enumerate() {
+            *acc = xxh_round(*acc, read_u64(&stripe[i * 8..]));
+        }
+    }
+}
+
+impl Digest for Xxh64 {
+    fn update(&mut self, data: &[u8]) {
+        self.total += data.len() as u64;
+        self.buf.extend_from_slice(data);
+        let stripes = self.buf.len() / 32 * 32;
+        for stripe in self.buf[..stripes].chunks_exact(32) {
+            Xxh64::stripe(&mut self.acc, stripe);
+        }
+        self.buf.drain(..stripes);
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let mut h = if self.total >= 32 {
+            let [v1, v2, v3, v4] = self.acc;
+            let mut h = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for v in self.acc.iter() {
+                h = xxh_merge(h, *v);
+            }
+            h
+        } else {
+            self.seed.wrapping_add(XXH_P5)
+        };
+        h = h.wrapping_add(self.total);
+        let words = self.buf.chunks_exact(8);
+        let mut rest = words.remainder();
+        for word in words {
+            h ^= xxh_round(0, read_u64(word));
+            h = h.rotate_left(27).wrapping_mul(XXH_P1).wrapping_add(XXH_P4);
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+            h ^= (word as u64).wrapping_mul(XXH_P1);
+            h = h.rotate_left(23).wrapping_mul(XXH_P2).wrapping_add(XXH_P3);
+            rest = &rest[4..];
+        }
+        for byte in rest {
+            h ^= (*byte as u64).wrapping_mul(XXH_P5);
+            h = h.rotate_left(11).wrapping_mul(XXH_P1);
+        }
+        h ^= h >> 33;
+        h = h.wrapping_mul(XXH_P2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(XXH_P3);
+        h ^= h >> 32;
+        h.to_be_bytes().to_vec()
+    }
+}
+
+/// CRC-32 (IEEE 802.3, as used by zip, gzip and png).
+struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 == 1 {
+                    0xedb88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        Crc32 {
+            table,
+            crc: 0xffffffff,
+        }
+    }
+}
+
+impl Digest for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.crc = self.table[((self.crc ^ *byte as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        (self.crc ^ 0xffffffff).to_be_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Input of len bytes in the repeating 0..251 pattern used by the BLAKE3
+    // test vectors.
+    fn painted(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    // Hex digest of data fed to the algorithm step bytes at a time.
+    fn hex_digest(name: &str, data: &[u8], step: usize) -> String {
+        let mut digest = new_digest(name).unwrap();
+        for piece in data.chunks(step.max(1)) {
+            digest.update(piece);
+        }
+        digest
+            .finish()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn check_vectors(name: &str, vectors: &[(Vec<u8>, &str)]) {
+        for (data, expected) in vectors {
+            assert_eq!(
+                *expected,
+                hex_digest(name, data, data.len()),
+                "{} of {} bytes",
+                name,
+                data.len()
+            );
+        }
+    }
+
+    const FIPS_448: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    const FIPS_896: &[u8] = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+
+    #[test]
+    fn test_md5() {
+        // RFC 1321 test suite.
+        check_vectors(
+            "md5",
+            &[
+                (b"".to_vec(), "d41d8cd98f00b204e9800998ecf8427e"),
+                (b"a".to_vec(), "0cc175b9c0f1b6a831c399e269772661"),
+                (b"abc".to_vec(), "900150983cd24fb0d6963f7d28e17f72"),
+                (
+                    b"message digest".to_vec(),
+                    "f96b697d7cb7938d525a2f31aaf161d0",
+                ),
+                (
+                    b"abcdefghijklmnopqrstuvwxyz".to_vec(),
+                    "c3fcd3d76192e4007dfb496cca67e13b",
+                ),
+                (
+                    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789".to_vec(),
+                    "d174ab98d277d9f5a5611c2c9f419d9f",
+                ),
+                (b"1234567890".repeat(8), "57edf4a22be3c955ac49da2e2107b67a"),
+                (painted(55), "6912ee65fff2d9f9ce2508cddf8bcda0"),
+                (painted(56), "51fdd1acda72405dfdfa03fcb85896d7"),
+                (painted(64), "b2d3f56bc197fd985d5965079b5e7148"),
+                (painted(112), "d1fec2ac3715e791ca5f489f300381b3"),
+                (painted(128), "37eff01866ba3f538421b30b7cbefcac"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_sha1() {
+        // FIPS 180 examples then padding boundaries.
+        check_vectors(
+            "sha1",
+            &[
+                (b"".to_vec(), "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+                (b"abc".to_vec(), "a9993e364706816aba3e25717850c26c9cd0d89d"),
+                (
+                    FIPS_448.to_vec(),
+                    "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+                ),
+                (
+                    FIPS_896.to_vec(),
+                    "a49b2446a02c645bf419f995b67091253a04a259",
+                ),
+                (
+                    vec![b'a'; 1_000_000],
+                    "34aa973cd4c4daa4f61eeb2bdbad27316534016f",
+                ),
+                (painted(55), "8ae2d46729cfe68ff927af5eec9c7d1b66d65ac2"),
+                (painted(56), "636e2ec698dac903498e648bd2f3af641d3c88cb"),
+                (painted(64), "c6138d514ffa2135bfce0ed0b8fac65669917ec7"),
+                (painted(112), "e4ce142d09a84a8645338dd6535cbfaaf800d320"),
+                (painted(128), "e6434bc401f98603d7eda504790c98c67385d535"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_sha256() {
+        check_vectors(
+            "sha256",
+            &[
+                (
+                    b"".to_vec(),
+                    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                ),
+                (
+                    b"abc".to_vec(),
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                ),
+                (
+                    FIPS_448.to_vec(),
+                    "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+                ),
+                (
+                    FIPS_896.to_vec(),
+                    "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1",
+                ),
+                (
+                    vec![b'a'; 1_000_000],
+                    "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+                ),
+                (
+                    painted(55),
+                    "463eb28e72f82e0a96c0a4cc53690c571281131f672aa229e0d45ae59b598b59",
+                ),
+                (
+                    painted(56),
+                    "da2ae4d6b36748f2a318f23e7ab1dfdf45acdc9d049bd80e59de82a60895f562",
+                ),
+                (
+                    painted(64),
+                    "fdeab9acf3710362bd2658cdc9a29e8f9c757fcf9811603a8c447cd1d9151108",
+                ),
+                (
+                    painted(112),
+                    "09373f127d34e61dbbaa8bc4499c87074f2ddb10e1b465f506d7d70a15011979",
+                ),
+                (
+                    painted(128),
+                    "471fb943aa23c511f6f72f8d1652d9c880cfa392ad80503120547703e56a2be5",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_sha512() {
+        check_vectors(
+            "sha512",
+            &[
+                (
+                    b"".to_vec(),
+                    "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+                ),
+                (
+                    b"abc".to_vec(),
+                    "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+                ),
+                (
+                    FIPS_448.to_vec(),
+                    "204a8fc6dda82f0a0ced7beb8e08a41657c16ef468b228a8279be331a703c33596fd15c13b1b07f9aa1d3bea57789ca031ad85c7a71dd70354ec631238ca3445",
+                ),
+                (
+                    FIPS_896.to_vec(),
+                    "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909",
+                ),
+                (
+                    vec![b'a'; 1_000_000],
+                    "e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973ebde0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b",
+                ),
+                (
+                    painted(55),
+                    "6856647f269c2ee3d8128f0b25427659d880641ef343300dd3cd4679168f58d6527fda70b4ebc854e2065e172b7d58c1536992c0810599259ba84a2b40c65414",
+                ),
+                (
+                    painted(56),
+                    "8b12b2f6fe400a51d29656e2b8c42a1bbfe6fcf3e425da430db05d1a2dda14790dee20fa8b22d8762afffe4988a5c98a4430d22a17e41e23d90fa61ab75671a9",
+                ),
+                (
+                    painted(64),
+                    "ee4320ebaf3fdb4f2c832b137200c08e235e0fa7bbd0eb1740c7063ba8a0d151da77e003398e1714a955d475b05e3e950b639503b452ec185de4229bc4873949",
+                ),
+                (
+                    painted(112),
+                    "c5fbd731d19d2ae1180f001be72c2c1aaba1d7b094b3748880e24593b8e117a750e11c1bd867cc2f96dace8c8b74abd2d5c4f236be444e77d30d1916174070b9",
+                ),
+                (
+                    painted(128),
+                    "1dffd5e3adb71d45d2245939665521ae001a317a03720a45732ba1900ca3b8351fc5c9b4ca513eba6f80bc7b1d1fdad4abd13491cb824d61b08d8c0e1561b3f7",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_blake3() {
+        // From the BLAKE3 test vectors, lengths around the 64 byte block, the
+        // 1024 byte chunk and parent node boundaries.
+        check_vectors(
+            "blake3",
+            &[
+                (
+                    painted(0),
+                    "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+                ),
+                (
+                    painted(1),
+                    "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213",
+                ),
+                (
+                    painted(63),
+                    "e9bc37a594daad83be9470df7f7b3798297c3d834ce80ba85d6e207627b7db7b",
+                ),
+                (
+                    painted(64),
+                    "4eed7141ea4a5cd4b788606bd23f46e212af9cacebacdc7d1f4c6dc7f2511b98",
+                ),
+                (
+                    painted(65),
+                    "de1e5fa0be70df6d2be8fffd0e99ceaa8eb6e8c93a63f2d8d1c30ecb6b263dee",
+                ),
+                (
+                    painted(1023),
+                    "10108970eeda3eb932baac1428c7a2163b0e924c9a9e25b35bba72b28f70bd11",
+                ),
+                (
+                    painted(1024),
+                    "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7",
+                ),
+                (
+                    painted(1025),
+                    "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444",
+                ),
+                (
+                    painted(2048),
+                    "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a",
+                ),
+                (
+                    painted(2049),
+                    "5f4d72f40d7a5f82b15ca2b2e44b1de3c2ef86c426c95c1af0b6879522563030",
+                ),
+                (
+                    painted(3072),
+                    "b98cb0ff3623be03326b373de6b9095218513e64f1ee2edd2525c7ad1e5cffd2",
+                ),
+                (
+                    painted(3073),
+                    "7124b49501012f81cc7f11ca069ec9226cecb8a2c850cfe644e327d22d3e1cd3",
+                ),
+                (
+                    painted(4096),
+                    "015094013f57a5277b59d8475c0501042c0b642e531b0a1c8f58d2163229e969",
+                ),
+                (
+                    painted(4097),
+                    "9b4052b38f1c5fc8b1f9ff7ac7b27cd242487b3d890d15c96a1c25b8aa0fb995",
+                ),
+                (
+                    painted(5121),
+                    "628bd2cb2004694adaab7bbd778a25df25c47b9d4155a55f8fbd79f2fe154cff",
+                ),
+                (
+                    painted(8193),
+                    "bab6c09cb8ce8cf459261398d2e7aef35700bf488116ceb94a36d0f5f1b7bc3b",
+                ),
+                (
+                    painted(31744),
+                    "62b6960e1a44bcc1eb1a611a8d6235b6b4b78f32e7abc4fb4c6cdcce94895c47",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_xxhash() {
+        // XXH64 with seed 0, lengths around the 32 byte stripe.
+        check_vectors(
+            "xxhash",
+            &[
+                (b"".to_vec(), "ef46db3751d8e999"),
+                (b"abc".to_vec(), "44bc2cf5ad770999"),
+                (painted(1), "e934a84adb052768"),
+                (painted(31), "c346d2b59b4d8ee1"),
+                (painted(32), "cbf59c5116ff32b4"),
+                (painted(33), "0c535d1acafb8ead"),
+                (painted(55), "511cd496994ea839"),
+                (painted(56), "0cd869f80ff2b918"),
+                (painted(64), "f7c67301db6713f0"),
+                (painted(100), "6ac1e58032166597"),
+                (painted(1000), "f306f04aa88b54d3"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_crc32() {
+        check_vectors(
+            "crc32",
+            &[
+                (b"".to_vec(), "00000000"),
+                (b"123456789".to_vec(), "cbf43926"),
+                (painted(55), "fd4fdad4"),
+                (painted(64), "100ece8c"),
+                (painted(128), "24650d57"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_split_updates() {
+        // Feeding the input in pieces (that straddle block, stripe and chunk
+        // boundaries) must give the same digest as one update.
+        for name in DIGEST_NAMES.iter() {
+            for len in [0, 1, 55, 56, 64, 65, 112, 128, 1023, 1024, 1025, 3073, 8193].iter() {
+                let data = painted(*len);
+                let whole = hex_digest(name, &data, data.len());
+                for step in [1, 3, 31, 63, 64, 65, 127, 1000, 1024].iter() {
+                    assert_eq!(
+                        whole,
+                        hex_digest(name, &data, *step),
+                        "{} of {} bytes in pieces of {}",
+                        name,
+                        len,
+                        step
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use nix::libc;
+use rand_chacha::ChaCha8Rng;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
@@ -7,6 +8,7 @@ use std::rc::Rc;
 
 use sl_liner::Context;
 
+use crate::builtins_event::EventLoop;
 use crate::interner::*;
 use crate::process::*;
 use crate::reader::ReaderState;
@@ -145,6 +147,10 @@ pub struct Environment {
     pub terminal_fd: i32,
     pub grab_proc_output: bool,
     pub in_fork: bool,
+    // Timers, readable sources and signal watchers for event-loop.
+    pub event_loop: EventLoop,
+    // Generator for the random builtins (see with-rng), None uses the thread rng.
+    pub rng: Option<Rc<RefCell<ChaCha8Rng>>>,
 }
 
 impl Environment {
@@ -210,6 +216,8 @@ pub fn build_default_environment() -> Environment {
         terminal_fd,
         grab_proc_output: false,
         in_fork: false,
+        event_loop: EventLoop::default(),
+        rng: None,
     }
 }
 
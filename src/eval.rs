@@ -3,6 +3,8 @@ use std::cell::RefCell;
 use crate::analyze::*;
 use crate::builtins::{builtin_bquote, builtin_quote};
 use crate::builtins_bind::{builtin_def, builtin_var};
+use crate::builtins_iterator::call_iterator;
+use crate::builtins_record::{call_record, new_record};
 use crate::environment::*;
 use crate::signals::test_clear_sigint;
 use crate::symbols::*;
@@ -56,9 +58,17 @@ fn prep_stack(
     vars: &mut dyn Iterator<Item = Expression>,
     lambda: &Lambda,
     lambda_exp: Expression,
+    bound: &[Binding],
 ) -> Result<(), LispError> {
     let index = environment.stack.len();
-    setup_args(environment, lambda.num_params, lambda.has_rest, vars)?;
+    // Bound bindings are shared, not copied, so a set! on them is seen by the owner.
+    environment.stack.extend(bound.iter().cloned());
+    setup_args(
+        environment,
+        lambda.num_params - bound.len(),
+        lambda.has_rest,
+        vars,
+    )?;
     let symbols = lambda.syms.clone();
     if !lambda.no_recur {
         // Push the 'this-fn' value.
@@ -82,7 +92,9 @@ fn call_lambda_int(
     lambda: Lambda,
     args: &mut dyn Iterator<Item = Expression>,
     eval_args: bool,
+    bound: &[Binding],
 ) -> Result<Expression, LispError> {
+    let mut bound = bound;
     let mut lambda_int = lambda;
     let mut lambda: &mut Lambda = &mut lambda_int;
     let mut body: &MultiExpression = &lambda.body;
@@ -96,9 +108,9 @@ fn call_lambda_int(
             tvars.push(eval(environment, &v)?);
         }
         let ib = &mut tvars.iter().cloned();
-        prep_stack(environment, ib, lambda, lambda_current.clone())?;
+        prep_stack(environment, ib, lambda, lambda_current.clone(), bound)?;
     } else {
-        prep_stack(environment, args, lambda, lambda_current.clone())?;
+        prep_stack(environment, args, lambda, lambda_current.clone(), bound)?;
     }
 
     let mut llast_eval: Option<Expression> = None;
@@ -144,6 +156,7 @@ fn call_lambda_int(
                     &mut last_eval.iter(),
                     lambda,
                     lambda_current.clone(),
+                    bound,
                 )?;
             }
         } else if environment.exit_code.is_none() {
@@ -161,8 +174,9 @@ fn call_lambda_int(
                     environment.stack.truncate(stack_len);
                     environment.stack_frames.truncate(stack_frames_len);
                     environment.stack_frame_base = stack_base;
+                    bound = &[];
                     let ib = &mut parts.iter().cloned();
-                    prep_stack(environment, ib, lambda, lambda_current.clone())?;
+                    prep_stack(environment, ib, lambda, lambda_current.clone(), bound)?;
                 }
             }
         }
@@ -182,6 +196,19 @@ pub fn call_lambda(
     lambda_exp: Expression,
     args: &mut dyn Iterator<Item = Expression>,
     eval_args: bool,
+) -> Result<Expression, LispError> {
+    call_lambda_bound(environment, lambda_exp, &[], args, eval_args)
+}
+
+/// Call a lambda with its first parameters bound to existing bindings (for
+/// instance a record's fields) followed by args.  The bindings are shared with
+/// the caller so a set! on one of those parameters updates the original.
+pub fn call_lambda_bound(
+    environment: &mut Environment,
+    lambda_exp: Expression,
+    bound: &[Binding],
+    args: &mut dyn Iterator<Item = Expression>,
+    eval_args: bool,
 ) -> Result<Expression, LispError> {
     let lambda = if let ExpEnum::Lambda(l) = &lambda_exp.get().data {
         l.clone()
@@ -194,12 +221,19 @@ pub fn call_lambda(
             lambda_exp
         )));
     };
+    if lambda.num_params < bound.len() + usize::from(lambda.has_rest) {
+        return Err(LispError::new(format!(
+            "wrong number of parameters, expected at least {} got {}",
+            bound.len(),
+            lambda.num_params
+        )));
+    }
     let old_ns = environment.namespace.clone();
     environment.namespace = lambda.syms.namespace().clone();
     let stack_len = environment.stack.len();
     let stack_frames_len = environment.stack_frames.len();
     let old_base = environment.stack_frame_base;
-    let ret = call_lambda_int(environment, lambda_exp, lambda, args, eval_args);
+    let ret = call_lambda_int(environment, lambda_exp, lambda, args, eval_args, bound);
     environment.stack.truncate(stack_len);
     environment.stack_frames.truncate(stack_frames_len);
     environment.stack_frame_base = old_base;
@@ -241,6 +275,19 @@ fn eval_command(
             }
         }
         ExpEnum::Function(c) => (c.func)(environment, &mut *parts),
+        ExpEnum::RecordType(t) => {
+            let t = t.clone();
+            drop(com_exp_d);
+            new_record(environment, &t, parts)
+        }
+        ExpEnum::Record(_) => {
+            drop(com_exp_d);
+            call_record(environment, com_exp, parts)
+        }
+        ExpEnum::Iterator(_) => {
+            drop(com_exp_d);
+            call_iterator(environment, com_exp, parts)
+        }
         ExpEnum::DeclareDef => builtin_def(environment, &mut *parts),
         ExpEnum::DeclareVar => builtin_var(environment, &mut *parts),
         ExpEnum::Quote => builtin_quote(environment, &mut *parts),
@@ -300,6 +347,9 @@ fn fn_eval_lazy(
                             call_lambda(environment, exp.clone(), &mut parts, true)
                         }
                     }
+                    ExpEnum::RecordType(t) => new_record(environment, t, &mut parts),
+                    ExpEnum::Record(_) => call_record(environment, &exp, &mut parts),
+                    ExpEnum::Iterator(_) => call_iterator(environment, &exp, &mut parts),
                     _ => {
                         let msg = format!("Not a valid form {}, not found.", command_sym);
                         Err(LispError::new(msg))
@@ -328,6 +378,9 @@ fn fn_eval_lazy(
             }
         }
         ExpEnum::Function(c) => (c.func)(environment, &mut *parts),
+        ExpEnum::RecordType(t) => new_record(environment, t, &mut *parts),
+        ExpEnum::Record(_) => call_record(environment, &command, &mut *parts),
+        ExpEnum::Iterator(_) => call_iterator(environment, &command, &mut *parts),
         ExpEnum::DeclareDef => builtin_def(environment, &mut *parts),
         ExpEnum::DeclareVar => builtin_var(environment, &mut *parts),
         ExpEnum::Quote => builtin_quote(environment, &mut *parts),
@@ -425,11 +478,17 @@ fn internal_eval(
             }
         }
         ExpEnum::HashMap(_) => Ok(expression.clone()),
+        ExpEnum::Set(_) => Ok(expression.clone()),
+        ExpEnum::PVec(_) => Ok(expression.clone()),
+        ExpEnum::PMap(_) => Ok(expression.clone()),
         ExpEnum::String(_, _) => Ok(expression.clone()),
         ExpEnum::Regex(_) => Ok(expression.clone()),
+        ExpEnum::Bytes(_) => Ok(expression.clone()),
         ExpEnum::True => Ok(expression.clone()),
         ExpEnum::False => Ok(expression.clone()),
         ExpEnum::Float(_) => Ok(expression.clone()),
+        ExpEnum::Rational(_) => Ok(expression.clone()),
+        ExpEnum::Decimal(_) => Ok(expression.clone()),
         ExpEnum::Int(_) => Ok(expression.clone()),
         ExpEnum::Char(_) => Ok(expression.clone()),
         ExpEnum::CodePoint(_) => Ok(expression.clone()),
@@ -441,6 +500,12 @@ fn internal_eval(
         ExpEnum::Quote => Ok(Expression::alloc_data(ExpEnum::Nil)),
         ExpEnum::BackQuote => Ok(Expression::alloc_data(ExpEnum::Nil)),
         ExpEnum::Process(_) => Ok(expression.clone()),
+        ExpEnum::Pty(_) => Ok(expression.clone()),
+        ExpEnum::FsWatcher(_) => Ok(expression.clone()),
+        ExpEnum::Rng(_) => Ok(expression.clone()),
+        ExpEnum::RecordType(_) => Ok(expression.clone()),
+        ExpEnum::Record(_) => Ok(expression.clone()),
+        ExpEnum::Iterator(_) => Ok(expression.clone()),
         ExpEnum::File(_) => Ok(Expression::alloc_data(ExpEnum::Nil)),
         ExpEnum::LazyFn(_, _) => {
             let int_exp = expression.clone().resolve(environment)?;
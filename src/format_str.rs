@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::environment::*;
+use crate::numeric::*;
+use crate::types::*;
+
+// A parsed {arg:spec} replacement field.
+#[derive(Default)]
+struct Spec {
+    fill: Option<char>,
+    align: Option<char>,
+    plus: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+enum ArgRef {
+    Next,
+    Index(usize),
+    Name(String),
+}
+
+enum Piece {
+    Text(String),
+    Field(ArgRef, Spec),
+}
+
+fn parse_num(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut num = String::new();
+    while let Some(ch) = chars.peek() {
+        if ch.is_ascii_digit() {
+            num.push(*ch);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    num.parse().ok()
+}
+
+fn parse_spec(spec: &str, fn_name: &str) -> Result<Spec, LispError> {
+    let mut res = Spec::default();
+    let err = || LispError::new(format!("{}: invalid format spec '{}'", fn_name, spec));
+    let spec_chars: Vec<char> = spec.chars().collect();
+    let is_align = |ch: char| matches!(ch, '<' | '>' | '^');
+    let mut chars = if spec_chars.len() > 1 && is_align(spec_chars[1]) {
+        res.fill = Some(spec_chars[0]);
+        res.align = Some(spec_chars[1]);
+        spec[spec_chars[0].len_utf8() + 1..].chars().peekable()
+    } else if !spec_chars.is_empty() && is_align(spec_chars[0]) {
+        res.align = Some(spec_chars[0]);
+        spec[1..].chars().peekable()
+    } else {
+        spec.chars().peekable()
+    };
+    if chars.peek() == Some(&'+') {
+        res.plus = true;
+        chars.next();
+    }
+    if chars.peek() == Some(&'#') {
+        res.alternate = true;
+        chars.next();
+    }
+    if chars.peek() == Some(&'0') {
+        res.zero = true;
+        chars.next();
+    }
+    if let Some(width) = parse_num(&mut chars) {
+        res.width = width;
+    }
+    if chars.peek() == Some(&'.') {
+        chars.next();
+        res.precision = Some(parse_num(&mut chars).ok_or_else(err)?);
+    }
+    if let Some(kind) = chars.next() {
+        if !matches!(kind, 's' | 'x' | 'X' | 'o' | 'b' | 'e' | 'E' | '?') {
+            return Err(err());
+        }
+        res.kind = Some(kind);
+    }
+    if chars.next().is_some() {
+        return Err(err());
+    }
+    Ok(res)
+}
+
+fn parse_format(fmt: &str, fn_name: &str) -> Result<Vec<Piece>, LispError> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = fmt.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '}' => {
+                return Err(LispError::new(format!(
+                    "{}: unmatched '}}' in format string, use '}}}}' for a literal '}}'",
+                    fn_name
+                )))
+            }
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(ch) => field.push(ch),
+                        None => {
+                            return Err(LispError::new(format!(
+                            "{}: unterminated '{{' in format string, use '{{{{' for a literal '{{'",
+                            fn_name
+                        )))
+                        }
+                    }
+                }
+                let (arg, spec) = match field.find(':') {
+                    Some(i) => (&field[..i], &field[i + 1..]),
+                    None => (&field[..], ""),
+                };
+                let arg = arg.trim();
+                let arg = if arg.is_empty() {
+                    ArgRef::Next
+                } else if let Ok(idx) = arg.parse::<usize>() {
+                    ArgRef::Index(idx)
+                } else {
+                    ArgRef::Name(arg.to_string())
+                };
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(text.clone()));
+                    text.clear();
+                }
+                pieces.push(Piece::Field(arg, parse_spec(spec, fn_name)?));
+            }
+            _ => text.push(ch),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+fn pad(text: String, spec: &Spec, default_align: char) -> String {
+    let width = UnicodeWidthStr::width(&text[..]);
+    if width >= spec.width {
+        return text;
+    }
+    let padding = spec.width - width;
+    let fill = spec.fill.unwrap_or(' ').to_string();
+    match spec.align.unwrap_or(default_align) {
+        '<' => format!("{}{}", text, fill.repeat(padding)),
+        '^' => format!(
+            "{}{}{}",
+            fill.repeat(padding / 2),
+            text,
+            fill.repeat(padding - padding / 2)
+        ),
+        _ => format!("{}{}", fill.repeat(padding), text),
+    }
+}
+
+// Apply sign, prefix and zero padding to the digits of a number.
+fn format_number(negative: bool, prefix: &str, digits: String, spec: &Spec) -> String {
+    let sign = if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else {
+        ""
+    };
+    if spec.zero && spec.align.is_none() {
+        let len = sign.len() + prefix.len() + digits.len();
+        let zeros = "0".repeat(spec.width.saturating_sub(len));
+        format!("{}{}{}{}", sign, prefix, zeros, digits)
+    } else {
+        pad(format!("{}{}{}", sign, prefix, digits), spec, '>')
+    }
+}
+
+fn format_float(f: f64, spec: &Spec) -> String {
+    let digits = match (spec.kind, spec.precision) {
+        (Some('e'), Some(p)) => format!("{:.*e}", p, f.abs()),
+        (Some('e'), None) => format!("{:e}", f.abs()),
+        (Some('E'), Some(p)) => format!("{:.*E}", p, f.abs()),
+        (Some('E'), None) => format!("{:E}", f.abs()),
+        (_, Some(p)) => format!("{:.*}", p, f.abs()),
+        (_, None) => format!("{}", f.abs()),
+    };
+    format_number(f.is_sign_negative() && f != 0.0, "", digits, spec)
+}
+
+// Format a decimal or rational, a precision rounds it to that many places.
+fn format_exact(num: Num, spec: &Spec, fn_name: &str) -> Result<String, LispError> {
+    let text = match (spec.precision, num.exact()) {
+        (Some(precision), Some(exact)) => Decimal::round(exact, precision as u32)
+            .map_err(|err| LispError::new(format!("{}: {}", fn_name, err.reason)))?
+            .to_string(),
+        _ => match num {
+            Num::Rational(r) => r.to_string(),
+            Num::Decimal(d) => d.to_string(),
+            Num::Int(i) => i.to_string(),
+            Num::Float(f) => f.to_string(),
+        },
+    };
+    let negative = text.starts_with('-');
+    Ok(format_number(
+        negative,
+        "",
+        text.trim_start_matches('-').to_string(),
+        spec,
+    ))
+}
+
+fn format_value(
+    environment: &mut Environment,
+    exp: &Expression,
+    spec: &Spec,
+    fn_name: &str,
+) -> Result<String, LispError> {
+    let (int, float, exact) = match Num::from_data(&exp.get().data) {
+        Some(Num::Int(i)) => (Some(i), None, None),
+        Some(Num::Float(f)) => (None, Some(f), None),
+        // Exponent formats show decimals and rationals as floats.
+        Some(num) if matches!(spec.kind, Some('e') | Some('E')) => (None, Some(num.to_f64()), None),
+        Some(num) => (None, None, Some(num)),
+        None => (None, None, None),
+    };
+    match (spec.kind, int, float) {
+        (Some('?'), _, _) => Ok(pad(exp.to_string(), spec, '<')),
+        (Some(kind), Some(i), _) if matches!(kind, 'x' | 'X' | 'o' | 'b') => {
+            let n = i.unsigned_abs();
+            let (prefix, digits) = match kind {
+                'x' => ("0x", format!("{:x}", n)),
+                'X' => ("0x", format!("{:X}", n)),
+                'o' => ("0o", format!("{:o}", n)),
+                _ => ("0b", format!("{:b}", n)),
+            };
+            let prefix = if spec.alternate { prefix } else { "" };
+            Ok(format_number(i < 0, prefix, digits, spec))
+        }
+        (Some(kind), _, _) if matches!(kind, 'x' | 'X' | 'o' | 'b') => {
+            Err(LispError::new(format!(
+                "{}: format type '{}' requires an Int, got {}",
+                fn_name,
+                kind,
+                exp.display_type()
+            )))
+        }
+        (_, _, Some(f)) => Ok(format_float(f, spec)),
+        // A precision (or exponent format) on an Int means show it as a float.
+        (Some('e'), Some(i), _) | (Some('E'), Some(i), _) => Ok(format_float(i as f64, spec)),
+        (_, Some(i), _) if spec.precision.is_some() => Ok(format_float(i as f64, spec)),
+        (Some(kind), _, _) if matches!(kind, 'e' | 'E') => Err(LispError::new(format!(
+            "{}: format type '{}' requires a number, got {}",
+            fn_name,
+            kind,
+            exp.display_type()
+        ))),
+        (_, Some(i), _) => Ok(format_number(i < 0, "", i.unsigned_abs().to_string(), spec)),
+        _ => {
+            if let Some(num) = exact {
+                return format_exact(num, spec, fn_name);
+            }
+            let mut text = exp.as_string(environment)?;
+            if let Some(precision) = spec.precision {
+                text = text.chars().take(precision).collect();
+            }
+            Ok(pad(text, spec, '<'))
+        }
+    }
+}
+
+/// Format args with the format string fmt.  Fields are {arg:spec} where arg
+/// is empty (next positional arg), an index or a name and spec is
+/// [[fill]align][+][#][0][width][.precision][type].  If fmt has named fields
+/// the last arg is a hash map with their values (keyed by :name).
+pub fn format_string(
+    environment: &mut Environment,
+    fmt: &str,
+    mut args: Vec<Expression>,
+    fn_name: &str,
+) -> Result<String, LispError> {
+    let pieces = parse_format(fmt, fn_name)?;
+    let has_names = pieces
+        .iter()
+        .any(|p| matches!(p, Piece::Field(ArgRef::Name(_), _)));
+    let named = if has_names {
+        let map = args.pop().map(|a| a.get().data.clone());
+        if let Some(ExpEnum::HashMap(map)) = map {
+            map
+        } else {
+            return Err(LispError::new(format!(
+                "{}: named fields need a hash map of values as the last argument",
+                fn_name
+            )));
+        }
+    } else {
+        HashMap::new()
+    };
+    let positional = args;
+    let mut used = vec![false; positional.len()];
+    let mut next = 0;
+    let mut res = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Text(text) => res.push_str(&text),
+            Piece::Field(arg, spec) => {
+                let exp = match arg {
+                    ArgRef::Next | ArgRef::Index(_) => {
+                        let idx = if let ArgRef::Index(idx) = arg {
+                            idx
+                        } else {
+                            next += 1;
+                            next - 1
+                        };
+                        if let Some(exp) = positional.get(idx) {
+                            used[idx] = true;
+                            exp.clone()
+                        } else {
+                            return Err(LispError::new(format!(
+                                "{}: format string needs argument {} but only {} given",
+                                fn_name,
+                                idx,
+                                positional.len()
+                            )));
+                        }
+                    }
+                    ArgRef::Name(name) => {
+                        if let Some(exp) = named.get(&format!(":{}", name)[..]) {
+                            exp.clone()
+                        } else {
+                            return Err(LispError::new(format!(
+                                "{}: {{{}}} is not in the named arguments",
+                                fn_name, name
+                            )));
+                        }
+                    }
+                };
+                res.push_str(&format_value(environment, &exp, &spec, fn_name)?);
+            }
+        }
+    }
+    let unused = used.iter().filter(|u| !**u).count();
+    if unused > 0 {
+        return Err(LispError::new(format!(
+            "{}: {} argument(s) not used by the format string",
+            fn_name, unused
+        )));
+    }
+    Ok(res)
+}
@@ -26,6 +26,9 @@ pub use crate::completions::*;
 pub mod reader;
 pub use crate::reader::*;
 
+pub mod numeric;
+pub use crate::numeric::*;
+
 pub mod builtins_math;
 pub use crate::builtins_math::*;
 
@@ -50,6 +53,12 @@ pub use crate::builtins::*;
 pub mod builtins_system;
 pub use crate::builtins_system::*;
 
+pub mod builtins_pty;
+pub use crate::builtins_pty::*;
+
+pub mod builtins_event;
+pub use crate::builtins_event::*;
+
 pub mod umask_util;
 pub use crate::umask_util::*;
 
@@ -59,6 +68,48 @@ pub use crate::builtins_util::*;
 pub mod builtins_file;
 pub use crate::builtins_file::*;
 
+pub mod builtins_fs_watch;
+pub use crate::builtins_fs_watch::*;
+
+pub mod builtins_json;
+pub use crate::builtins_json::*;
+
+pub mod builtins_bytes;
+pub use crate::builtins_bytes::*;
+
+pub mod builtins_time;
+pub use crate::builtins_time::*;
+
+pub mod builtins_diff;
+pub use crate::builtins_diff::*;
+
+pub mod digest;
+pub use crate::digest::*;
+
+pub mod builtins_digest;
+pub use crate::builtins_digest::*;
+
+pub mod builtins_equal;
+pub use crate::builtins_equal::*;
+
+pub mod builtins_record;
+pub use crate::builtins_record::*;
+
+pub mod builtins_iterator;
+pub use crate::builtins_iterator::*;
+
+pub mod builtins_set;
+pub use crate::builtins_set::*;
+
+pub mod persistent;
+pub use crate::persistent::*;
+
+pub mod builtins_persistent;
+pub use crate::builtins_persistent::*;
+
+pub mod builtins_sort;
+pub use crate::builtins_sort::*;
+
 pub mod builtins_io;
 pub use crate::builtins_io::*;
 
@@ -86,6 +137,9 @@ pub use crate::builtins_bind::*;
 pub mod pretty_print;
 pub use crate::pretty_print::*;
 
+pub mod format_str;
+pub use crate::format_str::*;
+
 pub mod process;
 pub use crate::process::*;
 
@@ -0,0 +1,546 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::types::*;
+
+/// Most places a Decimal can have (10^18 is the largest power of ten in an i64).
+pub const MAX_DECIMAL_SCALE: u32 = 18;
+
+fn pow10(scale: u32) -> i128 {
+    10_i128.pow(scale)
+}
+
+fn check_scale(scale: u32) -> Result<(), LispError> {
+    if scale > MAX_DECIMAL_SCALE {
+        Err(LispError::new(format!(
+            "decimal: scale can be at most {}, got {}",
+            MAX_DECIMAL_SCALE, scale
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn rational_overflow() -> LispError {
+    LispError::new("rational overflow")
+}
+
+fn mul_i128(a: i128, b: i128) -> Result<i128, LispError> {
+    a.checked_mul(b).ok_or_else(rational_overflow)
+}
+
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    a = a.abs();
+    b = b.abs();
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+/// A fraction in lowest terms with a denominator greater than one, whole
+/// values are always Ints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rational {
+    num: i64,
+    den: i64,
+}
+
+impl Rational {
+    pub fn numerator(&self) -> i64 {
+        self.num
+    }
+
+    pub fn denominator(&self) -> i64 {
+        self.den
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+/// An exact base ten number (mantissa / 10^scale).  The scale is kept through
+/// arithmetic so #d1.50 plus 1 is #d2.50.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decimal {
+    mantissa: i64,
+    scale: u32,
+}
+
+impl Decimal {
+    pub fn new(mantissa: i64, scale: u32) -> Result<Decimal, LispError> {
+        check_scale(scale)?;
+        Ok(Decimal { mantissa, scale })
+    }
+
+    pub fn mantissa(&self) -> i64 {
+        self.mantissa
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Parse digits with an optional sign and fractional part ("-12.50").
+    pub fn parse(s: &str) -> Option<Decimal> {
+        let (neg, s) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (whole, frac) = match s.find('.') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+        let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty()
+            || !all_digits(whole)
+            || !all_digits(frac)
+            || (s.contains('.') && frac.is_empty())
+            || frac.len() > MAX_DECIMAL_SCALE as usize
+        {
+            return None;
+        }
+        let mantissa: i64 = format!("{}{}{}", if neg { "-" } else { "" }, whole, frac)
+            .parse()
+            .ok()?;
+        Some(Decimal {
+            mantissa,
+            scale: frac.len() as u32,
+        })
+    }
+
+    fn from_i128(mantissa: i128, scale: u32) -> Result<Decimal, LispError> {
+        match i64::try_from(mantissa) {
+            Ok(mantissa) => Decimal::new(mantissa, scale),
+            Err(_) => Err(LispError::new("decimal overflow")),
+        }
+    }
+
+    fn rescale(&self, scale: u32) -> Result<i128, LispError> {
+        (self.mantissa as i128)
+            .checked_mul(pow10(scale - self.scale))
+            .ok_or_else(|| LispError::new("decimal overflow"))
+    }
+
+    fn add(self, other: Decimal) -> Result<Decimal, LispError> {
+        let scale = self.scale.max(other.scale);
+        Decimal::from_i128(self.rescale(scale)? + other.rescale(scale)?, scale)
+    }
+
+    fn sub(self, other: Decimal) -> Result<Decimal, LispError> {
+        let scale = self.scale.max(other.scale);
+        Decimal::from_i128(self.rescale(scale)? - other.rescale(scale)?, scale)
+    }
+
+    fn mul(self, other: Decimal) -> Result<Decimal, LispError> {
+        let mut mantissa = self.mantissa as i128 * other.mantissa as i128;
+        let mut scale = self.scale + other.scale;
+        while scale > MAX_DECIMAL_SCALE && mantissa % 10 == 0 {
+            mantissa /= 10;
+            scale -= 1;
+        }
+        Decimal::from_i128(mantissa, scale)
+    }
+
+    /// Round x to scale places, halves round away from zero.
+    pub fn round(x: ExactNum, scale: u32) -> Result<Decimal, LispError> {
+        check_scale(scale)?;
+        let scaled = x
+            .num
+            .checked_mul(pow10(scale))
+            .ok_or_else(|| LispError::new("decimal overflow"))?;
+        let mut mantissa = scaled / x.den;
+        if (scaled % x.den).abs() * 2 >= x.den {
+            mantissa += scaled.signum();
+        }
+        Decimal::from_i128(mantissa, scale)
+    }
+
+    /// The decimal exactly equal to x with at least min_scale places, None if
+    /// x does not terminate within MAX_DECIMAL_SCALE places (1/3).
+    pub fn exactly(x: ExactNum, min_scale: u32) -> Option<Decimal> {
+        (min_scale..=MAX_DECIMAL_SCALE).find_map(|scale| {
+            let scaled = x.num.checked_mul(pow10(scale))?;
+            if scaled % x.den == 0 {
+                Decimal::from_i128(scaled / x.den, scale).ok()
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        let digits = self.mantissa.unsigned_abs().to_string();
+        if self.scale == 0 {
+            return write!(f, "{}{}", sign, digits);
+        }
+        let scale = self.scale as usize;
+        let digits = format!("{:0>width$}", digits, width = scale + 1);
+        let (whole, frac) = digits.split_at(digits.len() - scale);
+        write!(f, "{}{}.{}", sign, whole, frac)
+    }
+}
+
+/// Any exact number as a reduced fraction (positive denominator), the common
+/// form for exact arithmetic and comparison.
+#[derive(Clone, Copy, Debug)]
+pub struct ExactNum {
+    num: i128,
+    den: i128,
+}
+
+impl ExactNum {
+    /// num/den in lowest terms, den can not be 0.
+    pub fn new(num: i128, den: i128) -> ExactNum {
+        let g = gcd(num, den).max(1) * den.signum();
+        ExactNum {
+            num: num / g,
+            den: den / g,
+        }
+    }
+
+    pub fn numerator(&self) -> i128 {
+        self.num
+    }
+
+    pub fn denominator(&self) -> i128 {
+        self.den
+    }
+
+    fn add(self, other: ExactNum) -> Result<ExactNum, LispError> {
+        let num = mul_i128(self.num, other.den)?
+            .checked_add(mul_i128(other.num, self.den)?)
+            .ok_or_else(rational_overflow)?;
+        Ok(ExactNum::new(num, mul_i128(self.den, other.den)?))
+    }
+
+    fn sub(self, other: ExactNum) -> Result<ExactNum, LispError> {
+        let num = mul_i128(self.num, other.den)?
+            .checked_sub(mul_i128(other.num, self.den)?)
+            .ok_or_else(rational_overflow)?;
+        Ok(ExactNum::new(num, mul_i128(self.den, other.den)?))
+    }
+
+    fn mul(self, other: ExactNum) -> Result<ExactNum, LispError> {
+        Ok(ExactNum::new(
+            mul_i128(self.num, other.num)?,
+            mul_i128(self.den, other.den)?,
+        ))
+    }
+
+    fn div(self, other: ExactNum) -> Result<ExactNum, LispError> {
+        Ok(ExactNum::new(
+            mul_i128(self.num, other.den)?,
+            mul_i128(self.den, other.num)?,
+        ))
+    }
+
+    /// An Int if whole else a Rational, error if it does not fit in an i64.
+    pub fn to_num(self) -> Result<Num, LispError> {
+        match (i64::try_from(self.num), i64::try_from(self.den)) {
+            (Ok(num), Ok(1)) => Ok(Num::Int(num)),
+            (Ok(num), Ok(den)) => Ok(Num::Rational(Rational { num, den })),
+            _ => Err(rational_overflow()),
+        }
+    }
+}
+
+impl PartialEq for ExactNum {
+    fn eq(&self, other: &ExactNum) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ExactNum {}
+
+impl PartialOrd for ExactNum {
+    fn partial_cmp(&self, other: &ExactNum) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ExactNum {
+    fn cmp(&self, other: &ExactNum) -> Ordering {
+        match (
+            self.num.checked_mul(other.den),
+            other.num.checked_mul(self.den),
+        ) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => cmp_fractions(self.num, self.den, other.num, other.den),
+        }
+    }
+}
+
+// Compare a/b with c/d (b and d positive) without overflow by comparing the
+// whole parts then the reciprocals of the remainders.
+fn cmp_fractions(a: i128, b: i128, c: i128, d: i128) -> Ordering {
+    let (whole_ab, whole_cd) = (a.div_euclid(b), c.div_euclid(d));
+    if whole_ab != whole_cd {
+        return whole_ab.cmp(&whole_cd);
+    }
+    match (a.rem_euclid(b), c.rem_euclid(d)) {
+        (0, 0) => Ordering::Equal,
+        (0, _) => Ordering::Less,
+        (_, 0) => Ordering::Greater,
+        (rem_ab, rem_cd) => cmp_fractions(d, rem_cd, b, rem_ab),
+    }
+}
+
+/// A number from the numeric tower (Int, Rational, Decimal, Float).  Mixed
+/// arithmetic gives a Float if any argument is a Float, else a Rational (or Int)
+/// if any is a Rational, else a Decimal if any is a Decimal.
+#[derive(Clone, Copy, Debug)]
+pub enum Num {
+    Int(i64),
+    Rational(Rational),
+    Decimal(Decimal),
+    Float(f64),
+}
+
+impl Num {
+    pub fn from_data(data: &ExpEnum) -> Option<Num> {
+        match data {
+            ExpEnum::Int(i) => Some(Num::Int(*i)),
+            ExpEnum::Rational(r) => Some(Num::Rational(*r)),
+            ExpEnum::Decimal(d) => Some(Num::Decimal(*d)),
+            ExpEnum::Float(f) => Some(Num::Float(*f)),
+            _ => None,
+        }
+    }
+
+    /// The number in exp (or the first of its values), None if not a number.
+    pub fn from_exp(exp: &Expression) -> Option<Num> {
+        match &exp.get().data {
+            ExpEnum::Values(v) if !v.is_empty() => Num::from_exp(&v[0]),
+            data => Num::from_data(data),
+        }
+    }
+
+    pub fn into_data(self) -> ExpEnum {
+        match self {
+            Num::Int(i) => ExpEnum::Int(i),
+            Num::Rational(r) => ExpEnum::Rational(r),
+            Num::Decimal(d) => ExpEnum::Decimal(d),
+            Num::Float(f) => ExpEnum::Float(f),
+        }
+    }
+
+    pub fn into_exp(self) -> Expression {
+        Expression::alloc_data(self.into_data())
+    }
+
+    /// The exact value, None for a Float.
+    pub fn exact(self) -> Option<ExactNum> {
+        match self {
+            Num::Int(i) => Some(ExactNum::new(i as i128, 1)),
+            Num::Rational(r) => Some(ExactNum::new(r.num as i128, r.den as i128)),
+            Num::Decimal(d) => Some(ExactNum::new(d.mantissa as i128, pow10(d.scale))),
+            Num::Float(_) => None,
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Num::Int(i) => i as f64,
+            Num::Rational(r) => r.num as f64 / r.den as f64,
+            Num::Decimal(d) => d.mantissa as f64 / pow10(d.scale) as f64,
+            Num::Float(f) => f,
+        }
+    }
+
+    fn to_decimal(self) -> Result<Decimal, LispError> {
+        match self {
+            Num::Decimal(d) => Ok(d),
+            Num::Int(i) => Ok(Decimal {
+                mantissa: i,
+                scale: 0,
+            }),
+            _ => Err(LispError::new("decimal: requires an Int or Decimal")),
+        }
+    }
+
+    fn scale(self) -> u32 {
+        match self {
+            Num::Decimal(d) => d.scale,
+            _ => 0,
+        }
+    }
+
+    fn is_zero(self) -> bool {
+        match self {
+            Num::Int(i) => i == 0,
+            Num::Decimal(d) => d.mantissa == 0,
+            Num::Float(f) => f == 0.0,
+            Num::Rational(_) => false,
+        }
+    }
+
+    fn arith(
+        self,
+        other: Num,
+        int_op: fn(i64, i64) -> i64,
+        float_op: fn(f64, f64) -> f64,
+        exact_op: fn(ExactNum, ExactNum) -> Result<ExactNum, LispError>,
+        decimal_op: fn(Decimal, Decimal) -> Result<Decimal, LispError>,
+    ) -> Result<Num, LispError> {
+        match (self, other) {
+            (Num::Int(a), Num::Int(b)) => Ok(Num::Int(int_op(a, b))),
+            (Num::Float(_), _) | (_, Num::Float(_)) => {
+                Ok(Num::Float(float_op(self.to_f64(), other.to_f64())))
+            }
+            (Num::Rational(_), _) | (_, Num::Rational(_)) => {
+                exact_op(self.exact().unwrap(), other.exact().unwrap())?.to_num()
+            }
+            _ => Ok(Num::Decimal(decimal_op(
+                self.to_decimal()?,
+                other.to_decimal()?,
+            )?)),
+        }
+    }
+
+    pub fn checked_add(self, other: Num) -> Result<Num, LispError> {
+        self.arith(
+            other,
+            |a, b| a + b,
+            |a, b| a + b,
+            ExactNum::add,
+            Decimal::add,
+        )
+    }
+
+    pub fn checked_sub(self, other: Num) -> Result<Num, LispError> {
+        self.arith(
+            other,
+            |a, b| a - b,
+            |a, b| a - b,
+            ExactNum::sub,
+            Decimal::sub,
+        )
+    }
+
+    pub fn checked_mul(self, other: Num) -> Result<Num, LispError> {
+        self.arith(
+            other,
+            |a, b| a * b,
+            |a, b| a * b,
+            ExactNum::mul,
+            Decimal::mul,
+        )
+    }
+
+    /// Divide, Ints truncate (use a Rational to get an exact result) and a
+    /// Decimal result is exact or becomes a Rational.
+    pub fn checked_div(self, other: Num) -> Result<Num, LispError> {
+        if other.is_zero() {
+            return Err(LispError::new(match other {
+                Num::Float(_) => "Can not divide by 0.0.",
+                _ => "Can not divide by 0.",
+            }));
+        }
+        match (self, other) {
+            (Num::Int(a), Num::Int(b)) => Ok(Num::Int(a / b)),
+            (Num::Float(_), _) | (_, Num::Float(_)) => {
+                Ok(Num::Float(self.to_f64() / other.to_f64()))
+            }
+            (Num::Rational(_), _) | (_, Num::Rational(_)) => {
+                self.exact().unwrap().div(other.exact().unwrap())?.to_num()
+            }
+            _ => {
+                let res = self.exact().unwrap().div(other.exact().unwrap())?;
+                match Decimal::exactly(res, self.scale().max(other.scale())) {
+                    Some(d) => Ok(Num::Decimal(d)),
+                    None => res.to_num(),
+                }
+            }
+        }
+    }
+
+    pub fn negate(self) -> Result<Num, LispError> {
+        match self {
+            Num::Int(i) => i
+                .checked_neg()
+                .map(Num::Int)
+                .ok_or_else(|| LispError::new("integer overflow")),
+            Num::Rational(r) => r
+                .num
+                .checked_neg()
+                .map(|num| Num::Rational(Rational { num, den: r.den }))
+                .ok_or_else(rational_overflow),
+            Num::Decimal(d) => d
+                .mantissa
+                .checked_neg()
+                .map(|mantissa| {
+                    Num::Decimal(Decimal {
+                        mantissa,
+                        scale: d.scale,
+                    })
+                })
+                .ok_or_else(|| LispError::new("decimal overflow")),
+            Num::Float(f) => Ok(Num::Float(-f)),
+        }
+    }
+
+    // Position in the tower, orders equal values of different types.
+    fn rank(self) -> u32 {
+        match self {
+            Num::Int(_) => 0,
+            Num::Rational(_) => 1,
+            Num::Decimal(_) => 2,
+            Num::Float(_) => 3,
+        }
+    }
+
+    /// Total order by value (exactly unless a Float is involved), equal values
+    /// order Int, Rational, Decimal (fewer places first) then Float.
+    pub fn total_cmp(self, other: Num) -> Ordering {
+        let value = match (self.exact(), other.exact()) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => self.to_f64().total_cmp(&other.to_f64()),
+        };
+        value
+            .then(self.rank().cmp(&other.rank()))
+            .then(self.scale().cmp(&other.scale()))
+    }
+}
+
+/// The exact value of a float's shortest decimal form (0.1 is 1/10), None if
+/// that form needs an exponent.
+pub fn float_exact(f: f64) -> Option<ExactNum> {
+    Decimal::parse(&format!("{:?}", f)).and_then(|d| Num::Decimal(d).exact())
+}
+
+/// Parse the text of a rational (2/4 from #r2/4), None if it is not one.  A
+/// whole value (4/2) is an Int.
+pub fn parse_rational(text: &str) -> Option<Num> {
+    let (num, den) = text.split_once('/')?;
+    if den.is_empty() || !den.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let num: i64 = num.parse().ok()?;
+    let den: i64 = den.parse().ok()?;
+    if den == 0 {
+        None
+    } else {
+        ExactNum::new(num as i128, den as i128).to_num().ok()
+    }
+}
+
+/// Read a bare n/d literal (1/3, -2/5).  Only the form a Rational prints as
+/// (lowest terms, no leading zeros) is a literal, so a word like 2021/06 or
+/// 10/4 stays a symbol and a literal passed to a command is unchanged.
+pub fn read_rational_literal(text: &str) -> Option<Num> {
+    match parse_rational(text)? {
+        Num::Rational(r) if r.to_string() == text => Some(Num::Rational(r)),
+        _ => None,
+    }
+}
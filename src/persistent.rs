@@ -0,0 +1,432 @@
+// Persistent (immutable, structure sharing) vector and hash map.  Updates
+// return a new version that shares all unchanged nodes with the old one so a
+// change costs O(log32 n) node copies and old versions stay valid.
+
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::types::Expression;
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Clone, Debug)]
+enum VecNode {
+    Leaf(Rc<Vec<Expression>>),
+    Branch(Rc<Vec<VecNode>>),
+}
+
+/// A persistent vector, a 32 way trie with values in the leaves.
+#[derive(Clone, Debug)]
+pub struct PVec {
+    root: VecNode,
+    len: usize,
+    // Bits of the index consumed above the leaves (0 when the root is a leaf).
+    shift: u32,
+}
+
+impl Default for PVec {
+    fn default() -> Self {
+        PVec::new()
+    }
+}
+
+fn new_path(shift: u32, val: Expression) -> VecNode {
+    if shift == 0 {
+        VecNode::Leaf(Rc::new(vec![val]))
+    } else {
+        VecNode::Branch(Rc::new(vec![new_path(shift - BITS, val)]))
+    }
+}
+
+fn push_into(node: &VecNode, shift: u32, idx: usize, val: Expression) -> VecNode {
+    match node {
+        VecNode::Leaf(items) => {
+            let mut items = (**items).clone();
+            items.push(val);
+            VecNode::Leaf(Rc::new(items))
+        }
+        VecNode::Branch(children) => {
+            let mut children = (**children).clone();
+            let sub = (idx >> shift) & MASK;
+            if sub < children.len() {
+                children[sub] = push_into(&children[sub], shift - BITS, idx, val);
+            } else {
+                children.push(new_path(shift - BITS, val));
+            }
+            VecNode::Branch(Rc::new(children))
+        }
+    }
+}
+
+fn set_in(node: &VecNode, shift: u32, idx: usize, val: Expression) -> VecNode {
+    match node {
+        VecNode::Leaf(items) => {
+            let mut items = (**items).clone();
+            items[idx & MASK] = val;
+            VecNode::Leaf(Rc::new(items))
+        }
+        VecNode::Branch(children) => {
+            let mut children = (**children).clone();
+            let sub = (idx >> shift) & MASK;
+            children[sub] = set_in(&children[sub], shift - BITS, idx, val);
+            VecNode::Branch(Rc::new(children))
+        }
+    }
+}
+
+// Remove the last item (at idx), None if that leaves node empty.
+fn pop_from(node: &VecNode, shift: u32, idx: usize) -> Option<VecNode> {
+    match node {
+        VecNode::Leaf(items) => {
+            if items.len() == 1 {
+                return None;
+            }
+            let mut items = (**items).clone();
+            items.pop();
+            Some(VecNode::Leaf(Rc::new(items)))
+        }
+        VecNode::Branch(children) => {
+            let mut children = (**children).clone();
+            let sub = (idx >> shift) & MASK;
+            match pop_from(&children[sub], shift - BITS, idx) {
+                Some(child) => children[sub] = child,
+                None => {
+                    children.pop();
+                }
+            }
+            if children.is_empty() {
+                None
+            } else {
+                Some(VecNode::Branch(Rc::new(children)))
+            }
+        }
+    }
+}
+
+fn collect_vec(node: &VecNode, out: &mut Vec<Expression>) {
+    match node {
+        VecNode::Leaf(items) => out.extend(items.iter().cloned()),
+        VecNode::Branch(children) => {
+            for child in children.iter() {
+                collect_vec(child, out);
+            }
+        }
+    }
+}
+
+impl PVec {
+    pub fn new() -> Self {
+        PVec {
+            root: VecNode::Leaf(Rc::new(Vec::new())),
+            len: 0,
+            shift: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Expression> {
+        if idx >= self.len {
+            return None;
+        }
+        let mut node = &self.root;
+        let mut shift = self.shift;
+        loop {
+            match node {
+                VecNode::Leaf(items) => return items.get(idx & MASK),
+                VecNode::Branch(children) => {
+                    node = &children[(idx >> shift) & MASK];
+                    shift -= BITS;
+                }
+            }
+        }
+    }
+
+    /// A new vector with val appended.
+    pub fn push(&self, val: Expression) -> Self {
+        if self.len == WIDTH << self.shift {
+            // Root is full, grow a level.
+            let shift = self.shift + BITS;
+            let root = VecNode::Branch(Rc::new(vec![self.root.clone(), new_path(self.shift, val)]));
+            PVec {
+                root,
+                len: self.len + 1,
+                shift,
+            }
+        } else {
+            PVec {
+                root: push_into(&self.root, self.shift, self.len, val),
+                len: self.len + 1,
+                shift: self.shift,
+            }
+        }
+    }
+
+    /// A new vector with the item at idx replaced by val, idx must be < len.
+    pub fn set(&self, idx: usize, val: Expression) -> Self {
+        PVec {
+            root: set_in(&self.root, self.shift, idx, val),
+            len: self.len,
+            shift: self.shift,
+        }
+    }
+
+    /// A new vector without the last item.
+    pub fn pop(&self) -> Self {
+        if self.len <= 1 {
+            return PVec::new();
+        }
+        let mut root = pop_from(&self.root, self.shift, self.len - 1)
+            .expect("pop of non-empty vector left it empty");
+        let mut shift = self.shift;
+        // Drop a level once the root only has one child.
+        loop {
+            let child = match &root {
+                VecNode::Branch(children) if children.len() == 1 => children[0].clone(),
+                _ => break,
+            };
+            root = child;
+            shift -= BITS;
+        }
+        PVec {
+            root,
+            len: self.len - 1,
+            shift,
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<Expression> {
+        let mut out = Vec::with_capacity(self.len);
+        collect_vec(&self.root, &mut out);
+        out
+    }
+}
+
+impl From<Vec<Expression>> for PVec {
+    fn from(items: Vec<Expression>) -> Self {
+        let mut pvec = PVec::new();
+        for item in items {
+            pvec = pvec.push(item);
+        }
+        pvec
+    }
+}
+
+#[derive(Clone, Debug)]
+enum MapEntry {
+    Leaf(u64, Cow<'static, str>, Expression),
+    // Keys with the same full hash.
+    Collision(u64, Rc<Vec<(Cow<'static, str>, Expression)>>),
+    Node(Rc<MapNode>),
+}
+
+// A hash array mapped trie node, bitmap has a bit set for each of the 32
+// slots in use and entries holds them in slot order.
+#[derive(Clone, Debug, Default)]
+struct MapNode {
+    bitmap: u32,
+    entries: Vec<MapEntry>,
+}
+
+/// A persistent hash map (a hash array mapped trie) keyed like a HashMap
+/// expression.
+#[derive(Clone, Debug, Default)]
+pub struct PMap {
+    root: Rc<MapNode>,
+    len: usize,
+}
+
+fn key_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl MapNode {
+    fn slot(&self, hash: u64, shift: u32) -> (u32, usize) {
+        let bit = 1 << ((hash >> shift) as usize & MASK);
+        (bit, (self.bitmap & (bit - 1)).count_ones() as usize)
+    }
+
+    fn get(&self, hash: u64, shift: u32, key: &str) -> Option<&Expression> {
+        let (bit, pos) = self.slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        match &self.entries[pos] {
+            MapEntry::Leaf(_, k, v) if k == key => Some(v),
+            MapEntry::Leaf(_, _, _) => None,
+            MapEntry::Collision(_, items) => items.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            MapEntry::Node(node) => node.get(hash, shift + BITS, key),
+        }
+    }
+
+    // A copy of this node with key set to val, the bool is true if key is new.
+    fn insert(
+        &self,
+        hash: u64,
+        shift: u32,
+        key: Cow<'static, str>,
+        val: Expression,
+    ) -> (MapNode, bool) {
+        let mut node = self.clone();
+        let (bit, pos) = self.slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            node.bitmap |= bit;
+            node.entries.insert(pos, MapEntry::Leaf(hash, key, val));
+            return (node, true);
+        }
+        let (entry, added) = match &self.entries[pos] {
+            MapEntry::Leaf(h, k, _) if *k == key => (MapEntry::Leaf(*h, key, val), false),
+            MapEntry::Leaf(h, k, v) if *h == hash => (
+                MapEntry::Collision(hash, Rc::new(vec![(k.clone(), v.clone()), (key, val)])),
+                true,
+            ),
+            MapEntry::Collision(h, items) if *h == hash => {
+                let mut items = (**items).clone();
+                let added = match items.iter_mut().find(|(k, _)| *k == key) {
+                    Some(item) => {
+                        item.1 = val;
+                        false
+                    }
+                    None => {
+                        items.push((key, val));
+                        true
+                    }
+                };
+                (MapEntry::Collision(hash, Rc::new(items)), added)
+            }
+            MapEntry::Node(sub) => {
+                let (sub, added) = sub.insert(hash, shift + BITS, key, val);
+                (MapEntry::Node(Rc::new(sub)), added)
+            }
+            other => {
+                // Different hashes in the same slot, push both down a level.
+                let other_hash = match other {
+                    MapEntry::Leaf(h, _, _) | MapEntry::Collision(h, _) => *h,
+                    MapEntry::Node(_) => unreachable!(),
+                };
+                let mut sub = MapNode::default();
+                let (other_bit, _) = sub.slot(other_hash, shift + BITS);
+                sub.bitmap = other_bit;
+                sub.entries.push(other.clone());
+                let (sub, _) = sub.insert(hash, shift + BITS, key, val);
+                (MapEntry::Node(Rc::new(sub)), true)
+            }
+        };
+        node.entries[pos] = entry;
+        (node, added)
+    }
+
+    // A copy of this node without key, None if key is not in the node.
+    fn remove(&self, hash: u64, shift: u32, key: &str) -> Option<MapNode> {
+        let (bit, pos) = self.slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        let entry = match &self.entries[pos] {
+            MapEntry::Leaf(_, k, _) if k == key => None,
+            MapEntry::Leaf(_, _, _) => return None,
+            MapEntry::Collision(h, items) => {
+                let mut items = (**items).clone();
+                let idx = items.iter().position(|(k, _)| k == key)?;
+                items.remove(idx);
+                if items.len() == 1 {
+                    let (k, v) = items.pop().unwrap();
+                    Some(MapEntry::Leaf(*h, k, v))
+                } else {
+                    Some(MapEntry::Collision(*h, Rc::new(items)))
+                }
+            }
+            MapEntry::Node(sub) => {
+                let sub = sub.remove(hash, shift + BITS, key)?;
+                if sub.entries.is_empty() {
+                    None
+                } else if sub.entries.len() == 1 && !matches!(sub.entries[0], MapEntry::Node(_)) {
+                    // Pull a lone leaf back up.
+                    Some(sub.entries[0].clone())
+                } else {
+                    Some(MapEntry::Node(Rc::new(sub)))
+                }
+            }
+        };
+        let mut node = self.clone();
+        match entry {
+            Some(entry) => node.entries[pos] = entry,
+            None => {
+                node.bitmap &= !bit;
+                node.entries.remove(pos);
+            }
+        }
+        Some(node)
+    }
+
+    fn collect(&self, out: &mut Vec<(Cow<'static, str>, Expression)>) {
+        for entry in &self.entries {
+            match entry {
+                MapEntry::Leaf(_, k, v) => out.push((k.clone(), v.clone())),
+                MapEntry::Collision(_, items) => out.extend(items.iter().cloned()),
+                MapEntry::Node(node) => node.collect(out),
+            }
+        }
+    }
+}
+
+impl PMap {
+    pub fn new() -> Self {
+        PMap::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Expression> {
+        self.root.get(key_hash(key), 0, key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// A new map with key set to val.
+    pub fn insert(&self, key: Cow<'static, str>, val: Expression) -> Self {
+        let (root, added) = self.root.insert(key_hash(&key), 0, key, val);
+        PMap {
+            root: Rc::new(root),
+            len: if added { self.len + 1 } else { self.len },
+        }
+    }
+
+    /// A new map without key (shares this one if key is not in it).
+    pub fn remove(&self, key: &str) -> Self {
+        match self.root.remove(key_hash(key), 0, key) {
+            Some(root) => PMap {
+                root: Rc::new(root),
+                len: self.len - 1,
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// The key/value pairs in no particular order.
+    pub fn entries(&self) -> Vec<(Cow<'static, str>, Expression)> {
+        let mut out = Vec::with_capacity(self.len);
+        self.root.collect(&mut out);
+        out
+    }
+}
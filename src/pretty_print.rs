@@ -1,7 +1,11 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::Write as _;
 use std::io::{self, Write};
 
+use crate::builtins_hashmap::{display_key, get_text_key, key_kind, KeyKind};
+use crate::builtins_set::sorted_members;
 use crate::builtins_util::is_proper_list;
 use crate::environment::*;
 use crate::types::*;
@@ -57,6 +61,8 @@ impl fmt::Display for Expression {
             ExpEnum::False => write!(f, "false"),
             ExpEnum::Float(n) => write!(f, "{}", n),
             ExpEnum::Int(i) => write!(f, "{}", i),
+            ExpEnum::Rational(r) => write!(f, "{}", r),
+            ExpEnum::Decimal(d) => write!(f, "{}", d),
             ExpEnum::Symbol(s, _) => write!(f, "{}", s),
             ExpEnum::String(s, _) => write!(f, "\"{}\"", s),
             ExpEnum::Char(c) => write!(f, "#\\{}", c),
@@ -64,14 +70,67 @@ impl fmt::Display for Expression {
                 write!(f, "#/{}/", c.to_string().replace('/', "\\/"))
             }
             ExpEnum::CodePoint(c) => write!(f, "#{}", c.escape_unicode()),
+            ExpEnum::Bytes(b) => {
+                f.write_str("#u8(")?;
+                for (i, byte) in b.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{}", byte)?;
+                }
+                f.write_str(")")
+            }
             ExpEnum::Lambda(l) => lambda_out(f, l),
             ExpEnum::Macro(m) => lambda_out(f, m),
             ExpEnum::Process(ProcessState::Running(pid)) => write!(f, "#<PID: {} Running>", pid),
-            ExpEnum::Process(ProcessState::Over(pid, exit_status)) => write!(
-                f,
-                "#<PID: {}, EXIT STATUS: {},  Complete>",
-                pid, exit_status
-            ),
+            ExpEnum::Process(ProcessState::Over(pid, status)) => match status.signal_name() {
+                Some(signal) => write!(
+                    f,
+                    "#<PID: {}, SIGNAL: {}{},  Complete>",
+                    pid,
+                    signal,
+                    if status.core_dumped {
+                        " (core dumped)"
+                    } else {
+                        ""
+                    }
+                ),
+                None => write!(
+                    f,
+                    "#<PID: {}, EXIT STATUS: {},  Complete>",
+                    pid, status.exit_status
+                ),
+            },
+            ExpEnum::Pty(pty) => {
+                let pty = pty.borrow();
+                if pty.is_closed() {
+                    write!(f, "#<PTY: {} Closed>", pty.pid())
+                } else {
+                    write!(f, "#<PTY: {}>", pty.pid())
+                }
+            }
+            ExpEnum::Rng(_) => write!(f, "#<RNG>"),
+            ExpEnum::FsWatcher(watcher) => {
+                let watcher = watcher.borrow();
+                if watcher.is_closed() {
+                    write!(f, "#<FS-WATCHER: {} Closed>", watcher.root().display())
+                } else {
+                    write!(f, "#<FS-WATCHER: {}>", watcher.root().display())
+                }
+            }
+            ExpEnum::RecordType(t) => write!(f, "#<RECORD-TYPE: {}>", t.name()),
+            // Only the fields that can be read with :field are shown.
+            ExpEnum::Record(r) => {
+                let rtype = r.record_type();
+                write!(f, "#<{}", rtype.name())?;
+                for (idx, (name, val)) in rtype.fields().iter().zip(r.fields()).enumerate() {
+                    if rtype.is_readable(idx) {
+                        write!(f, " :{} {}", name, val.get())?;
+                    }
+                }
+                f.write_str(">")
+            }
+            ExpEnum::Iterator(_) => write!(f, "#<ITERATOR>"),
             ExpEnum::Function(_) => write!(f, "#<Function>"),
             ExpEnum::Vector(_) => {
                 let mut res = String::new();
@@ -152,11 +211,39 @@ impl fmt::Display for Expression {
                 let mut res = String::new();
                 res.push_str("(make-hash (");
                 for (key, val) in map.iter() {
-                    write!(res, "({} . {})", key, val)?;
+                    write!(res, "({} . {})", display_key(key), val)?;
                 }
                 res.push_str("))");
                 write!(f, "{}", res)
             }
+            ExpEnum::Set(set) => {
+                let mut res = String::new();
+                res.push_str("#s(");
+                list_out(&mut res, &mut sorted_members(set).into_iter());
+                res.push(')');
+                write!(f, "{}", res)
+            }
+            ExpEnum::PVec(v) => {
+                let mut res = String::new();
+                res.push_str("(persistent-vec");
+                for item in v.to_vec() {
+                    write!(res, " {}", item)?;
+                }
+                res.push(')');
+                write!(f, "{}", res)
+            }
+            ExpEnum::PMap(map) => {
+                // Sorted so the output is stable.
+                let mut entries = map.entries();
+                entries.sort_by(|(x, _), (y, _)| x.cmp(y));
+                let mut res = String::new();
+                res.push_str("(persistent-map");
+                for (key, val) in entries {
+                    write!(res, " {} {}", display_key(&key), val)?;
+                }
+                res.push(')');
+                write!(f, "{}", res)
+            }
             ExpEnum::File(file) => match &*file.borrow() {
                 FileState::Stdout => write!(f, "#<STDOUT>"),
                 FileState::Stderr => write!(f, "#<STDERR>"),
@@ -261,7 +348,7 @@ fn pretty_print_int(
                 writer.write_all(b"(make-hash (")?;
                 for (key, val) in map.iter() {
                     init_space(indent + 1, writer)?;
-                    write!(writer, "({} . {})", key, val)?;
+                    write!(writer, "({} . {})", display_key(key), val)?;
                 }
                 write!(writer, "))")?;
             }
@@ -272,6 +359,15 @@ fn pretty_print_int(
         ExpEnum::Regex(_) => {
             write!(writer, "{}", expression)?;
         }
+        ExpEnum::Bytes(_) => {
+            write!(writer, "{}", expression)?;
+        }
+        ExpEnum::Set(_) => {
+            write!(writer, "{}", expression)?;
+        }
+        ExpEnum::PVec(_) | ExpEnum::PMap(_) => {
+            write!(writer, "{}", expression)?;
+        }
         ExpEnum::Char(_c) => {
             write!(writer, "{}", expression)?;
         }
@@ -319,10 +415,18 @@ fn pretty_print_int(
         ExpEnum::False => expression.writef(environment, writer)?,
         ExpEnum::Float(_) => expression.writef(environment, writer)?,
         ExpEnum::Int(_) => expression.writef(environment, writer)?,
+        ExpEnum::Rational(_) => expression.writef(environment, writer)?,
+        ExpEnum::Decimal(_) => expression.writef(environment, writer)?,
         ExpEnum::Symbol(_, _) => expression.writef(environment, writer)?,
         ExpEnum::Function(_) => expression.writef(environment, writer)?,
         ExpEnum::LazyFn(_, _) => expression.writef(environment, writer)?,
         ExpEnum::Process(_) => expression.writef(environment, writer)?,
+        ExpEnum::Pty(_) => expression.writef(environment, writer)?,
+        ExpEnum::FsWatcher(_) => expression.writef(environment, writer)?,
+        ExpEnum::Rng(_) => expression.writef(environment, writer)?,
+        ExpEnum::RecordType(_) => expression.writef(environment, writer)?,
+        ExpEnum::Record(_) => expression.writef(environment, writer)?,
+        ExpEnum::Iterator(_) => expression.writef(environment, writer)?,
         ExpEnum::File(_) => expression.writef(environment, writer)?,
         ExpEnum::DeclareDef => expression.writef(environment, writer)?,
         ExpEnum::DeclareVar => expression.writef(environment, writer)?,
@@ -351,3 +455,398 @@ pub fn pretty_print(
     let mut handle = stdout.lock();
     pretty_print_int(expression, environment, 0, &mut handle)
 }
+
+fn readable_char(ch: &str, res: &mut String) {
+    match ch {
+        " " => res.push_str("#\\space"),
+        "\t" => res.push_str("#\\tab"),
+        "\n" => res.push_str("#\\newline"),
+        "\r" => res.push_str("#\\return"),
+        "\u{0008}" => res.push_str("#\\backspace"),
+        _ => {
+            let mut chars = ch.chars();
+            match (chars.next(), chars.next()) {
+                // Delimiters and control chars will not read back as is.
+                (Some(c), None) if c.is_ascii_control() || "()#\"';`,".contains(c) => {
+                    let _ = write!(res, "#\\x{:02x}", c as u32);
+                }
+                (Some(c), None) if c.is_whitespace() || c.is_control() => {
+                    let _ = write!(res, "#\\u{{{:x}}}", c as u32);
+                }
+                _ => {
+                    res.push_str("#\\");
+                    res.push_str(ch);
+                }
+            }
+        }
+    }
+}
+
+fn readable_str(s: &str, str_read_table: Option<&Expression>, res: &mut String) {
+    res.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            _ if ch.is_ascii_control() => {
+                let _ = write!(res, "\\x{:02x}", ch as u32);
+            }
+            _ if ch.is_control() => {
+                let _ = write!(res, "\\u{{{:x}}}", ch as u32);
+            }
+            _ => {
+                // Escape anything that would trigger a string reader macro ($ for instance).
+                if let Some(table) = str_read_table {
+                    if let ExpEnum::HashMap(map) = &table.get().data {
+                        let mut buf = [0; 4];
+                        if get_text_key(map, ch.encode_utf8(&mut buf)).is_some() {
+                            res.push('\\');
+                        }
+                    }
+                }
+                res.push(ch);
+            }
+        }
+    }
+    res.push('"');
+}
+
+// Mark every container that is reachable from itself (a cycle made with xdr!,
+// vec-set! or hash-set!) so it can be written with a #n= label.
+fn readable_cycles(
+    expression: &Expression,
+    on_path: &mut HashSet<usize>,
+    done: &mut HashSet<usize>,
+    labels: &mut HashMap<usize, Option<usize>>,
+) {
+    let id = expression.id();
+    if on_path.contains(&id) {
+        labels.insert(id, None);
+        return;
+    }
+    if done.contains(&id) {
+        return;
+    }
+    match &expression.get().data {
+        ExpEnum::Pair(_, _) => {
+            // Walk the cdrs in a loop so long lists do not recurse deeply.
+            let mut chain = Vec::new();
+            let mut cur = expression.clone();
+            loop {
+                let cur_id = cur.id();
+                on_path.insert(cur_id);
+                chain.push(cur_id);
+                let (car, cdr) = if let ExpEnum::Pair(car, cdr) = &cur.get().data {
+                    (car.clone(), cdr.clone())
+                } else {
+                    break;
+                };
+                readable_cycles(&car, on_path, done, labels);
+                let cdr_id = cdr.id();
+                if !matches!(cdr.get().data, ExpEnum::Pair(_, _)) {
+                    readable_cycles(&cdr, on_path, done, labels);
+                    break;
+                }
+                if on_path.contains(&cdr_id) {
+                    labels.insert(cdr_id, None);
+                    break;
+                }
+                if done.contains(&cdr_id) {
+                    break;
+                }
+                cur = cdr;
+            }
+            for id in chain {
+                on_path.remove(&id);
+                done.insert(id);
+            }
+        }
+        ExpEnum::Vector(list) => {
+            on_path.insert(id);
+            for item in list {
+                readable_cycles(item, on_path, done, labels);
+            }
+            on_path.remove(&id);
+            done.insert(id);
+        }
+        ExpEnum::HashMap(map) => {
+            on_path.insert(id);
+            for item in map.values() {
+                readable_cycles(item, on_path, done, labels);
+            }
+            on_path.remove(&id);
+            done.insert(id);
+        }
+        ExpEnum::PVec(v) => {
+            on_path.insert(id);
+            for item in v.to_vec() {
+                readable_cycles(&item, on_path, done, labels);
+            }
+            on_path.remove(&id);
+            done.insert(id);
+        }
+        ExpEnum::PMap(map) => {
+            on_path.insert(id);
+            for (_, item) in map.entries() {
+                readable_cycles(&item, on_path, done, labels);
+            }
+            on_path.remove(&id);
+            done.insert(id);
+        }
+        ExpEnum::Record(r) => {
+            on_path.insert(id);
+            for field in r.fields() {
+                readable_cycles(&field.get(), on_path, done, labels);
+            }
+            on_path.remove(&id);
+            done.insert(id);
+        }
+        ExpEnum::Values(v) if !v.is_empty() => readable_cycles(&v[0], on_path, done, labels),
+        ExpEnum::Wrapper(exp) => readable_cycles(exp, on_path, done, labels),
+        _ => {}
+    }
+}
+
+fn readable_symbol(sym: &str, res: &mut String) -> Result<(), LispError> {
+    // A symbol that would read back as something else (or not at all).
+    if sym.is_empty()
+        || sym.starts_with('#')
+        || sym.parse::<i64>().is_ok()
+        || (sym.parse::<f64>().is_ok() && sym.chars().any(|c| c.is_ascii_digit()))
+        || sym
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "()\"';`,".contains(c))
+    {
+        return Err(LispError::new(format!(
+            "symbol {} has no readable form",
+            sym
+        )));
+    }
+    res.push_str(sym);
+    Ok(())
+}
+
+struct Readable<'a> {
+    str_read_table: Option<&'a Expression>,
+    labels: HashMap<usize, Option<usize>>,
+    next_label: usize,
+}
+
+impl<'a> Readable<'a> {
+    fn list_out(
+        &mut self,
+        itr: &mut dyn Iterator<Item = Expression>,
+        res: &mut String,
+    ) -> Result<(), LispError> {
+        let mut first = true;
+        for p in itr {
+            if !first {
+                res.push(' ');
+            } else {
+                first = false;
+            }
+            self.write(&p, res)?;
+        }
+        Ok(())
+    }
+
+    // Write the #n= label the first time a cyclic container is seen and return
+    // true if it was already written (only the #n# reference was needed).
+    fn label(&mut self, expression: &Expression, res: &mut String) -> bool {
+        let id = expression.id();
+        match self.labels.get(&id) {
+            Some(Some(n)) => {
+                let _ = write!(res, "#{}#", n);
+                true
+            }
+            Some(None) => {
+                self.next_label += 1;
+                self.labels.insert(id, Some(self.next_label));
+                let _ = write!(res, "#{}=", self.next_label);
+                false
+            }
+            None => false,
+        }
+    }
+
+    // Write a hash map (or persistent map) key.
+    fn key(&self, key: &str, res: &mut String) {
+        match key_kind(key) {
+            KeyKind::Text(text) => readable_str(text, self.str_read_table, res),
+            // Already in readable form.
+            KeyKind::Value(text) => res.push_str(text),
+        }
+    }
+
+    fn write(&mut self, expression: &Expression, res: &mut String) -> Result<(), LispError> {
+        match &expression.get().data {
+            ExpEnum::True => res.push_str("#t"),
+            ExpEnum::False => res.push_str("#f"),
+            ExpEnum::Nil => res.push_str("nil"),
+            ExpEnum::Int(i) => {
+                let _ = write!(res, "{}", i);
+            }
+            // Debug keeps the decimal point (1.0 not 1) so it reads back as a float.
+            ExpEnum::Float(f) if f.is_finite() => {
+                let _ = write!(res, "{:?}", f);
+            }
+            ExpEnum::Rational(r) => {
+                let _ = write!(res, "{}", r);
+            }
+            ExpEnum::Decimal(d) => {
+                let _ = write!(res, "#d{}", d);
+            }
+            ExpEnum::Symbol(s, _) => readable_symbol(s, res)?,
+            ExpEnum::String(s, _) => readable_str(s, self.str_read_table, res),
+            ExpEnum::Char(c) => readable_char(c, res),
+            ExpEnum::CodePoint(c) => {
+                let _ = write!(res, "#\\u{{{:x}}}", *c as u32);
+            }
+            ExpEnum::Regex(r) => {
+                let _ = write!(res, "#/{}/", r.to_string().replace('/', "\\/"));
+            }
+            ExpEnum::Bytes(_) => {
+                let _ = write!(res, "{}", expression);
+            }
+            ExpEnum::Vector(_) => {
+                if self.label(expression, res) {
+                    return Ok(());
+                }
+                res.push_str("#(");
+                self.list_out(&mut expression.iter(), res)?;
+                res.push(')');
+            }
+            ExpEnum::Set(set) => {
+                res.push_str("#s(");
+                self.list_out(&mut sorted_members(set).into_iter(), res)?;
+                res.push(')');
+            }
+            ExpEnum::Pair(car, cdr) => {
+                if self.label(expression, res) {
+                    return Ok(());
+                }
+                res.push('(');
+                self.write(car, res)?;
+                let mut cur = cdr.clone();
+                loop {
+                    let next = match &cur.get().data {
+                        ExpEnum::Nil => break,
+                        ExpEnum::Pair(car, cdr) if !self.labels.contains_key(&cur.id()) => {
+                            res.push(' ');
+                            self.write(car, res)?;
+                            cdr.clone()
+                        }
+                        _ => {
+                            res.push_str(" . ");
+                            self.write(&cur, res)?;
+                            break;
+                        }
+                    };
+                    cur = next;
+                }
+                res.push(')');
+            }
+            ExpEnum::HashMap(map) => {
+                if self.label(expression, res) {
+                    return Ok(());
+                }
+                // Sorted so the output is stable.
+                let mut keys: Vec<&Cow<'static, str>> = map.keys().collect();
+                keys.sort();
+                res.push_str("#h(");
+                let mut first = true;
+                for key in keys {
+                    if !first {
+                        res.push(' ');
+                    } else {
+                        first = false;
+                    }
+                    self.key(key, res);
+                    res.push(' ');
+                    self.write(&map[key], res)?;
+                }
+                res.push(')');
+            }
+            ExpEnum::PVec(v) => {
+                if self.label(expression, res) {
+                    return Ok(());
+                }
+                res.push_str("#pv(");
+                self.list_out(&mut v.to_vec().into_iter(), res)?;
+                res.push(')');
+            }
+            ExpEnum::PMap(map) => {
+                if self.label(expression, res) {
+                    return Ok(());
+                }
+                // Sorted so the output is stable.
+                let mut entries = map.entries();
+                entries.sort_by(|(x, _), (y, _)| x.cmp(y));
+                res.push_str("#pm(");
+                let mut first = true;
+                for (key, val) in entries {
+                    if !first {
+                        res.push(' ');
+                    } else {
+                        first = false;
+                    }
+                    self.key(&key, res);
+                    res.push(' ');
+                    self.write(&val, res)?;
+                }
+                res.push(')');
+            }
+            // All the fields (private ones too) so it reads back as an equal record.
+            ExpEnum::Record(r) => {
+                if self.label(expression, res) {
+                    return Ok(());
+                }
+                let rtype = r.record_type();
+                res.push_str("#S(");
+                readable_symbol(rtype.full_name(), res)?;
+                for (name, val) in rtype.fields().iter().zip(r.fields()) {
+                    let _ = write!(res, " :{} ", name);
+                    self.write(&val.get(), res)?;
+                }
+                res.push(')');
+            }
+            ExpEnum::Values(v) if !v.is_empty() => self.write(&v[0], res)?,
+            ExpEnum::Wrapper(exp) => self.write(exp, res)?,
+            _ => {
+                return Err(LispError::new(format!(
+                    "{} has no readable form",
+                    expression.display_type()
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Produce a string that the reader will turn back into an equal value.
+/// Values that can not be read back (processes, files, functions, etc) are
+/// an error.
+pub fn readable_string(
+    expression: &Expression,
+    environment: &Environment,
+) -> Result<String, LispError> {
+    let str_read_table = lookup_expression(environment, "*string-read-table*");
+    let mut labels = HashMap::new();
+    readable_cycles(
+        expression,
+        &mut HashSet::new(),
+        &mut HashSet::new(),
+        &mut labels,
+    );
+    let mut readable = Readable {
+        str_read_table: str_read_table.as_ref(),
+        labels,
+        next_label: 0,
+    };
+    let mut res = String::new();
+    readable.write(expression, &mut res)?;
+    Ok(res)
+}
@@ -2,12 +2,14 @@ use std::env;
 use std::fmt::Write as _;
 use std::io;
 use std::os::unix::io::AsRawFd;
+use std::time::Duration;
 
 use nix::{
+    libc,
     sys::{
         signal::{kill, Signal},
         termios,
-        wait::{self, WaitPidFlag, WaitStatus},
+        wait::{WaitPidFlag, WaitStatus},
     },
     unistd::{self, Pid},
 };
@@ -18,11 +20,40 @@ use crate::signals::test_clear_sigint;
 use crate::types::*;
 use crate::unix::*;
 
-pub fn try_wait_pid(environment: &Environment, pid: u32) -> (bool, Option<i32>) {
+fn timeval_to_duration(tv: &libc::timeval) -> Duration {
+    Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+}
+
+fn process_status(status: WaitStatus, usage: &libc::rusage) -> Option<ProcessStatus> {
+    let (exit_status, signal, core_dumped) = match status {
+        WaitStatus::Exited(_, code) => (code, None, false),
+        WaitStatus::Signaled(_, sig, core_dumped) => {
+            (128 + sig as i32, Some(sig as i32), core_dumped)
+        }
+        _ => return None,
+    };
+    // Linux reports max rss in kilobytes, Mac OS in bytes.
+    #[allow(clippy::unnecessary_cast)] // c_long is not i64 on all targets.
+    let max_rss = if cfg!(target_os = "macos") {
+        usage.ru_maxrss as i64 / 1024
+    } else {
+        usage.ru_maxrss as i64
+    };
+    Some(ProcessStatus {
+        exit_status,
+        signal,
+        core_dumped,
+        user_time: timeval_to_duration(&usage.ru_utime),
+        system_time: timeval_to_duration(&usage.ru_stime),
+        max_rss,
+    })
+}
+
+pub fn try_wait_pid(environment: &Environment, pid: u32) -> (bool, Option<ProcessStatus>) {
     let mut opts = WaitPidFlag::WUNTRACED;
     opts.insert(WaitPidFlag::WCONTINUED);
     opts.insert(WaitPidFlag::WNOHANG);
-    match wait::waitpid(Pid::from_raw(pid as i32), Some(opts)) {
+    match wait4(Pid::from_raw(pid as i32), Some(opts)) {
         Err(nix::errno::Errno::ECHILD) => {
             // Does not exist.
             let mut code = None;
@@ -40,22 +71,26 @@ pub fn try_wait_pid(environment: &Environment, pid: u32) -> (bool, Option<i32>)
             remove_job(environment, pid);
             (true, None)
         }
-        Ok(WaitStatus::Exited(_, status)) => {
+        Ok((status @ WaitStatus::Exited(..), usage))
+        | Ok((status @ WaitStatus::Signaled(..), usage)) => {
+            let status = process_status(status, &usage);
             if let Some(pval) = environment.procs.borrow_mut().remove(&pid) {
-                pval.0
-                    .get_mut()
-                    .data
-                    .replace(ExpEnum::Process(ProcessState::Over(pid, status)));
+                if let Some(status) = status {
+                    pval.0
+                        .get_mut()
+                        .data
+                        .replace(ExpEnum::Process(ProcessState::Over(pid, status)));
+                }
             }
             remove_job(environment, pid);
-            (true, Some(status))
+            (true, status)
         }
-        Ok(WaitStatus::Stopped(..)) => {
+        Ok((WaitStatus::Stopped(..), _)) => {
             environment.stopped_procs.borrow_mut().push(pid);
             mark_job_stopped(environment, pid);
             (true, None)
         }
-        Ok(WaitStatus::Continued(_)) => (false, None),
+        Ok((WaitStatus::Continued(_), _)) => (false, None),
         Ok(_) => (false, None),
     }
 }
@@ -64,8 +99,8 @@ pub fn wait_pid(
     environment: &mut Environment,
     pid: u32,
     term_settings: Option<&termios::Termios>,
-) -> Option<i32> {
-    let result: Option<i32>;
+) -> Option<ProcessStatus> {
+    let result: Option<ProcessStatus>;
     let mut int_cnt = 0;
     loop {
         if test_clear_sigint() {
@@ -87,10 +122,10 @@ pub fn wait_pid(
             result = status;
             if let Some(status) = status {
                 if environment.save_exit_status {
-                    env::set_var("LAST_STATUS", format!("{}", status));
+                    env::set_var("LAST_STATUS", format!("{}", status.exit_status));
                     environment.root_scope.borrow_mut().insert_exp_data(
                         environment.interner.intern("*last-status*"),
-                        ExpEnum::Int(i64::from(status)),
+                        ExpEnum::Int(i64::from(status.exit_status)),
                     );
                 }
             }
@@ -168,8 +203,8 @@ fn run_command(
                 wait_pid(environment, proc, None)
             };
             let result = match status {
-                Some(code) => {
-                    Expression::alloc_data(ExpEnum::Process(ProcessState::Over(proc, code)))
+                Some(status) => {
+                    Expression::alloc_data(ExpEnum::Process(ProcessState::Over(proc, status)))
                 }
                 None => Expression::alloc_data(ExpEnum::Nil),
             };
@@ -243,31 +278,35 @@ fn get_std_io(environment: &Environment, is_out: bool) -> Result<Option<i32>, Li
     }
 }
 
+// Add a command argument to args, lists are flattened.
+pub fn add_arg_s(args: &mut Vec<String>, exp: Expression) -> Result<(), LispError> {
+    match &exp.get().data {
+        ExpEnum::String(s, _) => args.push(s.to_string()),
+        ExpEnum::Char(c) => args.push(c.to_string()),
+        ExpEnum::CodePoint(c) => args.push(c.to_string()),
+        ExpEnum::Symbol(s, _) => args.push(s.to_string()),
+        ExpEnum::Pair(_, _) => {
+            for a in exp.iter() {
+                add_arg_s(args, a)?;
+            }
+        }
+        ExpEnum::Vector(_) => {
+            for a in exp.iter() {
+                add_arg_s(args, a)?;
+            }
+        }
+        _ => return Err(LispError::new(
+            "Sys command arguments need to be string (or symbols or lists that reduce to strings).",
+        )),
+    }
+    Ok(())
+}
+
 pub fn do_command(
     environment: &mut Environment,
     command: &str,
     parts: &mut dyn Iterator<Item = Expression>,
 ) -> Result<Expression, LispError> {
-    fn add_arg_s(args: &mut Vec<String>, exp: Expression) -> Result<(), LispError> {
-        match &exp.get().data {
-            ExpEnum::String(s, _) => args.push(s.to_string()),
-            ExpEnum::Char(c) => args.push(c.to_string()),
-            ExpEnum::CodePoint(c) => args.push(c.to_string()),
-            ExpEnum::Symbol(s, _) => args.push(s.to_string()),
-            ExpEnum::Pair(_, _) => {
-                for a in exp.iter() {
-                    add_arg_s(args, a)?;
-                }
-            }
-            ExpEnum::Vector(_) => {
-                for a in exp.iter() {
-                    add_arg_s(args, a)?;
-                }
-            }
-            _ => return Err(LispError::new("Sys command arguments need to be string (or symbols or lists that reduce to strings).")),
-        }
-        Ok(())
-    }
     let mut args = Vec::new();
     for a_exp in parts {
         add_arg_s(&mut args, eval(environment, a_exp)?)?;
@@ -8,8 +8,13 @@ use std::num::{ParseFloatError, ParseIntError};
 
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::builtins_hashmap::{get_text_key, hash_key, LispMap};
+use crate::builtins_record::read_record;
+use crate::builtins_set::set_key;
 use crate::environment::*;
 use crate::eval::eval;
+use crate::numeric::{parse_rational, read_rational_literal, Decimal, Num};
+use crate::persistent::PMap;
 use crate::types::*;
 
 #[derive(Clone, Debug)]
@@ -32,6 +37,10 @@ pub struct ReaderState {
     pub file_name: Option<&'static str>,
     pub clear_state: bool,
     pub in_read: bool,
+    // Reject reader macros (including #.) so untrusted data can be read.
+    pub safe: bool,
+    // Objects labeled with #n= in the form being read (for #n# references).
+    pub labels: HashMap<usize, Expression>,
 }
 
 impl ReaderState {
@@ -45,6 +54,7 @@ impl ReaderState {
         self.line = 1;
         self.clear_state = false;
         self.in_read = false;
+        self.labels.clear();
     }
 }
 
@@ -56,6 +66,8 @@ impl Default for ReaderState {
             line: 1,
             clear_state: false,
             in_read: false,
+            safe: false,
+            labels: HashMap::new(),
         }
     }
 }
@@ -152,8 +164,8 @@ fn consume_block_comment(chars: &mut CharIter, reader_state: &mut ReaderState) {
     }
 }
 
-fn end_symbol(ch: &str, read_table_term: &HashMap<&'static str, Expression>) -> bool {
-    if is_whitespace(ch) || read_table_term.contains_key(ch) {
+fn end_symbol(ch: &str, read_table_term: &LispMap) -> bool {
+    if is_whitespace(ch) || get_text_key(read_table_term, ch).is_some() {
         true
     } else {
         matches!(ch, "(" | ")" | "#" | "\"" | "," | "'" | "`")
@@ -300,6 +312,13 @@ fn read_utf_scalar(
 }
 
 fn wrap_trim(exp: Expression, meta: Option<ExpMeta>) -> Expression {
+    // Trimming is for command output, a reader macro tags a form with :no-trim
+    // when its result is exact (format padding for instance).
+    if let Some(tags) = &exp.get().meta_tags {
+        if tags.contains(":no-trim") {
+            return exp.clone();
+        }
+    }
     let trim_list = vec![
         make_exp(ExpEnum::Symbol("str-trim", SymLoc::None), meta),
         exp,
@@ -311,7 +330,7 @@ fn read_string(
     environment: &mut Environment,
     mut chars: CharIter,
     buffer: &mut String,
-    read_table: &HashMap<&'static str, Expression>,
+    read_table: &LispMap,
 ) -> Result<(Expression, CharIter), (ReadError, CharIter)> {
     buffer.clear();
     let mut last_ch_escape = false;
@@ -331,7 +350,7 @@ fn read_string(
         }
         if last_ch_escape {
             let mut do_match = true;
-            if read_table.contains_key(&*ch) {
+            if get_text_key(read_table, &ch).is_some() {
                 do_match = false;
                 buffer.push_str(&ch);
             }
@@ -376,9 +395,15 @@ fn read_string(
                 break;
             }
             let mut proc_ch = true;
-            if read_table.contains_key(&*ch) {
+            // Reading data takes string reader macro characters literally.
+            let macro_sym = if environment.reader_state.safe {
+                None
+            } else {
+                get_text_key(read_table, &ch)
+            };
+            if let Some(macro_sym) = macro_sym {
                 proc_ch = false;
-                if let ExpEnum::Symbol(s, _) = read_table.get(&*ch).unwrap().get().data {
+                if let ExpEnum::Symbol(s, _) = macro_sym.get().data {
                     let res = prep_reader_macro(environment, chars, s, &ch);
                     match res {
                         Ok((None, ichars)) => {
@@ -531,6 +556,8 @@ fn do_atom(
         }
         if symbol == "nil" {
             make_exp(ExpEnum::Nil, meta)
+        } else if let Some(num) = read_rational_literal(symbol) {
+            make_exp(num.into_data(), meta)
         } else {
             make_exp(
                 ExpEnum::Symbol(environment.interner.intern(symbol), SymLoc::None),
@@ -546,7 +573,7 @@ fn read_symbol(
     reader_state: &mut ReaderState,
     for_ch: bool,
     skip_underscore: bool,
-    read_table_term: &HashMap<&'static str, Expression>,
+    read_table_term: &LispMap,
 ) -> bool {
     fn maybe_number(ch: &str, has_e: &mut bool, last_e: &mut bool, has_decimal: &mut bool) -> bool {
         if ch == "." {
@@ -723,6 +750,14 @@ fn prep_reader_macro(
             panic!("read: something happened to char iterator in reader macro!");
         }
     }
+    if environment.reader_state.safe {
+        let token = if name == "reader-macro-dot" { "#." } else { ch };
+        let reason = format!(
+            "Reader macro {} not allowed when reading data: line {}, col: {}",
+            token, environment.reader_state.line, environment.reader_state.column
+        );
+        return Err((ReadError { reason }, chars));
+    }
     let stream_exp = Expression::alloc_data(ExpEnum::String("".into(), Some(chars)));
     {
         let mut exp_d = stream_exp.get_mut();
@@ -782,7 +817,7 @@ fn read_num_radix(
     buffer: &mut String,
     radix: u32,
     meta: Option<ExpMeta>,
-    read_table_term: &HashMap<&'static str, Expression>,
+    read_table_term: &LispMap,
 ) -> Result<(Expression, CharIter), (ReadError, CharIter)> {
     buffer.clear();
     read_symbol(
@@ -804,6 +839,41 @@ fn read_num_radix(
     }
 }
 
+// Read the exact number after #r (a rational like 2/4) or #d (a decimal like 19.99).
+fn read_exact_num(
+    environment: &mut Environment,
+    mut chars: CharIter, // Pass ownership in and out for reader macro support.
+    buffer: &mut String,
+    prefix: &str,
+    meta: Option<ExpMeta>,
+    read_table_term: &LispMap,
+) -> Result<(Expression, CharIter), (ReadError, CharIter)> {
+    buffer.clear();
+    read_symbol(
+        buffer,
+        &mut chars,
+        &mut environment.reader_state,
+        true,
+        true,
+        read_table_term,
+    );
+    let num = if prefix == "r" {
+        parse_rational(buffer)
+    } else {
+        Decimal::parse(buffer).map(Num::Decimal)
+    };
+    match num {
+        Some(num) => Ok((make_exp(num.into_data(), meta), chars)),
+        None => {
+            let reason = format!(
+                "Invalid number #{}{}: line {}, col: {}",
+                prefix, buffer, environment.reader_state.line, environment.reader_state.column
+            );
+            Err((ReadError { reason }, chars))
+        }
+    }
+}
+
 fn read_regex(
     environment: &mut Environment,
     mut chars: CharIter, // Pass ownership in and out for reader macro support.
@@ -898,6 +968,233 @@ fn read_vector(
     ))
 }
 
+fn read_bytes(
+    environment: &mut Environment,
+    chars: CharIter, // Pass ownership in and out for reader macro support.
+    buffer: &mut String,
+) -> Result<(Option<Expression>, CharIter), (ReadError, CharIter)> {
+    let line = environment.reader_state.line;
+    let column = environment.reader_state.column;
+    let (exp, chars) = read_vector(environment, chars, buffer, false)?;
+    let meta = exp.get().meta;
+    let mut bytes = Vec::new();
+    if let ExpEnum::Vector(items) = &exp.get().data {
+        for item in items {
+            match &item.get().data {
+                ExpEnum::Int(i) if (0..=255).contains(i) => bytes.push(*i as u8),
+                _ => {
+                    let reason = format!(
+                        "Byte literal must contain integers from 0 to 255, found {}: line {}, col: {}",
+                        item, line, column
+                    );
+                    return Err((ReadError { reason }, chars));
+                }
+            }
+        }
+    }
+    Ok((Some(make_exp(ExpEnum::Bytes(bytes), meta)), chars))
+}
+
+fn read_hash_map(
+    environment: &mut Environment,
+    chars: CharIter, // Pass ownership in and out for reader macro support.
+    buffer: &mut String,
+    in_back_quote: bool,
+) -> Result<(Option<Expression>, CharIter), (ReadError, CharIter)> {
+    let line = environment.reader_state.line;
+    let column = environment.reader_state.column;
+    let (exp, chars) = read_vector(environment, chars, buffer, in_back_quote)?;
+    let meta = exp.get().meta;
+    let mut map = LispMap::new();
+    let mut error = None;
+    if let ExpEnum::Vector(items) = &exp.get().data {
+        if items.len() % 2 != 0 {
+            error = Some("hash map literal needs a value for every key".to_string());
+        }
+        for pair in items.chunks_exact(2) {
+            match hash_key(environment, &pair[0]) {
+                Ok(key) => {
+                    map.insert(key, pair[1].clone());
+                }
+                Err(err) => {
+                    error = Some(format!("hash map literal key {}", err.reason));
+                    break;
+                }
+            }
+        }
+    }
+    if let Some(msg) = error {
+        let reason = format!("{}: line {}, col: {}", msg, line, column);
+        return Err((ReadError { reason }, chars));
+    }
+    Ok((Some(make_exp(ExpEnum::HashMap(map), meta)), chars))
+}
+
+fn read_set(
+    environment: &mut Environment,
+    chars: CharIter, // Pass ownership in and out for reader macro support.
+    buffer: &mut String,
+    in_back_quote: bool,
+) -> Result<(Option<Expression>, CharIter), (ReadError, CharIter)> {
+    let line = environment.reader_state.line;
+    let column = environment.reader_state.column;
+    let (exp, chars) = read_vector(environment, chars, buffer, in_back_quote)?;
+    let meta = exp.get().meta;
+    let mut set = HashMap::new();
+    if let ExpEnum::Vector(items) = &exp.get().data {
+        for item in items {
+            match set_key(environment, item) {
+                Ok(key) => {
+                    set.insert(key, item.clone());
+                }
+                Err(err) => {
+                    let reason = format!("{}: line {}, col: {}", err, line, column);
+                    return Err((ReadError { reason }, chars));
+                }
+            }
+        }
+    }
+    Ok((Some(make_exp(ExpEnum::Set(set), meta)), chars))
+}
+
+// Read #pv(item*) or #pm(key value ...), the persistent vector and map literals.
+fn read_persistent(
+    environment: &mut Environment,
+    chars: CharIter, // Pass ownership in and out for reader macro support.
+    buffer: &mut String,
+    in_back_quote: bool,
+    is_map: bool,
+) -> Result<(Option<Expression>, CharIter), (ReadError, CharIter)> {
+    let (exp, chars) = if is_map {
+        match read_hash_map(environment, chars, buffer, in_back_quote)? {
+            (Some(exp), chars) => (exp, chars),
+            (None, chars) => return Ok((None, chars)),
+        }
+    } else {
+        read_vector(environment, chars, buffer, in_back_quote)?
+    };
+    let meta = exp.get().meta;
+    let data = match &exp.get().data {
+        ExpEnum::HashMap(map) => ExpEnum::PMap(map.iter().fold(PMap::new(), |pmap, (key, val)| {
+            pmap.insert(key.clone(), val.clone())
+        })),
+        ExpEnum::Vector(items) => ExpEnum::PVec(items.clone().into()),
+        _ => return Ok((Some(exp.clone()), chars)),
+    };
+    Ok((Some(make_exp(data, meta)), chars))
+}
+
+fn read_record_literal(
+    environment: &mut Environment,
+    chars: CharIter, // Pass ownership in and out for reader macro support.
+    buffer: &mut String,
+    in_back_quote: bool,
+) -> Result<(Option<Expression>, CharIter), (ReadError, CharIter)> {
+    let line = environment.reader_state.line;
+    let column = environment.reader_state.column;
+    let (exp, chars) = read_vector(environment, chars, buffer, in_back_quote)?;
+    let res = if let ExpEnum::Vector(items) = &exp.get().data {
+        read_record(environment, items)
+    } else {
+        Err(LispError::new("record literal must start with a type name"))
+    };
+    match res {
+        Ok(record) => Ok((Some(record), chars)),
+        Err(err) => {
+            let reason = format!("{}: line {}, col: {}", err, line, column);
+            Err((ReadError { reason }, chars))
+        }
+    }
+}
+
+// Read #n=form (label form so it can be referenced) or #n# (a reference to the
+// form labeled n).  Used to read circular lists.
+fn read_label(
+    environment: &mut Environment,
+    mut chars: CharIter, // Pass ownership in and out for reader macro support.
+    buffer: &mut String,
+    in_back_quote: bool,
+    first_digit: &str,
+) -> Result<(Option<Expression>, CharIter), (ReadError, CharIter)> {
+    let mut num = first_digit.to_string();
+    environment.reader_state.column += 1;
+    while let Some(ch) = chars.peek() {
+        if ch.chars().all(|c| c.is_ascii_digit()) {
+            num.push_str(ch);
+            chars.next();
+            environment.reader_state.column += 1;
+        } else {
+            break;
+        }
+    }
+    let label: usize = match num.parse() {
+        Ok(label) => label,
+        Err(_) => {
+            let reason = format!(
+                "Invalid label #{}: line {}, col: {}",
+                num, environment.reader_state.line, environment.reader_state.column
+            );
+            return Err((ReadError { reason }, chars));
+        }
+    };
+    environment.reader_state.column += 1;
+    match chars.next().as_deref() {
+        Some("#") => {
+            if let Some(exp) = environment.reader_state.labels.get(&label) {
+                Ok((Some(exp.clone()), chars))
+            } else {
+                let reason = format!(
+                    "Undefined label #{}#: line {}, col: {}",
+                    label, environment.reader_state.line, environment.reader_state.column
+                );
+                Err((ReadError { reason }, chars))
+            }
+        }
+        Some("=") => {
+            // References inside the form point at this placeholder, it takes
+            // the contents of the form once read so they become the form.
+            let placeholder = Expression::make_nil();
+            environment
+                .reader_state
+                .labels
+                .insert(label, placeholder.clone());
+            match read_inner(environment, chars, buffer, in_back_quote, false) {
+                Ok((Some(exp), ichars)) if exp.id() == placeholder.id() => {
+                    let reason = format!(
+                        "Label #{}= can not refer only to itself: line {}, col: {}",
+                        label, environment.reader_state.line, environment.reader_state.column
+                    );
+                    Err((ReadError { reason }, ichars))
+                }
+                Ok((Some(exp), ichars)) => {
+                    let exp_d = exp.get();
+                    let mut place_d = placeholder.get_mut();
+                    place_d.data = exp_d.data.clone();
+                    place_d.meta = exp_d.meta;
+                    drop(place_d);
+                    Ok((Some(placeholder), ichars))
+                }
+                Ok((None, ichars)) => {
+                    let reason = format!(
+                        "Label #{}= without a form: line {}, col: {}",
+                        label, environment.reader_state.line, environment.reader_state.column
+                    );
+                    Err((ReadError { reason }, ichars))
+                }
+                Err((err, ichars)) => Err((err, ichars)),
+            }
+        }
+        _ => {
+            // Not a label, report it the same as any other unknown # dispatch.
+            let reason = format!(
+                "Found # with invalid char {}: line {}, col: {}",
+                first_digit, environment.reader_state.line, environment.reader_state.column
+            );
+            Err((ReadError { reason }, chars))
+        }
+    }
+}
+
 fn get_unquote_lst(exp: &Expression) -> Option<Expression> {
     let exp_d = exp.get();
     if let ExpEnum::Pair(car, cdr) = &exp_d.data {
@@ -940,6 +1237,8 @@ fn read_list(
 ) -> Result<(Expression, CharIter), (ReadError, CharIter)> {
     let mut head = ExpEnum::Nil;
     let mut tail = ExpEnum::Nil;
+    // The cdr that holds tail (None when tail is head).
+    let mut tail_holder: Option<Expression> = None;
     let meta = get_meta(
         environment.reader_state.file_name,
         environment.reader_state.line,
@@ -1010,15 +1309,21 @@ fn read_list(
                 } else {
                     exp
                 };
-                if let ExpEnum::Pair(_, cdr) = &tail {
-                    let mut cdr = cdr.get_mut();
-                    cdr.data = exp.get().data.clone();
-                    cdr.meta = exp.get().meta;
+                if let ExpEnum::Pair(car, _) = &tail {
+                    // Link exp itself (not a copy) so #n# references keep their identity.
+                    let new_tail = ExpEnum::Pair(car.clone(), exp);
+                    if let Some(holder) = &tail_holder {
+                        holder.get_mut().data = new_tail.clone();
+                    } else {
+                        head = new_tail.clone();
+                    }
+                    tail = new_tail;
                 }
             } else {
                 let new_tail = ExpEnum::Pair(exp.clone(), make_exp(ExpEnum::Nil, meta));
                 if let ExpEnum::Pair(_, cdr) = &tail {
                     cdr.get_mut().data = new_tail.clone();
+                    tail_holder = Some(cdr.clone());
                 }
                 tail = new_tail;
             }
@@ -1108,8 +1413,8 @@ fn read_inner(
 
     while let Some((ch, peek_ch)) = next2(&mut chars) {
         environment.reader_state.column += 1;
-        if read_table.contains_key(&*ch) {
-            if let ExpEnum::Symbol(s, _) = read_table.get(&*ch).unwrap().get().data {
+        if let Some(macro_sym) = get_text_key(read_table, &ch) {
+            if let ExpEnum::Symbol(s, _) = macro_sym.get().data {
                 let res = prep_reader_macro(environment, chars, s, &ch);
                 match res {
                     Ok((None, ichars)) => {
@@ -1119,8 +1424,8 @@ fn read_inner(
                     _ => return res,
                 }
             }
-        } else if read_table_term.contains_key(&*ch) {
-            if let ExpEnum::Symbol(s, _) = read_table_term.get(&*ch).unwrap().get().data {
+        } else if let Some(macro_sym) = get_text_key(read_table_term, &ch) {
+            if let ExpEnum::Symbol(s, _) = macro_sym.get().data {
                 let res = prep_reader_macro(environment, chars, s, &ch);
                 match res {
                     Ok((None, ichars)) => {
@@ -1283,6 +1588,52 @@ fn read_inner(
                         let (exp, chars) = read_vector(environment, chars, buffer, in_back_quote)?;
                         return Ok((Some(exp), chars));
                     }
+                    "u" if chars.peek().map(|c| c == "8").unwrap_or(false) => {
+                        chars.next();
+                        environment.reader_state.column += 2;
+                        if chars.next().as_deref() != Some("(") {
+                            let reason = format!(
+                                "Found #u8 without (: line {}, col: {}",
+                                environment.reader_state.line, environment.reader_state.column
+                            );
+                            return Err((ReadError { reason }, chars));
+                        }
+                        environment.reader_state.column += 1;
+                        return read_bytes(environment, chars, buffer);
+                    }
+                    "h" if chars.peek().map(|c| c == "(").unwrap_or(false) => {
+                        chars.next();
+                        environment.reader_state.column += 2;
+                        return read_hash_map(environment, chars, buffer, in_back_quote);
+                    }
+                    "s" if chars.peek().map(|c| c == "(").unwrap_or(false) => {
+                        chars.next();
+                        environment.reader_state.column += 2;
+                        return read_set(environment, chars, buffer, in_back_quote);
+                    }
+                    "p" if chars.peek().map(|c| c == "v" || c == "m").unwrap_or(false) => {
+                        let is_map = chars.next().as_deref() == Some("m");
+                        environment.reader_state.column += 2;
+                        if chars.next().as_deref() != Some("(") {
+                            let reason = format!(
+                                "Found #p{} without (: line {}, col: {}",
+                                if is_map { "m" } else { "v" },
+                                environment.reader_state.line,
+                                environment.reader_state.column
+                            );
+                            return Err((ReadError { reason }, chars));
+                        }
+                        environment.reader_state.column += 1;
+                        return read_persistent(environment, chars, buffer, in_back_quote, is_map);
+                    }
+                    "S" if chars.peek().map(|c| c == "(").unwrap_or(false) => {
+                        chars.next();
+                        environment.reader_state.column += 2;
+                        return read_record_literal(environment, chars, buffer, in_back_quote);
+                    }
+                    n if n.chars().all(|c| c.is_ascii_digit()) => {
+                        return read_label(environment, chars, buffer, in_back_quote, n);
+                    }
                     "t" => {
                         return Ok((Some(Expression::make_true()), chars));
                     }
@@ -1314,6 +1665,18 @@ fn read_inner(
                             read_num_radix(environment, chars, buffer, 2, meta, read_table_term)?;
                         return Ok((Some(exp), chars));
                     }
+                    // Read an exact rational (#r2/4) or decimal (#d19.99)
+                    "r" | "d" => {
+                        let (exp, chars) = read_exact_num(
+                            environment,
+                            chars,
+                            buffer,
+                            &peek_ch,
+                            meta,
+                            read_table_term,
+                        )?;
+                        return Ok((Some(exp), chars));
+                    }
                     ";" => match read_inner(environment, chars, buffer, in_back_quote, false) {
                         Ok((_, ichars)) => {
                             return Ok((None, ichars));
@@ -1373,6 +1736,19 @@ fn read_inner(
     Ok((None, chars))
 }
 
+// Read one top level datum.  Labels (#n=) are only visible inside the datum
+// that defines them so stale labels never leak into the next form.
+fn read_datum(
+    environment: &mut Environment,
+    chars: CharIter,
+    buffer: &mut String,
+) -> Result<(Option<Expression>, CharIter), (ReadError, CharIter)> {
+    let old_labels = std::mem::take(&mut environment.reader_state.labels);
+    let res = read_inner(environment, chars, buffer, false, false);
+    environment.reader_state.labels = old_labels;
+    res
+}
+
 fn read2(
     environment: &mut Environment,
     text: &str,
@@ -1401,7 +1777,7 @@ fn read2(
     }
     let mut cont = true;
     while cont {
-        let (exp, ichars) = match read_inner(environment, chars, &mut buffer, false, false) {
+        let (exp, ichars) = match read_datum(environment, chars, &mut buffer) {
             Ok(r) => r,
             Err((err, _)) => {
                 environment.reader_state.clear_state = true;
@@ -1469,7 +1845,7 @@ pub fn read_form_state(
         None
     };
     environment.reader_state.in_read = true;
-    let res = match read_inner(environment, chars, &mut buffer, false, false) {
+    let res = match read_datum(environment, chars, &mut buffer) {
         Ok((Some(exp), ichars)) => Ok((exp, ichars)),
         Ok((None, ichars)) => Err((
             ReadError {
@@ -2062,4 +2438,26 @@ two""#
         assert!(tokens[9] == "Symbol:0.23.123");
         assert!(tokens[10] == ")");
     }
+
+    #[test]
+    fn test_labels_per_datum() {
+        let mut environment = build_def_env();
+        let exp = read(&mut environment, "(#1=(a) #1#)", None, false).unwrap();
+        let items: Vec<Expression> = exp.iter().collect();
+        assert!(items[0].id() == items[1].id());
+        // A label does not carry over into the next top level form.
+        assert!(read(&mut environment, "#1=(a) #1#", None, false).is_err());
+        let chars: CharIter = Box::new(
+            UnicodeSegmentation::graphemes("#1=(a) #1#", true)
+                .map(Cow::Borrowed)
+                .peekable(),
+        );
+        let chars = match read_form(&mut environment, chars) {
+            Ok((_, chars)) => chars,
+            Err((err, _)) => panic!("{}", err.reason),
+        };
+        assert!(read_form(&mut environment, chars).is_err());
+        let err = read(&mut environment, "#12 x", None, false).unwrap_err();
+        assert!(err.reason.starts_with("Found # with invalid char 1"));
+    }
 }
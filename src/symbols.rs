@@ -4,18 +4,32 @@ use std::rc::Rc;
 
 use crate::builtins::add_builtins;
 use crate::builtins_bind::add_bind_builtins;
+use crate::builtins_bytes::add_bytes_builtins;
+use crate::builtins_diff::add_diff_builtins;
+use crate::builtins_digest::add_digest_builtins;
 use crate::builtins_edit::add_edit_builtins;
+use crate::builtins_equal::add_equal_builtins;
+use crate::builtins_event::add_event_builtins;
 use crate::builtins_file::add_file_builtins;
+use crate::builtins_fs_watch::add_fs_watch_builtins;
 use crate::builtins_hashmap::add_hash_builtins;
 use crate::builtins_io::add_io_builtins;
+use crate::builtins_iterator::add_iterator_builtins;
+use crate::builtins_json::add_json_builtins;
 use crate::builtins_math::*;
 use crate::builtins_namespace::add_namespace_builtins;
 use crate::builtins_pair::add_pair_builtins;
+use crate::builtins_persistent::add_persistent_builtins;
+use crate::builtins_pty::add_pty_builtins;
 use crate::builtins_rand::add_rand_builtins;
+use crate::builtins_record::add_record_builtins;
 use crate::builtins_regex::add_regex_builtins;
+use crate::builtins_set::add_set_builtins;
+use crate::builtins_sort::add_sort_builtins;
 use crate::builtins_stats::add_stats_builtins;
 use crate::builtins_str::add_str_builtins;
 use crate::builtins_system::add_system_builtins;
+use crate::builtins_time::add_time_builtins;
 use crate::builtins_types::add_type_builtins;
 use crate::builtins_values::add_values_builtins;
 use crate::builtins_vector::add_vec_builtins;
@@ -263,6 +277,19 @@ impl Namespace {
         let mut stats_data: HashMap<&'static str, (Expression, String)> = HashMap::new();
         add_builtins(interner, &mut data);
         add_system_builtins(interner, &mut data);
+        add_pty_builtins(interner, &mut data);
+        add_event_builtins(interner, &mut data);
+        add_json_builtins(interner, &mut data);
+        add_bytes_builtins(interner, &mut data);
+        add_time_builtins(interner, &mut data);
+        add_diff_builtins(interner, &mut data);
+        add_digest_builtins(interner, &mut data);
+        add_equal_builtins(interner, &mut data);
+        add_sort_builtins(interner, &mut data);
+        add_record_builtins(interner, &mut data);
+        add_iterator_builtins(interner, &mut data);
+        add_set_builtins(interner, &mut data);
+        add_persistent_builtins(interner, &mut data);
         add_root_math_builtins(interner, &mut data);
         add_math_builtins(interner, &mut math_data);
         add_stats_builtins(interner, &mut stats_data);
@@ -273,6 +300,7 @@ impl Namespace {
         add_values_builtins(interner, &mut data);
         add_edit_builtins(interner, &mut data);
         add_file_builtins(interner, &mut data);
+        add_fs_watch_builtins(interner, &mut data);
         add_io_builtins(interner, &mut data);
         add_pair_builtins(interner, &mut data);
         add_hash_builtins(interner, &mut data);
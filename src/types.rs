@@ -1,7 +1,8 @@
 use regex::Regex;
 use std::borrow::Cow;
 use std::cell::{Ref, RefCell, RefMut};
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
@@ -10,9 +11,21 @@ use std::iter;
 use std::marker::PhantomData;
 use std::num::{ParseFloatError, ParseIntError};
 use std::rc::Rc;
+use std::time::Duration;
 
+use nix::sys::signal::Signal;
+use rand_chacha::ChaCha8Rng;
+
+use crate::builtins_fs_watch::FsWatcher;
+use crate::builtins_hashmap::LispMap;
+use crate::builtins_iterator::IterState;
+use crate::builtins_pty::PtyState;
+use crate::builtins_record::{Record, RecordType};
+use crate::builtins_set::SetMap;
 use crate::environment::*;
 use crate::eval::call_lambda;
+use crate::numeric::{Decimal, Num, Rational};
+use crate::persistent::{PMap, PVec};
 use crate::process::*;
 use crate::symbols::*;
 use crate::unix::fd_to_file;
@@ -114,10 +127,32 @@ impl Lambda {
     }
 }
 
+// How a completed process finished and the resources it used.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProcessStatus {
+    // Exit code or 128 + signal number if the process was killed by a signal.
+    pub exit_status: i32,
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    // Max resident set size in kilobytes.
+    pub max_rss: i64,
+}
+
+impl ProcessStatus {
+    pub fn signal_name(&self) -> Option<String> {
+        self.signal.map(|sig| match Signal::try_from(sig) {
+            Ok(sig) => sig.to_string(),
+            Err(_) => format!("SIG{}", sig),
+        })
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum ProcessState {
-    Running(u32),   // pid
-    Over(u32, i32), // pid and exit status
+    Running(u32),             // pid
+    Over(u32, ProcessStatus), // pid and how it finished
 }
 
 pub enum FileState {
@@ -251,6 +286,9 @@ pub enum ExpEnum {
     False,
     Nil,
     Float(f64),
+    // Exact numbers (1/3 and #d19.99)
+    Rational(Rational),
+    Decimal(Decimal),
     Int(i64),
     Symbol(&'static str, SymLoc),
     // NOTE: String has an invariant to maintain, if Cow ever changes then the
@@ -270,11 +308,32 @@ pub enum ExpEnum {
     Vector(Vec<Expression>),
     Values(Vec<Expression>), // Used for multi value returns
     Pair(Expression, Expression),
-    HashMap(HashMap<&'static str, Expression>),
+    HashMap(LispMap),
+    // Members keyed like hash map keys (so equal? members are the same member)
+    Set(SetMap),
+    // Immutable, updates return a new version sharing structure with the old
+    PVec(PVec),
+    PMap(PMap),
+    // Raw bytes (binary file data, non-UTF-8 process output, etc)
+    Bytes(Vec<u8>),
 
     // Represents a running or completed system process
     Process(ProcessState),
 
+    // A process running on a pseudo-terminal
+    Pty(Rc<RefCell<PtyState>>),
+    // A watcher for changes to a file or directory tree
+    FsWatcher(Rc<RefCell<FsWatcher>>),
+    // A seeded random number generator (from make-rng)
+    Rng(Rc<RefCell<ChaCha8Rng>>),
+
+    // A record type (from defstruct), calling it makes an instance
+    RecordType(Rc<RecordType>),
+    // An instance of a record type
+    Record(Record),
+    // A native iterator (from make-iter and the iter- adapters)
+    Iterator(Rc<RefCell<IterState>>),
+
     // A file
     File(Rc<RefCell<FileState>>),
 
@@ -336,6 +395,8 @@ impl ExpEnum {
             ExpEnum::False => ExpEnum::False,
             ExpEnum::Nil => ExpEnum::Nil,
             ExpEnum::Float(n) => ExpEnum::Float(*n),
+            ExpEnum::Rational(r) => ExpEnum::Rational(*r),
+            ExpEnum::Decimal(d) => ExpEnum::Decimal(*d),
             ExpEnum::Int(i) => ExpEnum::Int(*i),
             ExpEnum::Symbol(s, _) => ExpEnum::Symbol(s, SymLoc::None),
             // XXX TODO- make a new Cow (next two)?
@@ -352,7 +413,16 @@ impl ExpEnum {
             ExpEnum::Values(v) => ExpEnum::Values(v.iter().map(copy_handle).collect()),
             ExpEnum::Pair(car, cdr) => ExpEnum::Pair(copy_handle(car), copy_handle(cdr)),
             ExpEnum::HashMap(map) => ExpEnum::HashMap(map.clone()), //XXX TODO- deep copy
+            ExpEnum::Set(set) => ExpEnum::Set(set.clone()),
+            ExpEnum::PVec(v) => ExpEnum::PVec(v.clone()),
+            ExpEnum::PMap(map) => ExpEnum::PMap(map.clone()),
             ExpEnum::Process(p) => ExpEnum::Process(*p),
+            ExpEnum::Pty(p) => ExpEnum::Pty(p.clone()),
+            ExpEnum::FsWatcher(w) => ExpEnum::FsWatcher(w.clone()),
+            ExpEnum::Rng(rng) => ExpEnum::Rng(rng.clone()),
+            ExpEnum::RecordType(t) => ExpEnum::RecordType(t.clone()),
+            ExpEnum::Record(r) => ExpEnum::Record(r.copy()),
+            ExpEnum::Iterator(i) => ExpEnum::Iterator(i.clone()),
             ExpEnum::File(f) => ExpEnum::File(f.clone()),
             ExpEnum::Wrapper(h) => ExpEnum::Wrapper(copy_handle(h)),
             ExpEnum::DeclareDef => ExpEnum::DeclareDef,
@@ -363,6 +433,7 @@ impl ExpEnum {
             ExpEnum::BackQuote => ExpEnum::BackQuote,
             ExpEnum::Undefined => ExpEnum::Undefined,
             ExpEnum::Regex(regex) => ExpEnum::Regex(regex.clone()),
+            ExpEnum::Bytes(b) => ExpEnum::Bytes(b.clone()),
         }
     }
 }
@@ -374,6 +445,8 @@ impl Clone for ExpEnum {
             ExpEnum::False => ExpEnum::False,
             ExpEnum::Nil => ExpEnum::Nil,
             ExpEnum::Float(n) => ExpEnum::Float(*n),
+            ExpEnum::Rational(r) => ExpEnum::Rational(*r),
+            ExpEnum::Decimal(d) => ExpEnum::Decimal(*d),
             ExpEnum::Int(i) => ExpEnum::Int(*i),
             ExpEnum::Symbol(s, l) => ExpEnum::Symbol(s, l.clone()),
             ExpEnum::String(s, _) => ExpEnum::String(s.clone(), None),
@@ -387,7 +460,16 @@ impl Clone for ExpEnum {
             ExpEnum::Values(v) => ExpEnum::Values(v.clone()),
             ExpEnum::Pair(car, cdr) => ExpEnum::Pair(car.clone(), cdr.clone()),
             ExpEnum::HashMap(map) => ExpEnum::HashMap(map.clone()),
+            ExpEnum::Set(set) => ExpEnum::Set(set.clone()),
+            ExpEnum::PVec(v) => ExpEnum::PVec(v.clone()),
+            ExpEnum::PMap(map) => ExpEnum::PMap(map.clone()),
             ExpEnum::Process(p) => ExpEnum::Process(*p),
+            ExpEnum::Pty(p) => ExpEnum::Pty(p.clone()),
+            ExpEnum::FsWatcher(w) => ExpEnum::FsWatcher(w.clone()),
+            ExpEnum::Rng(rng) => ExpEnum::Rng(rng.clone()),
+            ExpEnum::RecordType(t) => ExpEnum::RecordType(t.clone()),
+            ExpEnum::Record(r) => ExpEnum::Record(r.clone()),
+            ExpEnum::Iterator(i) => ExpEnum::Iterator(i.clone()),
             ExpEnum::File(f) => ExpEnum::File(f.clone()),
             ExpEnum::Wrapper(h) => ExpEnum::Wrapper(h.clone()),
             ExpEnum::DeclareDef => ExpEnum::DeclareDef,
@@ -398,6 +480,7 @@ impl Clone for ExpEnum {
             ExpEnum::BackQuote => ExpEnum::BackQuote,
             ExpEnum::Undefined => ExpEnum::Undefined,
             ExpEnum::Regex(regex) => ExpEnum::Regex(regex.clone()),
+            ExpEnum::Bytes(b) => ExpEnum::Bytes(b.clone()),
         }
     }
 }
@@ -408,6 +491,8 @@ impl fmt::Debug for ExpEnum {
             ExpEnum::True => write!(f, "ExpEnum::True"),
             ExpEnum::False => write!(f, "ExpEnum::False"),
             ExpEnum::Float(n) => write!(f, "ExpEnum::Float({})", n),
+            ExpEnum::Rational(r) => write!(f, "ExpEnum::Rational({})", r),
+            ExpEnum::Decimal(d) => write!(f, "ExpEnum::Decimal({})", d),
             ExpEnum::Int(i) => write!(f, "ExpEnum::Int({})", i),
             ExpEnum::Symbol(s, loc) => write!(f, "ExpEnum::Symbol({}, {:?})", s, loc),
             ExpEnum::String(s, _) => write!(f, "ExpEnum::String(\"{}\")", s),
@@ -425,15 +510,26 @@ impl fmt::Debug for ExpEnum {
             ExpEnum::Values(v) => write!(f, "ExpEnum::Vector({:?})", v),
             ExpEnum::Pair(e1, e2) => write!(f, "ExpEnum::Pair({:?} . {:?})", e1, e2),
             ExpEnum::HashMap(map) => write!(f, "ExpEnum::HashMap({:?})", map),
+            ExpEnum::Set(set) => write!(f, "ExpEnum::Set({:?})", set),
+            ExpEnum::PVec(v) => write!(f, "ExpEnum::PVec({:?})", v),
+            ExpEnum::PMap(map) => write!(f, "ExpEnum::PMap({:?})", map),
             ExpEnum::Function(_) => write!(f, "ExpEnum::Function(_)"),
             ExpEnum::Process(ProcessState::Running(pid)) => {
                 write!(f, "ExpEnum::Process(ProcessStats::Running({}))", pid)
             }
-            ExpEnum::Process(ProcessState::Over(pid, exit_status)) => write!(
+            ExpEnum::Process(ProcessState::Over(pid, status)) => write!(
                 f,
-                "ExpEnum::Process(ProcessState::Over({}, {}))",
-                pid, exit_status
+                "ExpEnum::Process(ProcessState::Over({}, {:?}))",
+                pid, status
             ),
+            ExpEnum::Pty(p) => write!(f, "ExpEnum::Pty({})", p.borrow().pid()),
+            ExpEnum::FsWatcher(w) => {
+                write!(f, "ExpEnum::FsWatcher({})", w.borrow().root().display())
+            }
+            ExpEnum::Rng(_) => write!(f, "ExpEnum::Rng(_)"),
+            ExpEnum::RecordType(t) => write!(f, "ExpEnum::RecordType({})", t.name()),
+            ExpEnum::Record(r) => write!(f, "ExpEnum::Record({})", r.record_type().name()),
+            ExpEnum::Iterator(_) => write!(f, "ExpEnum::Iterator(_)"),
             ExpEnum::File(_) => write!(f, "ExpEnum::File(_)"),
             ExpEnum::LazyFn(_, exp) => write!(f, "ExpEnum::LazyFn({:?})", exp),
             ExpEnum::Wrapper(exp) => write!(f, "ExpEnum::Wrapper({:?})", exp),
@@ -445,6 +541,7 @@ impl fmt::Debug for ExpEnum {
             ExpEnum::Quote => write!(f, "ExpEnum::Function(_)"),
             ExpEnum::BackQuote => write!(f, "ExpEnum::Function(_)"),
             ExpEnum::Undefined => write!(f, "ExpEnum::Undefined"),
+            ExpEnum::Bytes(b) => write!(f, "ExpEnum::Bytes({:?})", b),
             ExpEnum::Regex(regex) => {
                 write!(f, "ExpEnum::Regex({:?})", regex)
             }
@@ -605,6 +702,11 @@ impl Expression {
         Expression::alloc(self.get().clone())
     }
 
+    // Identity of the underlying object, clones of an expression share it.
+    pub fn id(&self) -> usize {
+        Rc::as_ptr(&self.data) as usize
+    }
+
     pub fn get(&self) -> Ref<ExpObj> {
         self.data.borrow()
     }
@@ -747,6 +849,8 @@ impl Expression {
             ExpEnum::True => "True".to_string(),
             ExpEnum::False => "False".to_string(),
             ExpEnum::Float(_) => "Float".to_string(),
+            ExpEnum::Rational(_) => "Rational".to_string(),
+            ExpEnum::Decimal(_) => "Decimal".to_string(),
             ExpEnum::Int(_) => "Int".to_string(),
             ExpEnum::Symbol(_, _) => "Symbol".to_string(),
             ExpEnum::String(_, _) => "String".to_string(),
@@ -755,6 +859,12 @@ impl Expression {
             ExpEnum::Lambda(_) => "Lambda".to_string(),
             ExpEnum::Macro(_) => "Macro".to_string(),
             ExpEnum::Process(_) => "Process".to_string(),
+            ExpEnum::Pty(_) => "Pty".to_string(),
+            ExpEnum::FsWatcher(_) => "FsWatcher".to_string(),
+            ExpEnum::Rng(_) => "Rng".to_string(),
+            ExpEnum::RecordType(_) => "RecordType".to_string(),
+            ExpEnum::Record(_) => "Record".to_string(),
+            ExpEnum::Iterator(_) => "Iterator".to_string(),
             ExpEnum::Function(f) => {
                 if f.is_special_form {
                     "SpecialForm".to_string()
@@ -773,6 +883,9 @@ impl Expression {
             }
             ExpEnum::Pair(_, _) => "Pair".to_string(),
             ExpEnum::HashMap(_) => "HashMap".to_string(),
+            ExpEnum::Set(_) => "Set".to_string(),
+            ExpEnum::PVec(_) => "PersistentVector".to_string(),
+            ExpEnum::PMap(_) => "PersistentMap".to_string(),
             ExpEnum::File(_) => "File".to_string(),
             ExpEnum::LazyFn(_, _) => "Lambda".to_string(),
             ExpEnum::Wrapper(exp) => {
@@ -787,6 +900,7 @@ impl Expression {
             ExpEnum::Quote => "SpecialForm".to_string(),
             ExpEnum::BackQuote => "SpecialForm".to_string(),
             ExpEnum::Regex(_) => "Regex".to_string(),
+            ExpEnum::Bytes(_) => "Bytes".to_string(),
             ExpEnum::Undefined => "Undefined".to_string(), //panic!("Tried to get type for undefined!"),
         }
     }
@@ -811,7 +925,7 @@ impl Expression {
 
     pub fn make_string(&self, environment: &Environment) -> Result<String, LispError> {
         match &self.get().data {
-            ExpEnum::Process(ProcessState::Over(pid, _exit_status)) => {
+            ExpEnum::Process(ProcessState::Over(pid, _status)) => {
                 self.pid_to_string(environment.procs.clone(), *pid)
             }
             ExpEnum::Process(ProcessState::Running(pid)) => {
@@ -878,10 +992,12 @@ impl Expression {
         match &self.get().data {
             ExpEnum::Float(f) => Ok(*f),
             ExpEnum::Int(i) => Ok(*i as f64),
+            ExpEnum::Rational(r) => Ok(Num::Rational(*r).to_f64()),
+            ExpEnum::Decimal(d) => Ok(Num::Decimal(*d).to_f64()),
             ExpEnum::Process(ProcessState::Running(_pid)) => {
                 Err(LispError::new("Not a number (process still running!)"))
             }
-            ExpEnum::Process(ProcessState::Over(pid, _exit_status)) => {
+            ExpEnum::Process(ProcessState::Over(pid, _status)) => {
                 let buffer = self.pid_to_string(environment.procs.clone(), *pid)?;
                 let potential_float: Result<f64, ParseFloatError> = buffer.parse();
                 match potential_float {
@@ -920,7 +1036,7 @@ impl Expression {
             ExpEnum::Process(ProcessState::Running(_pid)) => {
                 Err(LispError::new("Not an integer (process still running!)"))
             }
-            ExpEnum::Process(ProcessState::Over(pid, _exit_status)) => {
+            ExpEnum::Process(ProcessState::Over(pid, _status)) => {
                 let buffer = self.pid_to_string(environment.procs.clone(), *pid)?;
                 let potential_int: Result<i64, ParseIntError> = buffer.parse();
                 match potential_int {
@@ -962,7 +1078,7 @@ impl Expression {
             ExpEnum::Process(ps) => {
                 let pid = match ps {
                     ProcessState::Running(pid) => pid,
-                    ProcessState::Over(pid, _exit_status) => pid,
+                    ProcessState::Over(pid, _status) => pid,
                 };
                 let procs = environment.procs.clone();
                 let mut procs = procs.borrow_mut();
@@ -1201,15 +1317,8 @@ where
 {
     fn apply_ref_mut(&mut self, fn_name: &str, fun: F) -> LispResult<Expression> {
         let got = self.0.display_type();
-        let mut btreemap = BTreeMap::new();
-        let x = match &self.0.get().data {
-            ExpEnum::HashMap(map) => {
-                map.iter().fold(&mut btreemap, |accum, (k, v)| {
-                    accum.insert(*k, v.clone());
-                    accum
-                });
-                fun(&mut btreemap)
-            }
+        let entries: Vec<(Cow<'static, str>, Expression)> = match &self.0.get().data {
+            ExpEnum::HashMap(map) => map.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
             _ => {
                 return Err(LispError::new(ErrorStrings::mismatched_type(
                     fn_name,
@@ -1218,10 +1327,13 @@ where
                 )))
             }
         };
+        let mut btreemap: BTreeMap<&str, Expression> =
+            entries.iter().map(|(k, v)| (&k[..], v.clone())).collect();
+        let x = fun(&mut btreemap);
         let map = btreemap
             .into_iter()
-            .fold(HashMap::new(), |mut accum, (k, v)| {
-                accum.insert(k, v);
+            .fold(LispMap::new(), |mut accum, (k, v)| {
+                accum.insert(Cow::Owned(k.to_string()), v);
                 accum
             });
         self.0.data.borrow_mut().data.replace(ExpEnum::HashMap(map));
@@ -1229,20 +1341,18 @@ where
     }
 }
 
-impl<F> RustProcedureRefMut<HashMap<&str, Expression>, F>
-    for TypedWrapper<'_, HashMap<&str, Expression>, Expression>
+impl<F> RustProcedureRefMut<LispMap, F> for TypedWrapper<'_, LispMap, Expression>
 where
-    F: FnOnce(&mut HashMap<&str, Expression>) -> LispResult<Expression>,
+    F: FnOnce(&mut LispMap) -> LispResult<Expression>,
 {
     fn apply_ref_mut(&mut self, fn_name: &str, fun: F) -> LispResult<Expression> {
         try_inner_hash_map_mut!(fn_name, self.0, arg, fun(arg))
     }
 }
 
-impl<F> RustProcedure<HashMap<&str, Expression>, F>
-    for TypedWrapper<'_, HashMap<&str, Expression>, Expression>
+impl<F> RustProcedure<LispMap, F> for TypedWrapper<'_, LispMap, Expression>
 where
-    F: FnOnce(HashMap<&str, Expression>) -> LispResult<Expression>,
+    F: FnOnce(LispMap) -> LispResult<Expression>,
 {
     fn apply(&self, fn_name: &str, fun: F) -> LispResult<Expression> {
         try_inner_hash_map!(fn_name, self.0, arg, fun(arg.clone()))
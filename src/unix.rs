@@ -3,19 +3,26 @@ use std::convert::TryInto;
 use std::ffi::{CString, OsStr};
 use std::fs::File;
 use std::io::{self, BufWriter, Read, Write};
+use std::mem;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::FromRawFd;
 use std::ptr;
 use std::rc::Rc;
 
+use nix::errno::Errno;
 use nix::libc;
+use nix::pty::{self, Winsize};
 use nix::sys::signal::{self, SigHandler, Signal};
-use nix::unistd::{self, Pid};
+use nix::sys::wait;
+use nix::unistd::{self, ForkResult, Pid};
 
 use crate::environment::*;
 use crate::eval::*;
+use crate::pretty_print::readable_string;
 use crate::types::*;
 
+const CLOEXEC_MSG_FOOTER: [u8; 4] = *b"NOEX";
+
 pub trait IsMinusOne {
     fn is_minus_one(&self) -> bool;
 }
@@ -313,6 +320,75 @@ pub fn fork(
     Ok(pid)
 }
 
+// Fork a child that runs func and writes the result back over a pipe as
+// readable text, either (:ok value) or (:error "reason").  Returns the pid and
+// the read end of the pipe, the parent should read it to the end before waiting
+// on the child.
+pub fn fork_value<F>(environment: &mut Environment, func: F) -> Result<(u32, i32), LispError>
+where
+    F: FnOnce(&mut Environment) -> Result<Expression, LispError>,
+{
+    let (input, output) = anon_pipe()?;
+    let result = unsafe { cvt(libc::fork())? };
+    if result != 0 {
+        unsafe {
+            libc::close(output);
+        }
+        return Ok((result as u32, input));
+    }
+    unsafe {
+        libc::close(input);
+    }
+    environment.eval_level = 0;
+    environment.jobs.borrow_mut().clear();
+    environment.do_job_control = false;
+    environment.stopped_procs.borrow_mut().clear();
+    environment.procs.borrow_mut().clear();
+    environment.grab_proc_output = false;
+    environment.pipe_pgid = None;
+    environment.is_tty = false;
+    environment.in_fork = true;
+    let result = func(environment);
+    // Calling exit in the child means there is no value, just go.
+    if let Some(exit_code) = environment.exit_code {
+        let _ = io::stdout().flush();
+        unsafe {
+            libc::_exit(exit_code);
+        }
+    }
+    let (text, exit_code) = match result.and_then(|exp| readable_string(&exp, environment)) {
+        Ok(text) => (format!("(:ok {})", text), 0),
+        Err(err) => {
+            // Send the whole error (reason and backtrace) so the parent can raise
+            // the same error, drop the backtrace if it is not readable.
+            let reason = Expression::alloc_data(ExpEnum::String(err.reason.into(), None));
+            let backtrace = match err.backtrace {
+                Some(backtrace) => Expression::with_list(backtrace),
+                None => Expression::make_nil(),
+            };
+            match readable_string(&reason, environment) {
+                Ok(reason) => match readable_string(&backtrace, environment) {
+                    Ok(backtrace) => (format!("(:error {} {})", reason, backtrace), 1),
+                    Err(_) => (format!("(:error {})", reason), 1),
+                },
+                Err(_) => ("(:error \"unknown error\")".to_string(), 1),
+            }
+        }
+    };
+    let mut outf = fd_to_file(output);
+    if let Err(err) = outf.write_all(text.as_bytes()) {
+        eprintln!("Error writing result to parent: {}", err);
+    }
+    drop(outf);
+    let _ = io::stdout().flush();
+    if let Err(err) = reap_procs(environment) {
+        eprintln!("Error reaping procs in a forked process: {}", err);
+    }
+    unsafe {
+        libc::_exit(exit_code);
+    }
+}
+
 pub fn dup_fd(fd: i32) -> Result<i32, LispError> {
     Ok(unsafe { cvt(libc::dup(fd))? })
 }
@@ -373,6 +449,29 @@ pub fn fd_to_file(fd: i32) -> File {
     unsafe { File::from_raw_fd(fd) }
 }
 
+// Same as waitpid but also returns the resource usage of a reaped child.
+// Usage will be all zeros unless the child was reaped (exited or signaled).
+pub fn wait4(
+    pid: Pid,
+    options: Option<wait::WaitPidFlag>,
+) -> nix::Result<(wait::WaitStatus, libc::rusage)> {
+    let mut status: i32 = 0;
+    let mut usage = mem::MaybeUninit::<libc::rusage>::zeroed();
+    let options = options.map_or(0, |o| o.bits());
+    let res = unsafe { libc::wait4(pid.as_raw(), &mut status, options, usage.as_mut_ptr()) };
+    let res = Errno::result(res)?;
+    // Zeroed is a valid rusage and wait4 only ever fills it in.
+    let usage = unsafe { usage.assume_init() };
+    if res == 0 {
+        Ok((wait::WaitStatus::StillAlive, usage))
+    } else {
+        Ok((
+            wait::WaitStatus::from_raw(Pid::from_raw(res), status)?,
+            usage,
+        ))
+    }
+}
+
 fn os2c(s: &OsStr, saw_nul: &mut bool) -> CString {
     CString::new(s.as_bytes()).unwrap_or_else(|_e| {
         *saw_nul = true;
@@ -429,7 +528,6 @@ where
     I: IntoIterator<Item = S>,
     S: AsRef<OsStr>,
 {
-    const CLOEXEC_MSG_FOOTER: [u8; 4] = *b"NOEX";
     let (input, output) = anon_pipe()?;
     let result = unsafe { cvt(libc::fork())? };
 
@@ -493,23 +591,7 @@ where
                 }
 
                 let err = exec(program, args);
-                let errno = err.raw_os_error().unwrap_or(libc::EINVAL) as u32;
-                let errno = errno.to_be_bytes();
-                let bytes = [
-                    errno[0],
-                    errno[1],
-                    errno[2],
-                    errno[3],
-                    CLOEXEC_MSG_FOOTER[0],
-                    CLOEXEC_MSG_FOOTER[1],
-                    CLOEXEC_MSG_FOOTER[2],
-                    CLOEXEC_MSG_FOOTER[3],
-                ];
-                // pipe I/O up to PIPE_BUF bytes should be atomic, and then
-                // we want to be sure we *don't* run at_exit destructors as
-                // we're being torn down regardless
-                File::from_raw_fd(output).write_all(&bytes)?;
-                libc::_exit(1);
+                exec_failed(err, output);
             }
             n => n as u32,
         }
@@ -523,6 +605,36 @@ where
             cvt(libc::close(stdout))?;
         }
     }
+    exec_status(input)?;
+    setup_job(environment, pid, program);
+    Ok(pid)
+}
+
+// Write errno to the CLOEXEC pipe after a failed exec so the parent can report
+// it, then exit the child.  Only call in a forked child.
+unsafe fn exec_failed(err: io::Error, output: i32) -> ! {
+    let errno = err.raw_os_error().unwrap_or(libc::EINVAL) as u32;
+    let errno = errno.to_be_bytes();
+    let bytes = [
+        errno[0],
+        errno[1],
+        errno[2],
+        errno[3],
+        CLOEXEC_MSG_FOOTER[0],
+        CLOEXEC_MSG_FOOTER[1],
+        CLOEXEC_MSG_FOOTER[2],
+        CLOEXEC_MSG_FOOTER[3],
+    ];
+    // pipe I/O up to PIPE_BUF bytes should be atomic, and then
+    // we want to be sure we *don't* run at_exit destructors as
+    // we're being torn down regardless
+    let _ = File::from_raw_fd(output).write_all(&bytes);
+    libc::_exit(1);
+}
+
+// Read the CLOEXEC pipe from a fork/exec, if it closes without data then the
+// exec succeeded otherwise it will contain the errno from the failed exec.
+fn exec_status(input: i32) -> Result<(), LispError> {
     let mut bytes = [0; 8];
 
     let mut input = unsafe { File::from_raw_fd(input) };
@@ -530,8 +642,7 @@ where
     loop {
         match input.read(&mut bytes) {
             Ok(0) => {
-                setup_job(environment, pid, program);
-                return Ok(pid);
+                return Ok(());
             }
             Ok(8) => {
                 let (errno, footer) = bytes.split_at(4);
@@ -555,3 +666,68 @@ where
         }
     }
 }
+
+pub fn forkpty_exec<I, S>(
+    rows: u16,
+    cols: u16,
+    program: &str,
+    args: I,
+) -> Result<(File, u32), LispError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let winsize = Winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let (input, output) = anon_pipe()?;
+    // forkpty makes the child a session leader with the pty slave as its
+    // controlling terminal and stdin/stdout/stderr.
+    let result = match unsafe { pty::forkpty(&winsize, None) } {
+        Ok(result) => result,
+        Err(err) => {
+            close_fd(input)?;
+            close_fd(output)?;
+            return Err(LispError::new(format!("forkpty failed: {}", err)));
+        }
+    };
+    match result.fork_result {
+        ForkResult::Child => unsafe {
+            libc::close(input);
+            let err = exec(program, args);
+            exec_failed(err, output);
+        },
+        ForkResult::Parent { child } => {
+            let master = fd_to_file(result.master);
+            // Only the shell should hold the master, keep it out of later children.
+            cvt(unsafe { libc::fcntl(result.master, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+            close_fd(output)?;
+            if let Err(err) = exec_status(input) {
+                // The child has already exited, reap it.
+                let _ = wait::waitpid(child, None);
+                return Err(err);
+            }
+            Ok((master, child.as_raw() as u32))
+        }
+    }
+}
+
+pub fn pty_resize(master: i32, rows: u16, cols: u16) -> Result<(), LispError> {
+    let winsize = Winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe {
+        cvt(libc::ioctl(
+            master,
+            libc::TIOCSWINSZ,
+            &winsize as *const Winsize,
+        ))?;
+    }
+    Ok(())
+}
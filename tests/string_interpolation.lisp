@@ -35,3 +35,7 @@
 (assert-equal "print1" "$(str print1)")
 (assert-equal "exp1Xval1:echo1" "${STRI_TEST}X${stri1}:$(echo echo1)")
 (assert-equal "exp1Xval1:print1" "${STRI_TEST}X${stri1}:$(str print1)")
+
+; fmt output is not trimmed so padding survives interpolation.
+(assert-equal "[  200|val1 ]" "[$((fmt "{:>5}|{:<5}" stri2 stri1))]")
+(assert-equal "[  200]" "[$%((fmt "{:>5}" stri2))]")